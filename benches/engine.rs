@@ -0,0 +1,120 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use csv::{ByteRecord, StringRecord};
+use payments_engine::transaction::Transaction;
+use payments_engine::PaymentEngine;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::io::Write;
+
+const ROWS: u64 = 100_000;
+const CLIENTS: u64 = 1000;
+
+// Deposits for every client, with every `dispute_every`th one disputed and
+// then alternately resolved or charged back. 0 means no disputes.
+fn rows(dispute_every: u64) -> Vec<String> {
+    let mut rows = Vec::with_capacity(ROWS as usize);
+    let mut tx = 0;
+    while (rows.len() as u64) < ROWS {
+        tx += 1;
+        let client = tx % CLIENTS;
+        rows.push(format!(
+            "deposit,{},{},{}.{:04}",
+            client,
+            tx,
+            tx % 500,
+            tx % 10000
+        ));
+        if dispute_every != 0 && tx % dispute_every == 0 {
+            rows.push(format!("dispute,{},{},", client, tx));
+            let outcome = if tx % (2 * dispute_every) == 0 {
+                "resolve"
+            } else {
+                "chargeback"
+            };
+            rows.push(format!("{},{},{},", outcome, client, tx));
+        }
+    }
+    rows.truncate(ROWS as usize);
+    rows
+}
+
+fn transactions(rows: &[String]) -> Vec<Transaction> {
+    rows.iter()
+        .map(|row| {
+            let record = ByteRecord::from(row.split(',').collect::<Vec<&str>>());
+            Transaction::from_byte_record(&record).unwrap()
+        })
+        .collect()
+}
+
+fn parsing(c: &mut Criterion) {
+    let rows = rows(10);
+    let string_records: Vec<StringRecord> = rows
+        .iter()
+        .map(|row| StringRecord::from(row.split(',').collect::<Vec<&str>>()))
+        .collect();
+    let byte_records: Vec<ByteRecord> = string_records
+        .iter()
+        .map(|r| r.as_byte_record().clone())
+        .collect();
+
+    let mut group = c.benchmark_group("parse");
+    group.throughput(Throughput::Elements(ROWS));
+    group.bench_function("string_record", |b| {
+        b.iter(|| {
+            for record in &string_records {
+                Transaction::try_from(record.clone()).unwrap();
+            }
+        })
+    });
+    group.bench_function("byte_record", |b| {
+        b.iter(|| {
+            for record in &byte_records {
+                Transaction::from_byte_record(record).unwrap();
+            }
+        })
+    });
+    group.finish();
+}
+
+fn workloads(c: &mut Criterion) {
+    let mut group = c.benchmark_group("process");
+    group.throughput(Throughput::Elements(ROWS));
+    for (name, dispute_every) in &[("deposits_only", 0), ("dispute_heavy", 2)] {
+        let transactions = transactions(&rows(*dispute_every));
+        group.bench_function(*name, |b| {
+            b.iter(|| {
+                let mut engine = PaymentEngine::new();
+                engine
+                    .import_transactions(transactions.iter().cloned().map(Ok::<_, Box<dyn Error>>))
+                    .unwrap();
+                engine
+            })
+        });
+    }
+    group.finish();
+}
+
+fn end_to_end(c: &mut Criterion) {
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    writeln!(file, "type,client,tx,amount").unwrap();
+    for row in rows(10) {
+        writeln!(file, "{}", row).unwrap();
+    }
+    file.flush().unwrap();
+    let path = file.path().to_str().unwrap().to_string();
+
+    let mut group = c.benchmark_group("import_csv");
+    group.throughput(Throughput::Elements(ROWS));
+    group.bench_function("mixed", |b| {
+        b.iter(|| {
+            let mut engine = PaymentEngine::new();
+            engine.import_csv(&path).unwrap();
+            engine
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, parsing, workloads, end_to_end);
+criterion_main!(benches);
@@ -0,0 +1,32 @@
+// Only the gRPC service is generated, from the hand-written messages in
+// src/grpc.rs, so protoc isn't needed. Keep it in sync with
+// proto/transactions.proto.
+fn main() {
+    #[cfg(feature = "grpc")]
+    {
+        use tonic_build::manual::{Builder, Method, Service};
+
+        let method = |name: &str, route: &str, input: &str, output: &str| {
+            Method::builder()
+                .name(name)
+                .route_name(route)
+                .input_type(format!("crate::grpc::pb::{}", input))
+                .output_type(format!("crate::grpc::pb::{}", output))
+                .codec_path("tonic::codec::ProstCodec")
+        };
+        let service = Service::builder()
+            .name("Payments")
+            .package("payments")
+            .method(
+                method("process", "Process", "Transaction", "Outcome")
+                    .client_streaming()
+                    .server_streaming()
+                    .build(),
+            )
+            .method(method("get_account", "GetAccount", "AccountRequest", "Account").build())
+            .build();
+        // No PaymentsClient::connect, it needs the 2021 prelude. Connect an
+        // Endpoint and use PaymentsClient::new instead.
+        Builder::new().build_transport(false).compile(&[service]);
+    }
+}
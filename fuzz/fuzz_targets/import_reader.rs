@@ -0,0 +1,9 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use payments_engine::PaymentEngine;
+
+// Whatever the bytes, import_reader returns (Ok or Err) instead of panicking
+fuzz_target!(|data: &[u8]| {
+    let mut engine = PaymentEngine::new();
+    let _ = engine.import_reader(data);
+});
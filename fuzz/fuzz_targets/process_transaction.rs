@@ -0,0 +1,46 @@
+#![no_main]
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use payments_engine::compat::CompatLevel;
+use payments_engine::transaction::{Transaction, TransactionStatus, TransactionType};
+use payments_engine::PaymentEngine;
+use rust_decimal::Decimal;
+
+#[derive(Arbitrary, Debug)]
+struct Record {
+    kind: u8,
+    client_id: u16,
+    tx_id: u32,
+    amount: Option<(i64, u32)>,
+}
+
+// Records that made it through parsing but are otherwise anything: missing
+// amounts, negative or huge ones, repeated tx ids, disputes of anything...
+fuzz_target!(|input: (bool, Vec<Record>)| {
+    let (v2, records) = input;
+    let mut engine = PaymentEngine::new();
+    if v2 {
+        engine.set_compat_level(CompatLevel::V2);
+    }
+    for record in records {
+        let tx_type = match record.kind % 5 {
+            0 => TransactionType::Deposit,
+            1 => TransactionType::Withdrawal,
+            2 => TransactionType::Dispute,
+            3 => TransactionType::Resolve,
+            _ => TransactionType::Chargeback,
+        };
+        let amount = record
+            .amount
+            .and_then(|(m, scale)| Decimal::try_from_i128_with_scale(i128::from(m), scale).ok());
+        let _ = engine.process_transaction(Transaction {
+            tx_type,
+            client_id: record.client_id,
+            tx_id: record.tx_id,
+            amount,
+            status: TransactionStatus::OK,
+            description: None,
+            category: None,
+        });
+    }
+});
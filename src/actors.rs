@@ -0,0 +1,309 @@
+use crate::transaction::{ClientId, Transaction};
+use crate::PaymentEngine;
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::mem;
+use std::sync::{Arc, Condvar, Mutex};
+use tracing::debug;
+
+/*
+Actor per client, for the streaming modes. Every client id gets an actor that
+owns its account, its transactions and its dispute history (an engine of its
+own, set up like the one it came from), and a mailbox. The router puts each
+transaction in the mailbox of its client; an actor with mail is run on the
+Rayon pool until its mailbox is empty. A client's transactions are processed
+one at a time in the order they were sent, and different clients run on every
+core. Unlike parallel.rs there's no fixed number of shards, a busy client only
+holds up itself.
+
+Same caveats as the shards of parallel.rs: a repeated tx id is only caught
+within a client, and a dispute of another client's transaction is an unknown
+transaction. Each actor gets its own stores, so it's meant for the in-memory
+ones. An idempotent engine can't have actors, the tx ids it skips aren't tied
+to a client. After a transaction fails its actor drops the rest of its mail,
+and the router refuses new transactions, like a sequential run stopping at the
+error.
+*/
+
+// How many transactions can wait in mailboxes before send blocks
+const MAX_IN_FLIGHT: usize = 64 * 1024;
+
+struct Actor {
+    mailbox: Mutex<Mailbox>,
+    engine: Mutex<PaymentEngine>,
+}
+
+#[derive(Default)]
+struct Mailbox {
+    mail: VecDeque<Transaction>,
+    scheduled: bool,
+}
+
+#[derive(Default)]
+struct Progress {
+    // Sent but not processed (or dropped) yet
+    in_flight: usize,
+    error: Option<String>,
+}
+
+#[derive(Default)]
+struct Shared {
+    progress: Mutex<Progress>,
+    changed: Condvar,
+}
+
+impl Shared {
+    fn done(&self, count: usize, error: Option<String>) {
+        let mut progress = self.progress.lock().unwrap_or_else(|e| e.into_inner());
+        progress.in_flight -= count;
+        if progress.error.is_none() {
+            progress.error = error;
+        }
+        self.changed.notify_all();
+    }
+
+    fn failed(&self) -> bool {
+        let progress = self.progress.lock().unwrap_or_else(|e| e.into_inner());
+        progress.error.is_some()
+    }
+}
+
+pub struct ActorRouter {
+    // What's left of the engine the actors came from: its settings and
+    // anything that isn't a client's
+    home: PaymentEngine,
+    actors: HashMap<ClientId, Arc<Actor>>,
+    shared: Arc<Shared>,
+}
+
+impl ActorRouter {
+    /// Gives every client of the engine an actor, with its account and
+    /// transactions.
+    pub fn new(mut engine: PaymentEngine) -> Result<ActorRouter, Box<dyn Error>> {
+        if engine.is_idempotent() {
+            return Err("Idempotent mode needs a single engine, not an actor per client".into());
+        }
+        let mut engines: HashMap<ClientId, PaymentEngine> = HashMap::new();
+        let mut accounts = Vec::new();
+        for account in engine.accounts.iter() {
+            accounts.push(account?);
+        }
+        for account in accounts {
+            engine.accounts.remove(account.client_id)?;
+            let mut actor = engine.empty_shard(engines.len() + 1)?;
+            actor.accounts.put(account.clone())?;
+            engines.insert(account.client_id, actor);
+        }
+        let mut moving = Vec::new();
+        for transaction in engine.transactions.iter() {
+            let transaction = transaction?;
+            if engines.contains_key(&transaction.client_id) {
+                moving.push(transaction);
+            }
+        }
+        for transaction in moving {
+            engine.transactions.remove(transaction.tx_id)?;
+            if let Some(actor) = engines.get_mut(&transaction.client_id) {
+                actor.transactions.insert(transaction)?;
+            }
+        }
+        let actors = engines
+            .into_iter()
+            .map(|(client_id, engine)| (client_id, Arc::new(Actor::new(engine))))
+            .collect();
+        Ok(ActorRouter {
+            home: engine,
+            actors,
+            shared: Arc::new(Shared::default()),
+        })
+    }
+
+    /// Hands the transaction to its client's actor, starting one for a new
+    /// client. Waits while too many are in flight. Fails once a transaction
+    /// failed.
+    pub fn send(&mut self, transaction: Transaction) -> Result<(), Box<dyn Error>> {
+        {
+            let mut progress = self.shared.progress.lock().map_err(|_| "Actors poisoned")?;
+            while progress.in_flight >= MAX_IN_FLIGHT && progress.error.is_none() {
+                progress = self
+                    .shared
+                    .changed
+                    .wait(progress)
+                    .map_err(|_| "Actors poisoned")?;
+            }
+            if let Some(e) = &progress.error {
+                return Err(e.clone().into());
+            }
+            progress.in_flight += 1;
+        }
+        let actor = match self.actors.get(&transaction.client_id) {
+            Some(actor) => Arc::clone(actor),
+            None => {
+                debug!("New actor for client {}", transaction.client_id);
+                let engine = self.home.empty_shard(self.actors.len() + 1)?;
+                let actor = Arc::new(Actor::new(engine));
+                self.actors
+                    .insert(transaction.client_id, Arc::clone(&actor));
+                actor
+            }
+        };
+        let schedule = {
+            let mut mailbox = actor.mailbox.lock().map_err(|_| "Actors poisoned")?;
+            mailbox.mail.push_back(transaction);
+            !mem::replace(&mut mailbox.scheduled, true)
+        };
+        if schedule {
+            let shared = Arc::clone(&self.shared);
+            rayon::spawn(move || actor.run(&shared));
+        }
+        Ok(())
+    }
+
+    /// Waits until every transaction sent was processed, e.g. before
+    /// committing offsets. Returns the first error, if one failed.
+    pub fn flush(&self) -> Result<(), Box<dyn Error>> {
+        let mut progress = self.shared.progress.lock().map_err(|_| "Actors poisoned")?;
+        while progress.in_flight > 0 {
+            progress = self
+                .shared
+                .changed
+                .wait(progress)
+                .map_err(|_| "Actors poisoned")?;
+        }
+        match &progress.error {
+            Some(e) => Err(e.clone().into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Sends all the transactions and waits for them. Returns how many were
+    /// sent.
+    pub fn import_transactions<I>(&mut self, transactions: I) -> Result<u64, Box<dyn Error>>
+    where
+        I: IntoIterator<Item = Result<Transaction, Box<dyn Error>>>,
+    {
+        let mut sent = 0;
+        for transaction in transactions {
+            self.send(transaction?)?;
+            sent += 1;
+        }
+        self.flush()?;
+        Ok(sent)
+    }
+
+    /// Waits for the actors and puts their clients back in one engine. The
+    /// engine comes back even if a transaction failed, with everything
+    /// processed before the failure.
+    pub fn into_engine(self) -> (PaymentEngine, Result<(), Box<dyn Error>>) {
+        let flushed = self.flush();
+        let mut engine = self.home;
+        let mut result = flushed;
+        for (_, actor) in self.actors {
+            // The task that ran it last may still be on its way out
+            let absorbed = match actor.engine.lock() {
+                Ok(mut shard) => engine.absorb(mem::take(&mut *shard)),
+                Err(_) => Err("Actor poisoned".into()),
+            };
+            if let Err(e) = absorbed {
+                result = result.and(Err(e));
+            }
+        }
+        (engine, result)
+    }
+}
+
+impl Actor {
+    fn new(engine: PaymentEngine) -> Actor {
+        Actor {
+            mailbox: Mutex::new(Mailbox::default()),
+            engine: Mutex::new(engine),
+        }
+    }
+
+    // Processes the mail until there's none left. Only one task runs an
+    // actor at a time: send schedules one when there wasn't one.
+    fn run(&self, shared: &Shared) {
+        let mut engine = self.engine.lock().unwrap_or_else(|e| e.into_inner());
+        loop {
+            let transaction = {
+                let mut mailbox = self.mailbox.lock().unwrap_or_else(|e| e.into_inner());
+                if shared.failed() {
+                    let dropped = mailbox.mail.len();
+                    mailbox.mail.clear();
+                    mailbox.scheduled = false;
+                    shared.done(dropped, None);
+                    return;
+                }
+                match mailbox.mail.pop_front() {
+                    Some(transaction) => transaction,
+                    None => {
+                        mailbox.scheduled = false;
+                        return;
+                    }
+                }
+            };
+            let error = engine
+                .process_transaction(transaction)
+                .err()
+                .map(|e| e.to_string());
+            shared.done(1, error);
+        }
+    }
+}
+
+#[test]
+fn test_actors_match_sequential() {
+    let input = std::fs::read("test_files/a_bit_of_everything.csv").unwrap();
+    let opening = &b"type,client,tx,amount\ndeposit,1,9999,5\n"[..];
+    let mut sequential = PaymentEngine::new();
+    sequential.import_reader(opening).unwrap();
+    sequential.import_reader(&input[..]).unwrap();
+
+    let mut engine = PaymentEngine::new();
+    engine.import_reader(opening).unwrap();
+    let mut router = ActorRouter::new(engine).unwrap();
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(&input[..]);
+    let transactions = reader.byte_records().map(|record| {
+        Transaction::from_byte_record(&record?).map_err(|e| -> Box<dyn Error> { e.into() })
+    });
+    router.import_transactions(transactions).unwrap();
+    let (engine, result) = router.into_engine();
+    result.unwrap();
+
+    assert_eq!(engine.sorted_accounts(), sequential.sorted_accounts());
+    assert_eq!(
+        engine.sorted_transactions(),
+        sequential.sorted_transactions()
+    );
+
+    // A failure stops the router
+    let mut router = ActorRouter::new(engine).unwrap();
+    router
+        .send(Transaction {
+            tx_type: crate::transaction::TransactionType::Withdrawal,
+            client_id: 1,
+            tx_id: 10001,
+            amount: None,
+            status: crate::transaction::TransactionStatus::OK,
+            description: None,
+            category: None,
+        })
+        .unwrap();
+    assert!(router.flush().is_err());
+    assert!(router
+        .send(Transaction {
+            tx_type: crate::transaction::TransactionType::Deposit,
+            client_id: 2,
+            tx_id: 10002,
+            amount: Some(rust_decimal::Decimal::ONE),
+            status: crate::transaction::TransactionStatus::OK,
+            description: None,
+            category: None,
+        })
+        .is_err());
+    let (_, result) = router.into_engine();
+    assert!(result.is_err());
+}
@@ -0,0 +1,268 @@
+use crate::transaction::{ClientId, TransactionId, TransactionType};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, Write};
+
+/*
+For the risk team: clients whose applied transactions follow a suspicious
+pattern, scored so the worst come first.
+
+  client,pattern,score,count,txs
+  3,bust-out,0.5,1,12
+  7,chargeback ratio,0.4,2,4;9
+  5,structuring,0.75,3,20;21;22
+
+  bust-out          a deposit, then a withdrawal of all of it and a dispute
+                    of the deposit, with nothing of the client in between
+                    (txs are the deposits)
+  chargeback ratio  more than chargeback_ratio of the client's deposits
+                    charged back (txs are the chargebacks)
+  structuring       at least structuring_count deposits just under
+                    structuring_threshold, within structuring_margin of it
+                    (txs are the deposits)
+
+The score is the share of the client's deposits taking part in the pattern,
+rounded to 4 decimals, and the report goes by score, highest first, then by
+client. Declined and ignored transactions aren't part of any pattern, and
+only what happened since the engine was created is known.
+
+These are heuristics, a client in the report is one to look at, not one to
+lock. In a pipeline the rules go under the output, e.g. in YAML:
+
+  - type: anomalies
+    path: anomalies.csv
+    rules:
+      structuring_threshold: "10000"
+      structuring_count: 5
+*/
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AnomalyRules {
+    /// Share of deposits charged back over which a client is reported
+    pub chargeback_ratio: Decimal,
+    /// The reporting threshold deposits are kept under
+    pub structuring_threshold: Decimal,
+    /// How far under the threshold, as a share of it, a deposit still counts
+    pub structuring_margin: Decimal,
+    /// Deposits just under the threshold it takes to be reported
+    pub structuring_count: usize,
+}
+
+impl Default for AnomalyRules {
+    fn default() -> Self {
+        AnomalyRules {
+            chargeback_ratio: Decimal::new(1, 1),
+            structuring_threshold: Decimal::new(10000, 0),
+            structuring_margin: Decimal::new(1, 1),
+            structuring_count: 3,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum AnomalyPattern {
+    BustOut,
+    ChargebackRatio,
+    Structuring,
+}
+
+impl fmt::Display for AnomalyPattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            AnomalyPattern::BustOut => "bust-out",
+            AnomalyPattern::ChargebackRatio => "chargeback ratio",
+            AnomalyPattern::Structuring => "structuring",
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Anomaly {
+    pub client: ClientId,
+    pub pattern: AnomalyPattern,
+    pub score: Decimal,
+    pub txs: Vec<TransactionId>,
+}
+
+// How far a client got into a bust-out
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BustOut {
+    Deposited(TransactionId, Decimal),
+    Withdrawn(TransactionId),
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+struct ClientActivity {
+    deposits: usize,
+    bust_out: Option<BustOut>,
+    bust_outs: Vec<TransactionId>,
+    chargebacks: Vec<TransactionId>,
+    under_threshold: Vec<TransactionId>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct AnomalyReport {
+    rules: AnomalyRules,
+    clients: BTreeMap<ClientId, ClientActivity>,
+}
+
+impl AnomalyReport {
+    pub fn new(rules: AnomalyRules) -> Self {
+        AnomalyReport {
+            rules,
+            clients: BTreeMap::new(),
+        }
+    }
+
+    pub fn rules(&self) -> &AnomalyRules {
+        &self.rules
+    }
+
+    /// An applied transaction
+    pub(crate) fn record(
+        &mut self,
+        tx_type: TransactionType,
+        client: ClientId,
+        tx: TransactionId,
+        amount: Option<Decimal>,
+    ) {
+        let floor = self.rules.structuring_threshold
+            - self.rules.structuring_threshold * self.rules.structuring_margin;
+        let activity = self.clients.entry(client).or_default();
+        let bust_out = activity.bust_out.take();
+        match tx_type {
+            TransactionType::Deposit => {
+                activity.deposits += 1;
+                if let Some(amount) = amount {
+                    activity.bust_out = Some(BustOut::Deposited(tx, amount));
+                    if amount >= floor && amount < self.rules.structuring_threshold {
+                        activity.under_threshold.push(tx);
+                    }
+                }
+            }
+            TransactionType::Withdrawal => {
+                if let Some(BustOut::Deposited(deposit, deposited)) = bust_out {
+                    if amount == Some(deposited) {
+                        activity.bust_out = Some(BustOut::Withdrawn(deposit));
+                    }
+                }
+            }
+            TransactionType::Dispute if bust_out == Some(BustOut::Withdrawn(tx)) => {
+                activity.bust_outs.push(tx)
+            }
+            TransactionType::Chargeback => activity.chargebacks.push(tx),
+            _ => {}
+        }
+    }
+
+    /// Shards never share clients
+    pub(crate) fn merge(&mut self, other: AnomalyReport) {
+        self.clients.extend(other.clients);
+    }
+
+    /// What the rules flag, by score, highest first.
+    pub fn anomalies(&self) -> Vec<Anomaly> {
+        let mut anomalies = Vec::new();
+        for (&client, activity) in &self.clients {
+            // A chargeback can be of a deposit from a loaded state
+            let share = |count: usize| {
+                (Decimal::from(count) / Decimal::from(activity.deposits.max(1))).round_dp(4)
+            };
+            if !activity.bust_outs.is_empty() {
+                anomalies.push(Anomaly {
+                    client,
+                    pattern: AnomalyPattern::BustOut,
+                    score: share(activity.bust_outs.len()),
+                    txs: activity.bust_outs.clone(),
+                });
+            }
+            let ratio = share(activity.chargebacks.len());
+            if !activity.chargebacks.is_empty() && ratio > self.rules.chargeback_ratio {
+                anomalies.push(Anomaly {
+                    client,
+                    pattern: AnomalyPattern::ChargebackRatio,
+                    score: ratio,
+                    txs: activity.chargebacks.clone(),
+                });
+            }
+            if activity.under_threshold.len() >= self.rules.structuring_count.max(1) {
+                anomalies.push(Anomaly {
+                    client,
+                    pattern: AnomalyPattern::Structuring,
+                    score: share(activity.under_threshold.len()),
+                    txs: activity.under_threshold.clone(),
+                });
+            }
+        }
+        anomalies.sort_by(|a, b| b.score.cmp(&a.score).then(a.client.cmp(&b.client)));
+        anomalies
+    }
+
+    /// The report, by score, highest first.
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "client,pattern,score,count,txs")?;
+        for anomaly in self.anomalies() {
+            let txs: Vec<String> = anomaly.txs.iter().map(|tx| tx.to_string()).collect();
+            writeln!(
+                writer,
+                "{},{},{},{},{}",
+                anomaly.client,
+                anomaly.pattern,
+                anomaly.score.normalize(),
+                anomaly.txs.len(),
+                txs.join(";")
+            )?;
+        }
+        writer.flush()
+    }
+}
+
+#[test]
+fn test_anomaly_report() {
+    use crate::PaymentEngine;
+
+    let mut engine = PaymentEngine::new();
+    engine.track_anomalies(AnomalyRules {
+        structuring_threshold: Decimal::new(1000, 0),
+        ..AnomalyRules::default()
+    });
+    engine
+        .import_reader(
+            &b"type,client,tx,amount
+deposit,1,1,50
+withdrawal,1,2,50
+dispute,1,1,
+deposit,1,3,10
+deposit,2,4,20
+withdrawal,2,5,20
+deposit,2,6,1
+dispute,2,4,
+deposit,3,7,950
+deposit,3,8,999.99
+deposit,3,9,901
+deposit,3,10,1000
+deposit,4,11,5
+deposit,4,12,5
+dispute,4,11,
+chargeback,4,11,
+deposit,5,13,5
+deposit,5,14,5
+dispute,5,13,
+resolve,5,13,
+"[..],
+        )
+        .unwrap();
+    let mut report = Vec::new();
+    engine.anomalies().unwrap().write(&mut report).unwrap();
+    assert_eq!(
+        String::from_utf8(report).unwrap(),
+        "client,pattern,score,count,txs
+3,structuring,0.75,3,7;8;9
+1,bust-out,0.5,1,1
+4,chargeback ratio,0.5,1,11
+"
+    );
+}
@@ -0,0 +1,180 @@
+use crate::engine::Account;
+use crate::output::compress::{Compressed, Compression};
+use crate::transaction::*;
+use rust_decimal::Decimal;
+use serde::{Serialize, Serializer};
+use std::error::Error;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Append-only JSON lines log with one record per processed transaction, to
+/// be able to explain every balance. Shared by all the shards in parallel
+/// mode, so records of different clients can be interleaved but each line is
+/// written whole.
+pub struct AuditLog {
+    writer: Mutex<Compressed<BufWriter<File>>>,
+}
+
+impl AuditLog {
+    /// Appends to the log if it already exists.
+    pub fn open(path: &Path) -> io::Result<AuditLog> {
+        AuditLog::open_compressed(path, Compression::None)
+    }
+
+    /// Every flush ends a gzip member or zstd frame, see compress.rs
+    pub fn open_compressed(path: &Path, compression: Compression) -> io::Result<AuditLog> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(AuditLog {
+            writer: Mutex::new(compression.writer(BufWriter::new(file))),
+        })
+    }
+
+    pub(crate) fn write(&self, record: &AuditRecord) -> Result<(), Box<dyn Error>> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        let mut writer = self.writer.lock().map_err(|_| "Audit log poisoned")?;
+        writer.write_all(&line)?;
+        Ok(())
+    }
+
+    pub fn flush(&self) -> io::Result<()> {
+        match self.writer.lock() {
+            Ok(mut writer) => writer.end_member().and_then(|()| writer.flush()),
+            Err(_) => Err(io::Error::other("Audit log poisoned")),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Balances {
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+}
+
+impl From<&Account> for Balances {
+    fn from(account: &Account) -> Balances {
+        Balances {
+            available: account.funds_available,
+            held: account.funds_held,
+            total: account.funds_total,
+            locked: account.locked,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditResult {
+    Applied,
+    Ignored,
+    Failed,
+}
+
+/*
+e.g. for a dispute:
+{"type":"dispute","client":1,"tx":4,"amount":null,"description":null,"category":null,
+ "result":"applied","reason":null,
+ "before":{"available":"2001.0","held":"0","total":"2001.0","locked":false},
+ "after":{"available":"1.0","held":"2000.0","total":"2001.0","locked":false},
+ "status":["OK","Disputed"]}
+before is null for the first transaction of a client. status is the change
+in the status of the disputed transaction, if there was one.
+*/
+#[derive(Debug, Serialize)]
+pub struct AuditRecord {
+    #[serde(rename = "type", serialize_with = "display")]
+    pub tx_type: TransactionType,
+    pub client: ClientId,
+    pub tx: TransactionId,
+    pub amount: Option<Decimal>,
+    pub description: Option<Box<str>>,
+    pub category: Option<Box<str>>,
+    pub result: AuditResult,
+    pub reason: Option<String>,
+    pub before: Option<Balances>,
+    pub after: Option<Balances>,
+    pub status: Option<(Option<TransactionStatus>, Option<TransactionStatus>)>,
+}
+
+fn display<S: Serializer>(tx_type: &TransactionType, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(tx_type)
+}
+
+impl AuditRecord {
+    pub(crate) fn new(transaction: &Transaction) -> AuditRecord {
+        AuditRecord {
+            tx_type: transaction.tx_type,
+            client: transaction.client_id,
+            tx: transaction.tx_id,
+            amount: transaction.amount,
+            description: transaction.description.clone(),
+            category: transaction.category.clone(),
+            result: AuditResult::Applied,
+            reason: None,
+            before: None,
+            after: None,
+            status: None,
+        }
+    }
+
+    pub(crate) fn ignored(&mut self, reason: &str) {
+        self.result = AuditResult::Ignored;
+        self.reason = Some(reason.to_string());
+    }
+
+    pub(crate) fn failed(&mut self, error: &dyn Error) {
+        self.result = AuditResult::Failed;
+        self.reason = Some(error.to_string());
+    }
+}
+
+#[test]
+fn test_audit_log() {
+    use crate::PaymentEngine;
+    use std::sync::Arc;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("audit.jsonl");
+    let audit = Arc::new(AuditLog::open(&path).unwrap());
+    let mut engine = PaymentEngine::new();
+    engine.set_audit_log(audit.clone());
+    engine
+        .import_csv("test_files/a_bit_of_everything.csv")
+        .unwrap();
+    audit.flush().unwrap();
+
+    let log = std::fs::read_to_string(&path).unwrap();
+    let records: Vec<serde_json::Value> = log
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(records.len(), 20);
+    assert_eq!(records[0]["before"], serde_json::Value::Null);
+    assert_eq!(records[0]["after"]["available"], "1.0");
+    assert_eq!(records[4]["type"], "withdrawal");
+    assert_eq!(records[5]["result"], "ignored");
+    assert_eq!(records[5]["reason"], "not enough funds");
+    assert_eq!(records[6]["status"], serde_json::json!(["OK", "Disputed"]));
+    assert_eq!(records[7]["after"]["locked"], true);
+    assert_eq!(records[19]["reason"], "wrong status");
+
+    let path = dir.path().join("audit.jsonl.gz");
+    for _ in 0..2 {
+        let audit = Arc::new(AuditLog::open_compressed(&path, Compression::Gzip).unwrap());
+        let mut engine = PaymentEngine::new();
+        engine.set_audit_log(audit.clone());
+        engine.import_csv("test_files/input.csv").unwrap();
+        audit.flush().unwrap();
+    }
+    let mut log = String::new();
+    std::io::Read::read_to_string(
+        &mut flate2::read::MultiGzDecoder::new(File::open(&path).unwrap()),
+        &mut log,
+    )
+    .unwrap();
+    assert_eq!(log.lines().count(), 10);
+}
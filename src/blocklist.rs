@@ -0,0 +1,211 @@
+use crate::transaction::{ClientId, Transaction, TransactionId, TransactionType, TxOutcome};
+use rust_decimal::Decimal;
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+/*
+Clients that aren't allowed to move money (sanctions, compliance holds...),
+one client id per line, blank lines and # comments ignored:
+
+  # OFAC 2024-03
+  17
+  2041
+
+What happens to their transactions:
+
+  reject         all of them are rejected (the default)
+  hold-deposits  deposits are taken, but into held funds, where nothing can
+                 touch them; everything else is rejected
+
+Held deposits aren't stored, so they can't be disputed, and they stay held
+once the client is off the list: releasing them is a manual job. Every
+transaction of a blocked client goes into the report, with what was done
+with it:
+
+  type,client,tx,amount,result
+  deposit,17,4,100,held
+  withdrawal,17,5,20,blocked client
+*/
+
+#[derive(Debug, Default, PartialEq, Copy, Clone)]
+pub enum BlockMode {
+    #[default]
+    Reject,
+    HoldDeposits,
+}
+
+impl FromStr for BlockMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<BlockMode, String> {
+        match s {
+            "reject" => Ok(BlockMode::Reject),
+            "hold-deposits" => Ok(BlockMode::HoldDeposits),
+            _ => Err(format!("Unknown blocklist mode {}", s)),
+        }
+    }
+}
+
+deserialize_from_str!(BlockMode);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockedTx {
+    pub tx_type: TransactionType,
+    pub client: ClientId,
+    pub tx: TransactionId,
+    pub amount: Option<Decimal>,
+    pub outcome: TxOutcome,
+}
+
+impl BlockedTx {
+    pub(crate) fn new(transaction: &Transaction, outcome: TxOutcome) -> BlockedTx {
+        BlockedTx {
+            tx_type: transaction.tx_type,
+            client: transaction.client_id,
+            tx: transaction.tx_id,
+            amount: transaction.amount,
+            outcome,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Blocklist {
+    mode: BlockMode,
+    clients: HashSet<ClientId>,
+    activity: Vec<BlockedTx>,
+}
+
+impl Blocklist {
+    pub fn new(clients: HashSet<ClientId>, mode: BlockMode) -> Blocklist {
+        Blocklist {
+            mode,
+            clients,
+            activity: Vec::new(),
+        }
+    }
+
+    pub fn load(path: &Path, mode: BlockMode) -> Result<Blocklist, Box<dyn Error>> {
+        let mut clients = HashSet::new();
+        for line in fs::read_to_string(path)?.lines() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if !line.is_empty() {
+                clients.insert(
+                    line.parse()
+                        .map_err(|_| format!("Invalid client id {}", line))?,
+                );
+            }
+        }
+        Ok(Blocklist::new(clients, mode))
+    }
+
+    pub fn is_blocked(&self, client_id: ClientId) -> bool {
+        self.clients.contains(&client_id)
+    }
+
+    /// Whether a transaction of a blocked client is rejected outright, as
+    /// opposed to a deposit going into held funds.
+    pub fn rejects(&self, tx_type: TransactionType) -> bool {
+        !(self.mode == BlockMode::HoldDeposits && tx_type == TransactionType::Deposit)
+    }
+
+    pub(crate) fn record(&mut self, blocked: BlockedTx) {
+        self.activity.push(blocked);
+    }
+
+    /// Everything blocked clients did, in the order it was processed (per
+    /// shard in parallel runs).
+    pub fn activity(&self) -> &[BlockedTx] {
+        &self.activity
+    }
+
+    /// Same clients, no activity, for a new shard
+    pub(crate) fn empty(&self) -> Blocklist {
+        Blocklist::new(self.clients.clone(), self.mode)
+    }
+
+    pub(crate) fn merge(&mut self, other: Blocklist) {
+        self.activity.extend(other.activity);
+    }
+
+    pub fn write_report<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "type,client,tx,amount,result")?;
+        for blocked in &self.activity {
+            writeln!(
+                writer,
+                "{},{},{},{},{}",
+                blocked.tx_type,
+                blocked.client,
+                blocked.tx,
+                blocked.amount.map(|a| a.to_string()).unwrap_or_default(),
+                blocked.outcome.reason().unwrap_or("held")
+            )?;
+        }
+        writer.flush()
+    }
+}
+
+#[test]
+fn test_blocklist() {
+    use crate::PaymentEngine;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("blocked.txt");
+    fs::write(&path, "# sanctions\n2\n\n3 # pending review\n").unwrap();
+    let input = &b"type,client,tx,amount
+deposit,1,1,10
+deposit,2,2,10
+withdrawal,2,3,5
+deposit,3,4,7
+"[..];
+
+    let mut engine = PaymentEngine::new();
+    engine.set_blocklist(Blocklist::load(&path, BlockMode::Reject).unwrap());
+    engine.import_reader(input).unwrap();
+    assert_eq!(engine.len(), 1);
+    let mut report = Vec::new();
+    engine
+        .blocklist()
+        .unwrap()
+        .write_report(&mut report)
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(report).unwrap(),
+        "type,client,tx,amount,result
+deposit,2,2,10,blocked client
+withdrawal,2,3,5,blocked client
+deposit,3,4,7,blocked client
+"
+    );
+
+    let mut engine = PaymentEngine::new();
+    engine.set_blocklist(Blocklist::load(&path, BlockMode::HoldDeposits).unwrap());
+    engine.import_reader(input).unwrap();
+    let account = engine.account(2).unwrap().unwrap();
+    assert_eq!(
+        (account.available(), account.held()),
+        (Decimal::ZERO, Decimal::new(10, 0))
+    );
+    let results: Vec<_> = engine
+        .blocklist()
+        .unwrap()
+        .activity()
+        .iter()
+        .map(|blocked| blocked.outcome)
+        .collect();
+    assert_eq!(
+        results,
+        vec![
+            TxOutcome::Applied,
+            TxOutcome::RejectedBlocked,
+            TxOutcome::Applied
+        ]
+    );
+
+    fs::write(&path, "2\nseven\n").unwrap();
+    assert!(Blocklist::load(&path, BlockMode::Reject).is_err());
+}
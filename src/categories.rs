@@ -0,0 +1,115 @@
+use crate::transaction::ClientId;
+use rust_decimal::Decimal;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+/*
+For finance: how much went in and out of every client's account per category
+(the optional category column: payroll, refund, gaming...), instead of adding
+it up in spreadsheets.
+
+  client,category,transactions,in,out,net
+  1,payroll,2,3000,0,3000
+  1,refund,1,25.5,0,25.5
+  1,,3,0,120,-120
+
+In and out are what the transactions applied since the engine started
+tracking added to and took from the client's total, so whatever the compat
+level, net over a client's categories is how much its total changed. Deposits,
+withdrawals and holds are in their own category. Disputes, resolves and
+chargebacks are in the category of the transaction they dispute, captures and
+releases in the one of their hold, whatever their own column says. The ones
+without a category are in the empty one. Disputes, holds and the like count
+as transactions even though they don't change the total.
+*/
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct CategoryTotal {
+    pub transactions: u64,
+    pub funds_in: Decimal,
+    pub funds_out: Decimal,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct CategoryTotals {
+    totals: BTreeMap<(ClientId, Box<str>), CategoryTotal>,
+}
+
+impl CategoryTotals {
+    /// An applied transaction that changed the client's total by `change`
+    pub(crate) fn record(&mut self, client: ClientId, category: Option<Box<str>>, change: Decimal) {
+        let total = self
+            .totals
+            .entry((client, category.unwrap_or_default()))
+            .or_default();
+        total.transactions += 1;
+        match change.is_sign_negative() {
+            true => total.funds_out -= change,
+            false => total.funds_in += change,
+        }
+    }
+
+    /// Shards never share clients
+    pub(crate) fn merge(&mut self, other: CategoryTotals) {
+        self.totals.extend(other.totals);
+    }
+
+    /// By client, then category
+    pub fn iter(&self) -> impl Iterator<Item = (ClientId, &str, &CategoryTotal)> {
+        self.totals
+            .iter()
+            .map(|((client, category), total)| (*client, category.as_ref(), total))
+    }
+
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "client,category,transactions,in,out,net")?;
+        for (client, category, total) in self.iter() {
+            writeln!(
+                writer,
+                "{},{},{},{},{},{}",
+                client,
+                crate::transaction::quoted(category),
+                total.transactions,
+                total.funds_in.normalize(),
+                total.funds_out.normalize(),
+                (total.funds_in - total.funds_out).normalize()
+            )?;
+        }
+        writer.flush()
+    }
+}
+
+#[test]
+fn test_category_totals() {
+    use crate::PaymentEngine;
+
+    let mut engine = PaymentEngine::new();
+    engine.track_categories();
+    engine
+        .import_reader(
+            &b"type,client,tx,amount,category
+deposit,1,1,1000,payroll
+deposit,1,2,2000,payroll
+deposit,1,3,25.5,refund
+withdrawal,1,4,100,
+withdrawal,1,5,1000000,
+deposit,2,6,50,gaming
+dispute,2,6,,
+chargeback,2,6,,
+hold,1,7,20,
+capture,1,7,,payroll
+"[..],
+        )
+        .unwrap();
+    let mut report = Vec::new();
+    engine.categories().unwrap().write(&mut report).unwrap();
+    assert_eq!(
+        String::from_utf8(report).unwrap(),
+        "client,category,transactions,in,out,net
+1,,3,0,120,-120
+1,payroll,2,3000,0,3000
+1,refund,1,25.5,0,25.5
+2,gaming,3,50,0,50
+"
+    );
+}
@@ -0,0 +1,177 @@
+use crate::input::dialect::CsvDialect;
+use crate::state::{self, StateFormat};
+use crate::transaction::Transaction;
+use crate::PaymentEngine;
+use csv::{ByteRecord, Position};
+use serde::Deserialize;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{BufReader, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+/*
+For multi-hour runs: every so many transactions the byte offset in the input
+and the engine state are saved together, and a resumed run restores the state
+and carries on from the offset. The file is
+
+  "PECP", offset (u64, little endian), engine state (see save_state)
+
+and is replaced atomically. It's removed once the input is done, so a
+later --resume starts from scratch. Resuming with another input or with
+different engine settings gives nonsense, it's not checked.
+*/
+
+const MAGIC: &[u8; 4] = b"PECP";
+
+fn default_every() -> u64 {
+    1_000_000
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CheckpointSpec {
+    pub path: PathBuf,
+    /// Transactions between checkpoints
+    #[serde(default = "default_every")]
+    pub every: u64,
+    /// Carry on from the checkpoint if there's one
+    #[serde(default)]
+    pub resume: bool,
+}
+
+pub fn save(
+    engine: &PaymentEngine,
+    path: &Path,
+    offset: u64,
+    format: StateFormat,
+) -> Result<(), Box<dyn Error>> {
+    state::write_atomically(path, |writer| {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&offset.to_le_bytes())?;
+        engine.save_state(writer, format)
+    })
+}
+
+/// Loads the checkpoint into the engine and returns the offset to carry on
+/// from, or None if there's no checkpoint.
+pub fn restore(
+    engine: &mut PaymentEngine,
+    path: &Path,
+    format: StateFormat,
+) -> Result<Option<u64>, Box<dyn Error>> {
+    let mut reader = match File::open(path) {
+        Ok(file) => BufReader::new(file),
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let mut header = [0; 12];
+    reader.read_exact(&mut header)?;
+    if &header[..4] != MAGIC {
+        return Err("Not a checkpoint".into());
+    }
+    let mut offset = [0; 8];
+    offset.copy_from_slice(&header[4..]);
+    engine.restore_state(reader, format)?;
+    Ok(Some(u64::from_le_bytes(offset)))
+}
+
+/// Imports the CSV from the offset (0 for the whole file), checkpointing as
+/// it goes. Calls inspect with every transaction before processing it.
+pub fn import_csv<F>(
+    engine: &mut PaymentEngine,
+    input: &Path,
+    dialect: &CsvDialect,
+    spec: &CheckpointSpec,
+    format: StateFormat,
+    offset: u64,
+    mut inspect: F,
+) -> Result<(), Box<dyn Error>>
+where
+    F: FnMut(&Transaction),
+{
+    let mut rdr = dialect.seekable_reader(BufReader::new(File::open(input)?))?;
+    if offset > 0 {
+        let mut position = Position::new();
+        position.set_byte(offset);
+        rdr.inner_mut().seek(position)?;
+        info!("Resuming {:?} from byte {}", input, offset);
+    }
+    let mut record = ByteRecord::new();
+    let mut since_checkpoint = 0;
+    while rdr.read_byte_record(&mut record)? {
+        let transaction = Transaction::from_byte_record(&record)?;
+        inspect(&transaction);
+        engine.process_transaction(transaction)?;
+        since_checkpoint += 1;
+        if since_checkpoint == spec.every {
+            let offset = rdr.inner_mut().position().byte();
+            save(engine, &spec.path, offset, format)?;
+            info!("Checkpoint at byte {}", offset);
+            since_checkpoint = 0;
+        }
+    }
+    match fs::remove_file(&spec.path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+#[test]
+fn test_checkpoint_resume() {
+    let dir = tempfile::tempdir().unwrap();
+    let original = fs::read_to_string("test_files/a_bit_of_everything.csv").unwrap();
+    let input = dir.path().join("input.csv");
+    let spec = CheckpointSpec {
+        path: dir.path().join("checkpoint"),
+        every: 7,
+        resume: true,
+    };
+    let mut expected = PaymentEngine::new();
+    expected.import_reader(original.as_bytes()).unwrap();
+
+    // Interrupted by a bad row after the second checkpoint, same length so
+    // the offsets still hold once it's fixed
+    let lines: Vec<&str> = original.lines().collect();
+    let broken = original.replacen(lines[17], &lines[17].replacen("e", "3", 1), 1);
+    fs::write(&input, broken).unwrap();
+    let mut engine = PaymentEngine::new();
+    assert!(import_csv(
+        &mut engine,
+        &input,
+        &Default::default(),
+        &spec,
+        StateFormat::Cbor,
+        0,
+        |_| {}
+    )
+    .is_err());
+
+    fs::write(&input, &original).unwrap();
+    let mut resumed = PaymentEngine::new();
+    let offset = restore(&mut resumed, &spec.path, StateFormat::Cbor)
+        .unwrap()
+        .unwrap();
+    let mut processed = 0;
+    import_csv(
+        &mut resumed,
+        &input,
+        &Default::default(),
+        &spec,
+        StateFormat::Cbor,
+        offset,
+        |_| processed += 1,
+    )
+    .unwrap();
+    assert_eq!(processed, lines.len() - 1 - 14);
+    assert_eq!(resumed.sorted_accounts(), expected.sorted_accounts());
+    assert_eq!(
+        resumed.sorted_transactions(),
+        expected.sorted_transactions()
+    );
+    // Done, so there's nothing to resume any more
+    assert_eq!(
+        restore(&mut PaymentEngine::new(), &spec.path, StateFormat::Cbor).unwrap(),
+        None
+    );
+}
@@ -0,0 +1,44 @@
+use std::str::FromStr;
+
+/*
+Which semantics the engine follows. New behavior that would change the output
+for an existing input goes behind a new level, so downstream reconciliations
+keep getting the same numbers until they opt in.
+
+v1, the original semantics:
+  - only deposits can be disputed
+  - a chargeback releases the held funds back to available
+  - invalid rows (disputes of unknown transactions, overdrafts...) are ignored
+    silently
+  - locked accounts keep accepting deposits and withdrawals
+  - a repeated deposit tx id is a fatal error
+
+v2:
+  - withdrawals can be disputed too: the amount is held (and added to the
+    total) until it's resolved (the withdrawal stands) or charged back (the
+    amount goes back to available)
+  - a chargeback of a deposit removes the held funds from the account
+  - ignored rows are logged as warnings
+  - locked accounts don't accept deposits or withdrawals
+  - a repeated tx id is ignored like any other invalid row
+*/
+#[derive(Debug, Default, PartialEq, Copy, Clone)]
+pub enum CompatLevel {
+    #[default]
+    V1,
+    V2,
+}
+
+impl FromStr for CompatLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<CompatLevel, String> {
+        match s {
+            "v1" => Ok(CompatLevel::V1),
+            "v2" => Ok(CompatLevel::V2),
+            _ => Err(format!("Unknown compatibility level {}", s)),
+        }
+    }
+}
+
+deserialize_from_str!(CompatLevel);
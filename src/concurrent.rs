@@ -0,0 +1,120 @@
+use crate::engine::Account;
+use crate::transaction::{ClientId, Transaction, TxOutcome};
+use crate::PaymentEngine;
+use std::error::Error;
+use std::io;
+use std::sync::{Mutex, MutexGuard};
+
+/*
+The engine for servers answering on many threads at once. The clients are
+spread over shards by client_id % shards, each a PaymentEngine behind its own
+lock, so transactions of clients in different shards are processed in
+parallel, and the ones of a client are still processed one at a time, in the
+order they come in.
+
+The shards are the ones of parallel.rs, with the same caveats: a repeated tx
+id is only caught within a shard while serving (into_inner fails on it), and
+a dispute of another client's transaction is ignored as an unknown
+transaction when that client is in another shard. With one shard it's the
+plain engine behind a mutex.
+*/
+
+pub struct ConcurrentPaymentEngine {
+    shards: Vec<Mutex<PaymentEngine>>,
+}
+
+impl From<PaymentEngine> for ConcurrentPaymentEngine {
+    fn from(engine: PaymentEngine) -> ConcurrentPaymentEngine {
+        ConcurrentPaymentEngine {
+            shards: vec![Mutex::new(engine)],
+        }
+    }
+}
+
+impl ConcurrentPaymentEngine {
+    /// Splits the engine, with its accounts and transactions, in `shards`
+    pub fn new(
+        engine: PaymentEngine,
+        shards: usize,
+    ) -> Result<ConcurrentPaymentEngine, Box<dyn Error>> {
+        Ok(ConcurrentPaymentEngine {
+            shards: engine
+                .split(shards.max(1))?
+                .into_iter()
+                .map(Mutex::new)
+                .collect(),
+        })
+    }
+
+    fn shard(&self, client_id: ClientId) -> io::Result<MutexGuard<'_, PaymentEngine>> {
+        self.shards[client_id as usize % self.shards.len()]
+            .lock()
+            .map_err(|_| io::Error::other("Engine poisoned"))
+    }
+
+    pub fn process_transaction(
+        &self,
+        transaction: Transaction,
+    ) -> Result<TxOutcome, Box<dyn Error>> {
+        self.shard(transaction.client_id)?
+            .process_transaction(transaction)
+    }
+
+    pub fn account(&self, client_id: ClientId) -> io::Result<Option<Account>> {
+        self.shard(client_id)?.account(client_id)
+    }
+
+    /// Back to a single engine, e.g. to save its state on shutdown
+    pub fn into_inner(self) -> Result<PaymentEngine, Box<dyn Error>> {
+        let mut shards = self.shards.into_iter().map(|shard| {
+            shard
+                .into_inner()
+                .map_err(|_| Box::<dyn Error>::from("Engine poisoned"))
+        });
+        let mut engine = shards.next().ok_or("No shards")??;
+        for shard in shards {
+            engine.absorb(shard?)?;
+        }
+        Ok(engine)
+    }
+}
+
+#[test]
+fn test_concurrent_engine() {
+    use crate::transaction::{TransactionStatus, TransactionType};
+    use rust_decimal::Decimal;
+    use std::sync::Arc;
+    use std::thread;
+
+    let engine = Arc::new(ConcurrentPaymentEngine::new(PaymentEngine::new(), 4).unwrap());
+    let workers: Vec<_> = (0..8u16)
+        .map(|worker| {
+            let engine = Arc::clone(&engine);
+            thread::spawn(move || {
+                for i in 0..100u32 {
+                    let deposit = Transaction {
+                        tx_type: TransactionType::Deposit,
+                        client_id: worker % 5,
+                        tx_id: u32::from(worker) * 1000 + i,
+                        amount: Some(Decimal::new(15, 1)),
+                        status: TransactionStatus::OK,
+                        description: None,
+                        category: None,
+                    };
+                    engine.process_transaction(deposit).unwrap();
+                }
+            })
+        })
+        .collect();
+    for worker in workers {
+        worker.join().unwrap();
+    }
+    assert_eq!(
+        engine.account(1).unwrap().unwrap().total(),
+        Decimal::new(300, 0)
+    );
+
+    let engine = Arc::try_unwrap(engine).ok().unwrap().into_inner().unwrap();
+    assert_eq!(engine.len(), 5);
+    assert_eq!(engine.sorted_transactions().len(), 800);
+}
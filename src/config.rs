@@ -0,0 +1,174 @@
+use clap::{Arg, ArgAction, Command};
+use std::error::Error;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use toml::{Table, Value};
+
+/*
+A config file is just the command line flags, spelled with underscores:
+
+  state_format = "cbor"
+  threads = 4
+  no_header = true
+  currency_symbol = ["€", "EUR"]
+
+They're turned back into flags for clap, which validates them exactly as if
+they'd been typed, so every flag can go in the file and nothing has to be
+kept in sync. Flags given on the command line win over the file.
+*/
+
+pub fn read(path: &Path) -> Result<Table, Box<dyn Error>> {
+    Ok(toml::from_str(&fs::read_to_string(path)?)?)
+}
+
+fn flag(command: &Command, key: &str) -> Result<Arg, String> {
+    command
+        .get_arguments()
+        .find(|arg| arg.get_id() == key && arg.get_long().is_some())
+        .cloned()
+        .ok_or_else(|| format!("Unknown option in the config file: {}", key))
+}
+
+fn scalar(key: &str, value: &Value) -> Result<String, String> {
+    match value {
+        Value::String(s) => Ok(s.clone()),
+        Value::Integer(_) | Value::Float(_) | Value::Boolean(_) => Ok(value.to_string()),
+        _ => Err(format!("{} should be a string, a number or a boolean", key)),
+    }
+}
+
+/// The flags for the options in the config, except those `given` says were
+/// on the command line already.
+pub fn args<F>(config: &Table, command: &Command, given: F) -> Result<Vec<OsString>, String>
+where
+    F: Fn(&str) -> bool,
+{
+    let mut args = Vec::new();
+    for (key, value) in config {
+        let arg = flag(command, key)?;
+        if given(key) {
+            continue;
+        }
+        let long = format!("--{}", arg.get_long().unwrap_or_default());
+        match (arg.get_action(), value) {
+            (ArgAction::SetTrue, Value::Boolean(true)) => args.push(long.into()),
+            (ArgAction::SetTrue, Value::Boolean(false)) => {}
+            (ArgAction::SetTrue, _) => return Err(format!("{} should be true or false", key)),
+            (_, Value::Array(values)) => {
+                for value in values {
+                    args.push(format!("{}={}", long, scalar(key, value)?).into());
+                }
+            }
+            // Glued to the flag so a value like - isn't taken for one
+            (_, value) => args.push(format!("{}={}", long, scalar(key, value)?).into()),
+        }
+    }
+    Ok(args)
+}
+
+/// Every option of the command with its default, commented out if it has
+/// none, as a starting point for a config file.
+pub fn print_default<W: Write>(command: &Command, mut writer: W) -> io::Result<()> {
+    for arg in command.get_arguments() {
+        let key = arg.get_id().as_str();
+        if arg.get_long().is_none() || ["help", "version", "config"].contains(&key) {
+            continue;
+        }
+        if let Some(help) = arg.get_help() {
+            for line in help.to_string().lines() {
+                writeln!(writer, "# {}", line)?;
+            }
+        }
+        let defaults: Vec<String> = arg
+            .get_default_values()
+            .iter()
+            .map(|value| value.to_string_lossy().into_owned())
+            .collect();
+        match (arg.get_action(), defaults.as_slice()) {
+            (ArgAction::SetTrue, _) => writeln!(writer, "{} = false", key)?,
+            (_, []) => writeln!(writer, "# {} =", key)?,
+            (_, [value]) => writeln!(writer, "{} = {}", key, toml_value(value))?,
+            (_, values) => {
+                let values: Vec<String> = values.iter().map(|value| toml_value(value)).collect();
+                writeln!(writer, "{} = [{}]", key, values.join(", "))?
+            }
+        }
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+// Numbers as numbers, everything else quoted
+fn toml_value(value: &str) -> String {
+    match value.parse::<i64>() {
+        Ok(number) => number.to_string(),
+        Err(_) => match value.parse::<f64>() {
+            Ok(number) if !value.contains(|c: char| c.is_alphabetic()) => {
+                Value::Float(number).to_string()
+            }
+            _ => Value::String(value.to_string()).to_string(),
+        },
+    }
+}
+
+#[test]
+fn test_config_args() {
+    let command = Command::new("test")
+        .arg(Arg::new("threads").long("threads").default_value("1"))
+        .arg(
+            Arg::new("no_header")
+                .long("no-header")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("currency_symbol")
+                .long("currency-symbol")
+                .action(ArgAction::Append),
+        )
+        .arg(Arg::new("input"));
+    let config: Table = toml::from_str(
+        r#"
+threads = 4
+no_header = true
+currency_symbol = ["€", "EUR"]
+"#,
+    )
+    .unwrap();
+    let flags = args(&config, &command, |_| false).unwrap();
+    let matches = command
+        .clone()
+        .try_get_matches_from(std::iter::once(OsString::from("test")).chain(flags))
+        .unwrap();
+    assert_eq!(matches.get_one::<String>("threads").unwrap(), "4");
+    assert!(matches.get_flag("no_header"));
+    assert_eq!(
+        matches
+            .get_many::<String>("currency_symbol")
+            .unwrap()
+            .collect::<Vec<_>>(),
+        vec!["€", "EUR"]
+    );
+
+    // The command line wins
+    let flags = args(&config, &command, |key| key == "threads").unwrap();
+    assert!(!flags.contains(&OsString::from("--threads=4")));
+
+    for bad in &[
+        "input = \"x.csv\"",
+        "thread = 4",
+        "no_header = 1",
+        "threads = {}",
+    ] {
+        let config: Table = toml::from_str(bad).unwrap();
+        assert!(args(&config, &command, |_| false).is_err());
+    }
+
+    let mut printed = Vec::new();
+    print_default(&command, &mut printed).unwrap();
+    let printed: Table = toml::from_str(std::str::from_utf8(&printed).unwrap()).unwrap();
+    assert_eq!(printed.get("threads"), Some(&Value::Integer(1)));
+    assert_eq!(printed.get("no_header"), Some(&Value::Boolean(false)));
+    assert_eq!(printed.get("currency_symbol"), None);
+}
@@ -0,0 +1,194 @@
+use crate::transaction::{ClientId, Transaction, TransactionId, TransactionType, TxOutcome};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Write};
+
+/*
+For whoever owns the data quality of the input: the records the engine
+passed over because they conflict with others, which otherwise leave no
+trace but a count in the stats.
+
+  tx,client,type,issue,owner
+  3,1,deposit,duplicate,2
+  3,1,dispute,cross-client,2
+  9,4,resolve,not disputed,
+  12,4,chargeback,unknown tx,
+
+duplicate is a deposit, withdrawal or hold with the tx id of an earlier one
+(owner is the client of the first), cross-client a dispute, resolve,
+chargeback, capture or release of another client's transaction (owner is
+that client), unknown tx one of a transaction never seen and not disputed a
+resolve or chargeback of a transaction that isn't disputed. By tx id, in the
+order they came for the same tx id.
+
+In v1 a repeated deposit or hold fails the run rather than being passed
+over. With several threads every shard only knows its own clients, so a
+reference to a client of another shard is an unknown tx, and a tx id
+repeated across shards isn't caught.
+*/
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum ConflictKind {
+    Duplicate,
+    CrossClient,
+    UnknownTx,
+    NotDisputed,
+}
+
+impl fmt::Display for ConflictKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ConflictKind::Duplicate => "duplicate",
+            ConflictKind::CrossClient => "cross-client",
+            ConflictKind::UnknownTx => "unknown tx",
+            ConflictKind::NotDisputed => "not disputed",
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Conflict {
+    pub tx: TransactionId,
+    pub client: ClientId,
+    pub tx_type: TransactionType,
+    pub kind: ConflictKind,
+    /// The client of the other transaction, for duplicates and cross-client
+    /// references
+    pub owner: Option<ClientId>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ConflictReport {
+    conflicts: Vec<Conflict>,
+    // The client of every deposit, withdrawal and hold tx id, the engine
+    // only keeps the disputable ones
+    first: HashMap<TransactionId, ClientId>,
+}
+
+impl ConflictReport {
+    /// Before the transaction is applied, for the duplicates
+    pub(crate) fn see(&mut self, transaction: &Transaction) {
+        if !matches!(
+            transaction.tx_type,
+            TransactionType::Deposit | TransactionType::Withdrawal | TransactionType::Hold
+        ) {
+            return;
+        }
+        match self.first.get(&transaction.tx_id) {
+            Some(&owner) => self.conflicts.push(Conflict {
+                tx: transaction.tx_id,
+                client: transaction.client_id,
+                tx_type: transaction.tx_type,
+                kind: ConflictKind::Duplicate,
+                owner: Some(owner),
+            }),
+            None => {
+                self.first.insert(transaction.tx_id, transaction.client_id);
+            }
+        }
+    }
+
+    /// What the engine did with a reference to another transaction, `owner`
+    /// being the client of the stored one
+    pub(crate) fn record(
+        &mut self,
+        tx_type: TransactionType,
+        client: ClientId,
+        tx: TransactionId,
+        outcome: TxOutcome,
+        owner: Option<ClientId>,
+    ) {
+        let kind = match (outcome, tx_type) {
+            (TxOutcome::IgnoredUnknownTx, _) => ConflictKind::UnknownTx,
+            (TxOutcome::IgnoredWrongClient, _) => ConflictKind::CrossClient,
+            (
+                TxOutcome::IgnoredWrongStatus,
+                TransactionType::Resolve | TransactionType::Chargeback,
+            ) => ConflictKind::NotDisputed,
+            _ => return,
+        };
+        self.conflicts.push(Conflict {
+            tx,
+            client,
+            tx_type,
+            kind,
+            owner: owner.filter(|_| kind == ConflictKind::CrossClient),
+        });
+    }
+
+    /// Shards never share clients, their tx ids are only told apart
+    /// within each shard
+    pub(crate) fn merge(&mut self, other: ConflictReport) {
+        self.conflicts.extend(other.conflicts);
+        self.first.extend(other.first);
+    }
+
+    pub fn conflicts(&self) -> &[Conflict] {
+        &self.conflicts
+    }
+
+    pub fn len(&self) -> usize {
+        self.conflicts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.conflicts.is_empty()
+    }
+
+    /// The report, by tx id.
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let mut conflicts: Vec<&Conflict> = self.conflicts.iter().collect();
+        conflicts.sort_by_key(|conflict| conflict.tx);
+        writeln!(writer, "tx,client,type,issue,owner")?;
+        for conflict in conflicts {
+            write!(
+                writer,
+                "{},{},{},{},",
+                conflict.tx, conflict.client, conflict.tx_type, conflict.kind
+            )?;
+            if let Some(owner) = conflict.owner {
+                write!(writer, "{}", owner)?;
+            }
+            writeln!(writer)?;
+        }
+        writer.flush()
+    }
+}
+
+#[test]
+fn test_conflict_report() {
+    use crate::compat::CompatLevel;
+    use crate::PaymentEngine;
+
+    let mut engine = PaymentEngine::new();
+    engine.set_compat_level(CompatLevel::V2);
+    engine.track_conflicts();
+    engine
+        .import_reader(
+            &b"type,client,tx,amount
+deposit,2,3,5
+deposit,1,3,1
+dispute,1,3,
+resolve,2,3,
+chargeback,4,12,
+withdrawal,2,7,1
+withdrawal,2,7,1
+dispute,2,3,
+dispute,2,3,
+resolve,2,3,
+"[..],
+        )
+        .unwrap();
+    let mut report = Vec::new();
+    engine.conflicts().unwrap().write(&mut report).unwrap();
+    assert_eq!(
+        String::from_utf8(report).unwrap(),
+        "tx,client,type,issue,owner
+3,1,deposit,duplicate,2
+3,1,dispute,cross-client,2
+3,2,resolve,not disputed,
+7,2,withdrawal,duplicate,2
+12,4,chargeback,unknown tx,
+"
+    );
+}
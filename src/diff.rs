@@ -0,0 +1,169 @@
+use crate::transaction::ClientId;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::io::{Read, Write};
+
+/*
+Compares two account exports (the CSV export_accounts writes, columns found
+by name so --columns and --sort don't matter as long as the balances are
+there), e.g. before and after reprocessing with a fixed input. Only clients
+that differ are reported, with the deltas from old to new:
+
+  client,change,available,held,total,locked
+  2,changed,-10,10,0,
+  3,changed,0,0,0,locked
+  9,new,5,0,5,
+  4,removed,-1,0,-1,
+*/
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+struct Balances {
+    client: ClientId,
+    available: Decimal,
+    held: Decimal,
+    total: Decimal,
+    locked: bool,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Change {
+    New,
+    Removed,
+    Changed,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Change::New => "new",
+            Change::Removed => "removed",
+            Change::Changed => "changed",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccountDiff {
+    pub client: ClientId,
+    pub change: Change,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    /// Some(true) if it got locked, Some(false) if it got unlocked
+    pub locked: Option<bool>,
+}
+
+fn read<R: Read>(reader: R) -> Result<BTreeMap<ClientId, Balances>, Box<dyn Error>> {
+    let mut accounts = BTreeMap::new();
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(reader);
+    for balances in reader.deserialize() {
+        let balances: Balances = balances?;
+        if accounts.insert(balances.client, balances).is_some() {
+            return Err("Client repeated in the export".into());
+        }
+    }
+    Ok(accounts)
+}
+
+/// The clients that differ, by client id.
+pub fn diff<R: Read, S: Read>(old: R, new: S) -> Result<Vec<AccountDiff>, Box<dyn Error>> {
+    let old = read(old)?;
+    let mut new = read(new)?;
+    let zero = Decimal::new(0, 0);
+    let mut diffs = Vec::new();
+    for (client, before) in old {
+        let after = new.remove(&client);
+        let (change, after) = match &after {
+            Some(after) if *after == before => continue,
+            Some(after) => (Change::Changed, after),
+            None => (
+                Change::Removed,
+                &Balances {
+                    client,
+                    available: zero,
+                    held: zero,
+                    total: zero,
+                    locked: before.locked,
+                },
+            ),
+        };
+        diffs.push(AccountDiff {
+            client,
+            change,
+            available: after.available - before.available,
+            held: after.held - before.held,
+            total: after.total - before.total,
+            locked: Some(after.locked).filter(|locked| *locked != before.locked),
+        });
+    }
+    for (client, after) in new {
+        diffs.push(AccountDiff {
+            client,
+            change: Change::New,
+            available: after.available,
+            held: after.held,
+            total: after.total,
+            locked: Some(true).filter(|_| after.locked),
+        });
+    }
+    diffs.sort_by_key(|diff| diff.client);
+    Ok(diffs)
+}
+
+pub fn write<W: Write>(diffs: &[AccountDiff], mut writer: W) -> Result<(), Box<dyn Error>> {
+    writeln!(writer, "client,change,available,held,total,locked")?;
+    for diff in diffs {
+        writeln!(
+            writer,
+            "{},{},{},{},{},{}",
+            diff.client,
+            diff.change,
+            diff.available,
+            diff.held,
+            diff.total,
+            match diff.locked {
+                Some(true) => "locked",
+                Some(false) => "unlocked",
+                None => "",
+            }
+        )?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[test]
+fn test_diff() {
+    let old = b"client,available,held,total,locked
+1,10,0,10,false
+2,10,0,10,false
+3,5,0,5,false
+4,1,0,1,false
+";
+    // Other order, other columns
+    let new = b"total,client,locked,held,available,num_transactions
+10,1,false,0,10,1
+10,2,false,10,0,2
+5,3,true,0,5,3
+5,9,false,0,5,1
+";
+    let diffs = diff(&old[..], &new[..]).unwrap();
+    let mut report = Vec::new();
+    write(&diffs, &mut report).unwrap();
+    assert_eq!(
+        String::from_utf8(report).unwrap(),
+        "client,change,available,held,total,locked
+2,changed,-10,10,0,
+3,changed,0,0,0,locked
+4,removed,-1,0,-1,
+9,new,5,0,5,
+"
+    );
+    assert!(diff(&old[..], &old[..]).unwrap().is_empty());
+    assert!(diff(&b"client,total\n1,5\n"[..], &old[..]).is_err());
+}
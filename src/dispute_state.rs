@@ -0,0 +1,166 @@
+use crate::transaction::{Transaction, TransactionStatus, TransactionType, TxOutcome};
+
+/*
+The statuses a stored transaction goes through. Deposits and withdrawals get
+disputed:
+
+            dispute               chargeback
+  OK  ------------->  Disputed  -------------> Chargedback
+      <-------------
+           resolve
+
+and holds get settled, once:
+
+            capture                release
+  Captured <-------  OK (pending)  -------> Released
+
+Anything else (a second dispute, a resolve of a transaction that isn't
+disputed, a dispute of a hold, anything after a chargeback...) is ignored.
+Every pair of status and event is spelled out, so adding a status or an event
+won't compile until its transitions are decided.
+*/
+
+pub struct DisputeStateMachine;
+
+impl DisputeStateMachine {
+    /// The status of a stored transaction of type `stored` after `event`,
+    /// None if `event` isn't allowed in `status`.
+    pub fn next(
+        stored: TransactionType,
+        status: TransactionStatus,
+        event: TransactionType,
+    ) -> Option<TransactionStatus> {
+        use TransactionType::*;
+        match stored {
+            Deposit | Withdrawal => dispute_next(status, event),
+            Hold => hold_next(status, event),
+            // Never stored
+            Dispute | Resolve | Chargeback | Capture | Release => None,
+        }
+    }
+
+    /// Where `event`, a dispute, resolve, chargeback, capture or release,
+    /// takes `disputed`, the stored transaction it refers to. Only its own
+    /// client can do that.
+    pub fn transition(
+        disputed: &Transaction,
+        event: &Transaction,
+    ) -> Result<TransactionStatus, TxOutcome> {
+        if disputed.client_id != event.client_id {
+            return Err(TxOutcome::IgnoredWrongClient);
+        }
+        DisputeStateMachine::next(disputed.tx_type, disputed.status, event.tx_type)
+            .ok_or(TxOutcome::IgnoredWrongStatus)
+    }
+}
+
+fn dispute_next(status: TransactionStatus, event: TransactionType) -> Option<TransactionStatus> {
+    use TransactionStatus::*;
+    use TransactionType::*;
+    match (status, event) {
+        (OK, Dispute) => Some(Disputed),
+        (OK, Resolve) | (OK, Chargeback) => None,
+        (Disputed, Resolve) => Some(OK),
+        (Disputed, Chargeback) => Some(Chargedback),
+        (Disputed, Dispute) => None,
+        (Chargedback, Dispute) | (Chargedback, Resolve) | (Chargedback, Chargeback) => None,
+        // Statuses of holds only
+        (Captured, _) | (Released, _) => None,
+        // Only holds get captured or released
+        (_, Capture) | (_, Release) => None,
+        // Not events, they never change a stored transaction
+        (_, Deposit) | (_, Withdrawal) | (_, Hold) => None,
+    }
+}
+
+fn hold_next(status: TransactionStatus, event: TransactionType) -> Option<TransactionStatus> {
+    use TransactionStatus::*;
+    use TransactionType::*;
+    match (status, event) {
+        (OK, Capture) => Some(Captured),
+        (OK, Release) => Some(Released),
+        // A pending hold isn't a payment yet, there's nothing to dispute
+        (OK, Dispute) | (OK, Resolve) | (OK, Chargeback) => None,
+        (Captured, Capture) | (Captured, Release) => None,
+        (Captured, Dispute) | (Captured, Resolve) | (Captured, Chargeback) => None,
+        (Released, Capture) | (Released, Release) => None,
+        (Released, Dispute) | (Released, Resolve) | (Released, Chargeback) => None,
+        // Statuses of disputes only
+        (Disputed, _) | (Chargedback, _) => None,
+        (_, Deposit) | (_, Withdrawal) | (_, Hold) => None,
+    }
+}
+
+#[test]
+fn test_transitions() {
+    use TransactionStatus::*;
+    use TransactionType::*;
+
+    let statuses = [OK, Disputed, Chargedback, Captured, Released];
+    let events = [
+        Deposit, Withdrawal, Dispute, Resolve, Chargeback, Hold, Capture, Release,
+    ];
+    let allowed = [
+        (Deposit, OK, Dispute, Disputed),
+        (Deposit, Disputed, Resolve, OK),
+        (Deposit, Disputed, Chargeback, Chargedback),
+        (Withdrawal, OK, Dispute, Disputed),
+        (Withdrawal, Disputed, Resolve, OK),
+        (Withdrawal, Disputed, Chargeback, Chargedback),
+        (Hold, OK, Capture, Captured),
+        (Hold, OK, Release, Released),
+    ];
+    for stored in events.iter() {
+        for status in statuses.iter() {
+            for event in events.iter() {
+                let expected = allowed
+                    .iter()
+                    .find(|(kind, from, on, _)| kind == stored && from == status && on == event)
+                    .map(|(_, _, _, to)| *to);
+                assert_eq!(
+                    DisputeStateMachine::next(*stored, *status, *event),
+                    expected,
+                    "{:?} on {:?} {:?}",
+                    event,
+                    status,
+                    stored
+                );
+            }
+        }
+    }
+
+    let deposit = Transaction {
+        tx_type: Deposit,
+        client_id: 1,
+        tx_id: 1,
+        amount: None,
+        status: OK,
+        description: None,
+        category: None,
+    };
+    let event = |tx_type, client_id| Transaction {
+        tx_type,
+        client_id,
+        tx_id: 1,
+        amount: None,
+        status: OK,
+        description: None,
+        category: None,
+    };
+    assert_eq!(
+        DisputeStateMachine::transition(&deposit, &event(Dispute, 1)),
+        Ok(Disputed)
+    );
+    assert_eq!(
+        DisputeStateMachine::transition(&deposit, &event(Dispute, 2)),
+        Err(TxOutcome::IgnoredWrongClient)
+    );
+    assert_eq!(
+        DisputeStateMachine::transition(&deposit, &event(Resolve, 1)),
+        Err(TxOutcome::IgnoredWrongStatus)
+    );
+    assert_eq!(
+        DisputeStateMachine::transition(&deposit, &event(Capture, 1)),
+        Err(TxOutcome::IgnoredWrongStatus)
+    );
+}
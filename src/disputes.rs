@@ -0,0 +1,122 @@
+use crate::transaction::{ClientId, Transaction, TransactionId, TransactionType};
+use rust_decimal::Decimal;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+/*
+For the chargeback team: every transaction that was disputed during the run,
+with the disputes, resolves and chargebacks that changed its status, in the
+order they happened. Ignored ones (say, a resolve of a transaction that isn't
+disputed) aren't part of its history. Only what happened since the engine was
+created is known, disputes in a loaded state are still disputed but have no
+history here.
+
+  tx,client,amount,status,events
+  7,2,10.5,chargedback,dispute;resolve;dispute;chargeback
+*/
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DisputeRecord {
+    pub client: ClientId,
+    pub amount: Option<Decimal>,
+    pub events: Vec<TransactionType>,
+}
+
+impl DisputeRecord {
+    pub fn status(&self) -> &'static str {
+        match self.events.last() {
+            Some(TransactionType::Resolve) => "resolved",
+            Some(TransactionType::Chargeback) => "chargedback",
+            _ => "disputed",
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct DisputeHistory {
+    records: BTreeMap<TransactionId, DisputeRecord>,
+}
+
+impl DisputeHistory {
+    /// `disputed` is the transaction as stored after the event was applied
+    pub(crate) fn record(&mut self, disputed: &Transaction, event: TransactionType) {
+        self.records
+            .entry(disputed.tx_id)
+            .or_insert_with(|| DisputeRecord {
+                client: disputed.client_id,
+                amount: disputed.amount,
+                events: Vec::new(),
+            })
+            .events
+            .push(event);
+    }
+
+    /// Shards never share clients, so never transactions either
+    pub(crate) fn merge(&mut self, other: DisputeHistory) {
+        self.records.extend(other.records);
+    }
+
+    pub fn get(&self, tx_id: TransactionId) -> Option<&DisputeRecord> {
+        self.records.get(&tx_id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// The report, by tx id.
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "tx,client,amount,status,events")?;
+        for (tx_id, record) in &self.records {
+            let events: Vec<String> = record.events.iter().map(|e| e.to_string()).collect();
+            writeln!(
+                writer,
+                "{},{},{},{},{}",
+                tx_id,
+                record.client,
+                record.amount.map(|a| a.to_string()).unwrap_or_default(),
+                record.status(),
+                events.join(";")
+            )?;
+        }
+        writer.flush()
+    }
+}
+
+#[test]
+fn test_dispute_report() {
+    use crate::PaymentEngine;
+
+    let mut engine = PaymentEngine::new();
+    engine.track_disputes();
+    engine
+        .import_reader(
+            &b"type,client,tx,amount
+deposit,1,1,10
+deposit,2,2,5
+deposit,1,3,1
+dispute,1,1,
+resolve,1,1,
+resolve,1,1,
+dispute,1,1,
+chargeback,1,1,
+dispute,2,2,
+resolve,2,3,
+dispute,2,9,
+"[..],
+        )
+        .unwrap();
+    let mut report = Vec::new();
+    engine.disputes().unwrap().write(&mut report).unwrap();
+    assert_eq!(
+        String::from_utf8(report).unwrap(),
+        "tx,client,amount,status,events
+1,1,10,chargedback,dispute;resolve;dispute;chargeback
+2,2,5,disputed,dispute
+"
+    );
+}
@@ -0,0 +1,217 @@
+use crate::transaction::{ClientId, TransactionId, TransactionType};
+use rust_decimal::Decimal;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, Write};
+
+/*
+Every applied transaction as a balanced journal entry, for the accountants:
+
+  entry,tx,type,account,debit,credit
+  1,1,deposit,cash,10,
+  1,1,deposit,client:1:available,,10
+  2,1,dispute,client:1:available,10,
+  2,1,dispute,client:1:held,,10
+
+Client balances are what we owe the clients, so money coming in credits them
+and the other side is one of the internal accounts:
+
+  cash               money in and out: deposits, withdrawals, captured
+                     holds, and deposits charged back to the payer (v2)
+  chargeback_losses  what we give back to clients when they dispute a
+                     withdrawal (v2), ours to lose if it's charged back
+
+Moves between available and held (disputes, holds...) stay within the
+client. The entries come from the change in the client's balances, so an
+entry always balances and the client accounts always match the exported
+available and held. There are no fees in the engine, so no fees account. A
+client left with negative funds after a chargeback is money they owe us,
+still in their account here: writing it off is a decision for the books.
+*/
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LedgerAccount {
+    Cash,
+    ChargebackLosses,
+    Available(ClientId),
+    Held(ClientId),
+}
+
+impl fmt::Display for LedgerAccount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LedgerAccount::Cash => write!(f, "cash"),
+            LedgerAccount::ChargebackLosses => write!(f, "chargeback_losses"),
+            LedgerAccount::Available(client) => write!(f, "client:{}:available", client),
+            LedgerAccount::Held(client) => write!(f, "client:{}:held", client),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Posting {
+    pub entry: u64,
+    pub tx: TransactionId,
+    pub tx_type: TransactionType,
+    pub client: ClientId,
+    pub account: LedgerAccount,
+    /// Debit if positive, credit if negative
+    pub amount: Decimal,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct DoubleEntryLedger {
+    postings: Vec<Posting>,
+    entries: u64,
+}
+
+impl DoubleEntryLedger {
+    /// The entry for a transaction that took the client's (available, held)
+    /// from `before` to `after`, against `counterpart` for the difference in
+    /// total.
+    pub(crate) fn post(
+        &mut self,
+        tx_type: TransactionType,
+        tx: TransactionId,
+        client: ClientId,
+        counterpart: LedgerAccount,
+        before: (Decimal, Decimal),
+        after: (Decimal, Decimal),
+    ) {
+        let available = after.0 - before.0;
+        let held = after.1 - before.1;
+        let lines = [
+            (counterpart, available + held),
+            (LedgerAccount::Available(client), -available),
+            (LedgerAccount::Held(client), -held),
+        ];
+        let entry = self.entries + 1;
+        let mut posted = false;
+        for (account, amount) in lines.iter().filter(|(_, amount)| !amount.is_zero()) {
+            self.postings.push(Posting {
+                entry,
+                tx,
+                tx_type,
+                client,
+                account: *account,
+                amount: *amount,
+            });
+            posted = true;
+        }
+        if posted {
+            self.entries = entry;
+        }
+    }
+
+    pub fn postings(&self) -> &[Posting] {
+        &self.postings
+    }
+
+    /// Debits minus credits of every account with postings.
+    pub fn trial_balance(&self) -> BTreeMap<LedgerAccount, Decimal> {
+        let mut balances = BTreeMap::new();
+        for posting in &self.postings {
+            *balances.entry(posting.account).or_insert(Decimal::ZERO) += posting.amount;
+        }
+        balances
+    }
+
+    // Where the ledger is, to go back to it when a batch is rolled back
+    pub(crate) fn mark(&self) -> (usize, u64) {
+        (self.postings.len(), self.entries)
+    }
+
+    pub(crate) fn rewind(&mut self, (postings, entries): (usize, u64)) {
+        self.postings.truncate(postings);
+        self.entries = entries;
+    }
+
+    /// Shards never share clients, their entries go after ours
+    pub(crate) fn merge(&mut self, other: DoubleEntryLedger) {
+        let offset = self.entries;
+        self.postings
+            .extend(other.postings.into_iter().map(|posting| Posting {
+                entry: posting.entry + offset,
+                ..posting
+            }));
+        self.entries += other.entries;
+    }
+
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "entry,tx,type,account,debit,credit")?;
+        for posting in &self.postings {
+            let (debit, credit) = match posting.amount.is_sign_positive() {
+                true => (posting.amount.to_string(), String::new()),
+                false => (String::new(), (-posting.amount).to_string()),
+            };
+            writeln!(
+                writer,
+                "{},{},{},{},{},{}",
+                posting.entry, posting.tx, posting.tx_type, posting.account, debit, credit
+            )?;
+        }
+        writer.flush()
+    }
+}
+
+#[test]
+fn test_double_entry() {
+    use crate::compat::CompatLevel;
+    use crate::PaymentEngine;
+
+    let mut engine = PaymentEngine::new();
+    engine.set_compat_level(CompatLevel::V2);
+    engine.track_double_entry();
+    engine
+        .import_reader(
+            &b"type,client,tx,amount
+deposit,1,1,10
+withdrawal,1,2,3
+dispute,1,1,
+chargeback,1,1,
+deposit,2,3,5
+withdrawal,2,4,2
+dispute,2,4,
+chargeback,2,4,
+withdrawal,2,5,100
+"[..],
+        )
+        .unwrap();
+    let ledger = engine.double_entry().unwrap();
+
+    let mut report = Vec::new();
+    ledger.write(&mut report).unwrap();
+    let report = String::from_utf8(report).unwrap();
+    assert!(report.starts_with(
+        "entry,tx,type,account,debit,credit
+1,1,deposit,cash,10,
+1,1,deposit,client:1:available,,10
+2,2,withdrawal,cash,,3
+2,2,withdrawal,client:1:available,3,
+"
+    ));
+
+    let mut entries: BTreeMap<u64, Decimal> = BTreeMap::new();
+    for posting in ledger.postings() {
+        *entries.entry(posting.entry).or_default() += posting.amount;
+    }
+    assert_eq!(entries.len(), 8, "the declined withdrawal posts nothing");
+    assert!(entries.values().all(|sum| sum.is_zero()));
+
+    let balances = ledger.trial_balance();
+    assert_eq!(
+        balances[&LedgerAccount::Cash],
+        Decimal::new(10 - 3 - 10 + 5 - 2, 0)
+    );
+    assert_eq!(
+        balances[&LedgerAccount::ChargebackLosses],
+        Decimal::new(2, 0)
+    );
+    for client in 1..=2 {
+        let account = engine.account(client).unwrap().unwrap();
+        assert_eq!(
+            balances[&LedgerAccount::Available(client)],
+            -account.available()
+        );
+    }
+}
@@ -0,0 +1,2139 @@
+use crate::anomalies::{AnomalyReport, AnomalyRules};
+use crate::audit::{AuditLog, AuditRecord, Balances};
+use crate::blocklist::{BlockedTx, Blocklist};
+use crate::categories::CategoryTotals;
+use crate::compat::CompatLevel;
+use crate::conflicts::ConflictReport;
+use crate::dispute_state::DisputeStateMachine;
+use crate::disputes::DisputeHistory;
+use crate::double_entry::{DoubleEntryLedger, LedgerAccount};
+use crate::input::batch;
+use crate::input::dialect::CsvDialect;
+use crate::limits::{Limits, LimitsSpec};
+use crate::locks::LockHistory;
+use crate::meta::AccountsMeta;
+use crate::metrics::{Metrics, Outcome};
+use crate::observer::EngineObserver;
+use crate::output::{AccountColumn, AccountFilter, AccountOrder};
+use crate::policy::{AccountCreation, OverdraftLimits};
+use crate::resources::ResourceLimits;
+use crate::seen::SeenTxIds;
+use crate::state::{self, StateFormat};
+use crate::stats::Stats;
+use crate::store::{AccountStore, MemoryAccountStore, MemoryTransactionStore, TransactionStore};
+use crate::transaction::*;
+use crate::undo::{Delta, UndoLog};
+use crate::wal::Wal;
+use csv::ByteRecord;
+use rust_decimal::prelude::*;
+use serde::ser::{SerializeSeq, Serializer};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::{debug, debug_span, warn};
+
+pub struct PaymentEngine {
+    pub(crate) accounts: Box<dyn AccountStore>,
+    pub(crate) transactions: Box<dyn TransactionStore>, // We need to keep this to deal with disputes. In memory by default, see store.rs for the alternatives
+    compat: CompatLevel,
+    account_creation: AccountCreation,
+    overdraft: OverdraftLimits,
+    limits: Option<Limits>,
+    resources: ResourceLimits,
+    blocklist: Option<Blocklist>,
+    meta: Option<Arc<AccountsMeta>>,
+    audit: Option<Arc<AuditLog>>,
+    pub(crate) stats: Stats,
+    metrics: Option<Arc<Metrics>>,
+    observers: Vec<Arc<dyn EngineObserver>>,
+    wal: Option<Arc<Wal>>,
+    disputes: Option<DisputeHistory>,
+    conflicts: Option<ConflictReport>,
+    anomalies: Option<AnomalyReport>,
+    locks: Option<LockHistory>,
+    categories: Option<CategoryTotals>,
+    double_entry: Option<DoubleEntryLedger>,
+    seen: Option<SeenTxIds>,
+    undo: Option<UndoLog>,
+    batch: Option<OpenBatch>,
+}
+
+// What a transaction of an atomic batch can change, as it was before it
+struct Undo {
+    client_id: ClientId,
+    account: Option<Account>,
+    tx_id: TransactionId,
+    transaction: Option<Transaction>,
+    unseen: bool,
+    deltas: Option<Vec<Delta>>,
+}
+
+// See batch.rs
+struct OpenBatch {
+    undo: Vec<Undo>,
+    ledger: Option<(usize, u64)>,
+    categories: Option<CategoryTotals>,
+}
+
+pub type Accounts<'a> = Box<dyn Iterator<Item = io::Result<Account>> + 'a>;
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Account {
+    pub(crate) client_id: ClientId,
+    pub(crate) num_transactions: u32,
+    pub(crate) funds_available: Decimal,
+    pub(crate) funds_held: Decimal,
+    pub(crate) funds_total: Decimal, // TODO: Possibly redundant but let's keep around for now for basic sanity check
+    pub(crate) locked: bool,
+}
+
+impl Account {
+    pub fn client_id(&self) -> ClientId {
+        self.client_id
+    }
+
+    /// Deposits, withdrawals, disputes, resolves and chargebacks seen, applied
+    /// or not.
+    pub fn num_transactions(&self) -> u32 {
+        self.num_transactions
+    }
+
+    pub fn available(&self) -> Decimal {
+        self.funds_available
+    }
+
+    pub fn held(&self) -> Decimal {
+        self.funds_held
+    }
+
+    pub fn total(&self) -> Decimal {
+        self.funds_total
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    /// In overdraft, see OverdraftLimits
+    pub fn is_overdrawn(&self) -> bool {
+        self.funds_available < Decimal::ZERO
+    }
+
+    /// The account as a row of the default export, without the newline.
+    pub fn to_csv_row(&self) -> String {
+        self.to_string()
+    }
+}
+
+// client,available,held,total,locked, as export_accounts writes it
+impl fmt::Display for Account {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{},{},{},{},{}",
+            self.client_id, self.funds_available, self.funds_held, self.funds_total, self.locked
+        )
+    }
+}
+
+#[derive(Serialize)]
+struct SavedState<'a> {
+    accounts: StoredAccounts<'a>,
+    transactions: StoredTransactions<'a>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seen: Option<&'a SeenTxIds>,
+}
+
+struct StoredAccounts<'a>(&'a dyn AccountStore);
+
+impl serde::Serialize for StoredAccounts<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.0.len()))?;
+        for account in self.0.iter() {
+            let account = account.map_err(serde::ser::Error::custom)?;
+            seq.serialize_element(&account)?;
+        }
+        seq.end()
+    }
+}
+
+// Streams the transactions out of the store instead of collecting them first,
+// the store could be much bigger than memory
+struct StoredTransactions<'a>(&'a dyn TransactionStore);
+
+impl serde::Serialize for StoredTransactions<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.0.len()))?;
+        for transaction in self.0.iter() {
+            let transaction = transaction.map_err(serde::ser::Error::custom)?;
+            seq.serialize_element(&transaction)?;
+        }
+        seq.end()
+    }
+}
+
+#[derive(Deserialize)]
+struct LoadedState {
+    accounts: Vec<Account>,
+    transactions: Vec<Transaction>,
+    #[serde(default)]
+    seen: Option<SeenTxIds>,
+}
+
+// A row of the opening balances, see load_opening_balances
+#[derive(Deserialize)]
+struct OpeningBalance {
+    client: ClientId,
+    available: Decimal,
+    #[serde(default)]
+    held: Decimal,
+    total: Option<Decimal>,
+    #[serde(default)]
+    locked: bool,
+}
+
+// Moves the funds of a dispute, resolve, chargeback, capture or release, once
+// the state machine allowed it
+pub(crate) fn move_disputed(
+    account: &mut Account,
+    disputed: &Transaction,
+    event: TransactionType,
+    amount: Decimal,
+    v2: bool,
+) -> Result<(), Box<dyn Error>> {
+    let withdrawal = disputed.tx_type == TransactionType::Withdrawal;
+    match event {
+        TransactionType::Dispute => {
+            if withdrawal {
+                account.funds_total = add(account.funds_total, amount)?;
+            } else {
+                account.funds_available = sub(account.funds_available, amount)?;
+            }
+            account.funds_held = add(account.funds_held, amount)?;
+        }
+        TransactionType::Resolve => {
+            if withdrawal {
+                account.funds_total = sub(account.funds_total, amount)?;
+            } else {
+                account.funds_available = add(account.funds_available, amount)?;
+            }
+            account.funds_held = sub(account.funds_held, amount)?;
+        }
+        TransactionType::Chargeback => {
+            if v2 && !withdrawal {
+                account.funds_total = sub(account.funds_total, amount)?;
+            } else {
+                account.funds_available = add(account.funds_available, amount)?;
+            }
+            account.funds_held = sub(account.funds_held, amount)?;
+            account.locked = true; // If a chargeback occurs the client's account should be immediately frozen.
+        }
+        // The held amount leaves the account
+        TransactionType::Capture => {
+            account.funds_held = sub(account.funds_held, amount)?;
+            account.funds_total = sub(account.funds_total, amount)?;
+        }
+        TransactionType::Release => {
+            account.funds_held = sub(account.funds_held, amount)?;
+            account.funds_available = add(account.funds_available, amount)?;
+        }
+        TransactionType::Deposit | TransactionType::Withdrawal | TransactionType::Hold => {}
+    }
+    Ok(())
+}
+
+/// A transaction the engine can't apply without breaking its books: a
+/// repeated tx id, or an amount that overflows. Unlike a declined
+/// transaction it stops the import.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InvariantViolation(pub &'static str);
+
+impl fmt::Display for InvariantViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+impl Error for InvariantViolation {}
+
+const REPEATED_TX: InvariantViolation = InvariantViolation("Repeated transaction id");
+const OVERFLOW: InvariantViolation = InvariantViolation("Amount overflow");
+
+// Decimal's operators panic on overflow, which any input can trigger
+fn add(a: Decimal, b: Decimal) -> Result<Decimal, InvariantViolation> {
+    a.checked_add(b).ok_or(OVERFLOW)
+}
+
+fn sub(a: Decimal, b: Decimal) -> Result<Decimal, InvariantViolation> {
+    a.checked_sub(b).ok_or(OVERFLOW)
+}
+
+impl Default for PaymentEngine {
+    fn default() -> Self {
+        PaymentEngine::new()
+    }
+}
+
+impl PaymentEngine {
+    pub fn new() -> PaymentEngine {
+        PaymentEngine::with_transaction_store(Box::new(MemoryTransactionStore::default()))
+    }
+
+    pub fn with_transaction_store(transactions: Box<dyn TransactionStore>) -> PaymentEngine {
+        PaymentEngine::with_stores(Box::new(MemoryAccountStore::default()), transactions)
+    }
+
+    /// For plugging in other backends (a database, say). The processing logic
+    /// only ever talks to the stores through their traits.
+    pub fn with_stores(
+        accounts: Box<dyn AccountStore>,
+        transactions: Box<dyn TransactionStore>,
+    ) -> PaymentEngine {
+        PaymentEngine {
+            accounts,
+            transactions,
+            compat: CompatLevel::default(),
+            account_creation: AccountCreation::default(),
+            overdraft: OverdraftLimits::default(),
+            limits: None,
+            resources: ResourceLimits::default(),
+            blocklist: None,
+            meta: None,
+            audit: None,
+            stats: Stats::default(),
+            metrics: None,
+            observers: Vec::new(),
+            wal: None,
+            disputes: None,
+            conflicts: None,
+            anomalies: None,
+            locks: None,
+            categories: None,
+            double_entry: None,
+            seen: None,
+            undo: None,
+            batch: None,
+        }
+    }
+
+    /// See compat.rs for what changes between levels.
+    pub fn set_compat_level(&mut self, compat: CompatLevel) {
+        self.compat = compat;
+    }
+
+    pub fn compat_level(&self) -> CompatLevel {
+        self.compat
+    }
+
+    /// Remembers every deposit and withdrawal tx id processed, in the saved
+    /// state too, and skips them when they come again, in this run or a
+    /// later one restored from the state (see seen.rs). Has to be on before
+    /// restoring the state. An idempotent engine can't be split over threads
+    /// or actors: the tx ids aren't tied to a client.
+    pub fn is_idempotent(&self) -> bool {
+        self.seen.is_some()
+    }
+
+    pub fn set_idempotent(&mut self, idempotent: bool) {
+        self.seen = match idempotent {
+            true => self.seen.take().or_else(|| Some(SeenTxIds::default())),
+            false => None,
+        };
+    }
+
+    /// Lets withdrawals take the available funds below zero, down to the
+    /// client's limit. No overdraft by default.
+    pub fn set_overdraft_limits(&mut self, limits: OverdraftLimits) {
+        self.overdraft = limits;
+    }
+
+    /// Fraud checks on every transaction from now on, see limits.rs.
+    pub fn set_limits(&mut self, limits: LimitsSpec) {
+        self.limits = Some(Limits::new(limits));
+    }
+
+    /// Rejects (or holds the deposits of) the clients on the list from now
+    /// on, see blocklist.rs.
+    pub fn set_blocklist(&mut self, blocklist: Blocklist) {
+        self.blocklist = Some(blocklist);
+    }
+
+    /// Caps on accounts, stored transactions and memory, see resources.rs.
+    pub fn set_resource_limits(&mut self, resources: ResourceLimits) {
+        self.resources = resources;
+    }
+
+    /// The list and what its clients did, if there's one.
+    pub fn blocklist(&self) -> Option<&Blocklist> {
+        self.blocklist.as_ref()
+    }
+
+    /// Opens an account for every client in the metadata that doesn't have
+    /// one yet and locks the ones it says are locked, see meta.rs. The risk
+    /// tiers and the export columns use it from then on.
+    pub fn set_accounts_meta(&mut self, meta: AccountsMeta) -> io::Result<()> {
+        for (client_id, client) in meta.iter() {
+            let account = match self.accounts.get(client_id)? {
+                Some(mut account) => {
+                    account.locked |= client.locked;
+                    account
+                }
+                None => Account {
+                    client_id,
+                    num_transactions: 0,
+                    funds_available: Decimal::ZERO,
+                    funds_held: Decimal::ZERO,
+                    funds_total: Decimal::ZERO,
+                    locked: client.locked,
+                },
+            };
+            self.accounts.put(account)?;
+        }
+        self.meta = Some(Arc::new(meta));
+        Ok(())
+    }
+
+    pub fn accounts_meta(&self) -> Option<&AccountsMeta> {
+        self.meta.as_deref()
+    }
+
+    /// Which transactions open an account for a client we haven't seen, see
+    /// policy.rs.
+    pub fn set_account_creation(&mut self, policy: AccountCreation) {
+        self.account_creation = policy;
+    }
+
+    /// Every transaction processed from now on gets a record in the log.
+    pub fn set_audit_log(&mut self, audit: Arc<AuditLog>) {
+        self.audit = Some(audit);
+    }
+
+    pub fn audit_log(&self) -> Option<&Arc<AuditLog>> {
+        self.audit.as_ref()
+    }
+
+    /// Every transaction processed from now on is appended to the log before
+    /// it's applied, see wal.rs.
+    pub fn set_wal(&mut self, wal: Arc<Wal>) {
+        self.wal = Some(wal);
+    }
+
+    pub fn wal(&self) -> Option<&Arc<Wal>> {
+        self.wal.as_ref()
+    }
+
+    /// Keep the history of every dispute from now on, see disputes.rs.
+    pub fn track_disputes(&mut self) {
+        self.disputes.get_or_insert_with(DisputeHistory::default);
+    }
+
+    pub fn disputes(&self) -> Option<&DisputeHistory> {
+        self.disputes.as_ref()
+    }
+
+    /// Keep the records passed over because they conflict with others from
+    /// now on, see conflicts.rs.
+    pub fn track_conflicts(&mut self) {
+        self.conflicts.get_or_insert_with(ConflictReport::default);
+    }
+
+    pub fn conflicts(&self) -> Option<&ConflictReport> {
+        self.conflicts.as_ref()
+    }
+
+    /// Keep what the anomaly rules need from every applied transaction from
+    /// now on, see anomalies.rs.
+    pub fn track_anomalies(&mut self, rules: AnomalyRules) {
+        self.anomalies
+            .get_or_insert_with(|| AnomalyReport::new(rules));
+    }
+
+    pub fn anomalies(&self) -> Option<&AnomalyReport> {
+        self.anomalies.as_ref()
+    }
+
+    /// Keep the chargeback that locked each account from now on, see locks.rs.
+    pub fn track_locks(&mut self) {
+        self.locks.get_or_insert_with(LockHistory::default);
+    }
+
+    pub fn locks(&self) -> Option<&LockHistory> {
+        self.locks.as_ref()
+    }
+
+    /// Add up what goes in and out of every client's account per category
+    /// from now on, see categories.rs.
+    pub fn track_categories(&mut self) {
+        self.categories.get_or_insert_with(CategoryTotals::default);
+    }
+
+    pub fn categories(&self) -> Option<&CategoryTotals> {
+        self.categories.as_ref()
+    }
+
+    /// Post every transaction applied from now on to a double-entry ledger,
+    /// see double_entry.rs.
+    pub fn track_double_entry(&mut self) {
+        self.double_entry
+            .get_or_insert_with(DoubleEntryLedger::default);
+    }
+
+    pub fn double_entry(&self) -> Option<&DoubleEntryLedger> {
+        self.double_entry.as_ref()
+    }
+
+    /// Keeps what every applied transaction changed, for undo. See undo.rs.
+    pub fn track_undo(&mut self) {
+        self.undo.get_or_insert_with(UndoLog::default);
+    }
+
+    pub fn undo_log(&self) -> Option<&UndoLog> {
+        self.undo.as_ref()
+    }
+
+    /// Reverses everything applied under a tx id, see undo.rs. An error if
+    /// undo isn't tracked or nothing was applied under it.
+    pub fn undo(&mut self, tx_id: TransactionId) -> Result<(), Box<dyn Error>> {
+        let deltas = self
+            .undo
+            .as_mut()
+            .ok_or("Undo isn't tracked")?
+            .take(tx_id)
+            .ok_or_else(|| format!("Nothing applied under tx {} to undo", tx_id))?;
+        for delta in deltas.iter().rev() {
+            let mut account = self
+                .accounts
+                .get(delta.client)?
+                .ok_or_else(|| format!("Account {} is gone", delta.client))?;
+            let before = (account.funds_available, account.funds_held);
+            account.num_transactions = account.num_transactions.saturating_sub(1);
+            account.funds_available = sub(account.funds_available, delta.available)?;
+            account.funds_held = sub(account.funds_held, delta.held)?;
+            account.funds_total = sub(account.funds_total, add(delta.available, delta.held)?)?;
+            if delta.locked {
+                account.locked = false;
+            }
+            let after = (account.funds_available, account.funds_held);
+            self.accounts.put(account)?;
+            if let Some(ledger) = &mut self.double_entry {
+                ledger.post(
+                    delta.tx_type,
+                    tx_id,
+                    delta.client,
+                    delta.counterpart,
+                    before,
+                    after,
+                );
+            }
+        }
+        self.transactions.remove(tx_id)?;
+        if let Some(seen) = &mut self.seen {
+            seen.remove(tx_id);
+        }
+        self.stats.undone += 1;
+        debug!(tx_id, "Transaction undone");
+        Ok(())
+    }
+
+    /// For exposing what the engine does while it runs, see metrics.rs.
+    pub fn set_metrics(&mut self, metrics: Arc<Metrics>) {
+        self.metrics = Some(metrics);
+    }
+
+    /// Tells `observer` about every transaction processed from now on, see
+    /// observer.rs. There can be any number of them, called in the order
+    /// they were added.
+    pub fn add_observer(&mut self, observer: Arc<dyn EngineObserver>) {
+        self.observers.push(observer);
+    }
+
+    pub fn with_observer(mut self, observer: Arc<dyn EngineObserver>) -> PaymentEngine {
+        self.add_observer(observer);
+        self
+    }
+
+    /// What happened to the transactions processed so far by this engine
+    /// (not counting the ones in a loaded state).
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    /// How many accounts there are and how many of them are locked.
+    pub fn count_accounts(&self) -> io::Result<(u64, u64)> {
+        let (mut accounts, mut locked) = (0, 0);
+        for account in self.accounts.iter() {
+            accounts += 1;
+            if account?.locked {
+                locked += 1;
+            }
+        }
+        Ok((accounts, locked))
+    }
+
+    // One event per transaction, in its span. Ignored rows are only worth a
+    // warning from v2 on, v1 was silent. The span is only there at debug
+    // level, so the warning carries the ids itself.
+    fn log_outcome(
+        &self,
+        client_id: ClientId,
+        tx_id: TransactionId,
+        tx_type: TransactionType,
+        result: &Result<TxOutcome, Box<dyn Error>>,
+    ) {
+        match (result, self.compat) {
+            (Ok(TxOutcome::Applied), _) => debug!(outcome = "applied", "Transaction applied"),
+            (Ok(outcome), compat) => {
+                let reason = outcome.reason().unwrap_or_default();
+                let outcome = outcome.kind();
+                match compat {
+                    CompatLevel::V1 => debug!(outcome, reason, "Transaction ignored"),
+                    CompatLevel::V2 => warn!(
+                        client_id,
+                        tx_id,
+                        tx_type = %tx_type,
+                        outcome,
+                        reason,
+                        "Transaction ignored"
+                    ),
+                }
+            }
+            (Err(e), _) => debug!(outcome = "failed", error = %e, "Transaction failed"),
+        }
+    }
+
+    /// Applies a single transaction, creating the client's account if it's
+    /// the first time we see it and the account creation policy allows it.
+    /// Malformed transactions (a deposit without an
+    /// amount, balances that would overflow...) are errors, never panics.
+    /// Returns what it did, which is only Applied if it changed anything.
+    pub fn process_transaction(
+        &mut self,
+        transaction: Transaction,
+    ) -> Result<TxOutcome, Box<dyn Error>> {
+        let span = debug_span!(
+            "transaction",
+            client_id = transaction.client_id,
+            tx_id = transaction.tx_id,
+            tx_type = %transaction.tx_type,
+            amount = ?transaction.amount,
+        );
+        let _entered = span.enter();
+        self.resources.check_memory(self.stats.total())?;
+        if self.batch.is_some() {
+            let undo = self.undo_record(&transaction)?;
+            if let Some(batch) = &mut self.batch {
+                batch.undo.push(undo);
+            }
+        }
+        if let Some(wal) = &self.wal {
+            wal.append(&transaction)?;
+        }
+        let tx_type = transaction.tx_type;
+        let started = self.metrics.as_ref().map(|_| Instant::now());
+        // Only chargebacks lock, and anything over a limit with
+        // lock_on_violation, and only metrics, observers, the lock history
+        // and undo care
+        let lock_watched = self.metrics.is_some()
+            || self.locks.is_some()
+            || self.undo.is_some()
+            || !self.observers.is_empty();
+        let can_lock = tx_type == TransactionType::Chargeback
+            || self.limits.as_ref().is_some_and(Limits::lock_on_violation);
+        let was_locked = match lock_watched && can_lock {
+            true => self
+                .accounts
+                .get(transaction.client_id)?
+                .is_some_and(|account| account.locked),
+            false => true,
+        };
+        let client_id = transaction.client_id;
+        let tx_id = transaction.tx_id;
+        let disputed_before = match (&self.disputes, tx_type) {
+            (
+                Some(_),
+                TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback,
+            ) => self.disputed_status(tx_type, tx_id)?,
+            _ => None,
+        };
+        let observed = match self.observers.is_empty() {
+            true => None,
+            false => Some(transaction.clone()),
+        };
+        let posting = match self.double_entry.is_some() || self.undo.is_some() {
+            true => Some(self.double_entry_before(&transaction)?),
+            false => None,
+        };
+        let categorized = match self.categories {
+            Some(_) => Some((
+                self.category_of(&transaction)?,
+                self.balances_of(client_id)?,
+            )),
+            None => None,
+        };
+        if let Some(conflicts) = &mut self.conflicts {
+            conflicts.see(&transaction);
+        }
+        let amount = transaction.amount;
+        let result = match self.audit.clone() {
+            None => self.apply(transaction),
+            Some(audit) => self.apply_audited(transaction, &audit),
+        };
+        self.stats.count(tx_type, &result);
+        self.log_outcome(client_id, tx_id, tx_type, &result);
+        if disputed_before.is_some() {
+            self.record_dispute(tx_type, tx_id, disputed_before)?;
+        }
+        if let (Some(_), Ok(outcome)) = (&self.conflicts, &result) {
+            self.record_conflict(tx_type, client_id, tx_id, *outcome)?;
+        }
+        if let (Some(anomalies), Ok(TxOutcome::Applied)) = (&mut self.anomalies, &result) {
+            anomalies.record(tx_type, client_id, tx_id, amount);
+        }
+        let newly_locked = match was_locked {
+            true => None,
+            false => self
+                .accounts
+                .get(client_id)?
+                .filter(|account| account.locked),
+        };
+        // A transaction over a limit that locked the account changed nothing
+        // else, but undoing it unlocks the account
+        let applied = matches!(result, Ok(TxOutcome::Applied));
+        if let Some((counterpart, before)) = posting.filter(|_| applied || newly_locked.is_some()) {
+            let after = self.balances_of(client_id)?;
+            if let (Some(ledger), true) = (&mut self.double_entry, applied) {
+                ledger.post(tx_type, tx_id, client_id, counterpart, before, after);
+            }
+            if let Some(undo) = &mut self.undo {
+                undo.record(
+                    tx_id,
+                    Delta {
+                        tx_type,
+                        client: client_id,
+                        available: sub(after.0, before.0)?,
+                        held: sub(after.1, before.1)?,
+                        locked: newly_locked.is_some(),
+                        counterpart,
+                    },
+                );
+            }
+        }
+        if let (Some((category, before)), Ok(TxOutcome::Applied)) = (categorized, &result) {
+            let after = self.balances_of(client_id)?;
+            if let Some(categories) = &mut self.categories {
+                categories.record(
+                    client_id,
+                    category,
+                    sub(add(after.0, after.1)?, add(before.0, before.1)?)?,
+                );
+            }
+        }
+        if let (Some(locks), Some(account)) = (&mut self.locks, &newly_locked) {
+            // A limit locks with the transaction over it, a chargeback with
+            // the one charged back
+            let amount = match tx_type {
+                TransactionType::Chargeback => self
+                    .transactions
+                    .get(tx_id)?
+                    .and_then(|charged| charged.amount),
+                _ => amount,
+            };
+            locks.record(account, tx_id, amount);
+        }
+        if let (Some(transaction), Ok(outcome)) = (&observed, &result) {
+            self.notify(transaction, *outcome, newly_locked.as_ref())?;
+        }
+        if let (Some(metrics), Some(started)) = (&self.metrics, started) {
+            let outcome = match &result {
+                Ok(TxOutcome::Applied) => Outcome::Applied,
+                Ok(outcome @ TxOutcome::DeclinedInsufficientFunds) => {
+                    Outcome::Declined(outcome.reason().unwrap_or_default())
+                }
+                Ok(outcome) => Outcome::Ignored(outcome.reason().unwrap_or_default()),
+                Err(_) => Outcome::Failed,
+            };
+            metrics.record(tx_type, outcome, newly_locked.is_some(), started.elapsed());
+        }
+        result
+    }
+
+    fn notify(
+        &self,
+        transaction: &Transaction,
+        outcome: TxOutcome,
+        newly_locked: Option<&Account>,
+    ) -> Result<(), Box<dyn Error>> {
+        if !outcome.is_applied() {
+            for observer in &self.observers {
+                observer.on_rejected(transaction, outcome);
+            }
+        } else if let Some(account) = self.accounts.get(transaction.client_id)? {
+            for observer in &self.observers {
+                observer.on_applied(transaction, &account);
+            }
+        }
+        if let Some(account) = newly_locked {
+            for observer in &self.observers {
+                observer.on_account_locked(account, transaction.tx_id);
+            }
+        }
+        Ok(())
+    }
+
+    fn apply_audited(
+        &mut self,
+        transaction: Transaction,
+        audit: &AuditLog,
+    ) -> Result<TxOutcome, Box<dyn Error>> {
+        let before = self.accounts.get(transaction.client_id)?;
+        let status_before = self.disputed_status(transaction.tx_type, transaction.tx_id)?;
+        let mut record = AuditRecord::new(&transaction);
+        let result = self.apply(transaction);
+        record.before = before.as_ref().map(Balances::from);
+        record.after = self
+            .accounts
+            .get(record.client)?
+            .as_ref()
+            .map(Balances::from);
+        let status_after = self.disputed_status(record.tx_type, record.tx)?;
+        if status_before != status_after {
+            record.status = Some((status_before, status_after));
+        }
+        match &result {
+            Ok(outcome) => {
+                if let Some(reason) = outcome.reason() {
+                    record.ignored(reason);
+                }
+            }
+            Err(e) => record.failed(e.as_ref()),
+        }
+        audit.write(&record)?;
+        result
+    }
+
+    // A transaction's own, or the one of the transaction it refers to
+    fn category_of(&self, transaction: &Transaction) -> io::Result<Option<Box<str>>> {
+        Ok(match transaction.tx_type {
+            TransactionType::Deposit | TransactionType::Withdrawal | TransactionType::Hold => {
+                transaction.category.clone()
+            }
+            _ => self
+                .transactions
+                .get(transaction.tx_id)?
+                .and_then(|referred| referred.category),
+        })
+    }
+
+    // Available and held, zero for a client without an account
+    fn balances_of(&self, client_id: ClientId) -> io::Result<(Decimal, Decimal)> {
+        Ok(self
+            .accounts
+            .get(client_id)?
+            .map_or((Decimal::ZERO, Decimal::ZERO), |account| {
+                (account.funds_available, account.funds_held)
+            }))
+    }
+
+    // The other side of the transaction's entry, and the client's balances
+    // before it. Disputed withdrawals are given back at our expense.
+    fn double_entry_before(
+        &self,
+        transaction: &Transaction,
+    ) -> Result<(LedgerAccount, (Decimal, Decimal)), Box<dyn Error>> {
+        let counterpart = match transaction.tx_type {
+            TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback
+                if self
+                    .transactions
+                    .get(transaction.tx_id)?
+                    .is_some_and(|disputed| disputed.tx_type == TransactionType::Withdrawal) =>
+            {
+                LedgerAccount::ChargebackLosses
+            }
+            _ => LedgerAccount::Cash,
+        };
+        Ok((counterpart, self.balances_of(transaction.client_id)?))
+    }
+
+    // Only events that changed the status make it into the history
+    fn record_dispute(
+        &mut self,
+        tx_type: TransactionType,
+        tx_id: TransactionId,
+        before: Option<TransactionStatus>,
+    ) -> Result<(), Box<dyn Error>> {
+        let after = self.transactions.get(tx_id)?;
+        if let (Some(history), Some(disputed)) = (&mut self.disputes, after) {
+            if Some(disputed.status) != before {
+                history.record(&disputed, tx_type);
+            }
+        }
+        Ok(())
+    }
+
+    fn record_conflict(
+        &mut self,
+        tx_type: TransactionType,
+        client_id: ClientId,
+        tx_id: TransactionId,
+        outcome: TxOutcome,
+    ) -> Result<(), Box<dyn Error>> {
+        let owner = match outcome {
+            TxOutcome::IgnoredWrongClient => self.transactions.get(tx_id)?.map(|t| t.client_id),
+            _ => None,
+        };
+        if let Some(conflicts) = &mut self.conflicts {
+            conflicts.record(tx_type, client_id, tx_id, outcome, owner);
+        }
+        Ok(())
+    }
+
+    // Status of the transaction a dispute, resolve or chargeback refers to
+    fn disputed_status(
+        &self,
+        tx_type: TransactionType,
+        tx_id: TransactionId,
+    ) -> Result<Option<TransactionStatus>, Box<dyn Error>> {
+        Ok(match tx_type {
+            TransactionType::Deposit | TransactionType::Withdrawal | TransactionType::Hold => None,
+            _ => self.transactions.get(tx_id)?.map(|stored| stored.status),
+        })
+    }
+
+    fn apply(&mut self, transaction: Transaction) -> Result<TxOutcome, Box<dyn Error>> {
+        let replayable = matches!(
+            transaction.tx_type,
+            TransactionType::Deposit | TransactionType::Withdrawal | TransactionType::Hold
+        );
+        let tx_id = transaction.tx_id;
+        match &self.seen {
+            Some(seen) if replayable && seen.contains(tx_id) => {
+                return Ok(TxOutcome::SkippedReplayed);
+            }
+            _ => {}
+        }
+        // Only deposits to be held get past this
+        let blocked = match &mut self.blocklist {
+            Some(blocklist) if blocklist.is_blocked(transaction.client_id) => {
+                if blocklist.rejects(transaction.tx_type) {
+                    let outcome = TxOutcome::RejectedBlocked;
+                    blocklist.record(BlockedTx::new(&transaction, outcome));
+                    return Ok(outcome);
+                }
+                Some(BlockedTx::new(&transaction, TxOutcome::Applied))
+            }
+            _ => None,
+        };
+        let mut outcome = TxOutcome::Applied;
+        let v2 = self.compat == CompatLevel::V2;
+        let creates = self.account_creation.creates(transaction.tx_type);
+        let room = self.resources.room_for_account(&*self.accounts);
+        // Changed on a copy, so an error half way leaves it as it was, and
+        // written back through the same lookup
+        let stored = self.accounts.get_mut(transaction.client_id)?;
+        let mut account = match &stored {
+            Some(account) => (*account).clone(),
+            None if !creates => {
+                return Ok(TxOutcome::IgnoredUnknownAccount);
+            }
+            None => {
+                room?;
+                debug!("New account");
+                Account {
+                    client_id: transaction.client_id,
+                    num_transactions: 0,
+                    funds_available: Decimal::new(0, 0),
+                    funds_held: Decimal::new(0, 0),
+                    funds_total: Decimal::new(0, 0),
+                    locked: false,
+                }
+            }
+        };
+        let account_ref = &mut account;
+        account_ref.num_transactions = account_ref.num_transactions.saturating_add(1);
+        debug!(account = ?account_ref, "Account before");
+        let now = Instant::now();
+        let over_limit = match &mut self.limits {
+            Some(limits) => {
+                let tier = self
+                    .meta
+                    .as_ref()
+                    .and_then(|meta| meta.risk_tier(transaction.client_id));
+                limits.check(&transaction, tier, now)
+            }
+            None => None,
+        };
+        match transaction.tx_type {
+            _ if over_limit.is_some() => {
+                outcome = over_limit.unwrap_or(outcome);
+                if self.limits.as_ref().is_some_and(Limits::lock_on_violation) {
+                    account_ref.locked = true;
+                }
+            }
+            TransactionType::Deposit | TransactionType::Withdrawal | TransactionType::Hold
+                if v2 && account_ref.locked =>
+            {
+                outcome = TxOutcome::RejectedLocked;
+            }
+            TransactionType::Deposit | TransactionType::Withdrawal | TransactionType::Hold
+                if v2 && self.transactions.contains(transaction.tx_id)? =>
+            {
+                outcome = TxOutcome::IgnoredDuplicateTx;
+            }
+            // Not stored: nobody gets to dispute it
+            TransactionType::Deposit if blocked.is_some() => {
+                let amount = transaction.amount.ok_or("Deposit without amount")?;
+                account_ref.funds_held = add(account_ref.funds_held, amount)?;
+                account_ref.funds_total = add(account_ref.funds_total, amount)?;
+            }
+            TransactionType::Deposit => {
+                let amount = transaction.amount.ok_or("Deposit without amount")?;
+                let available = add(account_ref.funds_available, amount)?;
+                let total = add(account_ref.funds_total, amount)?;
+                // Assumption here: Only Deposits can be disputed so we don't store the rest
+                self.resources.room_for_transaction(&*self.transactions)?;
+                if !self.transactions.insert_new(transaction)? {
+                    return Err(REPEATED_TX.into());
+                }
+                account_ref.funds_available = available;
+                account_ref.funds_total = total;
+            }
+            TransactionType::Withdrawal => {
+                let amount = transaction.amount.ok_or("Withdrawal without amount")?;
+                let overdraft = self.overdraft.limit(transaction.client_id);
+                if add(account_ref.funds_available, overdraft)? >= amount {
+                    account_ref.funds_available = sub(account_ref.funds_available, amount)?;
+                    account_ref.funds_total = sub(account_ref.funds_total, amount)?;
+                    if let Some(limits) = &mut self.limits {
+                        limits.withdrawn(transaction.client_id, amount, now);
+                    }
+                    // v2 lets withdrawals be disputed too
+                    if v2 {
+                        self.resources.room_for_transaction(&*self.transactions)?;
+                        self.transactions.insert(transaction)?;
+                    }
+                } else {
+                    outcome = TxOutcome::DeclinedInsufficientFunds;
+                }
+            }
+            TransactionType::Hold => {
+                if self.transactions.contains(transaction.tx_id)? {
+                    return Err(REPEATED_TX.into());
+                }
+                let amount = transaction.amount.ok_or("Hold without amount")?;
+                if amount <= Decimal::ZERO {
+                    outcome = TxOutcome::IgnoredInvalidAmount;
+                } else if account_ref.funds_available >= amount {
+                    self.resources.room_for_transaction(&*self.transactions)?;
+                    account_ref.funds_available = sub(account_ref.funds_available, amount)?;
+                    account_ref.funds_held = add(account_ref.funds_held, amount)?;
+                    self.transactions.insert(transaction)?;
+                } else {
+                    outcome = TxOutcome::DeclinedInsufficientFunds;
+                }
+            }
+            TransactionType::Dispute
+            | TransactionType::Resolve
+            | TransactionType::Chargeback
+            | TransactionType::Capture
+            | TransactionType::Release => match self.transactions.get(transaction.tx_id)? {
+                Some(orig_txt) => {
+                    debug!(disputed = ?orig_txt, "Found the disputed transaction");
+                    match DisputeStateMachine::transition(&orig_txt, &transaction) {
+                        Ok(status) => {
+                            let amount =
+                                orig_txt.amount.ok_or("Stored transaction without amount")?;
+                            move_disputed(account_ref, &orig_txt, transaction.tx_type, amount, v2)?;
+                            self.transactions.set_status(transaction.tx_id, status)?;
+                        }
+                        Err(refused) => outcome = refused,
+                    }
+                }
+                None => outcome = TxOutcome::IgnoredUnknownTx,
+            },
+        };
+        debug!(account = ?account_ref, "Account after");
+        match stored {
+            Some(slot) => *slot = account,
+            None => self.accounts.put(account)?,
+        }
+        if let (Some(seen), true) = (&mut self.seen, replayable) {
+            seen.insert(tx_id);
+        }
+        if let (Some(blocklist), Some(blocked)) = (&mut self.blocklist, blocked) {
+            blocklist.record(BlockedTx { outcome, ..blocked });
+        }
+        Ok(outcome)
+    }
+
+    /// Whether every transaction only touches the stores and the stats, so it
+    /// can be applied some other way than process_transaction, see
+    /// vectorized.rs.
+    #[cfg(feature = "parquet")]
+    pub(crate) fn is_plain(&self) -> bool {
+        self.compat == CompatLevel::V1
+            && self.account_creation == AccountCreation::Any
+            && self.limits.is_none()
+            && self.resources == ResourceLimits::default()
+            && self.blocklist.is_none()
+            && self.audit.is_none()
+            && self.metrics.is_none()
+            && self.observers.is_empty()
+            && self.wal.is_none()
+            && self.disputes.is_none()
+            && self.conflicts.is_none()
+            && self.anomalies.is_none()
+            && self.locks.is_none()
+            && self.categories.is_none()
+            && self.double_entry.is_none()
+            && self.seen.is_none()
+            && self.undo.is_none()
+            && self.batch.is_none()
+    }
+
+    #[cfg(feature = "parquet")]
+    pub(crate) fn overdraft_limit(&self, client_id: ClientId) -> Decimal {
+        self.overdraft.limit(client_id)
+    }
+
+    /// Feeds already parsed transactions (from any input format) into the engine.
+    pub fn import_transactions<I>(&mut self, transactions: I) -> Result<(), Box<dyn Error>>
+    where
+        I: IntoIterator<Item = Result<Transaction, Box<dyn Error>>>,
+    {
+        for transaction in transactions {
+            self.process_transaction(transaction?)?;
+        }
+        Ok(())
+    }
+
+    pub fn import_csv(&mut self, filename: &str) -> Result<(), Box<dyn Error>> {
+        self.import_reader(File::open(filename)?)
+    }
+
+    /// CSV from anywhere, e.g. stdin or a socket. Never panics, whatever the
+    /// bytes: malformed input is an error.
+    pub fn import_reader<R: Read>(&mut self, reader: R) -> Result<(), Box<dyn Error>> {
+        self.import_reader_with_dialect(reader, &CsvDialect::default())
+    }
+
+    /// Same as import_reader for files that aren't quite the standard CSV.
+    pub fn import_reader_with_dialect<R: Read>(
+        &mut self,
+        reader: R,
+        dialect: &CsvDialect,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut rdr = dialect.reader(reader)?;
+        if let Some(rollback) = dialect.batches {
+            return batch::import(self, rdr, rollback, |_| {});
+        }
+        // One record buffer reused for every row
+        let mut record = ByteRecord::new();
+        self.import_transactions(std::iter::from_fn(move || {
+            match rdr.read_byte_record(&mut record) {
+                Ok(true) => {
+                    debug!("{:?}", record);
+                    Some(Transaction::from_byte_record(&record).map_err(|e| e.into()))
+                }
+                Ok(false) => None,
+                Err(e) => Some(Err(e)),
+            }
+        }))
+    }
+
+    /// Splits the engine in `shards` engines by `client_id % shards`. The
+    /// transactions follow the client that owns them. Shard 0 keeps the
+    /// original store, the others get a new one of the same kind.
+    pub(crate) fn split(mut self, shards: usize) -> Result<Vec<PaymentEngine>, Box<dyn Error>> {
+        let mut engines = Vec::with_capacity(shards);
+        for shard in 1..shards {
+            engines.push(self.empty_shard(shard)?);
+        }
+        let shard_of = |client_id: ClientId| client_id as usize % shards;
+
+        let mut moving = Vec::new();
+        for account in self.accounts.iter() {
+            let account = account?;
+            if shard_of(account.client_id) != 0 {
+                moving.push(account);
+            }
+        }
+        for account in moving {
+            self.accounts.remove(account.client_id)?;
+            engines[shard_of(account.client_id) - 1]
+                .accounts
+                .put(account)?;
+        }
+        let mut moving = Vec::new();
+        for transaction in self.transactions.iter() {
+            let transaction = transaction?;
+            if shard_of(transaction.client_id) != 0 {
+                moving.push(transaction);
+            }
+        }
+        for transaction in moving {
+            self.transactions.remove(transaction.tx_id)?;
+            engines[shard_of(transaction.client_id) - 1]
+                .transactions
+                .insert(transaction)?;
+        }
+
+        engines.insert(0, self);
+        Ok(engines)
+    }
+
+    /// An engine set up like this one, with no accounts or transactions, for
+    /// the clients of another shard.
+    pub(crate) fn empty_shard(&self, shard: usize) -> Result<PaymentEngine, Box<dyn Error>> {
+        // Every shard would only skip the tx ids of its own clients
+        if self.seen.is_some() {
+            return Err("Idempotent mode needs a single engine, it can't be sharded".into());
+        }
+        let mut engine = PaymentEngine::with_stores(
+            self.accounts.new_shard(shard)?,
+            self.transactions.new_shard(shard)?,
+        );
+        engine.compat = self.compat;
+        engine.account_creation = self.account_creation;
+        engine.overdraft = self.overdraft.clone();
+        engine.limits = self.limits.as_ref().map(Limits::empty);
+        engine.resources = self.resources.clone();
+        engine.blocklist = self.blocklist.as_ref().map(Blocklist::empty);
+        engine.meta = self.meta.clone();
+        engine.audit = self.audit.clone();
+        engine.metrics = self.metrics.clone();
+        engine.observers = self.observers.clone();
+        engine.wal = self.wal.clone();
+        engine.disputes = self.disputes.as_ref().map(|_| DisputeHistory::default());
+        engine.conflicts = self.conflicts.as_ref().map(|_| ConflictReport::default());
+        engine.anomalies = self
+            .anomalies
+            .as_ref()
+            .map(|report| AnomalyReport::new(report.rules().clone()));
+        engine.locks = self.locks.as_ref().map(|_| LockHistory::default());
+        engine.categories = self.categories.as_ref().map(|_| CategoryTotals::default());
+        engine.double_entry = self
+            .double_entry
+            .as_ref()
+            .map(|_| DoubleEntryLedger::default());
+        engine.undo = self.undo.as_ref().map(|_| UndoLog::default());
+        Ok(engine)
+    }
+
+    /// Inverse of split. Shards never share clients so there are no accounts
+    /// to merge, but they can share tx ids: one stored by two shards is a
+    /// repeated tx id, as it would have been in a sequential run.
+    pub(crate) fn absorb(&mut self, shard: PaymentEngine) -> Result<(), Box<dyn Error>> {
+        for account in shard.accounts.iter() {
+            self.accounts.put(account?)?;
+        }
+        for transaction in shard.transactions.iter() {
+            let transaction = transaction?;
+            if self.transactions.contains(transaction.tx_id)? {
+                return Err(format!("Repeated tx id {}", transaction.tx_id).into());
+            }
+            self.transactions.insert(transaction)?;
+        }
+        self.stats.merge(&shard.stats);
+        if let (Some(history), Some(shard)) = (&mut self.disputes, shard.disputes) {
+            history.merge(shard);
+        }
+        if let (Some(report), Some(shard)) = (&mut self.conflicts, shard.conflicts) {
+            report.merge(shard);
+        }
+        if let (Some(report), Some(shard)) = (&mut self.anomalies, shard.anomalies) {
+            report.merge(shard);
+        }
+        if let (Some(history), Some(shard)) = (&mut self.locks, shard.locks) {
+            history.merge(shard);
+        }
+        if let (Some(totals), Some(shard)) = (&mut self.categories, shard.categories) {
+            totals.merge(shard);
+        }
+        if let (Some(ledger), Some(shard)) = (&mut self.double_entry, shard.double_entry) {
+            ledger.merge(shard);
+        }
+        if let (Some(seen), Some(shard)) = (&mut self.seen, shard.seen) {
+            seen.merge(shard);
+        }
+        if let (Some(undo), Some(shard)) = (&mut self.undo, shard.undo) {
+            undo.merge(shard);
+        }
+        if let (Some(limits), Some(shard)) = (&mut self.limits, shard.limits) {
+            limits.merge(shard);
+        }
+        if let (Some(blocklist), Some(shard)) = (&mut self.blocklist, shard.blocklist) {
+            blocklist.merge(shard);
+        }
+        Ok(())
+    }
+
+    /// Combines the state of another engine into this one, e.g. the
+    /// snapshots of runs over different slices of the clients. The balances
+    /// of a client both have are added up (and it's locked if either
+    /// locked it). Both can't have the same tx id, and the sums can't
+    /// overflow: either is an error, and nothing is merged.
+    pub fn merge(&mut self, other: PaymentEngine) -> Result<(), Box<dyn Error>> {
+        for transaction in other.transactions.iter() {
+            let tx_id = transaction?.tx_id;
+            if self.transactions.contains(tx_id)? {
+                return Err(format!("Both have transaction {}", tx_id).into());
+            }
+        }
+        // Every sum first, so an overflow leaves the accounts as they were
+        let mut merged_accounts = Vec::new();
+        for account in other.accounts.iter() {
+            let account = account?;
+            let merged = match self.accounts.get(account.client_id)? {
+                None => account,
+                Some(ours) => Account {
+                    client_id: ours.client_id,
+                    num_transactions: ours
+                        .num_transactions
+                        .saturating_add(account.num_transactions),
+                    funds_available: add(ours.funds_available, account.funds_available)?,
+                    funds_held: add(ours.funds_held, account.funds_held)?,
+                    funds_total: add(ours.funds_total, account.funds_total)?,
+                    locked: ours.locked || account.locked,
+                },
+            };
+            merged_accounts.push(merged);
+        }
+        for merged in merged_accounts {
+            self.accounts.put(merged)?;
+        }
+        for transaction in other.transactions.iter() {
+            self.transactions.insert(transaction?)?;
+        }
+        self.stats.merge(&other.stats);
+        if let (Some(seen), Some(other)) = (&mut self.seen, other.seen) {
+            seen.merge(other);
+        }
+        Ok(())
+    }
+
+    /// Starts an atomic batch: until it's committed or rolled back, the engine
+    /// keeps what every transaction changes so it can be undone (see
+    /// batch.rs).
+    pub fn begin_batch(&mut self) {
+        self.batch = Some(OpenBatch {
+            undo: Vec::new(),
+            ledger: self.double_entry.as_ref().map(DoubleEntryLedger::mark),
+            categories: self.categories.clone(),
+        });
+    }
+
+    pub fn commit_batch(&mut self) {
+        self.batch = None;
+    }
+
+    /// Undoes the transactions of the open batch, last first.
+    pub fn rollback_batch(&mut self) -> Result<(), Box<dyn Error>> {
+        let batch = match self.batch.take() {
+            Some(batch) => batch,
+            None => return Ok(()),
+        };
+        self.stats.rolled_back += batch.undo.len() as u64;
+        self.stats.rolled_back_batches += 1;
+        for undo in batch.undo.into_iter().rev() {
+            match undo.account {
+                Some(account) => self.accounts.put(account)?,
+                None => {
+                    self.accounts.remove(undo.client_id)?;
+                }
+            }
+            match undo.transaction {
+                Some(transaction) => self.transactions.insert(transaction)?,
+                None => self.transactions.remove(undo.tx_id)?,
+            }
+            if let (Some(seen), true) = (&mut self.seen, undo.unseen) {
+                seen.remove(undo.tx_id);
+            }
+            if let Some(log) = &mut self.undo {
+                log.restore(undo.tx_id, undo.deltas);
+            }
+        }
+        if let (Some(ledger), Some(mark)) = (&mut self.double_entry, batch.ledger) {
+            ledger.rewind(mark);
+        }
+        if batch.categories.is_some() {
+            self.categories = batch.categories;
+        }
+        Ok(())
+    }
+
+    fn undo_record(&self, transaction: &Transaction) -> io::Result<Undo> {
+        Ok(Undo {
+            client_id: transaction.client_id,
+            account: self.accounts.get(transaction.client_id)?,
+            tx_id: transaction.tx_id,
+            transaction: self.transactions.get(transaction.tx_id)?,
+            unseen: self
+                .seen
+                .as_ref()
+                .is_some_and(|seen| !seen.contains(transaction.tx_id)),
+            deltas: self
+                .undo
+                .as_ref()
+                .and_then(|undo| undo.deltas(transaction.tx_id))
+                .map(<[Delta]>::to_vec),
+        })
+    }
+
+    /// Writes the whole engine state (accounts plus the transactions that can
+    /// still be disputed) so another process can pick up where we left off.
+    pub fn save_state<W: Write>(
+        &self,
+        writer: W,
+        format: StateFormat,
+    ) -> Result<(), Box<dyn Error>> {
+        let saved = SavedState {
+            accounts: StoredAccounts(self.accounts.as_ref()),
+            transactions: StoredTransactions(self.transactions.as_ref()),
+            seen: self.seen.as_ref(),
+        };
+        state::write(writer, format, &saved)
+    }
+
+    pub fn load_state<R: Read>(
+        reader: R,
+        format: StateFormat,
+    ) -> Result<PaymentEngine, Box<dyn Error>> {
+        let mut engine = PaymentEngine::new();
+        engine.restore_state(reader, format)?;
+        Ok(engine)
+    }
+
+    /// Same as load_state but into this engine's stores, whatever they are.
+    /// Meant for freshly created engines: loaded accounts and transactions
+    /// replace existing ones with the same id.
+    pub fn restore_state<R: Read>(
+        &mut self,
+        reader: R,
+        format: StateFormat,
+    ) -> Result<(), Box<dyn Error>> {
+        let loaded: LoadedState = state::read(reader, format)?;
+        debug!(
+            "Loaded state with {} accounts and {} transactions",
+            loaded.accounts.len(),
+            loaded.transactions.len()
+        );
+        for account in loaded.accounts {
+            self.accounts.put(account)?;
+        }
+        for transaction in loaded.transactions {
+            self.transactions.insert(transaction)?;
+        }
+        if let (Some(seen), Some(loaded)) = (&mut self.seen, loaded.seen) {
+            seen.merge(loaded);
+        }
+        Ok(())
+    }
+
+    /// save_state to a file, in the default format, for processing daily
+    /// files incrementally: snapshot after each one, restore before the next.
+    pub fn snapshot<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        self.save_state_to(path.as_ref(), StateFormat::default())
+    }
+
+    pub fn restore<P: AsRef<Path>>(path: P) -> Result<PaymentEngine, Box<dyn Error>> {
+        PaymentEngine::load_state(BufReader::new(File::open(path)?), StateFormat::default())
+    }
+
+    /// Starts the accounts from yesterday's closing balances: CSV with
+    /// client, available, held and locked columns (total too, if it's the
+    /// export of an earlier run, checked against the other two). Only
+    /// balances, the transactions aren't there so they can't be disputed
+    /// any more; load_state carries those over too. A client can't already
+    /// have an account.
+    pub fn load_opening_balances<R: Read>(&mut self, reader: R) -> Result<(), Box<dyn Error>> {
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(reader);
+        for row in reader.deserialize() {
+            let row: OpeningBalance = row?;
+            let total = add(row.available, row.held)?;
+            if row.total.is_some_and(|expected| expected != total) {
+                return Err(format!("Client {}: total isn't available + held", row.client).into());
+            }
+            if self.accounts.get(row.client)?.is_some() {
+                return Err(format!("Client {} already has an account", row.client).into());
+            }
+            self.accounts.put(Account {
+                client_id: row.client,
+                num_transactions: 0,
+                funds_available: row.available,
+                funds_held: row.held,
+                funds_total: total,
+                locked: row.locked,
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Replaces path atomically, so the previous state survives if we die
+    /// halfway.
+    pub fn save_state_to(&self, path: &Path, format: StateFormat) -> Result<(), Box<dyn Error>> {
+        state::write_atomically(path, |writer| self.save_state(writer, format))
+    }
+
+    /// Account balances only, i.e. the binary equivalent of export_accounts.
+    pub fn save_accounts<W: Write>(
+        &self,
+        writer: W,
+        format: StateFormat,
+    ) -> Result<(), Box<dyn Error>> {
+        state::write(writer, format, &StoredAccounts(self.accounts.as_ref()))
+    }
+
+    /// Builds an engine from an account snapshot. Since the snapshot doesn't
+    /// carry transactions, nothing processed before it can be disputed.
+    pub fn load_accounts<R: Read>(
+        reader: R,
+        format: StateFormat,
+    ) -> Result<PaymentEngine, Box<dyn Error>> {
+        let accounts: Vec<Account> = state::read(reader, format)?;
+        let mut engine = PaymentEngine::new();
+        for account in accounts {
+            engine.accounts.put(account)?;
+        }
+        Ok(engine)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn sorted_accounts(&self) -> Vec<Account> {
+        let mut accounts: Vec<Account> = self.accounts.iter().map(|a| a.unwrap()).collect();
+        accounts.sort_by_key(|a| a.client_id);
+        accounts
+    }
+
+    #[cfg(test)]
+    pub(crate) fn sorted_transactions(&self) -> Vec<Transaction> {
+        let mut transactions: Vec<Transaction> =
+            self.transactions.iter().map(|t| t.unwrap()).collect();
+        transactions.sort_by_key(|t| t.tx_id);
+        transactions
+    }
+
+    /// The client's account, None if it never had a transaction. Accounts
+    /// live in a store that may not be in memory, so this is a copy.
+    pub fn account(&self, client_id: ClientId) -> io::Result<Option<Account>> {
+        self.accounts.get(client_id)
+    }
+
+    /// All the accounts, in no particular order, see ordered_accounts.
+    pub fn iter_accounts(&self) -> Accounts<'_> {
+        self.accounts.iter()
+    }
+
+    /// Number of accounts
+    pub fn len(&self) -> usize {
+        self.accounts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.accounts.is_empty()
+    }
+
+    /// All the accounts in the given order. Any order but None has to hold
+    /// them all in memory to sort them.
+    pub fn ordered_accounts(&self, order: AccountOrder) -> io::Result<Accounts<'_>> {
+        self.filtered_accounts(order, &AccountFilter::default())
+    }
+
+    /// The accounts the filter lets through, in the given order. They're
+    /// filtered before they're sorted, so only those are held in memory.
+    pub fn filtered_accounts(
+        &self,
+        order: AccountOrder,
+        filter: &AccountFilter,
+    ) -> io::Result<Accounts<'_>> {
+        let accounts = match filter.is_all() {
+            true => self.iter_accounts(),
+            false => {
+                let filter = filter.clone();
+                Box::new(self.iter_accounts().filter(move |account| {
+                    account
+                        .as_ref()
+                        .map_or(true, |account| filter.matches(account))
+                }))
+            }
+        };
+        if order == AccountOrder::None {
+            return Ok(accounts);
+        }
+        let mut accounts = accounts.collect::<io::Result<Vec<Account>>>()?;
+        match order {
+            AccountOrder::Total => accounts.sort_by(|a, b| {
+                b.funds_total
+                    .cmp(&a.funds_total)
+                    .then(a.client_id.cmp(&b.client_id))
+            }),
+            _ => accounts.sort_by_key(|a| a.client_id),
+        }
+        Ok(Box::new(accounts.into_iter().map(Ok)))
+    }
+
+    /// CSV, by client id, with the usual columns.
+    pub fn export_accounts<W: Write>(&self, writer: W) -> io::Result<()> {
+        self.export_accounts_with(
+            writer,
+            AccountOrder::default(),
+            &AccountColumn::DEFAULT,
+            &AccountFilter::default(),
+        )
+    }
+
+    pub fn export_accounts_with<W: Write>(
+        &self,
+        mut writer: W,
+        order: AccountOrder,
+        columns: &[AccountColumn],
+        filter: &AccountFilter,
+    ) -> io::Result<()> {
+        let header: Vec<&str> = columns.iter().map(|column| column.name()).collect();
+        writeln!(writer, "{}", header.join(","))?;
+        for account in self.filtered_accounts(order, filter)? {
+            self.write_account_row(&account?, columns, &mut writer)?;
+        }
+        writer.flush()
+    }
+
+    /// One line of the CSV account export, without the header
+    pub fn write_account_row<W: Write>(
+        &self,
+        account: &Account,
+        columns: &[AccountColumn],
+        mut writer: W,
+    ) -> io::Result<()> {
+        let meta = self
+            .meta
+            .as_ref()
+            .and_then(|meta| meta.get(account.client_id));
+        for (i, column) in columns.iter().enumerate() {
+            if i > 0 {
+                writer.write_all(b",")?;
+            }
+            column.write(account, meta, &mut writer)?;
+        }
+        writer.write_all(b"\n")
+    }
+}
+
+#[test]
+fn test_merge() {
+    let import = |input: &str| {
+        let mut engine = PaymentEngine::new();
+        engine.import_reader(input.as_bytes()).unwrap();
+        engine
+    };
+    let mut merged = import("type,client,tx,amount\ndeposit,1,1,10\ndeposit,2,2,5\n");
+    merged
+        .merge(import(
+            "type,client,tx,amount\ndeposit,2,3,1\ndispute,2,3,\ndeposit,3,4,2\n",
+        ))
+        .unwrap();
+    let mut expected = String::new();
+    for account in merged.sorted_accounts() {
+        expected.push_str(&account.to_csv_row());
+        expected.push('\n');
+    }
+    assert_eq!(expected, "1,10,0,10,false\n2,5,1,6,false\n3,2,0,2,false\n");
+    assert_eq!(merged.transactions.len(), 4);
+
+    assert!(merged
+        .merge(import("type,client,tx,amount\ndeposit,4,2,1\n"))
+        .is_err());
+    assert_eq!(merged.account(4).unwrap(), None);
+
+    // Client 3's balance overflows: client 1's isn't added to either
+    let mut huge = import("type,client,tx,amount\ndeposit,3,5,79228162514264337593543950335\n");
+    huge.merge(import("type,client,tx,amount\ndeposit,1,6,1\n"))
+        .unwrap();
+    assert!(merged.merge(huge).is_err());
+    assert_eq!(
+        merged.account(1).unwrap().unwrap().total(),
+        Decimal::new(10, 0)
+    );
+    assert_eq!(merged.transactions.len(), 4);
+}
+
+#[test]
+fn test_state_round_trip() {
+    let deposit = |client_id, tx_id, amount: &str| -> Result<Transaction, Box<dyn Error>> {
+        Ok(Transaction {
+            tx_type: TransactionType::Deposit,
+            client_id,
+            tx_id,
+            amount: Some(Decimal::from_str(amount).unwrap()),
+            status: TransactionStatus::OK,
+            description: None,
+            category: None,
+        })
+    };
+    let mut engine = PaymentEngine::new();
+    engine
+        .import_transactions(vec![
+            deposit(1, 1, "1.0001"),
+            deposit(2, 2, "20"),
+            Ok(Transaction {
+                tx_type: TransactionType::Dispute,
+                client_id: 1,
+                tx_id: 1,
+                amount: None,
+                status: TransactionStatus::OK,
+                description: None,
+                category: None,
+            }),
+        ])
+        .unwrap();
+
+    for format in [StateFormat::MessagePack, StateFormat::Cbor].iter() {
+        let mut buffer = Vec::new();
+        engine.save_state(&mut buffer, *format).unwrap();
+        let restored = PaymentEngine::load_state(buffer.as_slice(), *format).unwrap();
+        assert_eq!(restored.sorted_accounts(), engine.sorted_accounts());
+        assert_eq!(restored.sorted_transactions(), engine.sorted_transactions());
+
+        let mut buffer = Vec::new();
+        engine.save_accounts(&mut buffer, *format).unwrap();
+        let restored = PaymentEngine::load_accounts(buffer.as_slice(), *format).unwrap();
+        assert_eq!(restored.sorted_accounts(), engine.sorted_accounts());
+        assert!(restored.transactions.is_empty());
+    }
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("state.bin");
+    std::fs::write(&path, "previous").unwrap();
+    engine.snapshot(&path).unwrap();
+    let restored = PaymentEngine::restore(&path).unwrap();
+    assert_eq!(restored.sorted_accounts(), engine.sorted_accounts());
+    assert_eq!(restored.sorted_transactions(), engine.sorted_transactions());
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    assert!(PaymentEngine::restore(dir.path().join("missing.bin")).is_err());
+}
+
+#[test]
+fn test_compat_v2() {
+    let transaction =
+        |tx_type, tx_id, amount: Option<&str>| -> Result<Transaction, Box<dyn Error>> {
+            Ok(Transaction {
+                tx_type,
+                client_id: 1,
+                tx_id,
+                amount: amount.map(|a| Decimal::from_str(a).unwrap()),
+                status: TransactionStatus::OK,
+                description: None,
+                category: None,
+            })
+        };
+    let mut engine = PaymentEngine::new();
+    engine.set_compat_level(CompatLevel::V2);
+    engine
+        .import_transactions(vec![
+            transaction(TransactionType::Deposit, 1, Some("10")),
+            transaction(TransactionType::Withdrawal, 2, Some("4")),
+            transaction(TransactionType::Dispute, 2, None),
+        ])
+        .unwrap();
+    let account = engine.accounts.get(1).unwrap().unwrap();
+    assert_eq!(
+        (
+            account.funds_available,
+            account.funds_held,
+            account.funds_total
+        ),
+        (Decimal::new(6, 0), Decimal::new(4, 0), Decimal::new(10, 0))
+    );
+
+    engine
+        .import_transactions(vec![
+            transaction(TransactionType::Resolve, 2, None),
+            transaction(TransactionType::Dispute, 1, None),
+            transaction(TransactionType::Chargeback, 1, None),
+            transaction(TransactionType::Deposit, 1, Some("10")),
+            transaction(TransactionType::Deposit, 3, Some("10")),
+        ])
+        .unwrap();
+    let account = engine.accounts.get(1).unwrap().unwrap();
+    assert_eq!(
+        (
+            account.funds_available,
+            account.funds_held,
+            account.funds_total
+        ),
+        (Decimal::new(-4, 0), Decimal::new(0, 0), Decimal::new(-4, 0))
+    );
+    assert!(account.locked);
+}
+
+#[test]
+fn test_outcomes() {
+    let transaction = |tx_type, client_id, tx_id, amount: Option<i64>| Transaction {
+        tx_type,
+        client_id,
+        tx_id,
+        amount: amount.map(|a| Decimal::new(a, 0)),
+        status: TransactionStatus::OK,
+        description: None,
+        category: None,
+    };
+    let mut engine = PaymentEngine::new();
+    engine.set_compat_level(CompatLevel::V2);
+    let mut outcome = |tx_type, client_id, tx_id, amount| {
+        engine
+            .process_transaction(transaction(tx_type, client_id, tx_id, amount))
+            .unwrap()
+    };
+    use TransactionType::*;
+    assert_eq!(outcome(Deposit, 1, 1, Some(10)), TxOutcome::Applied);
+    assert_eq!(
+        outcome(Withdrawal, 1, 2, Some(20)),
+        TxOutcome::DeclinedInsufficientFunds
+    );
+    assert_eq!(
+        outcome(Deposit, 1, 1, Some(5)),
+        TxOutcome::IgnoredDuplicateTx
+    );
+    assert_eq!(outcome(Dispute, 1, 9, None), TxOutcome::IgnoredUnknownTx);
+    assert_eq!(outcome(Dispute, 2, 1, None), TxOutcome::IgnoredWrongClient);
+    assert_eq!(outcome(Resolve, 1, 1, None), TxOutcome::IgnoredWrongStatus);
+    assert_eq!(outcome(Dispute, 1, 1, None), TxOutcome::Applied);
+    assert_eq!(outcome(Chargeback, 1, 1, None), TxOutcome::Applied);
+    assert_eq!(outcome(Deposit, 1, 3, Some(1)), TxOutcome::RejectedLocked);
+}
+
+// Well formed but otherwise arbitrary inputs: few clients and disputes of
+// earlier tx ids so plenty of them hit, and plenty don't
+#[cfg(test)]
+fn arbitrary_transactions() -> impl proptest::strategy::Strategy<Value = Vec<Transaction>> {
+    use proptest::prelude::*;
+    prop::collection::vec((0u8..5, 1u16..4, any::<u32>(), 1i64..100_000_000), 0..300).prop_map(
+        |ops| {
+            ops.into_iter()
+                .enumerate()
+                .map(|(i, (kind, client_id, tx_ref, amount))| {
+                    let i = i as TransactionId;
+                    let (tx_type, tx_id, amount) = match kind {
+                        0 => (TransactionType::Deposit, i, Some(Decimal::new(amount, 4))),
+                        1 => (
+                            TransactionType::Withdrawal,
+                            i,
+                            Some(Decimal::new(amount, 4)),
+                        ),
+                        2 => (TransactionType::Dispute, tx_ref % (i + 1), None),
+                        3 => (TransactionType::Resolve, tx_ref % (i + 1), None),
+                        _ => (TransactionType::Chargeback, tx_ref % (i + 1), None),
+                    };
+                    Transaction {
+                        tx_type,
+                        client_id,
+                        tx_id,
+                        amount,
+                        status: TransactionStatus::OK,
+                        description: None,
+                        category: None,
+                    }
+                })
+                .collect()
+        },
+    )
+}
+
+#[cfg(test)]
+proptest::proptest! {
+    #[test]
+    fn test_engine_invariants(transactions in arbitrary_transactions(), v2: bool) {
+        let mut engine = PaymentEngine::new();
+        if v2 {
+            engine.set_compat_level(CompatLevel::V2);
+        }
+        for transaction in transactions {
+            let client_id = transaction.client_id;
+            let tx_type = transaction.tx_type;
+            let before = engine.accounts.get(client_id).unwrap();
+            let stored_before = engine.transactions.get(transaction.tx_id).unwrap();
+            engine
+                .import_transactions(std::iter::once(Ok(transaction.clone())))
+                .unwrap();
+            let after = engine.accounts.get(client_id).unwrap().unwrap();
+            let stored_after = engine.transactions.get(transaction.tx_id).unwrap();
+
+            assert_eq!(after.funds_total, after.funds_available + after.funds_held);
+            assert!(after.funds_held >= Decimal::ZERO);
+            let before = before.unwrap_or(Account {
+                client_id,
+                num_transactions: 0,
+                funds_available: Decimal::ZERO,
+                funds_held: Decimal::ZERO,
+                funds_total: Decimal::ZERO,
+                locked: false,
+            });
+            assert!(after.locked || !before.locked, "accounts never get unlocked");
+            let balances =
+                |a: &Account| (a.funds_available, a.funds_held, a.funds_total, a.locked);
+            match tx_type {
+                TransactionType::Deposit | TransactionType::Withdrawal => {
+                    assert_eq!(after.funds_held, before.funds_held);
+                }
+                // Ignored dispute, resolve or chargeback: nothing moves
+                _ if stored_before.map(|t| t.status) == stored_after.map(|t| t.status) => {
+                    assert_eq!(balances(&after), balances(&before));
+                }
+                TransactionType::Chargeback => assert!(after.locked),
+                // v2 disputes of withdrawals put the amount back in the total
+                _ if !v2 => assert_eq!(after.funds_total, before.funds_total),
+                _ => {}
+            }
+        }
+    }
+}
+
+#[test]
+fn test_import_reader_malformed() {
+    let inputs: [&[u8]; 5] = [
+        b"type,client,tx,amount\ndeposit,1,1\n",
+        b"type,client,tx,amount\ndeposit,1,1,1\ndeposit,1,1,1\n",
+        b"type,client,tx,amount\ndeposit,1,1,79228162514264337593543950335\ndeposit,1,2,1\n",
+        b"type,client,tx,amount\ndeposit,70000,1,1\n",
+        b"type,client,tx,amount\n\xff\xfe,1,1,1\n",
+    ];
+    for input in inputs.iter() {
+        let mut engine = PaymentEngine::new();
+        assert!(engine.import_reader(*input).is_err());
+    }
+}
+
+#[test]
+fn test_overdraft() {
+    let mut limits = OverdraftLimits::new(Decimal::new(10, 0));
+    limits.clients.insert(2, Decimal::new(0, 0));
+    let mut engine = PaymentEngine::new();
+    engine.set_overdraft_limits(limits);
+    engine
+        .import_reader(
+            &b"type,client,tx,amount
+deposit,1,1,5
+withdrawal,1,2,12
+withdrawal,1,3,4
+deposit,2,4,5
+withdrawal,2,5,6
+"[..],
+        )
+        .unwrap();
+    assert_eq!(engine.stats().withdrawal.declined, 2);
+    let mut exported = Vec::new();
+    engine
+        .export_accounts_with(
+            &mut exported,
+            AccountOrder::Client,
+            &[
+                AccountColumn::Client,
+                AccountColumn::Available,
+                AccountColumn::Overdrawn,
+            ],
+            &AccountFilter::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(exported).unwrap(),
+        "client,available,overdrawn\n1,-7,true\n2,5,false\n"
+    );
+}
+
+#[test]
+fn test_limits() {
+    let mut engine = PaymentEngine::new();
+    engine.set_limits(LimitsSpec {
+        max_amount: Some(Decimal::new(100, 0)),
+        lock_on_violation: true,
+        ..Default::default()
+    });
+    engine
+        .import_reader(&b"type,client,tx,amount\ndeposit,1,1,50\ndeposit,2,2,500\n"[..])
+        .unwrap();
+    assert_eq!(engine.stats().limit_violations, 1);
+    assert!(!engine.account(1).unwrap().unwrap().is_locked());
+    let account = engine.account(2).unwrap().unwrap();
+    assert!(account.is_locked());
+    assert_eq!(account.total(), Decimal::ZERO);
+}
+
+#[test]
+fn test_holds() {
+    let mut engine = PaymentEngine::new();
+    engine
+        .import_reader(
+            &b"type,client,tx,amount
+deposit,1,1,100
+hold,1,2,30
+hold,1,3,20
+hold,1,4,80
+capture,1,2,
+release,1,3,
+capture,1,3,
+dispute,1,2,
+release,2,2,
+capture,1,9,
+hold,1,5,-4
+hold,1,6,0
+capture,1,5,
+"[..],
+        )
+        .unwrap();
+    let account = engine.account(1).unwrap().unwrap();
+    assert_eq!(
+        (account.available(), account.held(), account.total()),
+        (Decimal::new(70, 0), Decimal::new(0, 0), Decimal::new(70, 0))
+    );
+    let stats = engine.stats();
+    assert_eq!(
+        (stats.hold.applied, stats.hold.declined, stats.hold.ignored),
+        (2, 1, 2)
+    );
+    assert_eq!((stats.capture.applied, stats.capture.ignored), (1, 3));
+    assert_eq!((stats.release.applied, stats.release.ignored), (1, 1));
+    assert_eq!(stats.dispute.ignored, 1);
+}
+
+#[test]
+fn test_account_creation() {
+    let input = b"type,client,tx,amount\nwithdrawal,1,1,5\ndispute,2,9,\ndeposit,3,2,1\n";
+    let clients = |policy| -> Vec<ClientId> {
+        let mut engine = PaymentEngine::new();
+        engine.set_account_creation(policy);
+        engine.import_reader(&input[..]).unwrap();
+        engine
+            .ordered_accounts(AccountOrder::Client)
+            .unwrap()
+            .map(|account| account.unwrap().client_id)
+            .collect()
+    };
+    assert_eq!(clients(AccountCreation::Any), vec![1, 2, 3]);
+    assert_eq!(clients(AccountCreation::Deposit), vec![3]);
+    assert!(clients(AccountCreation::Never).is_empty());
+
+    let mut engine = PaymentEngine::new();
+    engine.set_account_creation(AccountCreation::Deposit);
+    let withdrawal = Transaction {
+        tx_type: TransactionType::Withdrawal,
+        client_id: 4,
+        tx_id: 3,
+        amount: Some(Decimal::new(1, 0)),
+        status: TransactionStatus::OK,
+        description: None,
+        category: None,
+    };
+    assert_eq!(
+        engine.process_transaction(withdrawal).unwrap(),
+        TxOutcome::IgnoredUnknownAccount
+    );
+}
+
+#[test]
+fn test_account_accessors() {
+    let mut engine = PaymentEngine::new();
+    engine
+        .import_reader(
+            &b"type,client,tx,amount\ndeposit,7,1,2.5\ndeposit,7,2,1\ndispute,7,2,\n"[..],
+        )
+        .unwrap();
+    let account = engine.account(7).unwrap().unwrap();
+    assert_eq!(account.client_id(), 7);
+    assert_eq!(account.num_transactions(), 3);
+    assert_eq!(
+        (account.available(), account.held(), account.total()),
+        (Decimal::new(25, 1), Decimal::new(1, 0), Decimal::new(35, 1))
+    );
+    assert!(!account.is_locked());
+    assert_eq!(account.to_csv_row(), "7,2.5,1,3.5,false");
+
+    let mut exported = Vec::new();
+    engine.export_accounts(&mut exported).unwrap();
+    assert_eq!(
+        String::from_utf8(exported).unwrap(),
+        format!("client,available,held,total,locked\n{}\n", account)
+    );
+    let json = serde_json::to_string(&account).unwrap();
+    assert_eq!(serde_json::from_str::<Account>(&json).unwrap(), account);
+
+    assert_eq!(engine.len(), 1);
+    assert!(engine.account(8).unwrap().is_none());
+    let clients: Vec<ClientId> = engine
+        .iter_accounts()
+        .map(|account| account.unwrap().client_id())
+        .collect();
+    assert_eq!(clients, vec![7]);
+}
+
+#[test]
+fn test_export_order() {
+    let mut engine = PaymentEngine::new();
+    engine
+        .import_reader(
+            &b"type,client,tx,amount\ndeposit,3,1,1\ndeposit,1,2,5\ndeposit,2,3,5\ndeposit,10,4,2\n"[..],
+        )
+        .unwrap();
+    let clients = |order| -> Vec<ClientId> {
+        engine
+            .ordered_accounts(order)
+            .unwrap()
+            .map(|account| account.unwrap().client_id)
+            .collect()
+    };
+    assert_eq!(clients(AccountOrder::Client), vec![1, 2, 3, 10]);
+    assert_eq!(clients(AccountOrder::Total), vec![1, 2, 10, 3]);
+    assert_eq!(clients(AccountOrder::None).len(), 4);
+
+    let mut exported = Vec::new();
+    engine.export_accounts(&mut exported).unwrap();
+    assert_eq!(
+        String::from_utf8(exported).unwrap(),
+        "client,available,held,total,locked\n1,5,0,5,false\n2,5,0,5,false\n3,1,0,1,false\n10,2,0,2,false\n"
+    );
+
+    let mut exported = Vec::new();
+    engine
+        .export_accounts_with(
+            &mut exported,
+            AccountOrder::Client,
+            &[AccountColumn::NumTransactions, AccountColumn::Client],
+            &AccountFilter::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(exported).unwrap(),
+        "num_transactions,client\n1,1\n1,2\n1,3\n1,10\n"
+    );
+}
+
+#[test]
+fn test_opening_balances() {
+    let mut engine = PaymentEngine::new();
+    engine
+        .load_opening_balances(
+            &b"client,available,held,total,locked\n1,10,5,15,false\n2,-3,0,-3,true\n"[..],
+        )
+        .unwrap();
+    engine
+        .import_reader(&b"type,client,tx,amount\ndeposit,1,1,1\nwithdrawal,1,2,20\n"[..])
+        .unwrap();
+    let mut exported = Vec::new();
+    engine.export_accounts(&mut exported).unwrap();
+    assert_eq!(
+        String::from_utf8(exported).unwrap(),
+        "client,available,held,total,locked\n1,11,5,16,false\n2,-3,0,-3,true\n"
+    );
+
+    let mut engine = PaymentEngine::new();
+    assert!(engine
+        .load_opening_balances(&b"client,available,held,total\n1,10,5,10\n"[..])
+        .is_err());
+    assert!(engine
+        .load_opening_balances(&b"client,available\n1,10\n1,5\n"[..])
+        .is_err());
+}
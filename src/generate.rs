@@ -0,0 +1,201 @@
+use crate::transaction::{ClientId, TransactionId};
+use std::io::{self, BufWriter, Write};
+
+/// What `generate` should produce.
+#[derive(Debug, Clone)]
+pub struct GeneratorConfig {
+    pub rows: u64,
+    pub clients: ClientId,
+    /// Roughly the fraction of rows that are disputes, with about as many
+    /// resolves and chargebacks following them
+    pub dispute_rate: f64,
+    pub seed: u64,
+    /// Mix in rows the engine has to reject or ignore: overdrafts, disputes
+    /// of unknown or someone else's transactions, resolves without a dispute,
+    /// padding around the fields...
+    pub adversarial: bool,
+}
+
+// Disputes are picked among the last few deposits, like real ones tend to be
+const RECENT_DEPOSITS: usize = 4096;
+
+/*
+splitmix64, so the same seed gives the same file on every platform and with
+every version of everything, which a general purpose RNG crate doesn't promise.
+*/
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    fn chance(&mut self, p: f64) -> bool {
+        ((self.next() >> 11) as f64 / (1u64 << 53) as f64) < p
+    }
+}
+
+struct Generator<W: Write> {
+    writer: W,
+    rng: Rng,
+    config: GeneratorConfig,
+    next_tx: TransactionId,
+    recent: Vec<(ClientId, TransactionId)>,
+    disputed: Vec<(ClientId, TransactionId)>,
+}
+
+impl<W: Write> Generator<W> {
+    fn client(&mut self) -> ClientId {
+        1 + self.rng.below(u64::from(self.config.clients)) as ClientId
+    }
+
+    fn amount(&mut self, max: u64) -> String {
+        format!("{}.{:04}", self.rng.below(max), self.rng.below(10000))
+    }
+
+    fn row(
+        &mut self,
+        tx_type: &str,
+        client: ClientId,
+        tx: TransactionId,
+        amount: &str,
+    ) -> io::Result<()> {
+        writeln!(self.writer, "{},{},{},{}", tx_type, client, tx, amount)
+    }
+
+    fn new_tx(&mut self) -> TransactionId {
+        self.next_tx += 1;
+        self.next_tx
+    }
+
+    fn deposit(&mut self) -> io::Result<()> {
+        let (client, tx, amount) = (self.client(), self.new_tx(), self.amount(1000));
+        if self.recent.len() == RECENT_DEPOSITS {
+            let index = self.rng.below(RECENT_DEPOSITS as u64) as usize;
+            self.recent.swap_remove(index);
+        }
+        self.recent.push((client, tx));
+        self.row("deposit", client, tx, &amount)
+    }
+
+    fn withdrawal(&mut self) -> io::Result<()> {
+        let (client, tx, amount) = (self.client(), self.new_tx(), self.amount(100));
+        self.row("withdrawal", client, tx, &amount)
+    }
+
+    fn dispute(&mut self) -> io::Result<()> {
+        let index = self.rng.below(self.recent.len() as u64) as usize;
+        let (client, tx) = self.recent.swap_remove(index);
+        self.disputed.push((client, tx));
+        self.row("dispute", client, tx, "")
+    }
+
+    fn settle(&mut self) -> io::Result<()> {
+        let index = self.rng.below(self.disputed.len() as u64) as usize;
+        let (client, tx) = self.disputed.swap_remove(index);
+        if self.rng.chance(0.7) {
+            self.row("resolve", client, tx, "")
+        } else {
+            self.row("chargeback", client, tx, "")
+        }
+    }
+
+    fn adversarial(&mut self) -> io::Result<()> {
+        let client = self.client();
+        match self.rng.below(5) {
+            0 => {
+                let tx = self.new_tx();
+                self.row("withdrawal", client, tx, "1000000.0")
+            }
+            // tx ids start at 1
+            1 => self.row("dispute", client, 0, ""),
+            2 if !self.recent.is_empty() => {
+                let (owner, tx) = self.recent[self.rng.below(self.recent.len() as u64) as usize];
+                // Someone else's, when there's someone else
+                let other = owner % self.config.clients + 1;
+                self.row("dispute", other, tx, "")
+            }
+            3 if !self.recent.is_empty() => {
+                let (owner, tx) = self.recent[self.rng.below(self.recent.len() as u64) as usize];
+                self.row("resolve", owner, tx, "")
+            }
+            _ => {
+                let (tx, amount) = (self.new_tx(), self.amount(1000));
+                writeln!(self.writer, " deposit , {} , {} , {} ", client, tx, amount)?;
+                self.recent.push((client, tx));
+                Ok(())
+            }
+        }
+    }
+
+    fn next_row(&mut self) -> io::Result<()> {
+        let rate = self.config.dispute_rate;
+        if self.config.adversarial && self.rng.chance(0.05) {
+            self.adversarial()
+        } else if !self.recent.is_empty() && self.rng.chance(rate) {
+            self.dispute()
+        } else if !self.disputed.is_empty() && self.rng.chance(rate) {
+            self.settle()
+        } else if self.rng.chance(0.7) {
+            self.deposit()
+        } else {
+            self.withdrawal()
+        }
+    }
+}
+
+/// Writes a reproducible CSV input: same config, same file.
+pub fn generate<W: Write>(writer: W, config: &GeneratorConfig) -> io::Result<()> {
+    if config.clients == 0 || config.rows > u64::from(TransactionId::MAX) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Need at least one client and no more rows than tx ids",
+        ));
+    }
+    let mut generator = Generator {
+        writer: BufWriter::new(writer),
+        rng: Rng(config.seed),
+        config: config.clone(),
+        next_tx: 0,
+        recent: Vec::with_capacity(RECENT_DEPOSITS),
+        disputed: Vec::new(),
+    };
+    writeln!(generator.writer, "type,client,tx,amount")?;
+    for _ in 0..config.rows {
+        generator.next_row()?;
+    }
+    generator.writer.flush()
+}
+
+#[test]
+fn test_generate() {
+    let mut config = GeneratorConfig {
+        rows: 5000,
+        clients: 50,
+        dispute_rate: 0.05,
+        seed: 42,
+        adversarial: false,
+    };
+    let mut first = Vec::new();
+    generate(&mut first, &config).unwrap();
+    let mut second = Vec::new();
+    generate(&mut second, &config).unwrap();
+    assert_eq!(first, second);
+    assert_eq!(first.split(|b| *b == b'\n').count(), 5002);
+
+    config.adversarial = true;
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("adversarial.csv");
+    generate(std::fs::File::create(&path).unwrap(), &config).unwrap();
+    let mut engine = crate::PaymentEngine::new();
+    engine.import_csv(path.to_str().unwrap()).unwrap();
+    assert_eq!(engine.accounts.len(), 50);
+}
@@ -0,0 +1,248 @@
+use crate::concurrent::ConcurrentPaymentEngine;
+use crate::transaction::{ClientId, Transaction, TxOutcome};
+use crate::PaymentEngine;
+use std::convert::TryFrom;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status, Streaming};
+use tracing::{debug, info};
+
+/*
+Online mode over gRPC, see the Payments service in proto/transactions.proto.
+Process is a bidirectional stream: one Outcome comes back for each
+Transaction sent, in the same order. GetAccount is the same as
+GET /accounts/<id> in server.rs.
+*/
+
+/// Hand-written like the ones in input/protobuf.rs; the service code is
+/// generated from them by build.rs.
+pub mod pb {
+    pub use crate::input::protobuf::pb::{Transaction, TransactionType};
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+    #[repr(i32)]
+    pub enum Result {
+        Applied = 0,
+        Declined = 1,
+        Ignored = 2,
+        Failed = 3,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Outcome {
+        #[prost(uint32, tag = "1")]
+        pub tx: u32,
+        #[prost(enumeration = "Result", tag = "2")]
+        pub result: i32,
+        #[prost(string, tag = "3")]
+        pub reason: String,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct AccountRequest {
+        #[prost(uint32, tag = "1")]
+        pub client: u32,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Account {
+        #[prost(uint32, tag = "1")]
+        pub client: u32,
+        #[prost(string, tag = "2")]
+        pub available: String,
+        #[prost(string, tag = "3")]
+        pub held: String,
+        #[prost(string, tag = "4")]
+        pub total: String,
+        #[prost(bool, tag = "5")]
+        pub locked: bool,
+    }
+
+    include!(concat!(env!("OUT_DIR"), "/payments.Payments.rs"));
+}
+
+use pb::payments_server::{Payments, PaymentsServer};
+
+pub struct GrpcService {
+    engine: Arc<ConcurrentPaymentEngine>,
+}
+
+impl GrpcService {
+    pub fn new(engine: PaymentEngine) -> GrpcService {
+        GrpcService {
+            engine: Arc::new(engine.into()),
+        }
+    }
+
+    /// With the engine split in `shards`, see concurrent.rs
+    pub fn with_shards(
+        engine: PaymentEngine,
+        shards: usize,
+    ) -> Result<GrpcService, Box<dyn std::error::Error>> {
+        Ok(GrpcService {
+            engine: Arc::new(ConcurrentPaymentEngine::new(engine, shards)?),
+        })
+    }
+}
+
+fn process(engine: &ConcurrentPaymentEngine, message: pb::Transaction) -> pb::Outcome {
+    let tx = message.tx;
+    let result = Transaction::try_from(message)
+        .map_err(|e| e.into())
+        .and_then(|transaction| engine.process_transaction(transaction));
+    let (result, reason) = match result {
+        Ok(TxOutcome::Applied) => (pb::Result::Applied, String::new()),
+        Ok(outcome @ TxOutcome::DeclinedInsufficientFunds) => (
+            pb::Result::Declined,
+            outcome.reason().unwrap_or_default().to_string(),
+        ),
+        Ok(outcome) => (
+            pb::Result::Ignored,
+            outcome.reason().unwrap_or_default().to_string(),
+        ),
+        Err(e) => (pb::Result::Failed, e.to_string()),
+    };
+    pb::Outcome {
+        tx,
+        result: result as i32,
+        reason,
+    }
+}
+
+#[tonic::async_trait]
+impl Payments for GrpcService {
+    type ProcessStream = ReceiverStream<Result<pb::Outcome, Status>>;
+
+    async fn process(
+        &self,
+        request: Request<Streaming<pb::Transaction>>,
+    ) -> Result<Response<Self::ProcessStream>, Status> {
+        let mut transactions = request.into_inner();
+        let engine = self.engine.clone();
+        let (sender, receiver) = mpsc::channel(128);
+        tokio::spawn(async move {
+            loop {
+                let outcome = match transactions.message().await {
+                    Ok(Some(message)) => Ok(process(&engine, message)),
+                    Ok(None) => break,
+                    Err(status) => Err(status),
+                };
+                let failed = outcome.is_err();
+                if sender.send(outcome).await.is_err() || failed {
+                    break; // The client went away, or its stream broke
+                }
+            }
+            debug!("Stream finished");
+        });
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+
+    async fn get_account(
+        &self,
+        request: Request<pb::AccountRequest>,
+    ) -> Result<Response<pb::Account>, Status> {
+        let client_id = ClientId::try_from(request.into_inner().client)
+            .map_err(|_| Status::invalid_argument("Client id out of range"))?;
+        match self.engine.account(client_id) {
+            Ok(Some(account)) => Ok(Response::new(pb::Account {
+                client: u32::from(account.client_id),
+                available: account.funds_available.to_string(),
+                held: account.funds_held.to_string(),
+                total: account.funds_total.to_string(),
+                locked: account.locked,
+            })),
+            Ok(None) => Err(Status::not_found("No such account")),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }
+    }
+}
+
+/// Serves until the server fails. Blocks, the async runtime is created here.
+pub fn serve(
+    addr: SocketAddr,
+    engine: PaymentEngine,
+    shards: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let service = GrpcService::with_shards(engine, shards)?;
+    let runtime = tokio::runtime::Runtime::new()?;
+    runtime.block_on(async {
+        info!("Listening on {} (gRPC)", addr);
+        tonic::transport::Server::builder()
+            .add_service(PaymentsServer::new(service))
+            .serve(addr)
+            .await
+    })?;
+    Ok(())
+}
+
+#[test]
+fn test_grpc() {
+    use pb::payments_client::PaymentsClient;
+    use tokio_stream::wrappers::TcpListenerStream;
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(async {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(PaymentsServer::new(GrpcService::new(PaymentEngine::new())))
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+        let channel = tonic::transport::Endpoint::from_shared(format!("http://{}", addr))
+            .unwrap()
+            .connect()
+            .await
+            .unwrap();
+        let mut client = PaymentsClient::new(channel);
+
+        let message = |r#type: pb::TransactionType, tx: u32, amount: &str| pb::Transaction {
+            r#type: r#type as i32,
+            client: 1,
+            tx,
+            amount: amount.to_string(),
+            description: String::new(),
+            category: String::new(),
+        };
+        let transactions = vec![
+            message(pb::TransactionType::Deposit, 1, "10.5"),
+            message(pb::TransactionType::Withdrawal, 2, "20"),
+            message(pb::TransactionType::Dispute, 1, ""),
+            message(pb::TransactionType::Deposit, 3, "1.0.0"),
+            message(pb::TransactionType::Resolve, 4, ""),
+        ];
+        let mut outcomes = client
+            .process(tokio_stream::iter(transactions))
+            .await
+            .unwrap()
+            .into_inner();
+        let mut results = Vec::new();
+        while let Some(outcome) = outcomes.message().await.unwrap() {
+            results.push((outcome.tx, outcome.result()));
+        }
+        assert_eq!(
+            results,
+            vec![
+                (1, pb::Result::Applied),
+                (2, pb::Result::Declined),
+                (1, pb::Result::Applied),
+                (3, pb::Result::Failed),
+                (4, pb::Result::Ignored),
+            ]
+        );
+
+        let account = client
+            .get_account(pb::AccountRequest { client: 1 })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(
+            (account.held.as_str(), account.total.as_str()),
+            ("10.5", "10.5")
+        );
+        let missing = client.get_account(pb::AccountRequest { client: 2 }).await;
+        assert_eq!(missing.unwrap_err().code(), tonic::Code::NotFound);
+    });
+}
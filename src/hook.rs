@@ -0,0 +1,235 @@
+use crate::pipeline::{JournalEntry, SourceCounter};
+use crate::transaction;
+use crate::PaymentEngine;
+use serde::Deserialize;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::process;
+use tracing::debug;
+
+/// Runs after the engine is done, for custom reports that don't belong in
+/// the core. Gets the final state, the journal (every transaction that went
+/// into the engine, in order, after the transforms, along with its source) and
+/// the number of transactions read from each source.
+pub trait Hook {
+    fn run(
+        &mut self,
+        engine: &PaymentEngine,
+        journal: &[JournalEntry],
+        sources: &[SourceCounter],
+    ) -> Result<(), Box<dyn Error>>;
+}
+
+/// Runs an external program. The accounts and the journal are written to
+/// temporary CSV files whose paths are passed in the PAYMENTS_ACCOUNTS and
+/// PAYMENTS_JOURNAL environment variables; the journal has the same format
+/// as the input plus a `source` column, then the description and category.
+pub struct CommandHook {
+    pub command: PathBuf,
+    pub args: Vec<String>,
+}
+
+pub fn write_journal<W: Write>(writer: W, journal: &[JournalEntry]) -> Result<(), Box<dyn Error>> {
+    let mut writer = BufWriter::new(writer);
+    writeln!(writer, "type,client,tx,amount,source,description,category")?;
+    for JournalEntry {
+        source,
+        transaction,
+    } in journal
+    {
+        write!(
+            writer,
+            "{},{},{},",
+            transaction.tx_type, transaction.client_id, transaction.tx_id
+        )?;
+        if let Some(amount) = transaction.amount {
+            write!(writer, "{}", amount)?;
+        }
+        write!(writer, ",{},", source)?;
+        if let Some(description) = &transaction.description {
+            write!(writer, "{}", transaction::quoted(description))?;
+        }
+        write!(writer, ",")?;
+        if let Some(category) = &transaction.category {
+            write!(writer, "{}", transaction::quoted(category))?;
+        }
+        writeln!(writer)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+impl CommandHook {
+    fn run_command(&self, accounts: &PathBuf, journal: &PathBuf) -> Result<(), Box<dyn Error>> {
+        debug!("Running hook {:?} {:?}", self.command, self.args);
+        let status = process::Command::new(&self.command)
+            .args(&self.args)
+            .env("PAYMENTS_ACCOUNTS", accounts)
+            .env("PAYMENTS_JOURNAL", journal)
+            .status()?;
+        if !status.success() {
+            return Err(format!("{:?} failed: {}", self.command, status).into());
+        }
+        Ok(())
+    }
+}
+
+impl Hook for CommandHook {
+    fn run(
+        &mut self,
+        engine: &PaymentEngine,
+        journal: &[JournalEntry],
+        _sources: &[SourceCounter],
+    ) -> Result<(), Box<dyn Error>> {
+        let dir = std::env::temp_dir();
+        let accounts_path = dir.join(format!("payments-{}-accounts.csv", process::id()));
+        let journal_path = dir.join(format!("payments-{}-journal.csv", process::id()));
+        let result = engine
+            .export_accounts(File::create(&accounts_path)?)
+            .map_err(|e| e.into())
+            .and_then(|_| write_journal(File::create(&journal_path)?, journal))
+            .and_then(|_| self.run_command(&accounts_path, &journal_path));
+        let _ = fs::remove_file(accounts_path);
+        let _ = fs::remove_file(journal_path);
+        result
+    }
+}
+
+/// How hooks are written in a pipeline definition. Hooks written in Rust are
+/// registered with Pipeline::run_with_hooks instead.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum HookSpec {
+    Command {
+        command: PathBuf,
+        #[serde(default)]
+        args: Vec<String>,
+    },
+}
+
+impl HookSpec {
+    pub fn build(&self) -> Box<dyn Hook> {
+        match self {
+            HookSpec::Command { command, args } => Box::new(CommandHook {
+                command: command.clone(),
+                args: args.clone(),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+struct CountingHook {
+    journal: usize,
+    accounts: usize,
+    sources: Vec<u64>,
+}
+
+#[cfg(test)]
+impl Hook for CountingHook {
+    fn run(
+        &mut self,
+        engine: &PaymentEngine,
+        journal: &[JournalEntry],
+        sources: &[SourceCounter],
+    ) -> Result<(), Box<dyn Error>> {
+        self.journal = journal.len();
+        self.accounts = engine.len();
+        self.sources = sources.iter().map(|s| s.transactions).collect();
+        Ok(())
+    }
+}
+
+#[test]
+fn test_hooks() {
+    use crate::input::InputFormat;
+    use crate::pipeline::{Pipeline, SourceSpec};
+    use std::sync::{Arc, Mutex};
+
+    // The hook is moved into the pipeline, so report back through a mutex
+    struct Shared(Arc<Mutex<CountingHook>>);
+    impl Hook for Shared {
+        fn run(
+            &mut self,
+            engine: &PaymentEngine,
+            journal: &[JournalEntry],
+            sources: &[SourceCounter],
+        ) -> Result<(), Box<dyn Error>> {
+            self.0.lock().unwrap().run(engine, journal, sources)
+        }
+    }
+
+    let dir = tempfile::tempdir().unwrap();
+    let other = dir.path().join("other.csv");
+    fs::write(
+        &other,
+        "type,client,tx,amount,description,category
+deposit,3,10,5.0,\"Refund, \"\"order\"\" 7\",refund
+withdrawal,3,11,1,,
+",
+    )
+    .unwrap();
+    let pipeline = Pipeline {
+        sources: vec![
+            SourceSpec {
+                id: Some("small".to_string()),
+                path: PathBuf::from("test_files/input.csv"),
+                format: InputFormat::Csv,
+                dialect: Default::default(),
+                layout: None,
+                client: None,
+                sheet: None,
+            },
+            SourceSpec {
+                id: None,
+                path: other.clone(),
+                format: InputFormat::Csv,
+                dialect: Default::default(),
+                layout: None,
+                client: None,
+                sheet: None,
+            },
+        ],
+        transforms: Vec::new(),
+        engine: Default::default(),
+        outputs: Vec::new(),
+        hooks: vec![HookSpec::Command {
+            command: PathBuf::from("sh"),
+            args: vec![
+                "-c".to_string(),
+                format!("cp $PAYMENTS_JOURNAL {:?}", dir.path().join("journal.csv")),
+            ],
+        }],
+        concurrent: true,
+        metrics: None,
+        checkpoint: None,
+        progress: false,
+        timings: false,
+    };
+    let counts = Arc::new(Mutex::new(CountingHook {
+        journal: 0,
+        accounts: 0,
+        sources: Vec::new(),
+    }));
+    pipeline
+        .run_with_hooks(vec![Box::new(Shared(counts.clone()))])
+        .unwrap();
+    assert_eq!(counts.lock().unwrap().journal, 7);
+    assert_eq!(counts.lock().unwrap().accounts, 3);
+    assert_eq!(counts.lock().unwrap().sources, vec![5, 2]);
+    // The sources are interleaved, but each one keeps its order
+    let journal = fs::read_to_string(dir.path().join("journal.csv")).unwrap();
+    let small: Vec<&str> = journal
+        .lines()
+        .filter(|l| l.ends_with(",small,,"))
+        .collect();
+    assert_eq!(small[0], "deposit,1,1,1.0,small,,");
+    assert_eq!(small[4], "withdrawal,2,5,3.0,small,,");
+    assert!(journal.contains(&format!("withdrawal,3,11,1,{},,\n", other.display())));
+    assert!(journal.contains(&format!(
+        "deposit,3,10,5.0,{},\"Refund, \"\"order\"\" 7\",refund\n",
+        other.display()
+    )));
+}
@@ -0,0 +1,216 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+use tracing::warn;
+
+/*
+Just enough HTTP/1.1 for the built-in endpoints: one request per connection,
+bodies only with Content-Length. Anything that needs more than that belongs
+behind a real server. Same for post, the client side of webhooks: plain
+http:// only, put a proxy in front of anything that needs TLS.
+
+Requests are capped (MAX_LINE, MAX_HEADERS, MAX_BODY) and the servers stop
+waiting for a client after READ_TIMEOUT, so a client can't make them buffer
+without end or hold a connection forever. Every connection gets a thread, up
+to a number of them at once, and a failed accept (out of file descriptors,
+say) is logged and retried after ACCEPT_BACKOFF instead of stopping the
+server.
+*/
+const MAX_BODY: usize = 64 * 1024 * 1024;
+const MAX_LINE: usize = 8 * 1024;
+const MAX_HEADERS: usize = 100;
+pub const READ_TIMEOUT: Duration = Duration::from_secs(30);
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+#[derive(Debug)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub body: Vec<u8>,
+}
+
+fn bad_request(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+// A line of the request head, up to MAX_LINE bytes
+fn read_line<R: BufRead>(reader: &mut R) -> io::Result<String> {
+    let mut line = String::new();
+    reader.take(MAX_LINE as u64 + 1).read_line(&mut line)?;
+    match line.len() > MAX_LINE {
+        true => Err(bad_request("Line too long")),
+        false => Ok(line),
+    }
+}
+
+pub fn read_request<R: BufRead>(reader: &mut R) -> io::Result<Request> {
+    let line = read_line(reader)?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next().ok_or_else(|| bad_request("Empty request"))?;
+    let path = parts.next().ok_or_else(|| bad_request("No path"))?;
+    let (method, path) = (method.to_string(), path.to_string());
+
+    let mut length = 0;
+    for headers in 0.. {
+        let header = read_line(reader)?;
+        if header.trim().is_empty() {
+            break;
+        }
+        if headers == MAX_HEADERS {
+            return Err(bad_request("Too many headers"));
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                length = value
+                    .trim()
+                    .parse()
+                    .map_err(|_| bad_request("Bad Content-Length"))?;
+            }
+        }
+    }
+    if length > MAX_BODY {
+        return Err(bad_request("Body too big"));
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    Ok(Request { method, path, body })
+}
+
+pub fn write_response<W: Write>(
+    writer: &mut W,
+    status: u16,
+    content_type: &str,
+    body: &[u8],
+) -> io::Result<()> {
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Internal Server Error",
+    };
+    write!(
+        writer,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        reason,
+        content_type,
+        body.len()
+    )?;
+    writer.write_all(body)?;
+    writer.flush()
+}
+
+// How many connections are being handled, for serve_connections to wait on
+struct Slots {
+    taken: Mutex<usize>,
+    freed: Condvar,
+}
+
+struct Slot(Arc<Slots>);
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        let mut taken = self.0.taken.lock().unwrap_or_else(|e| e.into_inner());
+        *taken -= 1;
+        self.0.freed.notify_one();
+    }
+}
+
+/// Accepts connections for as long as the process lives and hands each to
+/// `handle` on a thread of its own, with at most `max_connections` of them
+/// at once. A connection over that waits in the listen backlog.
+pub fn serve_connections<F>(listener: TcpListener, max_connections: usize, handle: F)
+where
+    F: Fn(TcpStream) + Send + Sync + 'static,
+{
+    let handle = Arc::new(handle);
+    let slots = Arc::new(Slots {
+        taken: Mutex::new(0),
+        freed: Condvar::new(),
+    });
+    loop {
+        {
+            let mut taken = slots.taken.lock().unwrap_or_else(|e| e.into_inner());
+            while *taken >= max_connections.max(1) {
+                taken = slots.freed.wait(taken).unwrap_or_else(|e| e.into_inner());
+            }
+            *taken += 1;
+        }
+        let slot = Slot(slots.clone());
+        let stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                warn!("Accepting a connection failed: {}", e);
+                drop(slot);
+                thread::sleep(ACCEPT_BACKOFF);
+                continue;
+            }
+        };
+        let handle = handle.clone();
+        thread::spawn(move || {
+            let _slot = slot;
+            handle(stream);
+        });
+    }
+}
+
+/// POSTs `body` to an http:// URL and returns the response status.
+pub fn post(url: &str, content_type: &str, body: &[u8], timeout: Duration) -> io::Result<u16> {
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| bad_request("Only http:// URLs are supported"))?;
+    let (authority, path) = match rest.find('/') {
+        Some(slash) => rest.split_at(slash),
+        None => (rest, "/"),
+    };
+    let addr = match authority.contains(':') {
+        true => authority.to_string(),
+        false => format!("{}:80", authority),
+    };
+    let addr = addr
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| bad_request("Host not found"))?;
+    let mut stream = TcpStream::connect_timeout(&addr, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    write!(
+        stream,
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        path,
+        authority,
+        content_type,
+        body.len()
+    )?;
+    stream.write_all(body)?;
+    stream.flush()?;
+    let mut status = String::new();
+    BufReader::new(&stream).read_line(&mut status)?;
+    status
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| bad_request("Bad response"))
+}
+
+#[test]
+fn test_read_request() {
+    let read = |request: &str| read_request(&mut request.as_bytes()).map(|r| r.body);
+    let body = read("POST /transactions HTTP/1.1\r\nContent-Length: 2\r\n\r\n{}").unwrap();
+    assert_eq!(body, b"{}");
+    let long = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_LINE));
+    assert_eq!(read(&long).unwrap_err().to_string(), "Line too long");
+    let many = format!(
+        "GET / HTTP/1.1\r\n{}\r\n",
+        "X-A: b\r\n".repeat(MAX_HEADERS + 1)
+    );
+    assert_eq!(read(&many).unwrap_err().to_string(), "Too many headers");
+    assert!(read(&format!(
+        "GET / HTTP/1.1\r\n{}\r\n",
+        "X-A: b\r\n".repeat(MAX_HEADERS)
+    ))
+    .is_ok());
+}
@@ -0,0 +1,98 @@
+use serde::Deserialize;
+
+/// How amounts are written when it's not plain `1234.56`: `1.234,56`,
+/// `1,234.56`, `€ 12,50`... They're rewritten into the plain form before
+/// parsing. Thousands separators have to be in the right places, so that
+/// `1,234.56` read as `1.234,56` is an error rather than 1.23456.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AmountFormat {
+    pub decimal_separator: char,
+    pub thousands_separator: Option<char>,
+    /// Removed wherever they are, e.g. $, € or EUR
+    pub currency_symbols: Vec<String>,
+}
+
+impl Default for AmountFormat {
+    fn default() -> Self {
+        AmountFormat {
+            decimal_separator: '.',
+            thousands_separator: None,
+            currency_symbols: Vec::new(),
+        }
+    }
+}
+
+// 1 to 3 digits, then groups of exactly 3
+fn grouped(digits: &str, separator: char) -> bool {
+    let mut groups = digits.split(separator);
+    let first = groups.next().unwrap_or_default();
+    (1..=3).contains(&first.len()) && groups.all(|group| group.len() == 3)
+}
+
+impl AmountFormat {
+    pub fn is_plain(&self) -> bool {
+        *self == AmountFormat::default()
+    }
+
+    pub fn normalize(&self, amount: &str) -> Result<String, &'static str> {
+        let mut amount = amount.to_string();
+        for symbol in &self.currency_symbols {
+            amount = amount.replace(symbol.as_str(), "");
+        }
+        let amount = amount.trim();
+        let (sign, amount) = match amount.strip_prefix('-') {
+            Some(rest) => ("-", rest.trim_start()),
+            None => ("", amount),
+        };
+        let (integer, fraction) = match amount.split_once(self.decimal_separator) {
+            Some((integer, fraction)) => (integer, Some(fraction)),
+            None => (amount, None),
+        };
+        let integer = match self.thousands_separator {
+            Some(separator) if integer.contains(separator) => {
+                if !grouped(integer, separator) {
+                    return Err("Misplaced thousands separator");
+                }
+                integer.replace(separator, "")
+            }
+            _ => integer.to_string(),
+        };
+        let fraction = fraction.unwrap_or_default();
+        if !fraction.bytes().all(|c| c.is_ascii_digit())
+            || !integer.bytes().all(|c| c.is_ascii_digit())
+        {
+            return Err("Invalid amount");
+        }
+        Ok(match fraction {
+            "" => format!("{}{}", sign, integer),
+            _ => format!("{}{}.{}", sign, integer, fraction),
+        })
+    }
+}
+
+#[test]
+fn test_amount_format() {
+    let european = AmountFormat {
+        decimal_separator: ',',
+        thousands_separator: Some('.'),
+        currency_symbols: vec!["€".to_string(), "EUR".to_string()],
+    };
+    assert_eq!(european.normalize("1.234,56").unwrap(), "1234.56");
+    assert_eq!(european.normalize("€ 12,5").unwrap(), "12.5");
+    assert_eq!(european.normalize("-1.000.000 EUR").unwrap(), "-1000000");
+    assert_eq!(european.normalize("234,0001").unwrap(), "234.0001");
+    assert!(european.normalize("1,234.56").is_err());
+    assert!(european.normalize("12.34").is_err());
+    assert!(european.normalize("1,2,3").is_err());
+
+    let american = AmountFormat {
+        thousands_separator: Some(','),
+        currency_symbols: vec!["$".to_string()],
+        ..Default::default()
+    };
+    assert_eq!(american.normalize("$1,234.56").unwrap(), "1234.56");
+    assert!(american.normalize("1.234,56").is_err());
+    assert!(AmountFormat::default().is_plain());
+    assert!(!american.is_plain());
+}
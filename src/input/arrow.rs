@@ -0,0 +1,215 @@
+use crate::input::InputFormat;
+use crate::transaction::{Transaction, TransactionStatus, TransactionType};
+use arrow_array::cast::AsArray;
+use arrow_array::types::{Decimal128Type, UInt16Type, UInt32Type};
+use arrow_array::{Array, ArrayRef, RecordBatch, StringArray};
+use arrow_cast::cast;
+use arrow_schema::{ArrowError, DataType};
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use rust_decimal::Decimal;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fs::File;
+use std::str::FromStr;
+
+/*
+Parquet and Arrow IPC files with columns named type, client, tx and amount,
+in any order, others ignored (e.g. the ledger written by output/parquet.rs).
+The integer columns can be of any integer type and the amount a decimal or a
+string; anything else is cast to what we need. A missing amount column is
+no amount for any row.
+*/
+
+type Row = Result<Transaction, Box<dyn Error>>;
+type Amount = Result<Option<Decimal>, &'static str>;
+
+pub type Transactions = Box<dyn Iterator<Item = Row>>;
+
+pub fn open(filename: &str, format: InputFormat) -> Result<Transactions, Box<dyn Error>> {
+    let file = File::open(filename)?;
+    let batches: Box<dyn Iterator<Item = Result<RecordBatch, ArrowError>>> = match format {
+        InputFormat::Parquet => Box::new(ParquetRecordBatchReaderBuilder::try_new(file)?.build()?),
+        InputFormat::Arrow => Box::new(arrow_ipc::reader::FileReader::try_new(file, None)?),
+        _ => return Err("Not a columnar format".into()),
+    };
+    Ok(Box::new(batches.flat_map(|batch| {
+        match batch
+            .map_err(|e| e.into())
+            .and_then(|batch| transactions(&batch))
+        {
+            Ok(transactions) => transactions,
+            Err(e) => vec![Err(e)],
+        }
+    })))
+}
+
+fn column(
+    batch: &RecordBatch,
+    name: &str,
+    data_type: &DataType,
+) -> Result<ArrayRef, Box<dyn Error>> {
+    let column = batch
+        .column_by_name(name)
+        .ok_or_else(|| format!("No {} column", name))?;
+    Ok(cast(column, data_type)?)
+}
+
+fn amounts(batch: &RecordBatch) -> Result<Vec<Amount>, Box<dyn Error>> {
+    let column = match batch.column_by_name("amount") {
+        Some(column) => column,
+        None => return Ok(vec![Ok(None); batch.num_rows()]),
+    };
+    if let DataType::Decimal128(_, scale) = column.data_type() {
+        let scale = u32::try_from(*scale).map_err(|_| "Negative amount scale")?;
+        let decimals = column.as_primitive::<Decimal128Type>();
+        return Ok(decimals
+            .iter()
+            .map(|value| match value {
+                Some(value) => Decimal::try_from_i128_with_scale(value, scale)
+                    .map(Some)
+                    .map_err(|_| "Invalid amount"),
+                None => Ok(None),
+            })
+            .collect());
+    }
+    let strings = cast(column, &DataType::Utf8)?;
+    Ok(strings
+        .as_string::<i32>()
+        .iter()
+        .map(|value| match value.map(|value| value.trim()) {
+            None | Some("") => Ok(None),
+            Some(value) => Decimal::from_str(value)
+                .map(Some)
+                .map_err(|_| "Invalid amount"),
+        })
+        .collect())
+}
+
+// A whole batch at a time, column by column. A bad column fails the batch,
+// a bad value only its row.
+fn transactions(batch: &RecordBatch) -> Result<Vec<Row>, Box<dyn Error>> {
+    let types = column(batch, "type", &DataType::Utf8)?;
+    let clients = column(batch, "client", &DataType::UInt16)?;
+    let txs = column(batch, "tx", &DataType::UInt32)?;
+    let types = types.as_string::<i32>();
+    let clients = clients.as_primitive::<UInt16Type>();
+    let txs = txs.as_primitive::<UInt32Type>();
+    // Optional, like the amount
+    let text = |name| match batch.column_by_name(name) {
+        Some(column) => cast(column, &DataType::Utf8).map(Some),
+        None => Ok(None),
+    };
+    let (descriptions, categories) = (text("description")?, text("category")?);
+    let descriptions = descriptions.as_ref().map(|d| d.as_string::<i32>());
+    let categories = categories.as_ref().map(|c| c.as_string::<i32>());
+    let amounts = amounts(batch)?;
+    Ok(amounts
+        .into_iter()
+        .enumerate()
+        .map(|(row, amount)| {
+            let tx_type = types
+                .is_valid(row)
+                .then(|| types.value(row).trim())
+                .ok_or("Unknown transaction type")
+                .and_then(TransactionType::from_str)?;
+            let client_id = clients
+                .is_valid(row)
+                .then(|| clients.value(row))
+                .ok_or("Invalid client id")?;
+            let tx_id = txs
+                .is_valid(row)
+                .then(|| txs.value(row))
+                .ok_or("Invalid transaction id")?;
+            Ok(Transaction {
+                tx_type,
+                client_id,
+                tx_id,
+                amount: amount?,
+                status: TransactionStatus::OK,
+                description: text_value(descriptions, row),
+                category: text_value(categories, row),
+            })
+        })
+        .collect())
+}
+
+fn text_value(column: Option<&StringArray>, row: usize) -> Option<Box<str>> {
+    column
+        .filter(|column| column.is_valid(row))
+        .map(|column| column.value(row).trim())
+        .filter(|text| !text.is_empty())
+        .map(Box::from)
+}
+
+#[test]
+fn test_columnar_input() {
+    use crate::pipeline::JournalEntry;
+    use arrow_array::{Int64Array, StringArray};
+    use arrow_schema::{Field, Schema};
+    use std::sync::Arc;
+
+    let dir = tempfile::tempdir().unwrap();
+    let mut expected = Vec::new();
+    let mut reader = csv::Reader::from_path("test_files/a_bit_of_everything.csv").unwrap();
+    for record in reader.byte_records() {
+        expected.push(Transaction::from_byte_record(&record.unwrap()).unwrap());
+    }
+
+    // What we write ourselves
+    let path = dir.path().join("ledger.parquet");
+    let source: Arc<str> = "test".into();
+    let journal: Vec<JournalEntry> = expected
+        .iter()
+        .map(|transaction| JournalEntry {
+            source: source.clone(),
+            transaction: transaction.clone(),
+        })
+        .collect();
+    crate::output::parquet::write_ledger(&journal, File::create(&path).unwrap()).unwrap();
+    let read: Vec<Transaction> = open(path.to_str().unwrap(), InputFormat::Parquet)
+        .unwrap()
+        .map(|transaction| transaction.unwrap())
+        .collect();
+    assert_eq!(read, expected);
+
+    // Other types, in another order, with an extra column and bad rows
+    let path = dir.path().join("input.arrow");
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("amount", DataType::Utf8, true),
+        Field::new("memo", DataType::Utf8, true),
+        Field::new("tx", DataType::Int64, false),
+        Field::new("client", DataType::Int64, false),
+        Field::new("type", DataType::Utf8, false),
+    ]));
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from(vec![
+            Some("1.5"),
+            None,
+            Some("x"),
+            Some("1"),
+        ])),
+        Arc::new(StringArray::from(vec![Some("a"), None, None, None])),
+        Arc::new(Int64Array::from(vec![1, 1, 2, 3])),
+        Arc::new(Int64Array::from(vec![7, 7, 7, 70000])),
+        Arc::new(StringArray::from(vec![
+            "deposit", "dispute", "deposit", "deposit",
+        ])),
+    ];
+    let batch = RecordBatch::try_new(schema.clone(), columns).unwrap();
+    let mut writer =
+        arrow_ipc::writer::FileWriter::try_new(File::create(&path).unwrap(), &schema).unwrap();
+    writer.write(&batch).unwrap();
+    writer.finish().unwrap();
+    let read: Vec<Row> = open(path.to_str().unwrap(), InputFormat::Arrow)
+        .unwrap()
+        .collect();
+    assert_eq!(read.len(), 4);
+    let deposit = read[0].as_ref().unwrap();
+    assert_eq!(
+        (deposit.client_id, deposit.tx_id, deposit.amount),
+        (7, 1, Some(Decimal::new(15, 1)))
+    );
+    assert_eq!(read[1].as_ref().unwrap().amount, None);
+    assert!(read[2].is_err());
+    assert!(read[3].is_err());
+}
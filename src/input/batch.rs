@@ -0,0 +1,243 @@
+use crate::input::dialect::CsvReader;
+use crate::transaction::Transaction;
+use crate::PaymentEngine;
+use csv::ByteRecord;
+use std::error::Error;
+use std::io::Read;
+use std::str::FromStr;
+use tracing::warn;
+
+/*
+Vendor files that group their rows in batches, with a batch column:
+
+  type,client,tx,amount,batch
+  deposit,1,1,10,B1
+  withdrawal,1,2,2.5,B1
+  deposit,2,3,5,B2
+
+A batch is a run of consecutive rows with the same batch id, rows with a
+blank one are on their own and fail the import as usual. A batch that fails
+is rolled back as a whole, as if it wasn't in the file:
+
+  errors  a batch fails when one of its rows can't be processed at all
+          (malformed, an overflow...)
+  strict  also when one of its rows isn't applied: declined, ignored or
+          rejected
+
+The rows after the one that failed are skipped. A file that can't be read to
+the end of a batch (a truncated file, a bad trailer...) rolls it back too,
+and then fails as usual.
+
+Rolling back restores the balances and locks, the stored transactions and
+their status, the seen tx ids (so the fixed batch can be sent again in
+idempotent mode) and the double-entry ledger. Whatever watches transactions
+as they go (audit log, metrics, observers and webhooks, the dispute, lock and
+blocked client histories, the fraud limit counters) has seen the batch and
+keeps it. Needs a header, a single thread, no transforms and no write-ahead
+log, which would replay the rolled back rows.
+*/
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum BatchRollback {
+    Errors,
+    Strict,
+}
+
+impl FromStr for BatchRollback {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<BatchRollback, String> {
+        match s {
+            "errors" => Ok(BatchRollback::Errors),
+            "strict" => Ok(BatchRollback::Strict),
+            _ => Err(format!("Unknown batch rollback mode: {}", s)),
+        }
+    }
+}
+
+deserialize_from_str!(BatchRollback);
+
+// The batch being imported, and why it failed if it did
+struct OpenBatch {
+    id: Vec<u8>,
+    failure: Option<String>,
+    skipped: u64,
+}
+
+fn close(engine: &mut PaymentEngine, batch: OpenBatch) -> Result<(), Box<dyn Error>> {
+    match batch.failure {
+        None => engine.commit_batch(),
+        Some(reason) => {
+            warn!(
+                batch = %String::from_utf8_lossy(&batch.id),
+                reason = %reason,
+                skipped = batch.skipped,
+                "Batch rolled back"
+            );
+            engine.rollback_batch()?;
+        }
+    }
+    Ok(())
+}
+
+/// Feeds a CSV file with a batch column into the engine, rolling back the
+/// batches that fail. `each` gets every transaction before the engine does.
+pub fn import<R: Read>(
+    engine: &mut PaymentEngine,
+    mut reader: CsvReader<R>,
+    rollback: BatchRollback,
+    mut each: impl FnMut(&Transaction),
+) -> Result<(), Box<dyn Error>> {
+    if engine.wal().is_some() {
+        return Err("Atomic batches don't work with a write-ahead log".into());
+    }
+    let mut record = ByteRecord::new();
+    let mut open: Option<OpenBatch> = None;
+    loop {
+        let more = match reader.read_byte_record(&mut record) {
+            Ok(more) => more,
+            Err(e) => {
+                if open.is_some() {
+                    engine.rollback_batch()?;
+                }
+                return Err(e);
+            }
+        };
+        let id = match more {
+            true => reader.batch().filter(|id| !id.is_empty()),
+            false => None,
+        };
+        if let Some(batch) = open.take_if(|batch| Some(batch.id.as_slice()) != id) {
+            close(engine, batch)?;
+        }
+        if !more {
+            return Ok(());
+        }
+        if let (true, Some(id)) = (open.is_none(), id) {
+            engine.begin_batch();
+            open = Some(OpenBatch {
+                id: id.to_vec(),
+                failure: None,
+                skipped: 0,
+            });
+        }
+        let batch = match &mut open {
+            None => {
+                let transaction = Transaction::from_byte_record(&record)?;
+                each(&transaction);
+                engine.process_transaction(transaction)?;
+                continue;
+            }
+            Some(batch) if batch.failure.is_some() => {
+                batch.skipped += 1;
+                continue;
+            }
+            Some(batch) => batch,
+        };
+        batch.failure = match Transaction::from_byte_record(&record) {
+            Err(e) => Some(e.to_string()),
+            Ok(transaction) => {
+                each(&transaction);
+                match engine.process_transaction(transaction) {
+                    Err(e) => Some(e.to_string()),
+                    Ok(outcome) if rollback == BatchRollback::Strict => {
+                        outcome.reason().map(str::to_string)
+                    }
+                    Ok(_) => None,
+                }
+            }
+        };
+    }
+}
+
+#[test]
+fn test_atomic_batches() {
+    use crate::input::control::ControlRecords;
+    use crate::input::dialect::CsvDialect;
+    use rust_decimal::Decimal;
+
+    let import = |input: &[u8], rollback| {
+        let dialect = CsvDialect {
+            batches: Some(rollback),
+            ..Default::default()
+        };
+        let mut engine = PaymentEngine::new();
+        engine.set_idempotent(true);
+        engine.track_double_entry();
+        let result = engine.import_reader_with_dialect(input, &dialect);
+        (engine, result)
+    };
+    let input = b"type,client,tx,amount,batch
+deposit,1,1,10,B1
+withdrawal,1,2,2.5,B1
+deposit,2,3,5,B2
+withdrawal,2,4,50,B2
+deposit,2,5,1,B2
+deposit,1,6,1,
+deposit,1,7,1,B3
+deposit,3,8,xyz,B3
+";
+    let (engine, result) = import(input, BatchRollback::Errors);
+    result.unwrap();
+    let account = engine.account(1).unwrap().unwrap();
+    assert_eq!(account.total(), Decimal::new(85, 1));
+    assert_eq!(account.num_transactions(), 3);
+    assert_eq!(
+        engine.account(2).unwrap().unwrap().total(),
+        Decimal::new(6, 0)
+    );
+    assert_eq!(engine.account(3).unwrap(), None);
+    assert_eq!(engine.stats().rolled_back, 1);
+    assert_eq!(engine.stats().rolled_back_batches, 1);
+
+    let (engine, result) = import(input, BatchRollback::Strict);
+    result.unwrap();
+    assert_eq!(engine.account(2).unwrap(), None);
+    assert!(engine.transactions.get(3).unwrap().is_none());
+    assert_eq!(engine.stats().rolled_back, 3);
+    let ledger = engine.double_entry().unwrap();
+    assert!(ledger.postings().iter().all(|posting| posting.client == 1));
+
+    // The fixed batch goes through, it wasn't marked as seen
+    let mut engine = engine;
+    let dialect = CsvDialect {
+        batches: Some(BatchRollback::Strict),
+        ..Default::default()
+    };
+    engine
+        .import_reader_with_dialect(
+            &b"type,client,tx,amount,batch\ndeposit,2,3,5,B2\nwithdrawal,2,4,5,B2\n"[..],
+            &dialect,
+        )
+        .unwrap();
+    assert_eq!(engine.account(2).unwrap().unwrap().total(), Decimal::ZERO);
+
+    // Truncated in the middle of a batch, no trailer
+    let dialect = CsvDialect {
+        batches: Some(BatchRollback::Errors),
+        control: Some(ControlRecords::Fail),
+        ..Default::default()
+    };
+    let mut engine = PaymentEngine::new();
+    assert!(engine
+        .import_reader_with_dialect(
+            &b"type,client,tx,amount,batch
+deposit,1,1,10,B1
+trailer,,1,10,
+deposit,1,2,10,B2
+"[..],
+            &dialect,
+        )
+        .is_err());
+    assert_eq!(
+        engine.account(1).unwrap().unwrap().total(),
+        Decimal::new(10, 0)
+    );
+    // Outside batches, errors are errors
+    assert!(import(
+        b"type,client,tx,amount,batch\ndeposit,1,1,xyz,\n",
+        BatchRollback::Errors
+    )
+    .1
+    .is_err());
+}
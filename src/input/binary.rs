@@ -0,0 +1,161 @@
+use crate::input::json::JsonTransaction;
+use crate::input::InputFormat;
+use crate::transaction::Transaction;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::io::{ErrorKind, Read};
+
+/*
+MessagePack and CBOR input, for producers that already emit compact binary
+events: a stream of records, each prefixed with its length in bytes as a
+big-endian u32. A record is a transaction as in JSON input (see json.rs), a
+map:
+
+  {"type": "deposit", "client": 1, "tx": 1, "amount": "1.5"}
+
+with the same optional description and category. MessagePack records can be
+arrays in that order too (rmp-serde's compact form). Amounts are best sent
+as strings, like with JSON. A record that can't be decoded is an error of
+its own and the next one is read, but a stream that ends in the middle of a
+record or announces one of more than a MiB ends there.
+*/
+
+// Far more than any transaction takes, so a corrupt length doesn't allocate
+// gigabytes
+const MAX_RECORD: usize = 1 << 20;
+
+pub struct RecordReader<R> {
+    reader: R,
+    format: InputFormat,
+    buffer: Vec<u8>,
+    // Read so far, for the errors
+    records: u64,
+    broken: bool,
+}
+
+impl<R: Read> RecordReader<R> {
+    /// For InputFormat::MessagePack or Cbor
+    pub fn new(reader: R, format: InputFormat) -> RecordReader<R> {
+        RecordReader {
+            reader,
+            format,
+            buffer: Vec::new(),
+            records: 0,
+            broken: false,
+        }
+    }
+
+    // None at the end of the stream, between records
+    fn next_record(&mut self) -> Result<Option<()>, Box<dyn Error>> {
+        let mut prefix = [0u8; 4];
+        let mut read = 0;
+        while read < prefix.len() {
+            match self.reader.read(&mut prefix[read..]) {
+                Ok(0) if read == 0 => return Ok(None),
+                Ok(0) => return Err("The stream ends in a length prefix".into()),
+                Ok(n) => read += n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        let length = u32::from_be_bytes(prefix) as usize;
+        if length > MAX_RECORD {
+            return Err(
+                format!("Record of {} bytes, more than a transaction takes", length).into(),
+            );
+        }
+        self.buffer.resize(length, 0);
+        self.reader.read_exact(&mut self.buffer)?;
+        Ok(Some(()))
+    }
+
+    fn decode(&self) -> Result<Transaction, Box<dyn Error>> {
+        let record: JsonTransaction = match self.format {
+            InputFormat::MessagePack => rmp_serde::from_slice(&self.buffer)?,
+            InputFormat::Cbor => ciborium::de::from_reader(self.buffer.as_slice())?,
+            _ => return Err("Not a binary record format".into()),
+        };
+        Ok(Transaction::try_from(record)?)
+    }
+}
+
+impl<R: Read> Iterator for RecordReader<R> {
+    type Item = Result<Transaction, Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.broken {
+            return None;
+        }
+        self.records += 1;
+        let read = match self.next_record() {
+            Ok(None) => return None,
+            Ok(Some(())) => self.decode(),
+            Err(e) => {
+                self.broken = true;
+                Err(e)
+            }
+        };
+        Some(read.map_err(|e| format!("Record {}: {}", self.records, e).into()))
+    }
+}
+
+#[test]
+fn test_binary_records() {
+    use crate::transaction::TransactionType;
+    use rust_decimal::Decimal;
+    use serde_json::json;
+
+    let records = [
+        json!({"type": "deposit", "client": 1, "tx": 1, "amount": "1.5", "description": "Top-up"}),
+        json!({"type": "refund", "client": 1, "tx": 2}),
+        json!({"type": "withdrawal", "client": 1, "tx": 3, "amount": 0.5}),
+    ];
+    for format in [InputFormat::MessagePack, InputFormat::Cbor] {
+        let mut stream = Vec::new();
+        for record in &records {
+            let encoded = match format {
+                InputFormat::MessagePack => rmp_serde::to_vec_named(record).unwrap(),
+                _ => {
+                    let mut encoded = Vec::new();
+                    ciborium::ser::into_writer(record, &mut encoded).unwrap();
+                    encoded
+                }
+            };
+            stream.extend_from_slice(&(encoded.len() as u32).to_be_bytes());
+            stream.extend_from_slice(&encoded);
+        }
+        // Cut short
+        stream.extend_from_slice(&[0, 0, 0, 9, 1]);
+        let read: Vec<Result<Transaction, Box<dyn Error>>> =
+            RecordReader::new(stream.as_slice(), format).collect();
+        assert_eq!(read.len(), 4, "{:?}", format);
+        let deposit = read[0].as_ref().unwrap();
+        assert_eq!(deposit.tx_type, TransactionType::Deposit);
+        assert_eq!(deposit.amount, Some(Decimal::new(15, 1)));
+        assert_eq!(deposit.description.as_deref(), Some("Top-up"));
+        assert_eq!(
+            read[1].as_ref().unwrap_err().to_string(),
+            "Record 2: Unknown transaction type"
+        );
+        assert_eq!(read[2].as_ref().unwrap().amount, Some(Decimal::new(5, 1)));
+        assert!(read[3]
+            .as_ref()
+            .unwrap_err()
+            .to_string()
+            .starts_with("Record 4: "));
+    }
+    let compact = rmp_serde::to_vec(&json!(["hold", 2, 5, "2", null, "gaming"])).unwrap();
+    let mut stream = (compact.len() as u32).to_be_bytes().to_vec();
+    stream.extend_from_slice(&compact);
+    let hold = RecordReader::new(stream.as_slice(), InputFormat::MessagePack)
+        .next()
+        .unwrap()
+        .unwrap();
+    assert_eq!(hold.tx_type, TransactionType::Hold);
+    assert_eq!(hold.category.as_deref(), Some("gaming"));
+    let huge = [0xff, 0xff, 0xff, 0xff];
+    assert!(RecordReader::new(&huge[..], InputFormat::Cbor)
+        .next()
+        .unwrap()
+        .is_err());
+}
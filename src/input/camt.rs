@@ -0,0 +1,253 @@
+use crate::input::statement_tx_id;
+use crate::transaction::{ClientId, Transaction, TransactionStatus, TransactionType};
+use roxmltree::{Document, Node};
+use rust_decimal::Decimal;
+use std::error::Error;
+use std::io::Read;
+use std::str::FromStr;
+
+/*
+ISO 20022 camt.053 bank to customer statements, what SEPA banks send at the
+end of the day, so the engine can run right after the bank's feed:
+
+  <Ntry>
+    <Amt Ccy="EUR">250.00</Amt>
+    <CdtDbtInd>CRDT</CdtDbtInd>
+    <Sts><Cd>BOOK</Cd></Sts>
+    <AcctSvcrRef>2024013100042</AcctSvcrRef>
+    <NtryDtls><TxDtls>
+      <Refs><EndToEndId>INV-2024-117</EndToEndId></Refs>
+      <RmtInf><Ustrd>Invoice 117</Ustrd></RmtInf>
+    </TxDtls></NtryDtls>
+  </Ntry>
+
+Every booked entry (Ntry), in the order of the file whatever statement it's
+in, is a deposit if it's a credit (CRDT) and a withdrawal if it's a debit
+(DBIT). Pending ones are left out, they come back booked in a later one. An
+entry with several transactions (TxDtls, a batch booking) is one of each,
+with their own amounts.
+
+An entry that returns an earlier one, a bounced transfer or an unpaid direct
+debit, is a dispute and a chargeback of it, whatever its direction: it says
+so with return information (RtrInf), a reversal indicator (RvslInd) or its
+bank transaction code (sub family RRTN or UPDD), and keeps the EndToEndId
+of the transaction it returns. The engine can only charge back deposits, so
+a returned withdrawal comes out as ignored.
+
+The tx id is from the EndToEndId, which is the same in a transaction and its
+return, or without one the bank's reference (AcctSvcrRef, then NtryRef): the
+reference if it's a number that fits, its CRC-32 if not. The unstructured
+remittance information (Ustrd) is the description, or without it the
+additional information of the transaction or entry. Like with OFX a
+statement doesn't say which client it is (--statement-client), and currencies,
+dates and balances are left out.
+*/
+
+type Row = Result<Transaction, Box<dyn Error>>;
+
+// Bank transaction code sub families that return a payment
+const RETURNS: [&str; 2] = ["RRTN", "UPDD"];
+
+/// The transactions of the statements, or why an entry can't be read, in
+/// order. Only fails if the input can't be read or isn't XML at all.
+pub fn read<R: Read>(mut reader: R, client: ClientId) -> Result<Vec<Row>, Box<dyn Error>> {
+    let mut text = String::new();
+    reader.read_to_string(&mut text)?;
+    let document = Document::parse(&text)?;
+    let mut read = Vec::new();
+    let entries = document
+        .descendants()
+        .filter(|node| node.has_tag_name("Ntry"));
+    for (number, entry) in entries.enumerate() {
+        match transactions(entry, client) {
+            Ok(transactions) => read.extend(transactions.into_iter().map(Ok)),
+            Err(e) => read.push(Err(format!("Statement entry {}: {}", number + 1, e).into())),
+        }
+    }
+    Ok(read)
+}
+
+fn transactions(entry: Node, client: ClientId) -> Result<Vec<Transaction>, &'static str> {
+    // Sts is a code of its own up to version 7, in a Cd since
+    let status = text(entry, &["Sts", "Cd"]).or_else(|| text(entry, &["Sts"]));
+    if status.is_some_and(|status| status != "BOOK") {
+        return Ok(Vec::new());
+    }
+    let tx_type = match text(entry, &["CdtDbtInd"]) {
+        Some("CRDT") => TransactionType::Deposit,
+        Some("DBIT") => TransactionType::Withdrawal,
+        _ => return Err("No CdtDbtInd"),
+    };
+    let reversal = text(entry, &["RvslInd"]) == Some("true")
+        || text(entry, &["BkTxCd", "Domn", "Fmly", "SubFmlyCd"])
+            .is_some_and(|code| RETURNS.contains(&code));
+    let details: Vec<Node> = entry
+        .children()
+        .filter(|node| node.has_tag_name("NtryDtls"))
+        .flat_map(|node| node.children().filter(|node| node.has_tag_name("TxDtls")))
+        .collect();
+    let parts = match details.as_slice() {
+        [] => vec![(amount(entry, &["Amt"])?, None)],
+        [detail] => vec![(amount(entry, &["Amt"])?, Some(*detail))],
+        _ => details
+            .iter()
+            .map(|detail| {
+                let amount = amount(*detail, &["Amt"])
+                    .or_else(|_| amount(*detail, &["AmtDtls", "TxAmt", "Amt"]))?;
+                Ok((amount, Some(*detail)))
+            })
+            .collect::<Result<_, &'static str>>()?,
+    };
+    let mut transactions = Vec::new();
+    for (amount, detail) in parts {
+        let reference = detail
+            .and_then(|detail| text(detail, &["Refs", "EndToEndId"]))
+            .filter(|reference| *reference != "NOTPROVIDED")
+            .or_else(|| detail.and_then(|detail| text(detail, &["Refs", "AcctSvcrRef"])))
+            .or_else(|| text(entry, &["AcctSvcrRef"]))
+            .or_else(|| text(entry, &["NtryRef"]))
+            .ok_or("No reference")?;
+        let description = detail
+            .and_then(|detail| {
+                let lines: Vec<&str> = detail
+                    .descendants()
+                    .filter(|node| node.has_tag_name("Ustrd"))
+                    .filter_map(|node| node.text())
+                    .map(str::trim)
+                    .collect();
+                Some(lines.join(" ")).filter(|joined| !joined.is_empty())
+            })
+            .or_else(|| {
+                detail
+                    .and_then(|detail| text(detail, &["AddtlTxInf"]))
+                    .map(String::from)
+            })
+            .or_else(|| text(entry, &["AddtlNtryInf"]).map(String::from))
+            .map(String::into_boxed_str);
+        let transaction = |tx_type, amount| Transaction {
+            tx_type,
+            client_id: client,
+            tx_id: statement_tx_id(reference),
+            amount,
+            status: TransactionStatus::OK,
+            description: description.clone(),
+            category: None,
+        };
+        let returned =
+            reversal || detail.is_some_and(|detail| child(detail, &["RtrInf"]).is_some());
+        match returned {
+            true => transactions.extend([
+                transaction(TransactionType::Dispute, None),
+                transaction(TransactionType::Chargeback, None),
+            ]),
+            false => transactions.push(transaction(tx_type, Some(amount))),
+        }
+    }
+    Ok(transactions)
+}
+
+// The element at the end of the path of child elements, by local name
+fn child<'a, 'input>(node: Node<'a, 'input>, path: &[&str]) -> Option<Node<'a, 'input>> {
+    path.iter().try_fold(node, |node, name| {
+        node.children().find(|child| child.has_tag_name(*name))
+    })
+}
+
+// Its text, None if it isn't there or is empty
+fn text<'a>(node: Node<'a, '_>, path: &[&str]) -> Option<&'a str> {
+    child(node, path)?
+        .text()
+        .map(str::trim)
+        .filter(|text| !text.is_empty())
+}
+
+fn amount(node: Node, path: &[&str]) -> Result<Decimal, &'static str> {
+    let amount = text(node, path).ok_or("No Amt")?;
+    Decimal::from_str(amount)
+        .ok()
+        .filter(|amount| !amount.is_sign_negative())
+        .ok_or("Invalid Amt")
+}
+
+#[test]
+fn test_camt() {
+    use crate::PaymentEngine;
+
+    let statement = br#"<?xml version="1.0" encoding="UTF-8"?>
+<Document xmlns="urn:iso:std:iso:20022:tech:xsd:camt.053.001.02">
+<BkToCstmrStmt><Stmt>
+  <Ntry>
+    <Amt Ccy="EUR">250.00</Amt><CdtDbtInd>CRDT</CdtDbtInd><Sts>BOOK</Sts>
+    <AcctSvcrRef>REF-1</AcctSvcrRef>
+    <NtryDtls><TxDtls>
+      <Refs><EndToEndId>INV-117</EndToEndId></Refs>
+      <RmtInf><Ustrd>Invoice</Ustrd><Ustrd>117</Ustrd></RmtInf>
+    </TxDtls></NtryDtls>
+  </Ntry>
+  <Ntry>
+    <Amt Ccy="EUR">100</Amt><CdtDbtInd>DBIT</CdtDbtInd><Sts>BOOK</Sts>
+    <NtryDtls>
+      <TxDtls><Refs><EndToEndId>7</EndToEndId></Refs><Amt Ccy="EUR">60</Amt></TxDtls>
+      <TxDtls>
+        <Refs><EndToEndId>NOTPROVIDED</EndToEndId><AcctSvcrRef>8</AcctSvcrRef></Refs>
+        <AmtDtls><TxAmt><Amt Ccy="EUR">40</Amt></TxAmt></AmtDtls>
+        <AddtlTxInf>Card fee</AddtlTxInf>
+      </TxDtls>
+    </NtryDtls>
+  </Ntry>
+  <Ntry>
+    <Amt Ccy="EUR">5</Amt><CdtDbtInd>CRDT</CdtDbtInd><Sts>PDNG</Sts><NtryRef>9</NtryRef>
+  </Ntry>
+  <Ntry>
+    <Amt Ccy="EUR">1</Amt><Sts><Cd>BOOK</Cd></Sts><NtryRef>10</NtryRef>
+  </Ntry>
+  <Ntry>
+    <Amt Ccy="EUR">250.00</Amt><CdtDbtInd>DBIT</CdtDbtInd><Sts><Cd>BOOK</Cd></Sts>
+    <BkTxCd><Domn><Cd>PMNT</Cd><Fmly><Cd>RCDT</Cd><SubFmlyCd>RRTN</SubFmlyCd></Fmly></Domn></BkTxCd>
+    <NtryDtls><TxDtls><Refs><EndToEndId>INV-117</EndToEndId></Refs></TxDtls></NtryDtls>
+    <AddtlNtryInf>Return AC04</AddtlNtryInf>
+  </Ntry>
+</Stmt></BkToCstmrStmt>
+</Document>"#;
+    let transactions = read(&statement[..], 3).unwrap();
+    let summary: Vec<String> = transactions
+        .iter()
+        .map(|row| match row {
+            Ok(transaction) => format!(
+                "{} {} {} {:?}",
+                transaction.tx_type,
+                transaction.tx_id,
+                transaction
+                    .amount
+                    .map(|amount| amount.to_string())
+                    .unwrap_or_default(),
+                transaction.description.as_deref().unwrap_or_default()
+            ),
+            Err(e) => e.to_string(),
+        })
+        .collect();
+    let invoice = statement_tx_id("INV-117");
+    assert_eq!(
+        summary,
+        vec![
+            format!("deposit {} 250.00 \"Invoice 117\"", invoice),
+            "withdrawal 7 60 \"\"".to_string(),
+            "withdrawal 8 40 \"Card fee\"".to_string(),
+            "Statement entry 4: No CdtDbtInd".to_string(),
+            format!("dispute {}  \"Return AC04\"", invoice),
+            format!("chargeback {}  \"Return AC04\"", invoice),
+        ]
+    );
+
+    let mut engine = PaymentEngine::new();
+    engine
+        .process_transaction(transactions[0].as_ref().unwrap().clone())
+        .unwrap();
+    for row in &transactions[4..] {
+        engine
+            .process_transaction(row.as_ref().unwrap().clone())
+            .unwrap();
+    }
+    assert!(engine.account(3).unwrap().unwrap().locked);
+    assert!(read("<Document><Ntry>".as_bytes(), 3).is_err());
+}
@@ -0,0 +1,239 @@
+use crate::input::dialect::CsvDialect;
+use crate::input::encoding::Encoding;
+use crate::transaction::Transaction;
+use crate::PaymentEngine;
+use csv::ByteRecord;
+use std::error::Error;
+use std::io::{self, Read};
+use std::sync::mpsc::{sync_channel, Receiver};
+use std::sync::Arc;
+use std::thread;
+
+/*
+Parsing CSV (the decimals above all) takes more CPU than processing the
+transactions, so this parses on every core and still processes on one
+thread, in file order:
+
+  reader thread  cuts the file into chunks of whole records
+  rayon pool     parses each chunk, with the header in front of it
+  this thread    processes the chunks as they come, in order
+
+Records are cut at newlines outside quotes, so quoted fields can have line
+breaks. Only for plain UTF-8 files without control records or batches, whose
+checks need to see the whole file in order. Failing rows stop the import
+where a sequential one would, after processing every row before them.
+*/
+
+const CHUNK_SIZE: usize = 1 << 20;
+const CHUNKS_IN_FLIGHT: usize = 32;
+
+/// Whether files of this dialect can be parsed in chunks
+pub fn supports(dialect: &CsvDialect) -> bool {
+    dialect.encoding == Encoding::Utf8
+        && dialect.control.is_none()
+        && dialect.batches.is_none()
+        && !dialect.lossy_utf8
+}
+
+// Where the first record that isn't a comment and the last record end (past
+// their newline), if the bytes have any. They have to start at the start of
+// a record.
+fn record_ends(bytes: &[u8], quote: u8, comment: Option<u8>) -> (Option<usize>, Option<usize>) {
+    let (mut first, mut last) = (None, None);
+    let mut quoted = false;
+    let mut in_comment = false;
+    let mut line_start = true;
+    for (i, &byte) in bytes.iter().enumerate() {
+        if line_start && Some(byte) == comment {
+            in_comment = true;
+        }
+        line_start = false;
+        if byte == b'\n' && !quoted {
+            if !in_comment && first.is_none() {
+                first = Some(i + 1);
+            }
+            last = Some(i + 1);
+            in_comment = false;
+            line_start = true;
+        } else if byte == quote && !in_comment {
+            quoted = !quoted;
+        }
+    }
+    (first, last)
+}
+
+// Reads chunks of at least CHUNK_SIZE bytes (but the last one) that end at
+// the end of a record, until the file ends or `send` says to stop.
+fn read_chunks<R: Read>(
+    mut reader: R,
+    quote: u8,
+    comment: Option<u8>,
+    mut send: impl FnMut(Vec<u8>) -> bool,
+) -> io::Result<()> {
+    let mut buffer = Vec::new();
+    loop {
+        let start = buffer.len();
+        buffer.resize(start + CHUNK_SIZE, 0);
+        let read = reader.read(&mut buffer[start..])?;
+        buffer.truncate(start + read);
+        if read == 0 {
+            if !buffer.is_empty() {
+                send(buffer);
+            }
+            return Ok(());
+        }
+        if buffer.len() < CHUNK_SIZE {
+            continue;
+        }
+        if let (_, Some(end)) = record_ends(&buffer, quote, comment) {
+            let rest = buffer.split_off(end);
+            if !send(buffer) {
+                return Ok(());
+            }
+            buffer = rest;
+        }
+    }
+}
+
+// The transactions of a chunk up to the first row that fails, and its error
+type Parsed = (Vec<Transaction>, Option<String>);
+
+fn parse(header: &[u8], chunk: &[u8], dialect: &CsvDialect) -> Parsed {
+    let mut transactions = Vec::new();
+    let mut reader = match dialect.reader(header.chain(chunk)) {
+        Ok(reader) => reader,
+        Err(e) => return (transactions, Some(e.to_string())),
+    };
+    let mut record = ByteRecord::new();
+    loop {
+        match reader.read_byte_record(&mut record) {
+            Ok(true) => match Transaction::from_byte_record(&record) {
+                Ok(transaction) => transactions.push(transaction),
+                Err(e) => return (transactions, Some(e.to_string())),
+            },
+            Ok(false) => return (transactions, None),
+            Err(e) => return (transactions, Some(e.to_string())),
+        }
+    }
+}
+
+/// Imports a CSV file parsing it in parallel, see above. `each` gets every
+/// transaction before the engine does. Returns how many were imported.
+pub fn import_csv<R: Read + Send>(
+    engine: &mut PaymentEngine,
+    file: R,
+    dialect: &CsvDialect,
+    mut each: impl FnMut(&Transaction),
+) -> Result<u64, Box<dyn Error>> {
+    if !supports(dialect) || !dialect.quote.is_ascii() {
+        return Err("This CSV dialect can't be parsed in chunks".into());
+    }
+    let quote = dialect.quote as u8;
+    let comment = match dialect.comment {
+        Some(comment) if !comment.is_ascii() => return Err("Comment must be ASCII".into()),
+        comment => comment.map(|comment| comment as u8),
+    };
+    let (sender, receiver) = sync_channel::<Receiver<Parsed>>(CHUNKS_IN_FLIGHT);
+    thread::scope(|scope| {
+        let reader = scope.spawn(move || {
+            let mut header = match dialect.header {
+                true => None,
+                false => Some(Arc::new(Vec::new())),
+            };
+            read_chunks(file, quote, comment, |mut chunk| {
+                let header = match &header {
+                    Some(header) => Arc::clone(header),
+                    None => {
+                        let (end, _) = record_ends(&chunk, quote, comment);
+                        let rest = chunk.split_off(end.unwrap_or(chunk.len()));
+                        let found = header.insert(Arc::new(std::mem::replace(&mut chunk, rest)));
+                        Arc::clone(found)
+                    }
+                };
+                let (parsed, result) = sync_channel(1);
+                let dialect = dialect.clone();
+                rayon::spawn(move || {
+                    let _ = parsed.send(parse(&header, &chunk, &dialect));
+                });
+                // Fails once the engine stopped
+                sender.send(result).is_ok()
+            })
+        });
+        let mut imported = 0;
+        let mut result: Result<(), Box<dyn Error>> = Ok(());
+        'chunks: for chunk in receiver.iter() {
+            let (transactions, error) = match chunk.recv() {
+                Ok(parsed) => parsed,
+                Err(_) => (Vec::new(), Some("Parser thread panicked".to_string())),
+            };
+            for transaction in transactions {
+                each(&transaction);
+                if let Err(e) = engine.process_transaction(transaction) {
+                    result = Err(e);
+                    break 'chunks;
+                }
+                imported += 1;
+            }
+            if let Some(e) = error {
+                result = Err(e.into());
+                break;
+            }
+        }
+        // Stops the reader
+        drop(receiver);
+        match reader.join() {
+            Ok(read) => read?,
+            Err(_) => return Err("Reader thread panicked".into()),
+        }
+        result.map(|_| imported)
+    })
+}
+
+#[test]
+fn test_chunked_parsing() {
+    use std::fs::File;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("big.csv");
+    let mut input = String::from("# generated\ntype,client,tx,amount,memo\n");
+    for tx in 1..=150_000 {
+        let client = tx % 97;
+        match tx % 5 {
+            0 => input.push_str(&format!("dispute,{},{},,\n", (tx - 3) % 97, tx - 3)),
+            1 => input.push_str(&format!(
+                "withdrawal,{},{},0.5,\"two\nlines, \"\"quoted\"\"\"\n",
+                client, tx
+            )),
+            _ => input.push_str(&format!("deposit,{},{},1.2345,\n", client, tx)),
+        }
+    }
+    std::fs::write(&path, &input).unwrap();
+    assert!(input.len() > 2 * CHUNK_SIZE);
+    let dialect = CsvDialect {
+        comment: Some('#'),
+        ..Default::default()
+    };
+
+    let mut sequential = PaymentEngine::new();
+    sequential
+        .import_reader_with_dialect(input.as_bytes(), &dialect)
+        .unwrap();
+    let mut chunked = PaymentEngine::new();
+    let mut seen = 0;
+    let file = File::open(&path).unwrap();
+    let imported = import_csv(&mut chunked, file, &dialect, |_| seen += 1).unwrap();
+    assert_eq!((imported, seen), (150_000, 150_000));
+    assert_eq!(chunked.sorted_accounts(), sequential.sorted_accounts());
+    assert_eq!(
+        chunked.sorted_transactions(),
+        sequential.sorted_transactions()
+    );
+
+    // Stops at the first bad row, like a sequential import
+    input.push_str("deposit,1,170000,x,\ndeposit,1,170001,1,\n");
+    std::fs::write(&path, &input).unwrap();
+    let mut chunked = PaymentEngine::new();
+    let file = File::open(&path).unwrap();
+    assert!(import_csv(&mut chunked, file, &dialect, |_| {}).is_err());
+    assert_eq!(chunked.sorted_accounts(), sequential.sorted_accounts());
+}
@@ -0,0 +1,187 @@
+use csv::ByteRecord;
+use rust_decimal::Decimal;
+use std::error::Error;
+use std::str::FromStr;
+use tracing::warn;
+
+/*
+Bank batch files wrap their rows in control records, so a truncated or
+doctored file can be told apart from a good one:
+
+  type,client,tx,amount
+  header,,,
+  deposit,1,1,10
+  withdrawal,1,2,2.5
+  trailer,,2,12.5
+
+The trailer has the number of rows since the header (or the previous
+trailer, or the start of the file) in the tx column and the sum of their
+amounts, whatever their type, in the amount column. A header only starts a
+new batch. A file can have several batches, and has to end with a trailer.
+Control records never reach the engine.
+
+What to do when a batch doesn't add up:
+
+  fail  stop reading the file, with an error
+  warn  log a warning and go on
+
+Resuming from a checkpoint starts counting at the checkpoint, so the batch
+the run stopped in won't reconcile.
+*/
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum ControlRecords {
+    Fail,
+    Warn,
+}
+
+impl FromStr for ControlRecords {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ControlRecords, String> {
+        match s {
+            "fail" => Ok(ControlRecords::Fail),
+            "warn" => Ok(ControlRecords::Warn),
+            _ => Err(format!("Unknown control record check: {}", s)),
+        }
+    }
+}
+
+deserialize_from_str!(ControlRecords);
+
+fn field(record: &ByteRecord, i: usize) -> Result<&str, Box<dyn Error>> {
+    Ok(std::str::from_utf8(record.get(i).unwrap_or_default())?.trim())
+}
+
+/// Rows and amounts of the current batch.
+#[derive(Debug)]
+pub struct BatchCheck {
+    mode: ControlRecords,
+    rows: u64,
+    sum: Decimal,
+    // Since the last control record
+    pending: bool,
+    trailers: u64,
+}
+
+impl BatchCheck {
+    pub fn new(mode: ControlRecords) -> BatchCheck {
+        BatchCheck {
+            mode,
+            rows: 0,
+            sum: Decimal::ZERO,
+            pending: false,
+            trailers: 0,
+        }
+    }
+
+    fn problem(&self, problem: String) -> Result<(), Box<dyn Error>> {
+        match self.mode {
+            ControlRecords::Fail => Err(problem.into()),
+            ControlRecords::Warn => {
+                warn!("{}", problem);
+                Ok(())
+            }
+        }
+    }
+
+    /// Takes in a record, already in the usual column order. Ok(true) if
+    /// it's a control record, that the engine mustn't see.
+    pub fn check(&mut self, record: &ByteRecord) -> Result<bool, Box<dyn Error>> {
+        let tx_type = field(record, 0)?;
+        if tx_type.eq_ignore_ascii_case("header") {
+            if self.pending {
+                self.problem(format!("Batch of {} rows without a trailer", self.rows))?;
+            }
+            self.reset();
+            return Ok(true);
+        }
+        if tx_type.eq_ignore_ascii_case("trailer") {
+            let rows: u64 = field(record, 2)?
+                .parse()
+                .map_err(|_| "Trailer without a row count")?;
+            let sum = match field(record, 3)? {
+                "" => Decimal::ZERO,
+                sum => Decimal::from_str(sum).map_err(|_| "Invalid trailer sum")?,
+            };
+            self.trailers += 1;
+            if (rows, sum) != (self.rows, self.sum) {
+                self.problem(format!(
+                    "Trailer {} expects {} rows adding up to {}, got {} adding up to {}",
+                    self.trailers, rows, sum, self.rows, self.sum
+                ))?;
+            }
+            self.reset();
+            return Ok(true);
+        }
+        self.rows += 1;
+        self.pending = true;
+        let amount = field(record, 3)?;
+        if !amount.is_empty() {
+            let amount = Decimal::from_str(amount).map_err(|_| "Invalid amount")?;
+            self.sum = self.sum.checked_add(amount).ok_or("Amount overflow")?;
+        }
+        Ok(false)
+    }
+
+    /// At the end of the file, which has to close its last batch.
+    pub fn finish(self) -> Result<(), Box<dyn Error>> {
+        if self.pending || self.trailers == 0 {
+            return self.problem(format!(
+                "The file ends without a trailer after {} rows",
+                self.rows
+            ));
+        }
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        self.rows = 0;
+        self.sum = Decimal::ZERO;
+        self.pending = false;
+    }
+}
+
+#[test]
+fn test_control_records() {
+    use crate::input::dialect::CsvDialect;
+    use crate::PaymentEngine;
+
+    let import = |input: &[u8], control| {
+        let dialect = CsvDialect {
+            control: Some(control),
+            ..Default::default()
+        };
+        let mut engine = PaymentEngine::new();
+        engine
+            .import_reader_with_dialect(input, &dialect)
+            .map(|_| engine.account(1).unwrap().unwrap().total())
+    };
+    let batches = b"type,client,tx,amount
+header,,,
+deposit,1,1,10
+withdrawal,1,2,2.5
+trailer,,2,12.5
+header,,,
+dispute,1,1,
+trailer,,1,
+";
+    assert_eq!(
+        import(batches, ControlRecords::Fail).unwrap(),
+        Decimal::new(75, 1)
+    );
+
+    let wrong_sum = b"type,client,tx,amount\ndeposit,1,1,10\ntrailer,,1,11\n";
+    assert!(import(wrong_sum, ControlRecords::Fail).is_err());
+    assert_eq!(
+        import(wrong_sum, ControlRecords::Warn).unwrap(),
+        Decimal::new(10, 0)
+    );
+    let truncated = b"type,client,tx,amount\ndeposit,1,1,10\ntrailer,,1,10\ndeposit,1,2,5\n";
+    assert!(import(truncated, ControlRecords::Fail).is_err());
+    assert!(import(
+        b"type,client,tx,amount\ndeposit,1,1,10\n",
+        ControlRecords::Fail
+    )
+    .is_err());
+}
@@ -0,0 +1,413 @@
+use crate::input::amount::AmountFormat;
+use crate::input::batch::BatchRollback;
+use crate::input::control::{BatchCheck, ControlRecords};
+use crate::input::encoding::Encoding;
+use crate::transform::{ColumnRemap, Transform};
+use csv::{ByteRecord, Reader, ReaderBuilder};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::io::{Read, Seek};
+use tracing::warn;
+
+/// How a CSV file is written. The default is what the spec says: comma
+/// separated, double quotes, a header row and no comments.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CsvDialect {
+    pub delimiter: char,
+    pub header: bool,
+    pub quote: char,
+    /// Lines starting with it are skipped
+    pub comment: Option<char>,
+    /// Header names of our columns, for files that call them something
+    /// else, e.g. type: txn_kind
+    pub map: BTreeMap<String, String>,
+    pub amount: AmountFormat,
+    pub encoding: Encoding,
+    /// Header and trailer records to check the file against, see control.rs
+    pub control: Option<ControlRecords>,
+    /// Roll back the batches (rows with the same batch column) that fail,
+    /// see batch.rs
+    pub batches: Option<BatchRollback>,
+    /// Replace invalid UTF-8 with U+FFFD instead of failing, and skip the
+    /// records where it's in one of our columns, logging their line
+    pub lossy_utf8: bool,
+}
+
+impl Default for CsvDialect {
+    fn default() -> Self {
+        CsvDialect {
+            delimiter: ',',
+            header: true,
+            quote: '"',
+            comment: None,
+            map: BTreeMap::new(),
+            amount: AmountFormat::default(),
+            encoding: Encoding::default(),
+            control: None,
+            batches: None,
+            lossy_utf8: false,
+        }
+    }
+}
+
+fn ascii(c: char) -> Result<u8, &'static str> {
+    if c.is_ascii() {
+        Ok(c as u8)
+    } else {
+        Err("Delimiter, quote and comment characters must be ASCII")
+    }
+}
+
+impl CsvDialect {
+    fn reader_builder(&self) -> Result<ReaderBuilder, &'static str> {
+        let mut builder = ReaderBuilder::new();
+        builder
+            .delimiter(ascii(self.delimiter)?)
+            .has_headers(self.header)
+            .quote(ascii(self.quote)?)
+            .comment(self.comment.map(ascii).transpose()?);
+        Ok(builder)
+    }
+}
+
+const COLUMNS: [&str; 4] = ["type", "client", "tx", "amount"];
+// Can be renamed in the map too, but isn't one of the transaction's columns
+const BATCH: &str = "batch";
+// Optional, after the amount
+const DESCRIPTION: &str = "description";
+const CATEGORY: &str = "category";
+
+impl CsvDialect {
+    /// Where type, client, tx and amount are in a file with this header: by
+    /// name, in any order, other columns ignored. None if they're already
+    /// where from_byte_record expects them. A header without our names (and
+    /// no map) is taken to be in the usual order, as before there were names.
+    /// Description and category columns are kept too, if there's an amount
+    /// column.
+    pub fn columns(&self, header: &ByteRecord) -> Result<Option<ColumnRemap>, String> {
+        if let Some(unknown) = self.map.keys().find(|key| {
+            !COLUMNS.contains(&key.as_str())
+                && ![BATCH, DESCRIPTION, CATEGORY].contains(&key.as_str())
+        }) {
+            return Err(format!("Unknown column in map: {}", unknown));
+        }
+        let position = |column: &str| self.position(header, column);
+        let mut columns = Vec::with_capacity(COLUMNS.len());
+        for column in &COLUMNS[..3] {
+            match position(column) {
+                Some(position) => columns.push(position),
+                None if self.map.is_empty() => return Ok(None),
+                None => return Err(format!("No {} column in the header", column)),
+            }
+        }
+        if let Some(amount) = position("amount") {
+            columns.push(amount);
+            match (position(DESCRIPTION), position(CATEGORY)) {
+                // Past the last column, so an empty description
+                (description, Some(category)) => {
+                    columns.push(description.unwrap_or(header.len()));
+                    columns.push(category);
+                }
+                (description, None) => columns.extend(description),
+            }
+        }
+        if columns.len() == header.len() && columns.iter().enumerate().all(|(i, c)| i == *c) {
+            return Ok(None);
+        }
+        Ok(Some(ColumnRemap { columns }))
+    }
+
+    fn position(&self, header: &ByteRecord, column: &str) -> Option<usize> {
+        let name = self.map.get(column).map_or(column, |name| name.as_str());
+        header.iter().position(|field| {
+            std::str::from_utf8(field).is_ok_and(|field| field.trim().eq_ignore_ascii_case(name))
+        })
+    }
+
+    /// A CSV reader for this dialect that gives back the columns in the
+    /// usual order, in UTF-8. Reads the header, if there's one.
+    pub fn reader<'r, R: Read + 'r>(
+        &self,
+        reader: R,
+    ) -> Result<CsvReader<Box<dyn Read + 'r>>, Box<dyn Error>> {
+        self.wrap(self.encoding.decode(reader)?)
+    }
+
+    /// For seeking by byte offset, which only makes sense without
+    /// transcoding.
+    pub fn seekable_reader<R: Read + Seek>(
+        &self,
+        reader: R,
+    ) -> Result<CsvReader<R>, Box<dyn Error>> {
+        if self.encoding != Encoding::Utf8 {
+            return Err("Only for UTF-8 input".into());
+        }
+        self.wrap(reader)
+    }
+
+    fn wrap<R: Read>(&self, reader: R) -> Result<CsvReader<R>, Box<dyn Error>> {
+        let mut reader = self.reader_builder()?.from_reader(reader);
+        let remap = match self.header {
+            true => self.columns(reader.byte_headers()?)?,
+            false => None,
+        };
+        let batch = match (self.batches, self.header) {
+            (None, _) => None,
+            (Some(_), false) => return Err("Batches need a header".into()),
+            (Some(_), true) => Some(
+                self.position(reader.byte_headers()?, BATCH)
+                    .ok_or("No batch column in the header")?,
+            ),
+        };
+        let amount = Some(self.amount.clone()).filter(|amount| !amount.is_plain());
+        Ok(CsvReader {
+            reader,
+            remap,
+            amount,
+            control: self.control.map(BatchCheck::new),
+            batch_column: batch,
+            batch: Vec::new(),
+            lossy_utf8: self.lossy_utf8,
+        })
+    }
+}
+
+pub struct CsvReader<R> {
+    reader: Reader<R>,
+    remap: Option<ColumnRemap>,
+    amount: Option<AmountFormat>,
+    control: Option<BatchCheck>,
+    batch_column: Option<usize>,
+    batch: Vec<u8>,
+    lossy_utf8: bool,
+}
+
+impl<R: Read> CsvReader<R> {
+    /// The next transaction record, control records are checked and
+    /// skipped.
+    pub fn read_byte_record(&mut self, record: &mut ByteRecord) -> Result<bool, Box<dyn Error>> {
+        loop {
+            let more = self.read_any_record(record)?;
+            match &mut self.control {
+                Some(control) if more => {
+                    if !control.check(record)? {
+                        return Ok(true);
+                    }
+                }
+                Some(_) => {
+                    if let Some(control) = self.control.take() {
+                        control.finish()?;
+                    }
+                    return Ok(false);
+                }
+                None => return Ok(more),
+            }
+        }
+    }
+
+    /// Batch id of the last record, trimmed, if the dialect has batches.
+    pub fn batch(&self) -> Option<&[u8]> {
+        self.batch_column.map(|_| self.batch.trim_ascii())
+    }
+
+    fn read_any_record(&mut self, record: &mut ByteRecord) -> Result<bool, Box<dyn Error>> {
+        let mut more = self.reader.read_byte_record(record)?;
+        while more && self.lossy_utf8 && !self.decode_lossily(record) {
+            more = self.reader.read_byte_record(record)?;
+        }
+        if let (true, Some(column)) = (more, self.batch_column) {
+            self.batch.clear();
+            self.batch
+                .extend_from_slice(record.get(column).unwrap_or_default());
+        }
+        if let (true, Some(remap)) = (more, &mut self.remap) {
+            remap.apply_record(record);
+        }
+        if let (true, Some(format), Some(amount)) = (more, &self.amount, record.get(3)) {
+            let amount = format.normalize(std::str::from_utf8(amount)?)?;
+            let mut normalized = ByteRecord::with_capacity(record.as_slice().len(), 4);
+            normalized.extend(record.iter().take(3));
+            normalized.push_field(amount.as_bytes());
+            normalized.extend(record.iter().skip(4));
+            *record = normalized;
+        }
+        Ok(more)
+    }
+
+    // Replaces invalid UTF-8 in the record with U+FFFD. False if it's in type,
+    // client, tx or amount, which can't be parsed then: the record is skipped.
+    // Free text (description, category) is decoded, wherever the header puts
+    // it.
+    fn decode_lossily(&self, record: &mut ByteRecord) -> bool {
+        let invalid = |field: &[u8]| std::str::from_utf8(field).is_err();
+        if !record.iter().any(invalid) {
+            return true;
+        }
+        let line = record.position().map_or(0, |position| position.line());
+        // Where from_byte_record expects them
+        const PARSED: [usize; 4] = [0, 1, 2, 3];
+        let ours = |column: usize| match &self.remap {
+            Some(remap) => PARSED
+                .iter()
+                .any(|&parsed| remap.columns.get(parsed) == Some(&column)),
+            None => PARSED.contains(&column),
+        };
+        if record
+            .iter()
+            .enumerate()
+            .any(|(column, field)| ours(column) && invalid(field))
+        {
+            warn!(
+                line,
+                "Invalid UTF-8 in a transaction column, skipping the record"
+            );
+            return false;
+        }
+        warn!(line, "Invalid UTF-8, decoded lossily");
+        let mut decoded = ByteRecord::with_capacity(record.as_slice().len(), record.len());
+        for field in record.iter() {
+            decoded.push_field(String::from_utf8_lossy(field).as_bytes());
+        }
+        decoded.set_position(record.position().cloned());
+        *record = decoded;
+        true
+    }
+
+    /// For seeking and such
+    pub fn inner_mut(&mut self) -> &mut Reader<R> {
+        &mut self.reader
+    }
+}
+
+#[test]
+fn test_header_columns() {
+    use crate::PaymentEngine;
+
+    let expected = |input: &[u8], dialect: &CsvDialect| {
+        let mut engine = PaymentEngine::new();
+        engine.import_reader_with_dialect(input, dialect).unwrap();
+        let mut expected = PaymentEngine::new();
+        expected
+            .import_reader(&b"type,client,tx,amount\ndeposit,1,1,1.5\nwithdrawal,1,2,0.5\n"[..])
+            .unwrap();
+        assert_eq!(engine.sorted_accounts(), expected.sorted_accounts());
+    };
+    expected(
+        b"memo,amount,tx,Client,type\nx,1.5,1,1,deposit\ny,0.5,2,1,withdrawal\n",
+        &CsvDialect::default(),
+    );
+    let mut dialect = CsvDialect::default();
+    dialect
+        .map
+        .insert("type".to_string(), "txn_kind".to_string());
+    dialect
+        .map
+        .insert("client".to_string(), "customer_id".to_string());
+    expected(
+        b"customer_id,tx,txn_kind,amount\n1,1,deposit,1.5\n1,2,withdrawal,0.5\n",
+        &dialect,
+    );
+    // Names we don't know, so positional
+    expected(
+        b"a,b,c,d\ndeposit,1,1,1.5\nwithdrawal,1,2,0.5\n",
+        &CsvDialect::default(),
+    );
+
+    let header = |fields: Vec<&str>| ByteRecord::from(fields);
+    assert!(dialect
+        .columns(&header(vec!["type", "client", "tx"]))
+        .is_err());
+    assert!(CsvDialect::default()
+        .columns(&header(vec!["type", " client", " tx", " amount"]))
+        .unwrap()
+        .is_none());
+    let remap = CsvDialect::default()
+        .columns(&header(vec![
+            "description",
+            "type",
+            "client",
+            "tx",
+            "amount",
+        ]))
+        .unwrap()
+        .unwrap();
+    assert_eq!(remap.columns, vec![1, 2, 3, 4, 0]);
+    let remap = CsvDialect::default()
+        .columns(&header(vec!["type", "client", "tx", "amount", "category"]))
+        .unwrap()
+        .unwrap();
+    assert_eq!(remap.columns, vec![0, 1, 2, 3, 5, 4]);
+    dialect.map.insert("kind".to_string(), "type".to_string());
+    assert!(dialect.columns(&header(vec!["type"])).is_err());
+}
+
+#[test]
+fn test_dialect() {
+    use crate::PaymentEngine;
+
+    let dialect = CsvDialect {
+        delimiter: ';',
+        header: false,
+        quote: '\'',
+        comment: Some('#'),
+        ..Default::default()
+    };
+    let mut engine = PaymentEngine::new();
+    engine
+        .import_reader_with_dialect(
+            &b"# exported 2021-05-01\n'deposit';1;1;'1.5'\nwithdrawal;1;2;0.5\n"[..],
+            &dialect,
+        )
+        .unwrap();
+    let mut expected = PaymentEngine::new();
+    expected
+        .import_reader(&b"type,client,tx,amount\ndeposit,1,1,1.5\nwithdrawal,1,2,0.5\n"[..])
+        .unwrap();
+    assert_eq!(engine.sorted_accounts(), expected.sorted_accounts());
+
+    let dialect = CsvDialect {
+        delimiter: '→',
+        ..Default::default()
+    };
+    assert!(dialect.reader_builder().is_err());
+}
+
+#[test]
+fn test_lossy_utf8() {
+    use crate::PaymentEngine;
+
+    let input = &b"memo,type,client,tx,amount\ncaf\xe9,deposit,1,1,1\nx,dep\xffosit,1,2,5\ny,deposit,1,3,2\xff\nz,deposit,1,4,1\n"[..];
+    let mut engine = PaymentEngine::new();
+    assert!(engine
+        .import_reader_with_dialect(input, &CsvDialect::default())
+        .is_err());
+
+    let dialect = CsvDialect {
+        lossy_utf8: true,
+        ..Default::default()
+    };
+    let mut reader = dialect.reader(input).unwrap();
+    let mut record = ByteRecord::new();
+    let mut txs = Vec::new();
+    while reader.read_byte_record(&mut record).unwrap() {
+        txs.push(record[2].to_vec());
+    }
+    assert_eq!(txs, vec![b"1".to_vec(), b"4".to_vec()]);
+
+    let mut engine = PaymentEngine::new();
+    engine.import_reader_with_dialect(input, &dialect).unwrap();
+    assert_eq!(engine.sorted_accounts()[0].funds_available.to_string(), "2");
+
+    // A description is decoded the same wherever the header puts it
+    for input in [
+        &b"type,client,tx,amount,description\ndeposit,1,1,1,caf\xe9\n"[..],
+        &b"description,type,client,tx,amount\ncaf\xe9,deposit,1,1,1\n"[..],
+    ] {
+        let mut reader = dialect.reader(input).unwrap();
+        assert!(reader.read_byte_record(&mut record).unwrap());
+        let transaction = crate::transaction::Transaction::from_byte_record(&record).unwrap();
+        assert_eq!(transaction.description.as_deref(), Some("caf\u{fffd}"));
+    }
+}
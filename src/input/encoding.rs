@@ -0,0 +1,107 @@
+use encoding_rs::{UTF_16LE, WINDOWS_1252};
+use encoding_rs_io::DecodeReaderBytesBuilder;
+use std::io::{self, Cursor, Read};
+use std::str::FromStr;
+
+/// Text encoding of an input file. Everything else is UTF-8, so other
+/// encodings are transcoded as they're read. A UTF-8 BOM is skipped (the csv
+/// crate does it) and a UTF-16 one switches to UTF-16 whatever was asked for,
+/// as files from Windows systems often have one.
+#[derive(Debug, Default, PartialEq, Copy, Clone)]
+pub enum Encoding {
+    #[default]
+    Utf8,
+    Utf16Le,
+    Latin1, // Decoded as Windows-1252, which is a superset
+}
+
+impl FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Encoding, String> {
+        match s {
+            "utf8" => Ok(Encoding::Utf8),
+            "utf16le" => Ok(Encoding::Utf16Le),
+            "latin1" => Ok(Encoding::Latin1),
+            _ => Err(format!("Unknown encoding: {}", s)),
+        }
+    }
+}
+
+deserialize_from_str!(Encoding);
+
+impl Encoding {
+    /// The reader as UTF-8. Plain UTF-8 goes through untouched.
+    pub fn decode<'r, R: Read + 'r>(self, mut reader: R) -> io::Result<Box<dyn Read + 'r>> {
+        let mut start = [0u8; 2];
+        let mut read = 0;
+        while read < start.len() {
+            match reader.read(&mut start[read..])? {
+                0 => break,
+                n => read += n,
+            }
+        }
+        let reader = Cursor::new(start[..read].to_vec()).chain(reader);
+        let encoding = match self {
+            Encoding::Utf8 if start[..read] == [0xFF, 0xFE] => UTF_16LE,
+            Encoding::Utf8 => return Ok(Box::new(reader)),
+            Encoding::Utf16Le => UTF_16LE,
+            Encoding::Latin1 => WINDOWS_1252,
+        };
+        Ok(Box::new(
+            DecodeReaderBytesBuilder::new()
+                .encoding(Some(encoding))
+                .bom_override(true)
+                .build(reader),
+        ))
+    }
+}
+
+#[test]
+fn test_encodings() {
+    use crate::input::dialect::CsvDialect;
+    use crate::PaymentEngine;
+
+    let text = "type,client,tx,amount\ndeposit,1,1,1.5\nwithdrawal,1,2,0.5\n";
+    let mut expected = PaymentEngine::new();
+    expected.import_reader(text.as_bytes()).unwrap();
+
+    let utf16 = |bom: bool| {
+        let mut bytes = if bom { vec![0xFF, 0xFE] } else { Vec::new() };
+        bytes.extend(text.encode_utf16().flat_map(|unit| unit.to_le_bytes()));
+        bytes
+    };
+    let mut utf8_bom = vec![0xEF, 0xBB, 0xBF];
+    utf8_bom.extend(text.bytes());
+    let inputs = [
+        (utf8_bom, Encoding::Utf8),
+        (utf16(true), Encoding::Utf8),
+        (utf16(true), Encoding::Utf16Le),
+        (utf16(false), Encoding::Utf16Le),
+        (
+            format!(
+                "memo,{}",
+                text.replace("\nd", "\n\u{e9},d")
+                    .replace("\nw", "\n\u{e9},w")
+            )
+            .chars()
+            .map(|c| c as u8)
+            .collect(),
+            Encoding::Latin1,
+        ),
+    ];
+    for (input, encoding) in inputs.iter() {
+        let dialect = CsvDialect {
+            encoding: *encoding,
+            ..Default::default()
+        };
+        let mut engine = PaymentEngine::new();
+        engine
+            .import_reader_with_dialect(input.as_slice(), &dialect)
+            .unwrap();
+        assert_eq!(engine.sorted_accounts(), expected.sorted_accounts());
+    }
+    // UTF-16 read as UTF-8 without a BOM to tell is just garbage
+    let mut engine = PaymentEngine::new();
+    assert!(engine.import_reader(utf16(false).as_slice()).is_err());
+}
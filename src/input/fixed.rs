@@ -0,0 +1,245 @@
+use crate::transaction::Transaction;
+use csv::ByteRecord;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::io::BufRead;
+use std::path::Path;
+
+/*
+Fixed-width text, as mainframes write it: one transaction per line, every
+field at the same byte offset on every line. Where they are comes from a
+layout file (--layout), TOML:
+
+  skip_lines = 1                # headers, banners...
+
+  [type]
+  offset = 0                    # in bytes, from 0
+  width = 2
+  [client]
+  offset = 2
+  width = 5
+  [tx]
+  offset = 7
+  width = 10
+  [amount]
+  offset = 17
+  width = 12
+  implied_decimals = 2          # 000000012345 is 123.45
+
+  [types]                       # the codes in the type field
+  "01" = "deposit"
+  "02" = "withdrawal"
+
+Fields are trimmed, so padding with spaces or leading zeros is fine, and a
+line that ends before a field leaves it empty. amount, description and
+category can be left out, and a type without a code in types has to be the
+name itself (deposit, withdrawal...). Blank lines are skipped. Then each
+line is parsed like a CSV row would be, so the same rules and errors apply.
+*/
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Field {
+    pub offset: usize,
+    pub width: usize,
+    /// Digits that are decimals although there's no decimal point
+    #[serde(default)]
+    pub implied_decimals: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Layout {
+    #[serde(default)]
+    pub skip_lines: usize,
+    #[serde(rename = "type")]
+    pub tx_type: Field,
+    pub client: Field,
+    pub tx: Field,
+    pub amount: Option<Field>,
+    pub description: Option<Field>,
+    pub category: Option<Field>,
+    /// Codes of the type field to type names
+    #[serde(default)]
+    pub types: HashMap<String, String>,
+}
+
+impl Layout {
+    pub fn load(path: &Path) -> Result<Layout, Box<dyn Error>> {
+        let layout: Layout = toml::from_str(&fs::read_to_string(path)?)?;
+        for name in layout.types.values() {
+            name.parse::<crate::transaction::TransactionType>()?;
+        }
+        Ok(layout)
+    }
+
+    // The line as a CSV row: type, client, tx, amount, description, category
+    fn record(&self, line: &[u8], record: &mut ByteRecord) -> Result<(), Box<dyn Error>> {
+        let field = |field: &Field| {
+            let end = line.len().min(field.offset + field.width);
+            line.get(field.offset..end).unwrap_or_default().trim_ascii()
+        };
+        record.clear();
+        let tx_type = field(&self.tx_type);
+        match std::str::from_utf8(tx_type)
+            .ok()
+            .and_then(|code| self.types.get(code))
+        {
+            Some(name) => record.push_field(name.as_bytes()),
+            None => record.push_field(tx_type),
+        }
+        record.push_field(field(&self.client));
+        record.push_field(field(&self.tx));
+        match &self.amount {
+            Some(amount) if amount.implied_decimals > 0 => {
+                record.push_field(implied(field(amount), amount.implied_decimals)?.as_bytes())
+            }
+            Some(amount) => record.push_field(field(amount)),
+            None => record.push_field(b""),
+        }
+        for text in [&self.description, &self.category] {
+            record.push_field(text.as_ref().map(field).unwrap_or_default());
+        }
+        Ok(())
+    }
+}
+
+// "-0012345" with 2 implied decimals is "-00123.45"
+fn implied(amount: &[u8], decimals: u32) -> Result<String, &'static str> {
+    let (sign, digits) = match amount.split_first() {
+        Some((sign @ (b'-' | b'+'), digits)) => (Some(*sign as char), digits),
+        _ => (None, amount),
+    };
+    if digits.is_empty() {
+        return Ok(String::new());
+    }
+    if !digits.iter().all(u8::is_ascii_digit) {
+        return Err("Invalid amount");
+    }
+    let decimals = decimals as usize;
+    let padded = format!(
+        "{:0>width$}",
+        std::str::from_utf8(digits).unwrap_or_default(),
+        width = decimals + 1
+    );
+    let (whole, fraction) = padded.split_at(padded.len() - decimals);
+    Ok(format!(
+        "{}{}.{}",
+        sign.map(String::from).unwrap_or_default(),
+        whole,
+        fraction
+    ))
+}
+
+/// The transactions of a fixed-width file, one per line, see above.
+pub struct FixedReader<R> {
+    reader: R,
+    layout: Layout,
+    line: Vec<u8>,
+    record: ByteRecord,
+    // Read so far, for the errors
+    lines: u64,
+}
+
+impl<R: BufRead> FixedReader<R> {
+    pub fn new(reader: R, layout: Layout) -> FixedReader<R> {
+        FixedReader {
+            reader,
+            layout,
+            line: Vec::new(),
+            record: ByteRecord::new(),
+            lines: 0,
+        }
+    }
+
+    fn next_transaction(&mut self) -> Result<Option<Transaction>, Box<dyn Error>> {
+        loop {
+            self.line.clear();
+            if self.reader.read_until(b'\n', &mut self.line)? == 0 {
+                return Ok(None);
+            }
+            self.lines += 1;
+            let line = self.line.strip_suffix(b"\n").unwrap_or(&self.line);
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            if self.lines <= self.layout.skip_lines as u64 || line.trim_ascii().is_empty() {
+                continue;
+            }
+            let parsed = self
+                .layout
+                .record(line, &mut self.record)
+                .and_then(|_| Ok(Transaction::from_byte_record(&self.record)?));
+            return parsed
+                .map(Some)
+                .map_err(|e| format!("Line {}: {}", self.lines, e).into());
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for FixedReader<R> {
+    type Item = Result<Transaction, Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_transaction().transpose()
+    }
+}
+
+#[test]
+fn test_fixed_width() {
+    use rust_decimal::Decimal;
+
+    let layout: Layout = toml::from_str(
+        r#"
+skip_lines = 1
+type = { offset = 0, width = 2 }
+client = { offset = 2, width = 5 }
+tx = { offset = 7, width = 10 }
+amount = { offset = 17, width = 12, implied_decimals = 2 }
+description = { offset = 29, width = 10 }
+
+[types]
+"01" = "deposit"
+"02" = "withdrawal"
+"03" = "dispute"
+"#,
+    )
+    .unwrap();
+    let input = b"HEADER 2024-01-31
+01000010000000001000000012345Salary
+02000010000000002000000000050
+03000010000000001
+01000020000000004000000000000
+
+01000020000000005000000000001\r
+0X000010000000006000000000001
+";
+    let read: Vec<Result<Transaction, Box<dyn Error>>> =
+        FixedReader::new(&input[..], layout).collect();
+    let transaction = read[0].as_ref().unwrap();
+    assert_eq!(transaction.client_id, 1);
+    assert_eq!(transaction.tx_id, 1);
+    assert_eq!(transaction.amount, Some(Decimal::new(12345, 2)));
+    assert_eq!(transaction.description.as_deref(), Some("Salary"));
+    let amounts: Vec<Option<Decimal>> = read[1..5]
+        .iter()
+        .map(|transaction| transaction.as_ref().unwrap().amount)
+        .collect();
+    assert_eq!(
+        amounts,
+        vec![
+            Some(Decimal::new(50, 2)),
+            None,
+            Some(Decimal::ZERO),
+            Some(Decimal::new(1, 2))
+        ]
+    );
+    assert_eq!(
+        read[5].as_ref().unwrap_err().to_string(),
+        "Line 8: Unknown transaction type"
+    );
+    assert_eq!(read.len(), 6);
+    assert_eq!(implied(b"5", 4).unwrap(), "0.0005");
+    assert_eq!(implied(b"-0012345", 2).unwrap(), "-00123.45");
+    assert!(implied(b"1.5", 2).is_err());
+}
@@ -0,0 +1,37 @@
+use crate::transaction::*;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::convert::TryFrom;
+use std::str::FromStr;
+
+/// A transaction as JSON, e.g. {"type": "deposit", "client": 1, "tx": 1, "amount": "1.5"}.
+/// Amounts are best sent as strings, numbers go through a float.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JsonTransaction {
+    #[serde(rename = "type")]
+    pub tx_type: String,
+    pub client: ClientId,
+    pub tx: TransactionId,
+    pub amount: Option<Decimal>,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub category: Option<String>,
+}
+
+impl TryFrom<JsonTransaction> for Transaction {
+    type Error = &'static str;
+
+    fn try_from(json: JsonTransaction) -> Result<Transaction, &'static str> {
+        Ok(Transaction {
+            tx_type: TransactionType::from_str(&json.tx_type)?,
+            client_id: json.client,
+            tx_id: json.tx,
+            amount: json.amount,
+            status: TransactionStatus::OK,
+            description: json.description.map(String::into_boxed_str),
+            category: json.category.map(String::into_boxed_str),
+        })
+    }
+}
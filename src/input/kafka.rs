@@ -0,0 +1,84 @@
+use crate::actors::ActorRouter;
+use crate::input::parse_message;
+use crate::transaction::Transaction;
+use crate::PaymentEngine;
+use kafka::consumer::{Consumer, FetchOffset, GroupOffsetStorage};
+use std::error::Error;
+use std::mem;
+use tracing::{debug, info, warn};
+
+pub struct KafkaSpec {
+    pub brokers: Vec<String>,
+    pub topic: String,
+    pub group: String,
+    /// Process the clients in actors, see actors.rs
+    pub actors: bool,
+}
+
+/// Consumes the topic forever. Each message is a CSV line or a JSON
+/// transaction (see parse_message). Offsets are only committed once every
+/// message up to them has been processed, so after a crash messages can be
+/// processed twice but never skipped.
+///
+/// Malformed messages are logged and skipped, otherwise a single one would
+/// stop the consumer for good. An error processing a well formed one stops
+/// the consumer without committing it.
+///
+/// With actors, the engine only has its state back once the consumer stops.
+pub fn consume(engine: &mut PaymentEngine, spec: &KafkaSpec) -> Result<(), Box<dyn Error>> {
+    if !spec.actors {
+        return consume_with(
+            spec,
+            |transaction| engine.process_transaction(transaction).map(|_| ()),
+            || Ok(()),
+        );
+    }
+    let router = std::cell::RefCell::new(ActorRouter::new(mem::take(engine))?);
+    let consumed = consume_with(
+        spec,
+        |transaction| router.borrow_mut().send(transaction),
+        // Offsets can only be committed once the actors processed it all
+        || router.borrow().flush(),
+    );
+    let (actors, result) = router.into_inner().into_engine();
+    *engine = actors;
+    consumed.and(result)
+}
+
+fn consume_with(
+    spec: &KafkaSpec,
+    mut process: impl FnMut(Transaction) -> Result<(), Box<dyn Error>>,
+    flush: impl Fn() -> Result<(), Box<dyn Error>>,
+) -> Result<(), Box<dyn Error>> {
+    let mut consumer = Consumer::from_hosts(spec.brokers.clone())
+        .with_topic(spec.topic.clone())
+        .with_group(spec.group.clone())
+        .with_fallback_offset(FetchOffset::Earliest)
+        .with_offset_storage(Some(GroupOffsetStorage::Kafka))
+        .create()?;
+    info!("Consuming {} as {}", spec.topic, spec.group);
+    loop {
+        let sets = consumer.poll()?;
+        if sets.is_empty() {
+            continue;
+        }
+        for set in sets.iter() {
+            for message in set.messages() {
+                match parse_message(message.value) {
+                    Ok(transaction) => process(transaction)?,
+                    Err(e) => warn!(
+                        "Skipping message {}:{}@{}: {}",
+                        set.topic(),
+                        set.partition(),
+                        message.offset,
+                        e
+                    ),
+                }
+            }
+            consumer.consume_messageset(set)?;
+        }
+        flush()?;
+        consumer.commit_consumed()?;
+        debug!("Offsets committed");
+    }
+}
@@ -0,0 +1,129 @@
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io;
+use std::ops::Deref;
+use std::path::Path;
+
+/*
+The input file mapped into memory, for --mmap: the CSV reader gets the
+mapping as one &[u8] and copies records straight out of it, so there's no
+read() per buffer and no second buffer in between. For very large files on
+fast local disks; on a network filesystem the page faults are slower than
+reading. The file mustn't change while it's mapped (a truncated file makes
+the reader crash), which is why the watch mode, whose files can still be
+growing, doesn't use it.
+
+Without mmap (not Unix) the file is read into memory instead.
+*/
+
+pub struct Mmap {
+    #[cfg(unix)]
+    ptr: *mut libc::c_void,
+    #[cfg(not(unix))]
+    bytes: Vec<u8>,
+    len: usize,
+}
+
+// The mapping is read only and owned by this struct
+unsafe impl Send for Mmap {}
+unsafe impl Sync for Mmap {}
+
+impl Mmap {
+    /// Maps the file at `path`.
+    ///
+    /// # Safety
+    ///
+    /// Nothing may truncate or write to the file while the Mmap lives: the
+    /// slice it derefs to would change under the reader, or fault (SIGBUS)
+    /// past the new end of the file.
+    #[cfg(unix)]
+    pub unsafe fn open<P: AsRef<Path>>(path: P) -> io::Result<Mmap> {
+        use std::os::unix::io::AsRawFd;
+
+        let file = File::open(path)?;
+        let len = usize::try_from(file.metadata()?.len())
+            .map_err(|_| io::Error::other("File too large to map"))?;
+        if len == 0 {
+            // Zero length mappings are an error
+            return Ok(Mmap {
+                ptr: std::ptr::null_mut(),
+                len,
+            });
+        }
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        // Read front to back, once
+        unsafe { libc::madvise(ptr, len, libc::MADV_SEQUENTIAL) };
+        Ok(Mmap { ptr, len })
+    }
+
+    /// Reads the file at `path` into memory.
+    ///
+    /// # Safety
+    ///
+    /// None needed, it's unsafe to match the Unix one.
+    #[cfg(not(unix))]
+    pub unsafe fn open<P: AsRef<Path>>(path: P) -> io::Result<Mmap> {
+        let bytes = std::fs::read(path)?;
+        Ok(Mmap {
+            len: bytes.len(),
+            bytes,
+        })
+    }
+}
+
+impl Deref for Mmap {
+    type Target = [u8];
+
+    #[cfg(unix)]
+    fn deref(&self) -> &[u8] {
+        if self.len == 0 {
+            return &[];
+        }
+        unsafe { std::slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+
+    #[cfg(not(unix))]
+    fn deref(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        if self.len > 0 {
+            unsafe { libc::munmap(self.ptr, self.len) };
+        }
+    }
+}
+
+#[test]
+fn test_mmap() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("input.csv");
+    std::fs::write(&path, "type,client,tx,amount\ndeposit,1,1,1.5\n").unwrap();
+    // SAFETY: the test's own temporary file, nothing else writes to it
+    let map = unsafe { Mmap::open(&path) }.unwrap();
+    assert_eq!(&map[..], &b"type,client,tx,amount\ndeposit,1,1,1.5\n"[..]);
+
+    let mut engine = crate::PaymentEngine::new();
+    engine.import_reader(&map[..]).unwrap();
+    assert_eq!(engine.len(), 1);
+
+    drop(map);
+    std::fs::write(&path, "").unwrap();
+    // SAFETY: as above
+    assert!(unsafe { Mmap::open(&path) }.unwrap().is_empty());
+}
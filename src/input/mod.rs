@@ -0,0 +1,124 @@
+pub mod amount;
+#[cfg(feature = "parquet")]
+pub mod arrow;
+pub mod batch;
+pub mod binary;
+pub mod camt;
+pub mod chunked;
+pub mod control;
+pub mod dialect;
+pub mod encoding;
+pub mod fixed;
+pub mod json;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+pub mod mmap;
+pub mod mt940;
+pub mod nacha;
+pub mod ofx;
+pub mod protobuf;
+#[cfg(feature = "xlsx")]
+pub mod xlsx;
+
+use crate::transaction::{Transaction, TransactionId};
+use csv::{ByteRecord, ReaderBuilder};
+use std::convert::TryFrom;
+use std::error::Error;
+use std::str::FromStr;
+
+#[derive(Debug, Default, PartialEq, Copy, Clone)]
+pub enum InputFormat {
+    #[default]
+    Csv,
+    Protobuf,
+    Parquet,     // Needs the parquet feature, see arrow.rs
+    Arrow,       // IPC file format, same
+    Fixed,       // Fixed-width text, needs a layout, see fixed.rs
+    Ofx,         // Bank statements, OFX or QFX, see ofx.rs
+    Camt,        // Bank statements, ISO 20022 camt.053, see camt.rs
+    Nacha,       // ACH files, see nacha.rs
+    Mt940,       // Bank statements, SWIFT MT940, see mt940.rs
+    Xlsx,        // Excel, needs the xlsx feature, see xlsx.rs
+    MessagePack, // Length-prefixed records, see binary.rs
+    Cbor,        // Same
+}
+
+impl FromStr for InputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<InputFormat, String> {
+        match s {
+            "csv" => Ok(InputFormat::Csv),
+            "protobuf" => Ok(InputFormat::Protobuf),
+            "parquet" => Ok(InputFormat::Parquet),
+            "arrow" => Ok(InputFormat::Arrow),
+            "fixed" => Ok(InputFormat::Fixed),
+            "ofx" | "qfx" => Ok(InputFormat::Ofx),
+            "camt" | "camt053" => Ok(InputFormat::Camt),
+            "nacha" | "ach" => Ok(InputFormat::Nacha),
+            "mt940" => Ok(InputFormat::Mt940),
+            "xlsx" => Ok(InputFormat::Xlsx),
+            "msgpack" => Ok(InputFormat::MessagePack),
+            "cbor" => Ok(InputFormat::Cbor),
+            _ => Err(format!("Unknown input format: {}", s)),
+        }
+    }
+}
+
+deserialize_from_str!(InputFormat);
+
+/// Where the transactions come from: files (see InputFormat) or a Kafka topic.
+#[derive(Debug, Default, PartialEq, Copy, Clone)]
+pub enum SourceKind {
+    #[default]
+    File,
+    Kafka,
+}
+
+impl FromStr for SourceKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<SourceKind, String> {
+        match s {
+            "file" => Ok(SourceKind::File),
+            "kafka" => Ok(SourceKind::Kafka),
+            _ => Err(format!("Unknown source: {}", s)),
+        }
+    }
+}
+
+/// A single transaction on its own, as it comes in a message: a JSON object
+/// (see json.rs) or a CSV line without header.
+pub fn parse_message(payload: &[u8]) -> Result<Transaction, Box<dyn Error>> {
+    let payload = payload.trim_ascii();
+    if payload.starts_with(b"{") {
+        let json: json::JsonTransaction = serde_json::from_slice(payload)?;
+        return Ok(Transaction::try_from(json)?);
+    }
+    let mut record = ByteRecord::new();
+    let mut reader = ReaderBuilder::new().has_headers(false).from_reader(payload);
+    if !reader.read_byte_record(&mut record)? {
+        return Err("Empty message".into());
+    }
+    Ok(Transaction::from_byte_record(&record)?)
+}
+
+/// The tx id of a bank's reference for a transaction (a FITID, an
+/// EndToEndId...): the reference if it's a number that fits, its CRC-32 if
+/// not, so the same statement gives the same ids every time.
+pub fn statement_tx_id(reference: &str) -> TransactionId {
+    match reference.parse::<TransactionId>() {
+        Ok(id) => id,
+        Err(_) => crc32fast::hash(reference.as_bytes()),
+    }
+}
+
+#[test]
+fn test_parse_message() {
+    let csv = parse_message(b"deposit, 1, 2, 3.5\n").unwrap();
+    let json =
+        parse_message(br#"{"type": "deposit", "client": 1, "tx": 2, "amount": "3.5"}"#).unwrap();
+    assert_eq!(csv, json);
+    assert!(parse_message(b"").is_err());
+    assert!(parse_message(br#"{"type": "refund", "client": 1, "tx": 2}"#).is_err());
+}
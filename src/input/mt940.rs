@@ -0,0 +1,204 @@
+use crate::input::statement_tx_id;
+use crate::transaction::{ClientId, Transaction, TransactionStatus, TransactionType};
+use rust_decimal::Decimal;
+use std::error::Error;
+use std::io::Read;
+use std::str::FromStr;
+
+/*
+SWIFT MT940 customer statements, what treasury gets from its banks, so they
+can be reconciled with the engine too:
+
+  :20:STMT240131
+  :25:NL91ABNA0417164300
+  :60F:C240130EUR1000,00
+  :61:2401310131D42,50NTRFINV117//B4A31XK92
+  Card payment
+  :86:Office supplies
+  :62F:C240131EUR957,50
+  -
+
+Every statement line (:61:), in the order of the file whatever statement
+it's in, is a deposit if it's a credit (C) and a withdrawal if it's a debit
+(D). A reversal is booked as what it does to the balance, a reversed credit
+(RC) a withdrawal and a reversed debit (RD) a deposit, as the line doesn't
+say which one it reverses. Balances and the rest of the tags are left out.
+
+The tx id is from the bank's reference (after the //), or without one the
+reference for the account owner unless it's NONREF: the reference if it's a
+number that fits, its CRC-32 if not. The :86: after the line is the
+description, its lines joined, or without one the supplementary details (the
+second line of the :61:). Like other statements it doesn't say which client
+it is (--statement-client).
+*/
+
+type Row = Result<Transaction, Box<dyn Error>>;
+
+/// The transactions of the statements, or why a statement line can't be
+/// read, in order. Only fails if the input can't be read at all.
+pub fn read<R: Read>(mut reader: R, client: ClientId) -> Result<Vec<Row>, Box<dyn Error>> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    // Banks still send Latin-1, only descriptions could be off
+    let text = String::from_utf8_lossy(&bytes);
+    let mut read: Vec<Row> = Vec::new();
+    // Waiting for its :86:
+    let mut pending: Option<Transaction> = None;
+    for (line, tag, value) in fields(&text) {
+        match (tag, &mut pending) {
+            ("86", Some(transaction)) => {
+                let information: Vec<&str> = value.lines().map(str::trim).collect();
+                transaction.description = Some(information.join(" ").into_boxed_str())
+                    .filter(|description| !description.is_empty());
+            }
+            ("61", _) => {
+                read.extend(pending.take().map(Ok));
+                match statement_line(&value, client) {
+                    Ok(transaction) => pending = Some(transaction),
+                    Err(e) => read.push(Err(format!("Line {}: {}", line, e).into())),
+                }
+            }
+            _ => read.extend(pending.take().map(Ok)),
+        }
+    }
+    read.extend(pending.map(Ok));
+    Ok(read)
+}
+
+// The tags and their values, continuation lines included, and the line they
+// start on. Block headers ({1:...) and the - closing a statement end a value.
+fn fields(text: &str) -> Vec<(u64, &str, String)> {
+    let mut fields: Vec<(u64, &str, String)> = Vec::new();
+    let mut open = false;
+    for (line, number) in text.lines().zip(1..) {
+        let tagged = line
+            .strip_prefix(':')
+            .and_then(|rest| rest.split_once(':'))
+            .filter(|(tag, _)| !tag.is_empty() && tag.len() <= 3);
+        match tagged {
+            Some((tag, value)) => {
+                fields.push((number, tag, value.to_string()));
+                open = true;
+            }
+            None if line.starts_with('-') || line.starts_with('{') => open = false,
+            None if open => {
+                if let Some((_, _, value)) = fields.last_mut() {
+                    value.push('\n');
+                    value.push_str(line);
+                }
+            }
+            None => {}
+        }
+    }
+    fields
+}
+
+// :61:YYMMDD[MMDD](C|D|RC|RD)[funds code]amount(type, 4)reference[//bank reference]
+//     [supplementary details]
+fn statement_line(value: &str, client: ClientId) -> Result<Transaction, &'static str> {
+    let (first, details) = match value.split_once('\n') {
+        Some((first, details)) => (first, details.trim()),
+        None => (value, ""),
+    };
+    let digits = first.bytes().take_while(u8::is_ascii_digit).count();
+    let rest = match digits {
+        6 | 10 => &first[digits..],
+        _ => return Err("Invalid value date"),
+    };
+    let (tx_type, rest) = if let Some(rest) = rest.strip_prefix("RC") {
+        (TransactionType::Withdrawal, rest)
+    } else if let Some(rest) = rest.strip_prefix("RD") {
+        (TransactionType::Deposit, rest)
+    } else if let Some(rest) = rest.strip_prefix('C') {
+        (TransactionType::Deposit, rest)
+    } else if let Some(rest) = rest.strip_prefix('D') {
+        (TransactionType::Withdrawal, rest)
+    } else {
+        return Err("Invalid debit/credit mark");
+    };
+    // The funds code is a letter, the amount starts with a digit
+    let rest = match rest.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        true => &rest[1..],
+        false => rest,
+    };
+    let length = rest
+        .bytes()
+        .take_while(|c| c.is_ascii_digit() || *c == b',')
+        .count();
+    let amount = rest[..length].replace(',', ".");
+    let amount = Decimal::from_str(amount.trim_end_matches('.')).map_err(|_| "Invalid amount")?;
+    let references = rest[length..].get(4..).ok_or("No transaction type")?;
+    let (owner, bank) = match references.split_once("//") {
+        Some((owner, bank)) => (owner.trim(), bank.trim()),
+        None => (references.trim(), ""),
+    };
+    let reference = Some(bank)
+        .filter(|bank| !bank.is_empty())
+        .or(Some(owner).filter(|owner| !owner.is_empty() && *owner != "NONREF"))
+        .ok_or("No reference")?;
+    Ok(Transaction {
+        tx_type,
+        client_id: client,
+        tx_id: statement_tx_id(reference),
+        amount: Some(amount),
+        status: TransactionStatus::OK,
+        description: Some(details)
+            .filter(|details| !details.is_empty())
+            .map(Into::into),
+        category: None,
+    })
+}
+
+#[test]
+fn test_mt940() {
+    let statement = b"{1:F01BANKBEBBAXXX0000000000}{2:O940}{4:
+:20:STMT240131
+:25:NL91ABNA0417164300
+:28C:00031/001
+:60F:C240130EUR1000,00
+:61:2401310131D42,50NTRFINV117//B4A31XK92
+Card payment
+:86:Office supplies,
+ paper and toner
+:61:240131C1500,NTRFNONREF//77
+:61:240131RCR10,00NCHGNONREF
+:61:240131X1,00NTRFREF
+:61:240131RD5,5NTRF0042
+:62F:C240131EUR2447,50
+-}
+";
+    let transactions = read(&statement[..], 4).unwrap();
+    let summary: Vec<String> = transactions
+        .iter()
+        .map(|row| match row {
+            Ok(transaction) => format!(
+                "{} {} {} {}",
+                transaction.tx_type,
+                transaction.tx_id,
+                transaction.amount.unwrap(),
+                transaction.description.as_deref().unwrap_or_default()
+            ),
+            Err(e) => e.to_string(),
+        })
+        .collect();
+    assert_eq!(
+        summary,
+        vec![
+            format!(
+                "withdrawal {} 42.50 Office supplies, paper and toner",
+                statement_tx_id("B4A31XK92")
+            ),
+            "deposit 77 1500 ".to_string(),
+            "Line 11: No reference".to_string(),
+            "Line 12: Invalid debit/credit mark".to_string(),
+            "deposit 42 5.5 ".to_string(),
+        ]
+    );
+    assert_eq!(transactions[0].as_ref().unwrap().client_id, 4);
+
+    let details = read(&b":61:240131C1,00NTRF1\nRefund\n-"[..], 1).unwrap();
+    assert_eq!(
+        details[0].as_ref().unwrap().description.as_deref(),
+        Some("Refund")
+    );
+}
@@ -0,0 +1,395 @@
+use crate::input::statement_tx_id;
+use crate::transaction::{ClientId, Transaction, TransactionStatus, TransactionType};
+use rust_decimal::Decimal;
+use std::error::Error;
+use std::io::Read;
+
+/*
+NACHA (ACH) files, as US banks exchange them: 94 character records, one per
+line or all in one, numbered by the spec from 1. Of them:
+
+  5  batch header     SEC code (PPD, CCD...) at 51-53
+  6  entry            transaction code 2-3, RDFI routing 4-11, amount in
+                      cents 30-39, individual id 40-54, name 55-76, trace
+                      number 80-94
+  7  addenda          type 2-3; returns (99) have their R-code at 4-6 and
+                      the trace number of the entry they return at 7-21
+  8  batch control    entries and addenda 5-10, entry hash 11-20, debits in
+                      cents 21-32, credits 33-44
+
+As the receiving bank sees them: a credit entry (22 checking, 32 savings,
+42 general ledger, 52 loan) is a deposit, a debit one (27, 37, 47, 55) a
+withdrawal. A return (21, 26, 31...) with its 99 addenda is a dispute and a
+chargeback of the entry it returns, with the R-code as description; the
+engine can only charge back deposits, so a returned debit comes out as
+ignored. Prenotes, zero dollar entries and notifications of change move no
+money and are left out.
+
+The tx id is from the trace number, which doesn't fit so it's its CRC-32.
+The client is the individual id if it's a number that fits, or else the one
+given (--statement-client). The individual name is the description.
+
+A batch only goes through after its control record checks out: the number of
+entries and addenda, the entry hash (the routing numbers added up, last ten
+digits) and the debit and credit totals. If it doesn't, or the batch is
+anything but PPD or CCD or has a record that can't be read, none of it does
+and it's one error instead. File headers and controls aren't checked.
+*/
+
+type Row = Result<Transaction, Box<dyn Error>>;
+
+const RECORD: usize = 94;
+
+// The record's characters from..to, counting from 1 and both included like
+// the spec does
+fn field(record: &str, from: usize, to: usize) -> &str {
+    &record[from - 1..to]
+}
+
+fn number(record: &str, from: usize, to: usize, name: &str) -> Result<u64, String> {
+    let digits = field(record, from, to);
+    match digits.bytes().all(|digit| digit.is_ascii_digit()) {
+        true => Ok(digits.parse().unwrap_or_default()),
+        false => Err(format!("Invalid {}", name)),
+    }
+}
+
+#[derive(Debug)]
+enum Kind {
+    Entry(TransactionType),
+    Return,
+    // Prenotes, zero dollar entries
+    Nothing,
+}
+
+#[derive(Debug)]
+struct Entry {
+    kind: Kind,
+    client: ClientId,
+    trace: String,
+    amount: Decimal,
+    name: String,
+    // R-code and original trace number, from the 99 addenda
+    returned: Option<(String, String)>,
+    notification: bool,
+}
+
+#[derive(Debug, Default)]
+struct Batch {
+    line: u64,
+    entries: Vec<Entry>,
+    records: u64,
+    hash: u64,
+    debits: u64,
+    credits: u64,
+    error: Option<String>,
+}
+
+impl Batch {
+    fn entry(&mut self, record: &str, client: Option<ClientId>) -> Result<(), String> {
+        let code = number(record, 2, 3, "transaction code")?;
+        let kind = match code {
+            22 | 32 | 42 | 52 => Kind::Entry(TransactionType::Deposit),
+            27 | 37 | 47 | 55 => Kind::Entry(TransactionType::Withdrawal),
+            21 | 26 | 31 | 36 | 41 | 46 | 51 | 56 => Kind::Return,
+            23 | 24 | 28 | 29 | 33 | 34 | 38 | 39 | 43 | 44 | 48 | 49 | 53 | 54 => Kind::Nothing,
+            _ => return Err(format!("Unknown transaction code {}", code)),
+        };
+        let cents = number(record, 30, 39, "amount")?;
+        self.records += 1;
+        self.hash += number(record, 4, 11, "routing number")?;
+        match code % 10 >= 5 {
+            true => self.debits += cents,
+            false => self.credits += cents,
+        }
+        let id = field(record, 40, 54).trim();
+        let client = match (id.parse::<ClientId>(), client) {
+            (Ok(client), _) | (Err(_), Some(client)) => client,
+            (Err(_), None) => {
+                return Err(
+                    "The individual id isn't a client id, and there's no --statement-client".into(),
+                )
+            }
+        };
+        self.entries.push(Entry {
+            kind,
+            client,
+            trace: field(record, 80, 94).to_string(),
+            amount: Decimal::new(cents as i64, 2),
+            name: field(record, 55, 76).trim().to_string(),
+            returned: None,
+            notification: false,
+        });
+        Ok(())
+    }
+
+    fn addenda(&mut self, record: &str) -> Result<(), String> {
+        self.records += 1;
+        let entry = self.entries.last_mut().ok_or("Addenda without an entry")?;
+        match field(record, 2, 3) {
+            "99" => {
+                entry.returned = Some((
+                    field(record, 4, 6).to_string(),
+                    field(record, 7, 21).to_string(),
+                ))
+            }
+            "98" => entry.notification = true,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn control(&self, record: &str) -> Result<(), String> {
+        let checks = [
+            (
+                "entry and addenda count",
+                number(record, 5, 10, "count")?,
+                self.records,
+            ),
+            (
+                "entry hash",
+                number(record, 11, 20, "entry hash")?,
+                self.hash % 10_000_000_000,
+            ),
+            (
+                "total debits",
+                number(record, 21, 32, "total debits")?,
+                self.debits,
+            ),
+            (
+                "total credits",
+                number(record, 33, 44, "total credits")?,
+                self.credits,
+            ),
+        ];
+        for (name, control, counted) in checks {
+            if control != counted {
+                return Err(format!(
+                    "Batch control doesn't match: {} {}, the entries add up to {}",
+                    name, control, counted
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn transactions(self) -> Result<Vec<Transaction>, String> {
+        let mut transactions = Vec::new();
+        for entry in self.entries {
+            let transaction = |tx_type, trace: &str, amount, description: String| Transaction {
+                tx_type,
+                client_id: entry.client,
+                tx_id: statement_tx_id(trace),
+                amount,
+                status: TransactionStatus::OK,
+                description: Some(description.into_boxed_str()).filter(|text| !text.is_empty()),
+                category: None,
+            };
+            match (&entry.kind, &entry.returned) {
+                (Kind::Entry(tx_type), _) => transactions.push(transaction(
+                    *tx_type,
+                    &entry.trace,
+                    Some(entry.amount),
+                    entry.name.clone(),
+                )),
+                (Kind::Return, Some((code, original))) => {
+                    for tx_type in [TransactionType::Dispute, TransactionType::Chargeback] {
+                        transactions.push(transaction(tx_type, original, None, code.clone()));
+                    }
+                }
+                (Kind::Return, None) if entry.notification => {}
+                (Kind::Return, None) => {
+                    return Err(format!("Return {} without a return addenda", entry.trace))
+                }
+                (Kind::Nothing, _) => {}
+            }
+        }
+        Ok(transactions)
+    }
+}
+
+/// The transactions of the file's batches, or why a batch or record can't
+/// be read, in order. Only fails if the input can't be read at all. The
+/// client is for entries whose individual id isn't one.
+pub fn read<R: Read>(mut reader: R, client: Option<ClientId>) -> Result<Vec<Row>, Box<dyn Error>> {
+    let mut text = String::new();
+    reader.read_to_string(&mut text)?;
+    let mut read: Vec<Row> = Vec::new();
+    let mut batch: Option<Batch> = None;
+    for (line, record) in records(&text) {
+        let error = |e: String| -> Row { Err(format!("Line {}: {}", line, e).into()) };
+        if record.len() != RECORD || !record.is_ascii() {
+            match &mut batch {
+                Some(batch) if batch.error.is_none() => {
+                    batch.error = Some(format!("Line {}: Not a 94 character record", line))
+                }
+                Some(_) => {}
+                None => read.push(error("Not a 94 character record".into())),
+            }
+            continue;
+        }
+        match (&record[..1], &mut batch) {
+            ("5", _) => {
+                if let Some(unfinished) = batch.take() {
+                    read.push(Err(format!(
+                        "Line {}: Batch without a control record",
+                        unfinished.line
+                    )
+                    .into()));
+                }
+                let sec = field(record, 51, 53);
+                batch = Some(Batch {
+                    line,
+                    error: Some(format!("{} entries aren't supported", sec))
+                        .filter(|_| !matches!(sec, "PPD" | "CCD")),
+                    ..Default::default()
+                });
+            }
+            ("6", Some(batch)) => {
+                if let (Err(e), None) = (batch.entry(record, client), &batch.error) {
+                    batch.error = Some(format!("Line {}: {}", line, e));
+                }
+            }
+            ("7", Some(batch)) => {
+                if let (Err(e), None) = (batch.addenda(record), &batch.error) {
+                    batch.error = Some(format!("Line {}: {}", line, e));
+                }
+            }
+            ("8", Some(_)) => {
+                let mut finished = batch.take().unwrap_or_default();
+                let start = finished.line;
+                let checked = match finished.error.take() {
+                    Some(e) => Err(e),
+                    None => finished
+                        .control(record)
+                        .map_err(|e| format!("Line {}: {}", line, e))
+                        .and_then(|_| finished.transactions()),
+                };
+                match checked {
+                    Ok(transactions) => read.extend(transactions.into_iter().map(Ok)),
+                    Err(e) => read.push(Err(format!("Batch at line {}: {}", start, e).into())),
+                }
+            }
+            ("6" | "7" | "8", None) => read.push(error("Record outside a batch".into())),
+            ("1" | "9", _) => {}
+            (kind, _) => read.push(error(format!("Unknown record type {}", kind))),
+        }
+    }
+    if let Some(unfinished) = batch {
+        read.push(Err(format!(
+            "Line {}: Batch without a control record",
+            unfinished.line
+        )
+        .into()));
+    }
+    Ok(read)
+}
+
+// The records and the line they're on: one per line, or many in a line if
+// the file wasn't split in lines
+fn records(text: &str) -> impl Iterator<Item = (u64, &str)> {
+    text.lines()
+        .zip(1..)
+        .filter(|(record, _)| !record.trim().is_empty())
+        .flat_map(|(record, line)| {
+            let chunked = record.len() > RECORD && record.len() % RECORD == 0 && record.is_ascii();
+            let size = match chunked {
+                true => RECORD,
+                false => record.len().max(1),
+            };
+            (0..record.len())
+                .step_by(size)
+                .map(move |start| (line, &record[start..start + size]))
+        })
+}
+
+#[test]
+fn test_nacha() {
+    fn header(sec: &str) -> String {
+        format!(
+            "5200ACME CORP{:27}1234567890{}PAYROLL   {:15}1091000010000001",
+            "", sec, ""
+        )
+    }
+    fn entry(code: u8, cents: u64, id: &str, name: &str, trace: &str) -> String {
+        format!(
+            "6{}091000019{:<17}{:010}{:<15}{:<22}  0{}",
+            code, "12345", cents, id, name, trace
+        )
+    }
+    fn returned(code: &str, trace: &str) -> String {
+        format!(
+            "799{}{}{:6}09100001{:44}091000010000009",
+            code, trace, "", ""
+        )
+    }
+    fn control(records: u64, hash: u64, debits: u64, credits: u64) -> String {
+        format!(
+            "8200{:06}{:010}{:012}{:012}1234567890{:25}091000010000001",
+            records, hash, debits, credits, ""
+        )
+    }
+    let file = [
+        format!("101 091000019 1234567892401310000A094101{:54}", ""),
+        header("PPD"),
+        entry(22, 150000, "7", "JANE DOE", "091000010000001"),
+        entry(27, 2550, "7", "JANE DOE", "091000010000002"),
+        entry(23, 0, "7", "PRENOTE", "091000010000003"),
+        control(3, 27300003, 2550, 150000),
+        header("CCD"),
+        entry(26, 150000, "", "JANE DOE", "091000010000004"),
+        returned("R01", "091000010000001"),
+        control(2, 9100001, 150000, 0),
+        header("PPD"),
+        entry(22, 100, "8", "WRONG TOTALS", "091000010000005"),
+        control(1, 9100001, 0, 99),
+        header("WEB"),
+        entry(22, 100, "8", "INTERNET", "091000010000006"),
+        control(1, 9100001, 0, 100),
+        format!("9000004000001{:81}", ""),
+    ];
+    for record in &file {
+        assert_eq!(record.len(), RECORD, "{}", record);
+    }
+    let transactions = read(file.join("\n").as_bytes(), Some(2)).unwrap();
+    let summary: Vec<String> = transactions
+        .iter()
+        .map(|row| match row {
+            Ok(transaction) => format!(
+                "{} {} {} {}",
+                transaction.tx_type,
+                transaction.client_id,
+                transaction
+                    .amount
+                    .map(|amount| amount.to_string())
+                    .unwrap_or_default(),
+                transaction.description.as_deref().unwrap_or_default()
+            ),
+            Err(e) => e.to_string(),
+        })
+        .collect();
+    assert_eq!(
+        summary,
+        vec![
+            "deposit 7 1500.00 JANE DOE",
+            "withdrawal 7 25.50 JANE DOE",
+            "dispute 2  R01",
+            "chargeback 2  R01",
+            "Batch at line 11: Line 13: Batch control doesn't match: total credits 99, the entries add up to 100",
+            "Batch at line 14: WEB entries aren't supported",
+        ]
+    );
+    let original = transactions[0].as_ref().unwrap().tx_id;
+    assert_eq!(original, statement_tx_id("091000010000001"));
+    assert_eq!(transactions[3].as_ref().unwrap().tx_id, original);
+
+    // All in one line, and a batch cut short
+    let file = [header("PPD"), entry(22, 1, "1", "", "091000010000007")].concat();
+    let transactions = read(file.as_bytes(), None).unwrap();
+    assert_eq!(
+        transactions[0].as_ref().unwrap_err().to_string(),
+        "Line 1: Batch without a control record"
+    );
+    let file = [header("PPD"), entry(22, 1, "x", "", "091000010000007")].join("\n");
+    assert!(read(file.as_bytes(), None).unwrap()[0].is_err());
+}
@@ -0,0 +1,157 @@
+use crate::input::statement_tx_id;
+use crate::transaction::{ClientId, Transaction, TransactionStatus, TransactionType};
+use rust_decimal::Decimal;
+use std::error::Error;
+use std::io::Read;
+use std::str::FromStr;
+
+/*
+OFX (and QFX, Quicken's flavour of it) bank statements, what online banking
+exports, so they can be run without converting them first. Both the old SGML
+files, where tags aren't closed:
+
+  <STMTTRN>
+  <TRNTYPE>DEBIT
+  <DTPOSTED>20240131
+  <TRNAMT>-42.50
+  <FITID>2024013100017
+  <NAME>GROCERY STORE
+  </STMTTRN>
+
+and the XML ones of OFX 2 are read: every STMTTRN, in the order of the file,
+whatever statement or account it's in, is one transaction. A positive TRNAMT
+is a deposit, a negative one a withdrawal of as much. A statement doesn't say
+which client it is, so that's given (--statement-client) and all of them are
+that client's.
+
+The tx id is the FITID, the bank's id for the transaction, if it's a number
+that fits, or else its CRC-32, so importing the same statement twice repeats
+ids rather than the transactions. Two FITIDs can share a CRC-32, which looks
+like a repeated transaction, but one in four billion. NAME, or MEMO without
+it, is the description. Everything else (dates, balances, TRNTYPE...) is
+left out.
+*/
+
+type Row = Result<Transaction, Box<dyn Error>>;
+
+/// The transactions of the statement, or why one of them can't be read, in
+/// order. Only fails if the input can't be read at all.
+pub fn read<R: Read>(mut reader: R, client: ClientId) -> Result<Vec<Row>, Box<dyn Error>> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    // SGML files are often Windows-1252, only descriptions could be off
+    let text = String::from_utf8_lossy(&bytes);
+    let mut read = Vec::new();
+    let mut rest = &text[..];
+    while let Some(start) = rest.find("<STMTTRN>") {
+        rest = &rest[start + "<STMTTRN>".len()..];
+        let end = ["</STMTTRN>", "<STMTTRN>", "</BANKTRANLIST>"]
+            .iter()
+            .filter_map(|tag| rest.find(tag))
+            .min()
+            .unwrap_or(rest.len());
+        let number = read.len() + 1;
+        read.push(
+            transaction(&rest[..end], client)
+                .map_err(|e| format!("Statement transaction {}: {}", number, e).into()),
+        );
+        rest = &rest[end..];
+    }
+    Ok(read)
+}
+
+fn transaction(block: &str, client: ClientId) -> Result<Transaction, &'static str> {
+    let amount = element(block, "TRNAMT").ok_or("No TRNAMT")?;
+    let amount = Decimal::from_str(&amount.replace(',', ".")).map_err(|_| "Invalid TRNAMT")?;
+    let fitid = element(block, "FITID").ok_or("No FITID")?;
+    let description = element(block, "NAME").or_else(|| element(block, "MEMO"));
+    Ok(Transaction {
+        tx_type: match amount.is_sign_negative() {
+            true => TransactionType::Withdrawal,
+            false => TransactionType::Deposit,
+        },
+        client_id: client,
+        tx_id: statement_tx_id(&fitid),
+        amount: Some(amount.abs()),
+        status: TransactionStatus::OK,
+        description: description.map(String::into_boxed_str),
+        category: None,
+    })
+}
+
+// The text of <TAG>, up to the next tag whether it's closed or not, without
+// entities. None if it isn't there or is empty.
+fn element(block: &str, tag: &str) -> Option<String> {
+    let open = format!("<{}>", tag);
+    let start = block.find(&open)? + open.len();
+    let value = block[start..].split('<').next()?.trim();
+    Some(
+        value
+            .replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&quot;", "\"")
+            .replace("&apos;", "'")
+            .replace("&amp;", "&"),
+    )
+    .filter(|value| !value.is_empty())
+}
+
+#[test]
+fn test_ofx() {
+    let sgml = b"OFXHEADER:100
+DATA:OFXSGML
+VERSION:102
+
+<OFX><BANKMSGSRSV1><STMTTRNRS><STMTRS><BANKTRANLIST>
+<STMTTRN>
+<TRNTYPE>CREDIT
+<DTPOSTED>20240131
+<TRNAMT>1500.00
+<FITID>2024013101
+<NAME>ACME PAYROLL
+</STMTTRN>
+<STMTTRN>
+<TRNTYPE>DEBIT
+<TRNAMT>-42,50
+<FITID>AB-77/x
+<MEMO>Groceries &amp; more
+<STMTTRN>
+<TRNTYPE>DEBIT
+<TRNAMT>lots
+<FITID>3
+</BANKTRANLIST></STMTRS></STMTTRNRS></BANKMSGSRSV1></OFX>
+";
+    let transactions = read(&sgml[..], 7).unwrap();
+    assert_eq!(transactions.len(), 3);
+    let payroll = transactions[0].as_ref().unwrap();
+    assert_eq!(payroll.tx_type, TransactionType::Deposit);
+    assert_eq!(payroll.client_id, 7);
+    assert_eq!(payroll.tx_id, 2024013101);
+    assert_eq!(payroll.amount, Some(Decimal::new(150000, 2)));
+    assert_eq!(payroll.description.as_deref(), Some("ACME PAYROLL"));
+    let groceries = transactions[1].as_ref().unwrap();
+    assert_eq!(groceries.tx_type, TransactionType::Withdrawal);
+    assert_eq!(groceries.tx_id, statement_tx_id("AB-77/x"));
+    assert_ne!(groceries.tx_id, statement_tx_id("AB-77/y"));
+    assert_eq!(groceries.amount, Some(Decimal::new(4250, 2)));
+    assert_eq!(groceries.description.as_deref(), Some("Groceries & more"));
+    assert_eq!(
+        transactions[2].as_ref().unwrap_err().to_string(),
+        "Statement transaction 3: Invalid TRNAMT"
+    );
+
+    let xml = br#"<?xml version="1.0"?><OFX><BANKTRANLIST>
+<STMTTRN><TRNTYPE>DEP</TRNTYPE><TRNAMT>10</TRNAMT><FITID>1</FITID></STMTTRN>
+<STMTTRN><TRNTYPE>DEBIT</TRNTYPE><TRNAMT>-2.5</TRNAMT></STMTTRN>
+</BANKTRANLIST></OFX>"#;
+    let transactions = read(&xml[..], 1).unwrap();
+    assert_eq!(
+        transactions[0].as_ref().unwrap().amount,
+        Some(Decimal::new(10, 0))
+    );
+    assert_eq!(transactions[0].as_ref().unwrap().description, None);
+    assert_eq!(
+        transactions[1].as_ref().unwrap_err().to_string(),
+        "Statement transaction 2: No FITID"
+    );
+}
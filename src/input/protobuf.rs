@@ -0,0 +1,270 @@
+use crate::transaction::{ClientId, Transaction, TransactionStatus, TransactionType};
+use prost::Message;
+use rust_decimal::prelude::*;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufReader, ErrorKind, Read};
+use std::net::{TcpListener, TcpStream};
+use tracing::{debug, info};
+
+/// Hand-written equivalent of proto/transactions.proto, so we don't need protoc
+/// at build time. Keep both in sync.
+pub mod pb {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+    #[repr(i32)]
+    pub enum TransactionType {
+        Deposit = 0,
+        Withdrawal = 1,
+        Dispute = 2,
+        Resolve = 3,
+        Chargeback = 4,
+        Hold = 5,
+        Capture = 6,
+        Release = 7,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Transaction {
+        #[prost(enumeration = "TransactionType", tag = "1")]
+        pub r#type: i32,
+        #[prost(uint32, tag = "2")]
+        pub client: u32,
+        #[prost(uint32, tag = "3")]
+        pub tx: u32,
+        #[prost(string, tag = "4")]
+        pub amount: String,
+        #[prost(string, tag = "5")]
+        pub description: String,
+        #[prost(string, tag = "6")]
+        pub category: String,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct TransactionBatch {
+        #[prost(message, repeated, tag = "1")]
+        pub transactions: Vec<Transaction>,
+    }
+}
+
+impl TryFrom<pb::Transaction> for Transaction {
+    type Error = &'static str;
+
+    fn try_from(message: pb::Transaction) -> Result<Transaction, &'static str> {
+        let tx_type = match pb::TransactionType::try_from(message.r#type) {
+            Ok(pb::TransactionType::Deposit) => TransactionType::Deposit,
+            Ok(pb::TransactionType::Withdrawal) => TransactionType::Withdrawal,
+            Ok(pb::TransactionType::Dispute) => TransactionType::Dispute,
+            Ok(pb::TransactionType::Resolve) => TransactionType::Resolve,
+            Ok(pb::TransactionType::Chargeback) => TransactionType::Chargeback,
+            Ok(pb::TransactionType::Hold) => TransactionType::Hold,
+            Ok(pb::TransactionType::Capture) => TransactionType::Capture,
+            Ok(pb::TransactionType::Release) => TransactionType::Release,
+            Err(_) => return Err("Unknown transaction type"),
+        };
+        let client_id = ClientId::try_from(message.client).map_err(|_| "Client id out of range")?;
+        let amount = match message.amount.trim() {
+            "" => None,
+            something => Some(Decimal::from_str(something).map_err(|_| "Invalid amount")?),
+        };
+
+        Ok(Transaction {
+            tx_type,
+            client_id,
+            tx_id: message.tx,
+            amount,
+            status: TransactionStatus::OK,
+            description: Some(message.description)
+                .filter(|description| !description.is_empty())
+                .map(String::into_boxed_str),
+            category: Some(message.category)
+                .filter(|category| !category.is_empty())
+                .map(String::into_boxed_str),
+        })
+    }
+}
+
+/// Reads a stream of length-delimited TransactionBatch or Transaction
+/// messages, in any mix, yielding the transactions one at a time. Only one
+/// batch is kept in memory.
+pub struct ProtobufReader<R> {
+    reader: R,
+    pending: std::vec::IntoIter<pb::Transaction>,
+}
+
+pub fn open(filename: &str) -> Result<ProtobufReader<BufReader<File>>, Box<dyn Error>> {
+    Ok(ProtobufReader::new(BufReader::new(File::open(filename)?)))
+}
+
+/// For protobuf sources given as tcp://ADDRESS instead of a file: waits there
+/// for a producer to connect, and its connection is the source, until it
+/// closes it. Only the first one is accepted.
+pub fn listen(address: &str) -> io::Result<TcpStream> {
+    let listener = TcpListener::bind(address)?;
+    info!(address, "Waiting for a protobuf producer");
+    let (stream, peer) = listener.accept()?;
+    info!(%peer, "Protobuf producer connected");
+    Ok(stream)
+}
+
+impl<R: Read> ProtobufReader<R> {
+    pub fn new(reader: R) -> ProtobufReader<R> {
+        ProtobufReader {
+            reader,
+            pending: Vec::new().into_iter(),
+        }
+    }
+
+    // Returns None on a clean end of file, i.e. if we're between batches.
+    fn read_length(&mut self) -> Result<Option<usize>, Box<dyn Error>> {
+        let mut length: u64 = 0;
+        for i in 0..10 {
+            let mut byte = [0u8; 1];
+            if let Err(e) = self.reader.read_exact(&mut byte) {
+                if e.kind() == ErrorKind::UnexpectedEof && i == 0 {
+                    return Ok(None);
+                }
+                return Err(e.into());
+            }
+            length |= u64::from(byte[0] & 0x7f) << (7 * i);
+            if byte[0] & 0x80 == 0 {
+                return Ok(Some(usize::try_from(length)?));
+            }
+        }
+        Err("Invalid batch length prefix".into())
+    }
+
+    fn next_batch(&mut self) -> Result<Option<pb::TransactionBatch>, Box<dyn Error>> {
+        let length = match self.read_length()? {
+            None => return Ok(None),
+            Some(length) => length,
+        };
+        // Grown as the bytes come rather than allocated up front, so a
+        // corrupt or hostile length can't ask for gigabytes
+        let mut buffer = Vec::new();
+        (&mut self.reader)
+            .take(length as u64)
+            .read_to_end(&mut buffer)?;
+        if buffer.len() < length {
+            return Err(format!(
+                "Batch of {} bytes, the input ends after {}",
+                length,
+                buffer.len()
+            )
+            .into());
+        }
+        // A batch only has field 1, a message, so it starts with its key 0x0A;
+        // the field 1 of a transaction is a number. Empty is an empty batch.
+        let batch = match buffer.first() {
+            None | Some(0x0A) => pb::TransactionBatch::decode(buffer.as_slice())?,
+            Some(_) => pb::TransactionBatch {
+                transactions: vec![pb::Transaction::decode(buffer.as_slice())?],
+            },
+        };
+        debug!("Read batch with {} transactions", batch.transactions.len());
+        Ok(Some(batch))
+    }
+}
+
+impl<R: Read> Iterator for ProtobufReader<R> {
+    type Item = Result<Transaction, Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(message) = self.pending.next() {
+                return Some(Transaction::try_from(message).map_err(|e| e.into()));
+            }
+            match self.next_batch() {
+                Ok(Some(batch)) => self.pending = batch.transactions.into_iter(),
+                Ok(None) => return None,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+#[test]
+fn test_read_protobuf_batches() {
+    let message = |r#type: pb::TransactionType, client, tx, amount: &str| pb::Transaction {
+        r#type: r#type as i32,
+        client,
+        tx,
+        amount: amount.to_string(),
+        description: String::new(),
+        category: String::new(),
+    };
+    let mut buffer = Vec::new();
+    pb::TransactionBatch {
+        transactions: vec![
+            message(pb::TransactionType::Deposit, 1, 1, "1.5"),
+            message(pb::TransactionType::Dispute, 1, 1, ""),
+        ],
+    }
+    .encode_length_delimited(&mut buffer)
+    .unwrap();
+    pb::TransactionBatch {
+        transactions: vec![message(pb::TransactionType::Withdrawal, 2, 2, "0.1234")],
+    }
+    .encode_length_delimited(&mut buffer)
+    .unwrap();
+
+    let transactions: Vec<Transaction> = ProtobufReader::new(buffer.as_slice())
+        .map(|t| t.unwrap())
+        .collect();
+    assert_eq!(transactions.len(), 3);
+    assert_eq!(transactions[0].tx_type, TransactionType::Deposit);
+    assert_eq!(
+        transactions[0].amount,
+        Some(Decimal::from_str("1.5").unwrap())
+    );
+    assert_eq!(transactions[1].tx_type, TransactionType::Dispute);
+    assert_eq!(transactions[1].amount, None);
+    assert_eq!(transactions[2].client_id, 2);
+
+    /* Single transactions between the batches */
+    let mut buffer = Vec::new();
+    message(pb::TransactionType::Deposit, 3, 4, "2")
+        .encode_length_delimited(&mut buffer)
+        .unwrap();
+    pb::TransactionBatch::default()
+        .encode_length_delimited(&mut buffer)
+        .unwrap();
+    message(pb::TransactionType::Chargeback, 3, 4, "")
+        .encode_length_delimited(&mut buffer)
+        .unwrap();
+    let types: Vec<TransactionType> = ProtobufReader::new(buffer.as_slice())
+        .map(|t| t.unwrap().tx_type)
+        .collect();
+    assert_eq!(
+        types,
+        vec![TransactionType::Deposit, TransactionType::Chargeback]
+    );
+
+    /* From a socket */
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    drop(listener);
+    let producer = std::thread::spawn(move || loop {
+        if let Ok(mut stream) = TcpStream::connect(address) {
+            return std::io::Write::write_all(&mut stream, &buffer).unwrap();
+        }
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    });
+    let socket = listen(&address.to_string()).unwrap();
+    assert_eq!(ProtobufReader::new(socket).count(), 2);
+    producer.join().unwrap();
+
+    /* Client ids must fit a u16 */
+    assert!(Transaction::try_from(message(pb::TransactionType::Deposit, 70000, 3, "1")).is_err());
+}
+
+#[test]
+fn test_oversized_length_prefix() {
+    let input = [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f];
+    let mut reader = ProtobufReader::new(&input[..]);
+    let error = reader.next().unwrap().unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Batch of 9223372036854775807 bytes, the input ends after 0"
+    );
+}
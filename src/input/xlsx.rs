@@ -0,0 +1,156 @@
+use crate::input::dialect::CsvDialect;
+use crate::transaction::Transaction;
+use crate::transform::Transform;
+use calamine::{Data, DataType, Reader, Xlsx};
+use csv::ByteRecord;
+use std::error::Error;
+use std::io::{Cursor, Read};
+
+/*
+Excel workbooks, for the transactions finance keeps in spreadsheets: one
+sheet (--sheet, or the first one) read like a CSV file of the same dialect.
+The header row finds the columns by name in any order, renamed with the map,
+text amounts go through the amount format, and every row is parsed like a
+CSV row, with the same errors, numbered like Excel does. Numbers are taken
+as they are, whatever their cell format shows (a cell showing 1,234.50 is
+1234.5). Empty rows are skipped. Delimiter, quote, comment, encoding,
+control records and batches don't apply to a sheet.
+
+The whole workbook is read in memory, it's zipped XML that can't be streamed
+anyway.
+*/
+
+type Row = Result<Transaction, Box<dyn Error>>;
+
+/// The transactions of the sheet, or why a row can't be read, in order.
+/// Fails if the workbook can't be read, hasn't got the sheet or its header
+/// doesn't fit the dialect.
+pub fn read<R: Read>(
+    mut reader: R,
+    sheet: Option<&str>,
+    dialect: &CsvDialect,
+) -> Result<Vec<Row>, Box<dyn Error>> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    let mut workbook: Xlsx<_> = Xlsx::new(Cursor::new(bytes))?;
+    let range = match sheet {
+        Some(sheet) => workbook
+            .worksheet_range(sheet)
+            .map_err(|e| format!("Sheet {}: {}", sheet, e))?,
+        None => workbook
+            .worksheet_range_at(0)
+            .ok_or("The workbook has no sheets")??,
+    };
+    // Excel counts rows from 1, the range from where the cells start
+    let first = u64::from(range.start().map_or(0, |(row, _)| row)) + 1;
+    let mut rows = range
+        .rows()
+        .zip(first..)
+        .filter(|(cells, _)| cells.iter().any(|cell| *cell != Data::Empty));
+    let mut remap = None;
+    if dialect.header {
+        match rows.next() {
+            Some((header, _)) => remap = dialect.columns(&record(header))?,
+            None => return Ok(Vec::new()),
+        }
+    }
+    let amount_column = remap
+        .as_ref()
+        .map_or(Some(3), |remap| remap.columns.get(3).copied());
+    let amount_format = Some(&dialect.amount).filter(|amount| !amount.is_plain());
+    let parse = |cells: &[Data], record: &mut ByteRecord| -> Result<Transaction, &'static str> {
+        let text_amount = amount_column
+            .and_then(|column| cells.get(column))
+            .and_then(|cell| cell.get_string());
+        if let (Some(format), Some(amount)) = (amount_format, text_amount) {
+            let amount = format.normalize(amount)?;
+            let mut normalized = ByteRecord::new();
+            normalized.extend(record.iter().take(3));
+            normalized.push_field(amount.as_bytes());
+            normalized.extend(record.iter().skip(4));
+            *record = normalized;
+        }
+        Transaction::from_byte_record(record)
+    };
+    Ok(rows
+        .map(|(cells, row)| {
+            let mut record = record(cells);
+            if let Some(remap) = &mut remap {
+                remap.apply_record(&mut record);
+            }
+            parse(cells, &mut record).map_err(|e| format!("Row {}: {}", row, e).into())
+        })
+        .collect())
+}
+
+// The cells as CSV fields: numbers in full, without exponent or trailing
+// zeros, and the rest as Excel shows them
+fn record(cells: &[Data]) -> ByteRecord {
+    let mut record = ByteRecord::with_capacity(0, cells.len());
+    for cell in cells {
+        record.push_field(cell.to_string().as_bytes());
+    }
+    record
+}
+
+#[test]
+fn test_xlsx() {
+    use rust_decimal::Decimal;
+    use rust_xlsxwriter::Workbook;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("transactions.xlsx");
+    let mut workbook = Workbook::new();
+    workbook.add_worksheet().set_name("Summary").unwrap();
+    let sheet = workbook.add_worksheet();
+    sheet.set_name("Transactions").unwrap();
+    let rows: [(&str, f64, f64, &str, &str); 4] = [
+        ("deposit", 1.0, 1.0, "Payroll", "1.234,50"),
+        ("withdrawal", 1.0, 2.0, "Rent", "10"),
+        ("refund", 1.0, 3.0, "", "1"),
+        ("withdrawal", 2.0, 4.0, "", ""),
+    ];
+    for (column, name) in ["Kind", "Note", "Client", "Tx", "Amount"]
+        .iter()
+        .enumerate()
+    {
+        sheet.write_string(2, column as u16, *name).unwrap();
+    }
+    for (row, (kind, client, tx, note, amount)) in rows.iter().enumerate() {
+        let row = row as u32 + 3;
+        sheet.write_string(row, 0, *kind).unwrap();
+        sheet.write_string(row, 1, *note).unwrap();
+        sheet.write_number(row, 2, *client).unwrap();
+        sheet.write_number(row, 3, *tx).unwrap();
+        sheet.write_string(row, 4, *amount).unwrap();
+    }
+    sheet.write_number(6, 4, 0.1).unwrap();
+    workbook.save(&path).unwrap();
+
+    let dialect: CsvDialect = toml::from_str(
+        r#"
+map = { type = "Kind", description = "Note" }
+amount = { decimal_separator = ",", thousands_separator = "." }
+"#,
+    )
+    .unwrap();
+    let read = |sheet| read(std::fs::File::open(&path).unwrap(), sheet, &dialect);
+    let transactions = read(Some("Transactions")).unwrap();
+    let payroll = transactions[0].as_ref().unwrap();
+    assert_eq!(payroll.amount, Some(Decimal::new(123450, 2)));
+    assert_eq!(payroll.description.as_deref(), Some("Payroll"));
+    assert_eq!(transactions[1].as_ref().unwrap().tx_id, 2);
+    assert_eq!(
+        transactions[2].as_ref().unwrap_err().to_string(),
+        "Row 6: Unknown transaction type"
+    );
+    // A number isn't normalized, its decimal point isn't the dialect's
+    assert_eq!(
+        transactions[3].as_ref().unwrap().amount,
+        Some(Decimal::new(1, 1))
+    );
+    assert_eq!(transactions.len(), 4);
+    // The first sheet hasn't got the header
+    assert!(read(None).is_ok_and(|transactions| transactions.is_empty()));
+    assert!(read(Some("Nope")).is_err());
+}
@@ -0,0 +1,186 @@
+use crate::compat::CompatLevel;
+use crate::engine::Account;
+use crate::input::dialect::CsvDialect;
+use crate::transaction::{Transaction, TransactionId, TransactionStatus};
+use crate::PaymentEngine;
+use csv::ByteRecord;
+use std::error::Error;
+use std::fmt;
+use std::io::Read;
+
+/*
+`inspect --tx 12345 input.csv`: the story of one transaction, the question
+support asks most. The whole file goes through a throwaway engine, like
+validate, and what's kept is every row with that tx id (the transaction and
+the disputes, resolves, chargebacks, captures or releases of it), what the
+engine did with each, its status at the end and its client's account:
+
+  line 2: deposit, client 1, tx 12345, amount 10.5: applied
+  line 40: dispute, client 1, tx 12345: applied
+  line 90: chargeback, client 1, tx 12345: applied
+  status: chargedback
+  client 1: available 250, held 0, total 250, locked
+
+Rows that can't be read, of this tx or any other, are skipped rather than
+stopping it.
+*/
+
+#[derive(Debug, Clone)]
+pub struct Sighting {
+    /// In the file, counting the header
+    pub line: u64,
+    pub transaction: Transaction,
+    /// What the engine did with it: applied, or why not
+    pub result: String,
+}
+
+#[derive(Debug, Default)]
+pub struct Inspection {
+    pub rows: Vec<Sighting>,
+    /// None if it was never stored, e.g. a withdrawal, which can't be
+    /// disputed in v1
+    pub status: Option<TransactionStatus>,
+    pub account: Option<Account>,
+}
+
+impl Inspection {
+    pub fn is_found(&self) -> bool {
+        !self.rows.is_empty()
+    }
+}
+
+/// Only fails if the input can't be read at all.
+pub fn inspect<R: Read>(
+    reader: R,
+    dialect: &CsvDialect,
+    compat: CompatLevel,
+    tx: TransactionId,
+) -> Result<Inspection, Box<dyn Error>> {
+    let mut rdr = dialect.reader(reader)?;
+    let mut engine = PaymentEngine::new();
+    engine.set_compat_level(compat);
+    let mut inspection = Inspection::default();
+    let mut record = ByteRecord::new();
+    loop {
+        let line = rdr.inner_mut().position().line();
+        match rdr.read_byte_record(&mut record) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(e) => {
+                let fatal = match e.downcast_ref::<csv::Error>() {
+                    Some(e) => e.is_io_error(),
+                    None => e.is::<std::io::Error>(),
+                };
+                match fatal {
+                    true => return Err(e),
+                    false => continue,
+                }
+            }
+        }
+        let transaction = match Transaction::from_byte_record(&record) {
+            Ok(transaction) => transaction,
+            Err(_) => continue,
+        };
+        let ours = transaction.tx_id == tx;
+        let processed = engine.process_transaction(transaction.clone());
+        if !ours {
+            continue;
+        }
+        let result = match processed {
+            Ok(outcome) => match outcome.reason() {
+                Some(reason) => format!("{} ({})", outcome.kind(), reason),
+                None => outcome.kind().to_string(),
+            },
+            Err(e) => format!("failed ({})", e),
+        };
+        inspection.rows.push(Sighting {
+            line,
+            transaction,
+            result,
+        });
+    }
+    if let Some(first) = inspection.rows.first() {
+        inspection.status = engine.transactions.get(tx)?.map(|stored| stored.status);
+        inspection.account = engine.account(first.transaction.client_id)?;
+    }
+    Ok(inspection)
+}
+
+impl fmt::Display for Sighting {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let transaction = &self.transaction;
+        write!(
+            f,
+            "line {}: {}, client {}, tx {}",
+            self.line, transaction.tx_type, transaction.client_id, transaction.tx_id
+        )?;
+        if let Some(amount) = transaction.amount {
+            write!(f, ", amount {}", amount)?;
+        }
+        if let Some(description) = &transaction.description {
+            write!(f, ", {:?}", description)?;
+        }
+        write!(f, ": {}", self.result)
+    }
+}
+
+impl fmt::Display for Inspection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for row in &self.rows {
+            writeln!(f, "{}", row)?;
+        }
+        match self.status {
+            Some(status) => writeln!(f, "status: {}", format!("{:?}", status).to_lowercase())?,
+            None => writeln!(f, "status: not stored, only deposits and holds are")?,
+        }
+        if let Some(account) = &self.account {
+            writeln!(
+                f,
+                "client {}: available {}, held {}, total {}, {}",
+                account.client_id,
+                account.funds_available,
+                account.funds_held,
+                account.funds_total,
+                match account.locked {
+                    true => "locked",
+                    false => "not locked",
+                }
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_inspect() {
+    let input = b"type,client,tx,amount,description
+deposit,1,7,10.5,Top-up
+deposit,1,8,1,
+dispute,1,7,,
+not a row
+resolve,2,7,,
+chargeback,1,7,,Card stolen
+withdrawal,1,9,1,
+";
+    let inspection = inspect(&input[..], &CsvDialect::default(), CompatLevel::V1, 7).unwrap();
+    let lines: Vec<u64> = inspection.rows.iter().map(|row| row.line).collect();
+    assert_eq!(lines, vec![2, 4, 6, 7]);
+    assert_eq!(inspection.rows[2].result, "ignored (wrong client)");
+    assert_eq!(inspection.status, Some(TransactionStatus::Chargedback));
+    let text = inspection.to_string();
+    assert!(text.starts_with("line 2: deposit, client 1, tx 7, amount 10.5, \"Top-up\": applied\n"));
+    assert!(text.contains("status: chargedback\n"), "{}", text);
+    assert!(
+        text.ends_with("client 1: available 10.5, held 0.0, total 10.5, locked\n"),
+        "{}",
+        text
+    );
+
+    let inspection = inspect(&input[..], &CsvDialect::default(), CompatLevel::V1, 9).unwrap();
+    assert_eq!(inspection.status, None);
+    assert!(
+        !inspect(&input[..], &CsvDialect::default(), CompatLevel::V1, 5)
+            .unwrap()
+            .is_found()
+    );
+}
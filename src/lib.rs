@@ -0,0 +1,69 @@
+// Lets the option enums that implement FromStr (and so work as CLI flags) be
+// used as is in pipeline and config files too
+macro_rules! deserialize_from_str {
+    ($type:ty) => {
+        impl<'de> serde::Deserialize<'de> for $type {
+            fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                let s = <String as serde::Deserialize>::deserialize(deserializer)?;
+                s.parse().map_err(serde::de::Error::custom)
+            }
+        }
+    };
+}
+
+pub mod actors;
+pub mod anomalies;
+pub mod audit;
+pub mod blocklist;
+pub mod categories;
+pub mod checkpoint;
+pub mod compat;
+pub mod concurrent;
+pub mod config;
+pub mod conflicts;
+pub mod diff;
+pub mod dispute_state;
+pub mod disputes;
+pub mod double_entry;
+pub mod engine;
+pub mod generate;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod hook;
+pub mod http;
+pub mod input;
+pub mod inspect;
+pub mod limits;
+pub mod locks;
+pub mod logging;
+pub mod meta;
+pub mod metrics;
+pub mod observer;
+pub mod output;
+pub mod parallel;
+pub mod pipeline;
+pub mod policy;
+pub mod profile;
+pub mod progress;
+pub mod reconcile;
+pub mod repl;
+pub mod resources;
+pub mod seen;
+pub mod server;
+pub mod sink;
+pub mod spool;
+pub mod state;
+pub mod stats;
+pub mod store;
+pub mod timings;
+pub mod transaction;
+pub mod transform;
+pub mod undo;
+pub mod validate;
+#[cfg(feature = "parquet")]
+pub mod vectorized;
+pub mod wal;
+pub mod watch;
+pub mod webhook;
+
+pub use engine::{Account, PaymentEngine};
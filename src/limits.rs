@@ -0,0 +1,264 @@
+use crate::transaction::{ClientId, Transaction, TransactionType, TxOutcome};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/*
+Fraud checks done before a transaction is applied, per client:
+
+  max_amount            largest single deposit, withdrawal or hold
+  max_daily_withdrawal  most a client can withdraw in 24 hours
+  max_transactions      most transactions of a client (of any type) in
+                        window_secs
+
+The input has no timestamps, so the time of a transaction is when the engine
+processes it: the windows are meant for the online modes (serve, grpc, Kafka,
+watch), in a batch run everything happens within the same few seconds.
+Transactions over a limit are rejected (and show up as such in the stats, the
+audit log and the metrics), and with lock_on_violation the account gets
+locked too.
+
+Clients with a risk tier (see meta.rs) get the limits of their tier instead,
+where it sets them, e.g. in TOML:
+
+  [high]
+  max_amount = "500"
+  max_transactions = 5
+*/
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsSpec {
+    pub max_amount: Option<Decimal>,
+    pub max_daily_withdrawal: Option<Decimal>,
+    pub max_transactions: Option<usize>,
+    pub window_secs: u64,
+    pub lock_on_violation: bool,
+    /// By risk tier, over the ones above
+    pub tiers: HashMap<String, TierLimits>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TierLimits {
+    pub max_amount: Option<Decimal>,
+    pub max_daily_withdrawal: Option<Decimal>,
+    pub max_transactions: Option<usize>,
+}
+
+impl LimitsSpec {
+    /// Reads tier limits from a TOML file, a table per tier.
+    pub fn load_tiers(&mut self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let tiers: HashMap<String, TierLimits> = toml::from_str(&fs::read_to_string(path)?)?;
+        self.tiers.extend(tiers);
+        Ok(())
+    }
+}
+
+impl Default for LimitsSpec {
+    fn default() -> Self {
+        LimitsSpec {
+            max_amount: None,
+            max_daily_withdrawal: None,
+            max_transactions: None,
+            window_secs: 60,
+            lock_on_violation: false,
+            tiers: HashMap::new(),
+        }
+    }
+}
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Debug, Default, Clone)]
+struct ClientActivity {
+    recent: VecDeque<Instant>,
+    withdrawals: VecDeque<(Instant, Decimal)>,
+}
+
+// Drops what's older than `window` from the front
+fn expire<T>(queue: &mut VecDeque<T>, now: Instant, window: Duration, at: fn(&T) -> Instant) {
+    while queue
+        .front()
+        .is_some_and(|oldest| now.saturating_duration_since(at(oldest)) >= window)
+    {
+        queue.pop_front();
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Limits {
+    spec: LimitsSpec,
+    clients: HashMap<ClientId, ClientActivity>,
+}
+
+impl Limits {
+    pub fn new(spec: LimitsSpec) -> Limits {
+        Limits {
+            spec,
+            clients: HashMap::new(),
+        }
+    }
+
+    pub fn lock_on_violation(&self) -> bool {
+        self.spec.lock_on_violation
+    }
+
+    /// Why the transaction can't go through, if it can't. The ones that can
+    /// count towards the client's velocity.
+    pub fn check(
+        &mut self,
+        transaction: &Transaction,
+        risk_tier: Option<&str>,
+        now: Instant,
+    ) -> Option<TxOutcome> {
+        let tier = risk_tier.and_then(|tier| self.spec.tiers.get(tier));
+        let max_amount = tier.and_then(|t| t.max_amount).or(self.spec.max_amount);
+        let max_daily_withdrawal = tier
+            .and_then(|t| t.max_daily_withdrawal)
+            .or(self.spec.max_daily_withdrawal);
+        let max_transactions = tier
+            .and_then(|t| t.max_transactions)
+            .or(self.spec.max_transactions);
+        let amount = match transaction.tx_type {
+            TransactionType::Deposit | TransactionType::Withdrawal | TransactionType::Hold => {
+                transaction.amount
+            }
+            _ => None,
+        };
+        if let (Some(max), Some(amount)) = (max_amount, amount) {
+            if amount > max {
+                return Some(TxOutcome::RejectedOverLimit);
+            }
+        }
+        let activity = self.clients.entry(transaction.client_id).or_default();
+        if let (Some(max), Some(amount), TransactionType::Withdrawal) =
+            (max_daily_withdrawal, amount, transaction.tx_type)
+        {
+            expire(&mut activity.withdrawals, now, DAY, |w| w.0);
+            // Too much to even add up is over any limit
+            let withdrawn = activity
+                .withdrawals
+                .iter()
+                .try_fold(amount, |withdrawn, w| withdrawn.checked_add(w.1));
+            if withdrawn.is_none_or(|withdrawn| withdrawn > max) {
+                return Some(TxOutcome::RejectedOverLimit);
+            }
+        }
+        if let Some(max) = max_transactions {
+            let window = Duration::from_secs(self.spec.window_secs);
+            expire(&mut activity.recent, now, window, |at| *at);
+            if activity.recent.len() >= max {
+                return Some(TxOutcome::RejectedVelocity);
+            }
+            activity.recent.push_back(now);
+        }
+        None
+    }
+
+    /// Counts an applied withdrawal towards the daily limit.
+    pub fn withdrawn(&mut self, client_id: ClientId, amount: Decimal, now: Instant) {
+        let daily = self.spec.max_daily_withdrawal.is_some()
+            || self
+                .spec
+                .tiers
+                .values()
+                .any(|tier| tier.max_daily_withdrawal.is_some());
+        if daily {
+            self.clients
+                .entry(client_id)
+                .or_default()
+                .withdrawals
+                .push_back((now, amount));
+        }
+    }
+
+    /// Same limits, no activity, for a new shard
+    pub(crate) fn empty(&self) -> Limits {
+        Limits::new(self.spec.clone())
+    }
+
+    /// Shards never share clients
+    pub(crate) fn merge(&mut self, other: Limits) {
+        self.clients.extend(other.clients);
+    }
+}
+
+#[test]
+fn test_limits() {
+    use crate::transaction::TransactionStatus;
+
+    let transaction = |tx_type, amount| Transaction {
+        tx_type,
+        client_id: 1,
+        tx_id: 1,
+        amount: Some(Decimal::new(amount, 0)),
+        status: TransactionStatus::OK,
+        description: None,
+        category: None,
+    };
+    let start = Instant::now();
+    let at = |secs| start + Duration::from_secs(secs);
+
+    let mut limits = Limits::new(LimitsSpec {
+        max_amount: Some(Decimal::new(100, 0)),
+        max_daily_withdrawal: Some(Decimal::new(150, 0)),
+        ..Default::default()
+    });
+    let withdrawal = transaction(TransactionType::Withdrawal, 80);
+    assert_eq!(
+        limits.check(&transaction(TransactionType::Deposit, 101), None, at(0)),
+        Some(TxOutcome::RejectedOverLimit)
+    );
+    assert_eq!(limits.check(&withdrawal, None, at(0)), None);
+    limits.withdrawn(1, Decimal::new(80, 0), at(0));
+    assert_eq!(
+        limits.check(&withdrawal, None, at(3600)),
+        Some(TxOutcome::RejectedOverLimit)
+    );
+    assert_eq!(limits.check(&withdrawal, None, at(24 * 3600)), None);
+    limits.withdrawn(1, Decimal::MAX, at(24 * 3600));
+    assert_eq!(
+        limits.check(&withdrawal, None, at(24 * 3600)),
+        Some(TxOutcome::RejectedOverLimit)
+    );
+
+    let mut limits = Limits::new(LimitsSpec {
+        max_transactions: Some(2),
+        window_secs: 10,
+        ..Default::default()
+    });
+    let deposit = transaction(TransactionType::Deposit, 1);
+    assert_eq!(limits.check(&deposit, None, at(0)), None);
+    assert_eq!(limits.check(&deposit, None, at(5)), None);
+    assert_eq!(
+        limits.check(&deposit, None, at(9)),
+        Some(TxOutcome::RejectedVelocity)
+    );
+    assert_eq!(limits.check(&deposit, None, at(10)), None);
+
+    let mut tiers = HashMap::new();
+    tiers.insert(
+        "high".to_string(),
+        TierLimits {
+            max_amount: Some(Decimal::new(10, 0)),
+            ..Default::default()
+        },
+    );
+    let mut limits = Limits::new(LimitsSpec {
+        max_amount: Some(Decimal::new(100, 0)),
+        tiers,
+        ..Default::default()
+    });
+    let deposit = transaction(TransactionType::Deposit, 50);
+    assert_eq!(limits.check(&deposit, None, at(0)), None);
+    assert_eq!(limits.check(&deposit, Some("low"), at(0)), None);
+    assert_eq!(
+        limits.check(&deposit, Some("high"), at(0)),
+        Some(TxOutcome::RejectedOverLimit)
+    );
+}
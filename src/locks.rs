@@ -0,0 +1,176 @@
+use crate::engine::Account;
+use crate::output::AccountOrder;
+use crate::transaction::{ClientId, TransactionId};
+use crate::PaymentEngine;
+use rust_decimal::Decimal;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::io::Write;
+
+/*
+For the fraud team: every locked account with the chargeback that locked it
+(or, with lock_on_violation, the transaction over a limit, see limits.rs) and
+the balances right after it did.
+
+  client,tx,amount,available,held,total
+  2,7,10.5,0,0,0
+
+Accounts that were already locked when the engine was created (a loaded
+state) have no known cause: tx and amount are empty and the balances are the
+current ones.
+*/
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LockCause {
+    pub tx: TransactionId,
+    pub amount: Option<Decimal>,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct LockHistory {
+    causes: BTreeMap<ClientId, LockCause>,
+}
+
+impl LockHistory {
+    /// `account` as it was left by the transaction that locked it
+    pub(crate) fn record(&mut self, account: &Account, tx: TransactionId, amount: Option<Decimal>) {
+        self.causes.insert(
+            account.client_id,
+            LockCause {
+                tx,
+                amount,
+                available: account.funds_available,
+                held: account.funds_held,
+                total: account.funds_total,
+            },
+        );
+    }
+
+    /// Shards never share clients
+    pub(crate) fn merge(&mut self, other: LockHistory) {
+        self.causes.extend(other.causes);
+    }
+
+    pub fn get(&self, client: ClientId) -> Option<&LockCause> {
+        self.causes.get(&client)
+    }
+}
+
+/// The report, by client id. Needs the engine for the accounts that were
+/// locked before it was tracking.
+pub fn write_report<W: Write>(
+    engine: &PaymentEngine,
+    history: &LockHistory,
+    mut writer: W,
+) -> Result<(), Box<dyn Error>> {
+    writeln!(writer, "client,tx,amount,available,held,total")?;
+    for account in engine.ordered_accounts(AccountOrder::Client)? {
+        let account = account?;
+        if !account.locked {
+            continue;
+        }
+        match history.get(account.client_id) {
+            Some(cause) => writeln!(
+                writer,
+                "{},{},{},{},{},{}",
+                account.client_id,
+                cause.tx,
+                cause.amount.map(|a| a.to_string()).unwrap_or_default(),
+                cause.available,
+                cause.held,
+                cause.total
+            )?,
+            None => writeln!(
+                writer,
+                "{},,,{},{},{}",
+                account.client_id, account.funds_available, account.funds_held, account.funds_total
+            )?,
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[test]
+fn test_locked_report() {
+    let mut engine = PaymentEngine::new();
+    engine
+        .import_reader(
+            &b"type,client,tx,amount\ndeposit,3,5,1\ndispute,3,5,\nchargeback,3,5,\n"[..],
+        )
+        .unwrap();
+    let mut state = Vec::new();
+    engine
+        .save_state(&mut state, crate::state::StateFormat::Cbor)
+        .unwrap();
+
+    let mut engine =
+        PaymentEngine::load_state(state.as_slice(), crate::state::StateFormat::Cbor).unwrap();
+    engine.track_locks();
+    engine
+        .import_reader(
+            &b"type,client,tx,amount
+deposit,1,1,10
+deposit,1,2,5
+dispute,1,2,
+chargeback,1,2,
+deposit,2,3,1
+dispute,2,3,
+chargeback,2,3,
+dispute,1,1,
+chargeback,1,1,
+"[..],
+        )
+        .unwrap();
+    let mut report = Vec::new();
+    write_report(&engine, engine.locks().unwrap(), &mut report).unwrap();
+    assert_eq!(
+        String::from_utf8(report).unwrap(),
+        "client,tx,amount,available,held,total
+1,2,5,15,0,15
+2,3,1,1,0,1
+3,,,1,0,1
+"
+    );
+}
+
+#[test]
+fn test_limit_lock() {
+    use crate::limits::LimitsSpec;
+    use crate::observer::EngineObserver;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<(ClientId, TransactionId)>>);
+
+    impl EngineObserver for Recorder {
+        fn on_account_locked(&self, account: &Account, tx: TransactionId) {
+            self.0.lock().unwrap().push((account.client_id(), tx));
+        }
+    }
+
+    let recorder = Arc::new(Recorder::default());
+    let mut engine = PaymentEngine::new().with_observer(recorder.clone());
+    engine.set_limits(LimitsSpec {
+        max_amount: Some(Decimal::new(100, 0)),
+        lock_on_violation: true,
+        ..Default::default()
+    });
+    engine.track_locks();
+    engine.track_undo();
+    engine
+        .import_reader(&b"type,client,tx,amount\ndeposit,2,1,50\ndeposit,2,2,500\n"[..])
+        .unwrap();
+    assert_eq!(*recorder.0.lock().unwrap(), vec![(2, 2)]);
+    let mut report = Vec::new();
+    write_report(&engine, engine.locks().unwrap(), &mut report).unwrap();
+    assert_eq!(
+        String::from_utf8(report).unwrap(),
+        "client,tx,amount,available,held,total\n2,2,500,50,0,50\n"
+    );
+    engine.undo(2).unwrap();
+    assert!(!engine.account(2).unwrap().unwrap().is_locked());
+}
@@ -0,0 +1,48 @@
+use std::io::{self, IsTerminal};
+use std::str::FromStr;
+use tracing_subscriber::EnvFilter;
+
+/*
+Logs go to stderr, stdout is for the export. RUST_LOG picks what's logged
+(error only by default), e.g. RUST_LOG=payments_engine=debug. In JSON every
+line is an object; the engine's per-transaction lines carry a transaction
+span with client_id, tx_id, tx_type and amount, and an outcome field
+(applied, declined, ignored or failed), so they can be filtered per client:
+
+  {"level":"DEBUG","fields":{"message":"Transaction ignored","outcome":"ignored",
+   "reason":"unknown transaction"},"span":{"client_id":2,"tx_id":7,
+   "tx_type":"dispute","amount":"None","name":"transaction"},...}
+*/
+
+#[derive(Debug, Default, PartialEq, Copy, Clone)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<LogFormat, String> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("Unknown log format: {}", s)),
+        }
+    }
+}
+
+deserialize_from_str!(LogFormat);
+
+/// Also picks up what dependencies log through the log crate.
+pub fn init(format: LogFormat) {
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env())
+        .with_writer(io::stderr)
+        .with_ansi(io::stderr().is_terminal());
+    match format {
+        LogFormat::Text => builder.init(),
+        LogFormat::Json => builder.json().init(),
+    }
+}
@@ -1,278 +1,1565 @@
-use csv::{Reader, StringRecord};
-use log::debug;
-use rust_decimal::prelude::*;
-use std::collections::HashMap;
-use std::convert::TryFrom;
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::{Args, CommandFactory, FromArgMatches, Parser, Subcommand};
+use clap_complete::Shell;
+use payments_engine::anomalies::AnomalyRules;
+use payments_engine::blocklist::BlockMode;
+use payments_engine::checkpoint::CheckpointSpec;
+use payments_engine::compat::CompatLevel;
+use payments_engine::config;
+use payments_engine::diff;
+use payments_engine::engine::InvariantViolation;
+use payments_engine::generate::{self, GeneratorConfig};
+use payments_engine::input::amount::AmountFormat;
+use payments_engine::input::batch::BatchRollback;
+use payments_engine::input::control::ControlRecords;
+use payments_engine::input::dialect::CsvDialect;
+use payments_engine::input::encoding::Encoding;
+use payments_engine::input::{InputFormat, SourceKind};
+use payments_engine::inspect;
+use payments_engine::limits::LimitsSpec;
+use payments_engine::logging::{self, LogFormat};
+use payments_engine::metrics::{self, Metrics};
+use payments_engine::output::compress::Compression;
+use payments_engine::output::partition::PartitionBy;
+use payments_engine::output::{
+    self, AccountColumn, AccountFilter, AccountOrder, ClientRanges, OutputFormat,
+};
+use payments_engine::pipeline::{EngineSpec, OutputSpec, Pipeline, PipelineError, SourceSpec};
+use payments_engine::policy::AccountCreation;
+use payments_engine::profile;
+use payments_engine::reconcile::Discrepancies;
+use payments_engine::repl;
+use payments_engine::resources::ResourceLimits;
+use payments_engine::server::Server;
+use payments_engine::state::StateFormat;
+use payments_engine::transaction::{ClientId, TransactionId};
+use payments_engine::validate;
+use payments_engine::wal;
+use payments_engine::watch::{WatchSpec, Watcher};
+use payments_engine::webhook::WebhookSpec;
+use payments_engine::PaymentEngine;
+use rust_decimal::Decimal;
 use std::env;
 use std::error::Error;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, BufReader, IsTerminal, Write};
+use std::net::{SocketAddr, TcpListener};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-type ClientId = u16; // client column is a valid u16 client ID
-type TransactionId = u32; // the tx is a valid u32 transaction ID
-
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 enum PaymentErrors {
-    WrongArgumentCount,
+    ReadPipeline,
+    CreateStore,
+    Audit,
+    Wal,
+    Webhook,
+    Overdraft,
+    Blocklist,
+    Limits,
+    AccountsMeta,
+    Metrics,
     ImportCsv,
+    ImportProtobuf,
+    ImportColumnar,
+    ImportFixed,
+    ImportStatement,
+    ImportXlsx,
+    ImportBinary,
+    LoadState,
+    OpeningBalances,
+    SaveState,
+    ExportAccounts,
+    WriteStats,
+    Hook,
+    Generate,
+    Serve,
+    Kafka,
+    Grpc,
+    Replay,
+    Checkpoint,
+    Watch,
+    Invalid,
+    Diff,
+    Merge,
+    Config,
+    Inspect,
+    Repl,
+    Docs,
+    Reconcile,
+}
+
+// What failed and, when it's known, why
+#[derive(Debug)]
+struct Failure {
+    error: PaymentErrors,
+    cause: Option<Box<dyn Error>>,
 }
 
-#[derive(Debug, PartialEq)]
-enum TransactionStatus {
-    OK,
-    Disputed,
-    Chargedback,
+impl PaymentErrors {
+    fn because<E: Into<Box<dyn Error>>>(self, cause: E) -> Failure {
+        Failure {
+            error: self,
+            cause: Some(cause.into()),
+        }
+    }
 }
 
-#[derive(Debug, PartialEq, Copy, Clone)]
-enum TransactionType {
-    Deposit,
-    Withdrawal,
-    Dispute,
-    Resolve,
-    Chargeback,
+impl From<PaymentErrors> for Failure {
+    fn from(error: PaymentErrors) -> Failure {
+        Failure { error, cause: None }
+    }
 }
 
-#[derive(Debug, PartialEq)]
-struct Transaction {
-    tx_type: TransactionType,
-    client_id: ClientId,
-    tx_id: TransactionId,
-    amount: Option<Decimal>,
-    status: TransactionStatus,
+impl From<PipelineError> for Failure {
+    fn from(error: PipelineError) -> Failure {
+        let (error, cause) = match error {
+            PipelineError::CreateStore(e) => (PaymentErrors::CreateStore, e),
+            PipelineError::Audit(e) => (PaymentErrors::Audit, e),
+            PipelineError::Wal(e) => (PaymentErrors::Wal, e),
+            PipelineError::Webhook(e) => (PaymentErrors::Webhook, e),
+            PipelineError::Overdraft(e) => (PaymentErrors::Overdraft, e),
+            PipelineError::Blocklist(e) => (PaymentErrors::Blocklist, e),
+            PipelineError::Limits(e) => (PaymentErrors::Limits, e),
+            PipelineError::AccountsMeta(e) => (PaymentErrors::AccountsMeta, e),
+            PipelineError::Metrics(e) => (PaymentErrors::Metrics, e),
+            PipelineError::Transform(e) => (PaymentErrors::ReadPipeline, e),
+            PipelineError::LoadState(e) => (PaymentErrors::LoadState, e),
+            PipelineError::OpeningBalances(e) => (PaymentErrors::OpeningBalances, e),
+            PipelineError::Import(InputFormat::Csv, e) => (PaymentErrors::ImportCsv, e),
+            PipelineError::Import(InputFormat::Protobuf, e) => (PaymentErrors::ImportProtobuf, e),
+            PipelineError::Import(InputFormat::Parquet | InputFormat::Arrow, e) => {
+                (PaymentErrors::ImportColumnar, e)
+            }
+            PipelineError::Import(InputFormat::Fixed, e) => (PaymentErrors::ImportFixed, e),
+            PipelineError::Import(
+                InputFormat::Ofx | InputFormat::Camt | InputFormat::Nacha | InputFormat::Mt940,
+                e,
+            ) => (PaymentErrors::ImportStatement, e),
+            PipelineError::Import(InputFormat::Xlsx, e) => (PaymentErrors::ImportXlsx, e),
+            PipelineError::Import(InputFormat::MessagePack | InputFormat::Cbor, e) => {
+                (PaymentErrors::ImportBinary, e)
+            }
+            PipelineError::SaveState(e) => (PaymentErrors::SaveState, e),
+            PipelineError::ExportAccounts(e) => (PaymentErrors::ExportAccounts, e),
+            PipelineError::WriteStats(e) => (PaymentErrors::WriteStats, e),
+            PipelineError::Hook(e) => (PaymentErrors::Hook, e),
+            PipelineError::Checkpoint(e) => (PaymentErrors::Checkpoint, e),
+            PipelineError::Reconcile(e) => (PaymentErrors::Reconcile, e),
+        };
+        error.because(cause)
+    }
 }
 
 /*
-columns type, client, tx, and amount. You can assume the type is a string, the
-client column is a valid u16 client ID, the tx is a valid u32 transaction ID, and
-the amount is a decimal value with a precision of up to four places past the decimal.
+Exit codes, for scripts and orchestrators to tell failures apart:
+
+  0  success
+  1  anything else: servers, hooks, webhooks, watch...
+  2  usage: bad flags (clap's own code), or a pipeline file that isn't one
+  3  I/O: a file or socket that can't be read or written
+  4  parse: input the engine can't read, or validate finding problems
+  5  invariant violation: a repeated tx id, an amount that overflows, states
+     that can't be merged
+  6  reconciliation: balances that don't match the --reconcile control file
+
+--error-json PATH (- for stderr) also writes the failure as one JSON object:
+
+  {"error":"ImportCsv","category":"parse","exit_code":4,"message":"Invalid amount"}
+
+Errors clap finds in the flags exit before there's anywhere to write it. With
+--threads the workers' errors come back as text, so invariant violations
+there are parse failures.
 */
-impl TryFrom<StringRecord> for Transaction {
-    type Error = &'static str;
-
-    fn try_from(record: StringRecord) -> Result<Transaction, &'static str> {
-        let tx_type = record.get(0).unwrap();
-        let tx_type = match tx_type {
-            "deposit" => TransactionType::Deposit,
-            "withdrawal" => TransactionType::Withdrawal,
-            "dispute" => TransactionType::Dispute,
-            "resolve" => TransactionType::Resolve,
-            "chargeback" => TransactionType::Chargeback,
-            _ => return Err("Unknown transaction type"),
-        };
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Category {
+    Other = 1,
+    Usage = 2,
+    Io = 3,
+    Parse = 4,
+    Invariant = 5,
+    Reconciliation = 6,
+}
+
+impl Category {
+    fn name(self) -> &'static str {
+        match self {
+            Category::Other => "other",
+            Category::Usage => "usage",
+            Category::Io => "io",
+            Category::Parse => "parse",
+            Category::Invariant => "invariant",
+            Category::Reconciliation => "reconciliation",
+        }
+    }
+}
+
+// An io::Error, or a CSV error that is one, other than the InvalidData of
+// bad input
+fn is_io(error: &(dyn Error + 'static)) -> bool {
+    let io = match (
+        error.downcast_ref::<io::Error>(),
+        error.downcast_ref::<csv::Error>(),
+    ) {
+        (Some(e), _) => e,
+        (_, Some(e)) => match e.kind() {
+            csv::ErrorKind::Io(e) => e,
+            _ => return false,
+        },
+        _ => return false,
+    };
+    io.kind() != io::ErrorKind::InvalidData
+}
+
+impl Failure {
+    fn category(&self) -> Category {
+        match (self.error, self.cause.as_deref()) {
+            (_, Some(cause)) if cause.is::<InvariantViolation>() => Category::Invariant,
+            (_, Some(cause)) if cause.is::<Discrepancies>() => Category::Reconciliation,
+            (_, Some(cause)) if is_io(cause) => Category::Io,
+            (PaymentErrors::ReadPipeline, _) => Category::Usage,
+            (
+                PaymentErrors::ImportCsv
+                | PaymentErrors::ImportProtobuf
+                | PaymentErrors::ImportColumnar
+                | PaymentErrors::ImportFixed
+                | PaymentErrors::ImportStatement
+                | PaymentErrors::ImportXlsx
+                | PaymentErrors::ImportBinary
+                | PaymentErrors::Invalid
+                | PaymentErrors::Reconcile,
+                _,
+            ) => Category::Parse,
+            (PaymentErrors::Merge, _) => Category::Invariant,
+            (
+                PaymentErrors::CreateStore
+                | PaymentErrors::Audit
+                | PaymentErrors::Wal
+                | PaymentErrors::Overdraft
+                | PaymentErrors::Blocklist
+                | PaymentErrors::Limits
+                | PaymentErrors::AccountsMeta
+                | PaymentErrors::LoadState
+                | PaymentErrors::OpeningBalances
+                | PaymentErrors::SaveState
+                | PaymentErrors::ExportAccounts
+                | PaymentErrors::WriteStats
+                | PaymentErrors::Config,
+                _,
+            ) => Category::Io,
+            _ => Category::Other,
+        }
+    }
+
+    // On stderr, and as JSON to `json` if there's one
+    fn report(&self, json: Option<&Path>) -> ExitCode {
+        let category = self.category();
+        let message = self.cause.as_ref().map(|cause| cause.to_string());
+        match &message {
+            Some(message) => eprintln!("Error: {:?}: {}", self.error, message),
+            None => eprintln!("Error: {:?}", self.error),
+        }
+        if let Some(path) = json {
+            let description = serde_json::json!({
+                "error": format!("{:?}", self.error),
+                "category": category.name(),
+                "exit_code": category as u8,
+                "message": message,
+            });
+            let written = match path == Path::new("-") {
+                true => writeln!(io::stderr(), "{}", description),
+                false => std::fs::write(path, format!("{}\n", description)),
+            };
+            if let Err(e) = written {
+                eprintln!("Writing {:?}: {}", path, e);
+            }
+        }
+        ExitCode::from(category as u8)
+    }
+}
+
+#[derive(Parser)]
+#[command(
+    about = "A very simple transaction processor",
+    args_conflicts_with_subcommands = true
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Read the options from this TOML file (see config print-default), the
+    /// flags given here win over it
+    #[arg(long)]
+    config: Option<PathBuf>,
+    /// Format of the logs on stderr: text or json (one object per line)
+    #[arg(long, global = true, default_value = "text")]
+    log_format: LogFormat,
+    /// Also write a failure as JSON (error, category, exit_code, message) to
+    /// this file, or - for stderr
+    #[arg(long, global = true)]
+    error_json: Option<PathBuf>,
+    #[command(flatten)]
+    process: ProcessArgs,
+}
 
-        // All these unwraps are safe assuming that the input is sane; the problem
-        // statement guarantees that.
-        let client_id: ClientId = record.get(1).unwrap().trim().parse::<ClientId>().unwrap();
-        let tx_id: TransactionId = record
-            .get(2)
-            .unwrap()
-            .trim()
-            .parse::<TransactionId>()
-            .unwrap();
-        let amount = match record.get(3) {
-            None => None,
-            Some ("") => None,
-            Some (something) => Some(Decimal::from_str(something.trim()).unwrap()),
+impl Cli {
+    // Parses the command line, and again with the flags of the config file
+    // in front of it if there's one
+    fn load() -> Cli {
+        let matches = Cli::command().get_matches();
+        let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+        let path = match &cli.config {
+            Some(path) => path,
+            None => return cli,
         };
+        let given = |key: &str| matches.value_source(key) == Some(ValueSource::CommandLine);
+        let flags = config::read(path)
+            .and_then(|table| Ok(config::args(&table, &Cli::command(), given)?))
+            .unwrap_or_else(|e| {
+                Cli::command()
+                    .error(ErrorKind::InvalidValue, format!("{:?}: {}", path, e))
+                    .exit()
+            });
+        let mut args: Vec<OsString> = env::args_os().take(1).collect();
+        args.extend(flags);
+        args.extend(env::args_os().skip(1));
+        Cli::parse_from(args)
+    }
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Print a config file with every option set to its default
+    PrintDefault,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run a pipeline definition (YAML) instead of passing everything as flags
+    Run { pipeline: PathBuf },
+    /// Write a reproducible synthetic input file, for benchmarks and tests
+    Generate {
+        #[arg(long, default_value_t = 1_000_000)]
+        rows: u64,
+        #[arg(long, default_value_t = 1000)]
+        clients: u16,
+        /// Roughly the fraction of rows that are disputes, with about as many
+        /// resolves and chargebacks following them
+        #[arg(long, default_value_t = 0.01)]
+        dispute_rate: f64,
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+        /// Also produce rows the engine should reject or ignore
+        #[arg(long)]
+        adversarial: bool,
+        output: PathBuf,
+    },
+    /// Serve the engine over HTTP (JSON) instead of processing files
+    Serve {
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        listen: SocketAddr,
+        /// Split the clients over this many independently locked engines, so
+        /// requests for clients of different shards run in parallel
+        #[arg(long, default_value_t = 1)]
+        shards: usize,
+        #[command(flatten)]
+        engine: ServerEngineArgs,
+    },
+    /// Serve the engine over gRPC (see proto/transactions.proto)
+    Grpc {
+        #[arg(long, default_value = "127.0.0.1:50051")]
+        listen: SocketAddr,
+        /// Split the clients over this many independently locked engines, so
+        /// requests for clients of different shards run in parallel
+        #[arg(long, default_value_t = 1)]
+        shards: usize,
+        #[command(flatten)]
+        engine: ServerEngineArgs,
+    },
+    /// Rebuild the engine from a write-ahead log (--wal), e.g. after a crash
+    Replay {
+        journal: PathBuf,
+        /// The state the logged run started from, if it didn't start from scratch
+        #[arg(long)]
+        load_state: Option<PathBuf>,
+        /// Same, for the opening balances
+        #[arg(long)]
+        opening_balances: Option<PathBuf>,
+        #[arg(long)]
+        save_state: Option<PathBuf>,
+        #[arg(long, default_value = "msgpack")]
+        state_format: StateFormat,
+        /// Has to be the one of the logged run
+        #[arg(long, default_value = "v1")]
+        compat_level: CompatLevel,
+        /// Same
+        #[arg(long, default_value = "any")]
+        account_creation: AccountCreation,
+        #[arg(long, default_value = "csv")]
+        output_format: OutputFormat,
+    },
+    /// The accounts as they were at some point of a write-ahead log, for
+    /// investigations
+    Query {
+        journal: PathBuf,
+        /// Only this client's account
+        #[arg(long)]
+        client: Option<ClientId>,
+        /// Right after this many transactions of the log, the whole log
+        /// without it. The log has no timestamps, so there's no --as-of
+        #[arg(long)]
+        at: Option<u64>,
+        /// Same as for replay
+        #[arg(long)]
+        load_state: Option<PathBuf>,
+        #[arg(long)]
+        opening_balances: Option<PathBuf>,
+        #[arg(long, default_value = "msgpack")]
+        state_format: StateFormat,
+        #[arg(long, default_value = "v1")]
+        compat_level: CompatLevel,
+        #[arg(long, default_value = "any")]
+        account_creation: AccountCreation,
+    },
+    /// Check a CSV file without exporting anything: every row that can't be
+    /// read, duplicate tx ids, disputes of missing or other clients'
+    /// transactions... Fails if there's any
+    Validate {
+        input: PathBuf,
+        /// The semantics to simulate
+        #[arg(long, default_value = "v1")]
+        compat_level: CompatLevel,
+        #[command(flatten)]
+        dialect: DialectArgs,
+    },
+    /// Every row of a CSV file with this tx id (the transaction, and the
+    /// disputes, resolves and chargebacks of it), what processing the file
+    /// did with each, the transaction's status at the end and its client's
+    /// account
+    Inspect {
+        input: PathBuf,
+        #[arg(long)]
+        tx: TransactionId,
+        /// The semantics to simulate
+        #[arg(long, default_value = "v1")]
+        compat_level: CompatLevel,
+        #[command(flatten)]
+        dialect: DialectArgs,
+    },
+    /// What's in a CSV file, without processing it: rows, distinct clients
+    /// and tx ids, rows per type, smallest, largest and total amount, and
+    /// the share of disputes, resolves and chargebacks
+    Stats {
+        input: PathBuf,
+        /// As JSON instead of text
+        #[arg(long)]
+        json: bool,
+        #[command(flatten)]
+        dialect: DialectArgs,
+    },
+    /// Type transactions and look at accounts one command at a time, to try
+    /// out the engine or reproduce a bug report without a CSV file. `help`
+    /// lists the commands
+    Repl {
+        /// Start from this saved state instead of from scratch
+        #[arg(long)]
+        load_state: Option<PathBuf>,
+        #[arg(long, default_value = "msgpack")]
+        state_format: StateFormat,
+        #[arg(long, default_value = "v1")]
+        compat_level: CompatLevel,
+        #[arg(long, default_value = "any")]
+        account_creation: AccountCreation,
+    },
+    /// Compare two account exports: balance deltas, newly locked accounts
+    /// and new or removed clients
+    Diff { old: PathBuf, new: PathBuf },
+    /// Combine the saved states of runs over different clients (one per
+    /// shard, say) into one, adding up the balances of the clients they
+    /// share. Fails if two of them have the same tx id
+    Merge {
+        #[arg(required = true, num_args = 2..)]
+        states: Vec<PathBuf>,
+        #[arg(long, default_value = "msgpack")]
+        state_format: StateFormat,
+        /// Save the merged state here, the accounts are printed either way
+        #[arg(long)]
+        save_state: Option<PathBuf>,
+    },
+    /// Print the shell completion script for bash, zsh, fish, elvish or
+    /// powershell, e.g. `payments-engine completions bash >
+    /// /etc/bash_completion.d/payments-engine`
+    Completions { shell: Shell },
+    /// Print the man page (roff), e.g. `payments-engine man | man -l -`
+    Man,
+    /// Config file helpers
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Keep processing CSV files as they land in a directory, or lines as
+    /// they're appended to a file
+    Watch {
+        /// Import every CSV file that shows up here, moving it into
+        /// processed/ or failed/ afterwards
+        #[arg(long, required_unless_present = "tail")]
+        dir: Option<PathBuf>,
+        /// Import the lines appended to this file
+        #[arg(long)]
+        tail: Option<PathBuf>,
+        /// Seconds between looks at the directory and the file
+        #[arg(long, default_value_t = 1.0)]
+        interval: f64,
+        /// Keep the accounts exported in this file
+        #[arg(long)]
+        export: Option<PathBuf>,
+        /// Seconds between exports, at most
+        #[arg(long, default_value_t = 10.0)]
+        export_every: f64,
+        /// csv, msgpack or cbor
+        #[arg(long, default_value = "csv")]
+        export_format: OutputFormat,
+        #[command(flatten)]
+        dialect: DialectArgs,
+        #[command(flatten)]
+        engine: ServerEngineArgs,
+    },
+}
+
+// The engine flags that make sense for the online modes
+#[derive(Args)]
+struct ServerEngineArgs {
+    #[arg(long, default_value = "v1")]
+    compat_level: CompatLevel,
+    #[arg(long, default_value = "any")]
+    account_creation: AccountCreation,
+    #[arg(long)]
+    load_state: Option<PathBuf>,
+    #[arg(long)]
+    opening_balances: Option<PathBuf>,
+    #[arg(long, default_value = "msgpack")]
+    state_format: StateFormat,
+    #[arg(long)]
+    low_memory: Option<PathBuf>,
+    #[arg(long, conflicts_with = "low_memory")]
+    compact_store: bool,
+    #[arg(long)]
+    audit: Option<PathBuf>,
+    /// Append every transaction to this write-ahead log before applying it
+    #[arg(long)]
+    wal: Option<PathBuf>,
+    /// POST chargebacks and locked accounts as JSON to this http:// URL,
+    /// can be repeated
+    #[arg(long)]
+    webhook: Vec<String>,
+    /// Skip deposits and withdrawals already processed
+    #[arg(long)]
+    idempotent: bool,
+    #[arg(long, default_value = "0")]
+    overdraft_limit: Decimal,
+    #[arg(long)]
+    overdraft_limits: Option<PathBuf>,
+    #[command(flatten)]
+    limits: LimitArgs,
+    #[command(flatten)]
+    resources: ResourceArgs,
+    #[arg(long)]
+    blocklist: Option<PathBuf>,
+    #[arg(long, default_value = "reject")]
+    blocklist_mode: BlockMode,
+    #[arg(long)]
+    accounts_meta: Option<PathBuf>,
+    #[arg(long)]
+    tier_limits: Option<PathBuf>,
+}
+
+// Protection against hostile input, see ResourceLimits
+#[derive(Args, Clone)]
+struct ResourceArgs {
+    /// Fail on a CSV record longer than this many bytes
+    #[arg(long)]
+    max_record_length: Option<usize>,
+    /// Fail on a CSV record with more than this many fields
+    #[arg(long)]
+    max_columns: Option<usize>,
+    /// Fail on a new client once there are this many accounts
+    #[arg(long)]
+    max_accounts: Option<usize>,
+    /// Fail on a deposit once this many transactions are stored for disputes
+    #[arg(long)]
+    max_stored_transactions: Option<usize>,
+    /// Fail transactions while the process uses more than this much memory
+    #[arg(long)]
+    max_memory_mb: Option<u64>,
+}
+
+impl From<ResourceArgs> for ResourceLimits {
+    fn from(args: ResourceArgs) -> ResourceLimits {
+        ResourceLimits {
+            max_record_length: args.max_record_length,
+            max_columns: args.max_columns,
+            max_accounts: args.max_accounts,
+            max_transactions: args.max_stored_transactions,
+            max_memory_mb: args.max_memory_mb,
+        }
+    }
+}
+
+// Fraud checks, see LimitsSpec
+#[derive(Args, Clone)]
+struct LimitArgs {
+    /// Reject deposits, withdrawals and holds over this amount
+    #[arg(long)]
+    max_amount: Option<Decimal>,
+    /// Reject withdrawals that take a client over this much in 24 hours
+    #[arg(long)]
+    max_daily_withdrawal: Option<Decimal>,
+    /// Reject a client's transactions over this many per --velocity-window
+    #[arg(long)]
+    max_transactions: Option<usize>,
+    /// Seconds
+    #[arg(long, default_value_t = 60)]
+    velocity_window: u64,
+    /// Also lock the account of a transaction over a limit
+    #[arg(long)]
+    lock_on_violation: bool,
+}
 
-        Ok(Transaction {
-            tx_type,
-            client_id,
-            tx_id,
-            amount,
-            status: TransactionStatus::OK,
+impl From<LimitArgs> for Option<LimitsSpec> {
+    fn from(args: LimitArgs) -> Option<LimitsSpec> {
+        if args.max_amount.is_none()
+            && args.max_daily_withdrawal.is_none()
+            && args.max_transactions.is_none()
+        {
+            return None;
+        }
+        Some(LimitsSpec {
+            max_amount: args.max_amount,
+            max_daily_withdrawal: args.max_daily_withdrawal,
+            max_transactions: args.max_transactions,
+            window_secs: args.velocity_window,
+            lock_on_violation: args.lock_on_violation,
+            tiers: Default::default(),
         })
     }
 }
 
-#[test]
-fn test_record_to_transaction() {
-    /* Deposits */
-    let tx_deposit =
-        Transaction::try_from(StringRecord::from(vec!["deposit", "1", "1", "1.0"])).unwrap();
-    assert_eq!(
-        tx_deposit,
-        Transaction {
-            tx_type: TransactionType::Deposit,
-            client_id: 1,
-            tx_id: 1,
-            amount: Some(Decimal::from_str("1.0").unwrap()),
-            status: TransactionStatus::OK
-        }
-    );
-
-    /* Transaction inequality */
-    assert_ne!(
-        tx_deposit,
-        Transaction {
-            tx_type: TransactionType::Withdrawal,
-            client_id: 1,
-            tx_id: 1,
-            amount: Some(Decimal::from_str("1.0").unwrap()),
-            status: TransactionStatus::OK
-        }
-    );
+impl From<ServerEngineArgs> for EngineSpec {
+    fn from(args: ServerEngineArgs) -> EngineSpec {
+        EngineSpec {
+            threads: 1,
+            parallel_parse: false,
+            mmap: false,
+            vectorized: false,
+            resources: args.resources.into(),
+            low_memory: args.low_memory,
+            compact_store: args.compact_store,
+            load_state: args.load_state,
+            opening_balances: args.opening_balances,
+            state_format: args.state_format,
+            compat_level: args.compat_level,
+            account_creation: args.account_creation,
+            audit: args.audit,
+            audit_compression: Compression::None,
+            wal: args.wal,
+            webhooks: args.webhook.into_iter().map(WebhookSpec::new).collect(),
+            idempotent: args.idempotent,
+            overdraft_limit: args.overdraft_limit,
+            overdraft_limits: args.overdraft_limits,
+            limits: args.limits.into(),
+            blocklist: args.blocklist,
+            blocklist_mode: args.blocklist_mode,
+            accounts_meta: args.accounts_meta,
+            tier_limits: args.tier_limits,
+        }
+    }
 }
 
-struct PaymentEngine {
-    accounts: HashMap<ClientId, Account>,
-    transactions: HashMap<TransactionId, Transaction>, // We need to keep this to deal with disputes. In a non-toy implementation this doesn't belong in memory though
+// How the CSV input is written, see CsvDialect
+#[derive(Args)]
+struct DialectArgs {
+    /// Field delimiter of the CSV input
+    #[arg(long, default_value_t = ',')]
+    delimiter: char,
+    /// The CSV input has no header row, the columns are type, client, tx, amount
+    #[arg(long)]
+    no_header: bool,
+    #[arg(long, default_value_t = '"')]
+    quote_char: char,
+    /// Skip CSV lines starting with this character
+    #[arg(long)]
+    comment_char: Option<char>,
+    /// Header names of the columns when they're not type, client, tx and
+    /// amount, e.g. type=txn_kind,client=customer_id
+    #[arg(long, value_delimiter = ',', value_parser = parse_mapping)]
+    map: Vec<(String, String)>,
+    /// Text encoding of the CSV input: utf8, utf16le or latin1. A UTF-16 BOM
+    /// is detected anyway
+    #[arg(long, default_value = "utf8")]
+    encoding: Encoding,
+    /// Decimal separator of the amounts, e.g. , for 1.234,56
+    #[arg(long, default_value_t = '.')]
+    decimal_separator: char,
+    /// Thousands separator of the amounts, if they have one
+    #[arg(long)]
+    thousands_separator: Option<char>,
+    /// Currency symbol to remove from the amounts (repeatable), e.g. €
+    #[arg(long)]
+    currency_symbol: Vec<String>,
+    /// Check the header and trailer records of the CSV input (row count and
+    /// sum of amounts), failing or warning when they don't match
+    #[arg(long)]
+    control_records: Option<ControlRecords>,
+    /// Roll back every batch (rows with the same batch column) that has a
+    /// row that fails (errors) or isn't applied (strict)
+    #[arg(long)]
+    atomic_batches: Option<BatchRollback>,
+    /// Replace invalid UTF-8 in the CSV input instead of failing, skipping
+    /// (and logging) the rows where it makes type, client, tx or amount
+    /// unreadable
+    #[arg(long)]
+    lossy_utf8: bool,
 }
 
-#[derive(Debug)]
-struct Account {
-    client_id: ClientId,
-    num_transactions: u32,
-    funds_available: Decimal,
-    funds_held: Decimal,
-    funds_total: Decimal, // TODO: Possibly redundant but let's keep around for now for basic sanity check
-    locked: bool,
+impl From<DialectArgs> for CsvDialect {
+    fn from(args: DialectArgs) -> CsvDialect {
+        CsvDialect {
+            delimiter: args.delimiter,
+            header: !args.no_header,
+            quote: args.quote_char,
+            comment: args.comment_char,
+            map: args.map.into_iter().collect(),
+            encoding: args.encoding,
+            amount: AmountFormat {
+                decimal_separator: args.decimal_separator,
+                thousands_separator: args.thousands_separator,
+                currency_symbols: args.currency_symbol,
+            },
+            control: args.control_records,
+            batches: args.atomic_batches,
+            lossy_utf8: args.lossy_utf8,
+        }
+    }
+}
+
+#[derive(Args)]
+struct ProcessArgs {
+    /// Format of the input file: csv, protobuf (length-delimited
+    /// TransactionBatch or Transaction messages; tcp://ADDRESS instead of a
+    /// file listens for a producer), msgpack or cbor (records prefixed with
+    /// their length as a big-endian u32), parquet, arrow (IPC file), fixed
+    /// (fixed-width text, see --layout), xlsx (Excel, see --sheet; needs the
+    /// xlsx feature), or the bank formats ofx (OFX or QFX), camt (ISO 20022
+    /// camt.053), mt940 (SWIFT MT940) and nacha (ACH), see --statement-client
+    #[arg(long, default_value = "csv")]
+    input_format: InputFormat,
+    /// TOML file with the offsets and widths of the fields of fixed-width
+    /// input, and its type codes
+    #[arg(long)]
+    layout: Option<PathBuf>,
+    /// Client the transactions of bank statements are of, and of ACH
+    /// entries whose individual id isn't a client id
+    #[arg(long)]
+    statement_client: Option<ClientId>,
+    /// Sheet of an Excel input to read, instead of the first one
+    #[arg(long)]
+    sheet: Option<String>,
+    #[command(flatten)]
+    dialect: DialectArgs,
+    /// Format of the account export on stdout: csv, msgpack, cbor or
+    /// parquet; or beancount for every transaction as an accounting entry
+    #[arg(long, default_value = "csv")]
+    output_format: OutputFormat,
+    /// Commodity of the amounts in the beancount output
+    #[arg(long, default_value = "USD")]
+    commodity: String,
+    /// Order of the exported accounts: client (id), total (biggest first) or
+    /// none (as they come, no sorting)
+    #[arg(long, default_value = "client")]
+    sort: AccountOrder,
+    /// Columns of the CSV account export, in this order. num_transactions,
+    /// overdrawn, and with --accounts-meta name, currency and risk_tier are
+    /// also available
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "client,available,held,total,locked"
+    )]
+    columns: Vec<AccountColumn>,
+    /// Only export these clients' accounts (CSV and Parquet), e.g.
+    /// 1,2,100-200
+    #[arg(long)]
+    clients: Option<ClientRanges>,
+    /// Only export the locked accounts (CSV and Parquet)
+    #[arg(long)]
+    only_locked: bool,
+    /// Write the CSV accounts into this many files, accounts_00.csv and on,
+    /// instead of stdout
+    #[arg(long)]
+    output_partition: Option<u16>,
+    /// How --output-partition splits the accounts: modulo (client id modulo
+    /// the number of files) or range (consecutive client ids)
+    #[arg(long, default_value = "modulo", requires = "output_partition")]
+    partition_by: PartitionBy,
+    /// Write every account to stdout as soon as its client is done, for
+    /// input sorted by client: a transaction of another client closes it
+    #[arg(long, conflicts_with_all = ["output_partition", "save_state", "output_db"])]
+    stream_accounts: bool,
+    /// Compress the account export (or the --output-partition files) and
+    /// the --audit log as they're written: none, gzip or zstd. Not for
+    /// Parquet, which is compressed already
+    #[arg(long, default_value = "none")]
+    output_compression: Compression,
+    /// Directory of the --output-partition files
+    #[arg(long, default_value = ".", requires = "output_partition")]
+    output_dir: PathBuf,
+    /// Start from a previously saved engine state instead of from scratch
+    #[arg(long)]
+    load_state: Option<PathBuf>,
+    /// Start from these balances (client,available,held,locked CSV, e.g.
+    /// yesterday's output) instead of from zero
+    #[arg(long)]
+    opening_balances: Option<PathBuf>,
+    /// Save the engine state after processing the input
+    #[arg(long)]
+    save_state: Option<PathBuf>,
+    /// Encoding of the state files: msgpack or cbor
+    #[arg(long, default_value = "msgpack")]
+    state_format: StateFormat,
+    /// Process the input with this many worker threads, sharded by client
+    /// (v1 only)
+    #[arg(long, default_value_t = 1)]
+    threads: usize,
+    /// Parse CSV input on every core even with one processing thread
+    /// (RAYON_NUM_THREADS sets how many)
+    #[arg(long)]
+    parallel_parse: bool,
+    /// Memory-map CSV input instead of reading it, for very large files on
+    /// fast local disks
+    #[arg(long)]
+    mmap: bool,
+    /// Apply runs of deposits and withdrawals a batch at a time, with Arrow
+    /// (needs the parquet feature)
+    #[arg(long, conflicts_with = "threads")]
+    vectorized: bool,
+    /// Keep the disputable transactions in an on-disk index at this path
+    /// instead of in memory, for inputs that don't fit in RAM
+    #[arg(long)]
+    low_memory: Option<PathBuf>,
+    /// Keep the disputable transactions in memory packed, in about half the
+    /// memory
+    #[arg(long, conflicts_with = "low_memory")]
+    compact_store: bool,
+    /// Engine semantics: v1 (the original ones) or v2 (stricter, see src/compat.rs)
+    #[arg(long, default_value = "v1")]
+    compat_level: CompatLevel,
+    /// Which transactions open an account for an unknown client: any, deposit
+    /// or never (see src/policy.rs)
+    #[arg(long, default_value = "any")]
+    account_creation: AccountCreation,
+    /// Append a JSON line per processed transaction, with the balances before
+    /// and after and why it was ignored, if it was
+    #[arg(long)]
+    audit: Option<PathBuf>,
+    /// Append every transaction to this write-ahead log before applying it,
+    /// see the replay command
+    #[arg(long)]
+    wal: Option<PathBuf>,
+    /// POST chargebacks and locked accounts as JSON to this http:// URL as
+    /// they happen, can be repeated
+    #[arg(long)]
+    webhook: Vec<String>,
+    /// Keep the processed deposit and withdrawal tx ids in the saved state
+    /// and skip them if they come again, for reprocessing overlapping files.
+    /// Needs a single engine: not with --threads or --actors
+    #[arg(long, conflicts_with_all = ["threads", "actors"])]
+    idempotent: bool,
+    /// Let withdrawals take any account this far below zero
+    #[arg(long, default_value = "0")]
+    overdraft_limit: Decimal,
+    /// Per client overdraft limits (client,limit CSV, or TOML with a default
+    /// and a [clients] table), over --overdraft-limit
+    #[arg(long)]
+    overdraft_limits: Option<PathBuf>,
+    #[command(flatten)]
+    limits: LimitArgs,
+    #[command(flatten)]
+    resources: ResourceArgs,
+    /// Reject the transactions of the client ids in this file, one per line
+    #[arg(long)]
+    blocklist: Option<PathBuf>,
+    /// reject, or hold-deposits to take blocked clients' deposits into held
+    /// funds
+    #[arg(long, default_value = "reject")]
+    blocklist_mode: BlockMode,
+    /// With --blocklist, write every transaction of a blocked client and what
+    /// was done with it (CSV) to this file
+    #[arg(long, requires = "blocklist")]
+    blocked_report: Option<PathBuf>,
+    /// Client names, currencies, risk tiers and initial locks (CSV with
+    /// client,name,currency,risk_tier,locked); the clients in it get an
+    /// account up front
+    #[arg(long)]
+    accounts_meta: Option<PathBuf>,
+    /// Limits by risk tier, a TOML table per tier with max_amount,
+    /// max_daily_withdrawal and max_transactions
+    #[arg(long)]
+    tier_limits: Option<PathBuf>,
+    /// Also write the accounts into this SQLite database (needs the sqlite feature)
+    #[arg(long)]
+    output_db: Option<PathBuf>,
+    /// With --output-db, also write every transaction into a ledger table
+    #[arg(long, requires = "output_db")]
+    ledger: bool,
+    /// Write every transaction disputed during the run, its final status and
+    /// its disputes, resolves and chargebacks in order (CSV) to this file
+    #[arg(long)]
+    disputes_report: Option<PathBuf>,
+    /// Write the repeated tx ids, and the disputes, resolves and chargebacks
+    /// of unknown, undisputed or other clients' transactions that were
+    /// passed over (CSV) to this file
+    #[arg(long)]
+    dedupe_report: Option<PathBuf>,
+    /// Write the clients whose transactions look like a bust-out, too many
+    /// chargebacks or structuring, scored (CSV) to this file
+    #[arg(long)]
+    anomaly_report: Option<PathBuf>,
+    /// Report clients with more than this share of their deposits charged back
+    #[arg(long, default_value = "0.1", requires = "anomaly_report")]
+    chargeback_ratio: Decimal,
+    /// Report clients with --structuring-count deposits just under this amount
+    #[arg(long, default_value = "10000", requires = "anomaly_report")]
+    structuring_threshold: Decimal,
+    /// How many deposits within 10% under --structuring-threshold it takes
+    #[arg(long, default_value = "3", requires = "anomaly_report")]
+    structuring_count: usize,
+    /// Write every locked account, the chargeback or transaction over a
+    /// limit that locked it and the balances right after (CSV) to this file
+    #[arg(long)]
+    locked_report: Option<PathBuf>,
+    /// Write what went in and out of every client's account per category
+    /// (the optional category column) as CSV to this file
+    #[arg(long)]
+    categories_report: Option<PathBuf>,
+    /// Write every applied transaction as balanced debit and credit postings
+    /// against the client and internal accounts (CSV) to this file
+    #[arg(long)]
+    double_entry: Option<PathBuf>,
+    /// Write an end of run summary (JSON) to this file, or to stderr with -
+    #[arg(long)]
+    stats: Option<PathBuf>,
+    /// Check the final balances against this control file (CSV with client
+    /// and any of available, held, total and locked) and exit with 6 if they
+    /// don't match
+    #[arg(long)]
+    reconcile: Option<PathBuf>,
+    /// How far apart amounts can be and still match the control file
+    #[arg(long, default_value = "0", requires = "reconcile")]
+    reconcile_tolerance: Decimal,
+    /// Write the differences with the control file (CSV) to this file
+    /// instead of stderr, even if there are none
+    #[arg(long, requires = "reconcile")]
+    reconcile_report: Option<PathBuf>,
+    /// Serve Prometheus metrics on this address (e.g. 127.0.0.1:9000) while running
+    #[arg(long)]
+    metrics_addr: Option<SocketAddr>,
+    /// Show a progress bar, rows/s, ETA and rejected rows on stderr
+    #[arg(long)]
+    progress: bool,
+    /// Report the time spent parsing, in the engine and exporting on stderr
+    #[arg(long)]
+    timings: bool,
+    /// Save the state and the position in the input here every
+    /// --checkpoint-every transactions (CSV input, one thread)
+    #[arg(long)]
+    checkpoint: Option<PathBuf>,
+    #[arg(long, default_value_t = 1_000_000)]
+    checkpoint_every: u64,
+    /// Carry on from the --checkpoint of an interrupted run
+    #[arg(long, requires = "checkpoint")]
+    resume: bool,
+    /// Where the transactions come from: file (the input argument) or kafka.
+    /// A Kafka consumer runs until it fails, so it takes no outputs, reports
+    /// or --save-state
+    #[arg(long, default_value = "file")]
+    source: SourceKind,
+    /// Kafka brokers, comma separated (e.g. localhost:9092)
+    #[arg(long, value_delimiter = ',', default_value = "localhost:9092")]
+    brokers: Vec<String>,
+    /// Kafka topic to consume; each message is a CSV line or a JSON transaction
+    #[arg(long, default_value = "transactions")]
+    topic: String,
+    /// Kafka consumer group, whose offsets are committed after processing
+    #[arg(long, default_value = "payments-engine")]
+    group: String,
+    /// With --source kafka, process each client in an actor of its own so
+    /// clients are processed on every core (see src/actors.rs)
+    #[arg(long, conflicts_with = "low_memory")]
+    actors: bool,
+    input: Option<PathBuf>,
+}
+
+fn parse_mapping(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((column, name)) => Ok((column.trim().to_string(), name.trim().to_string())),
+        None => Err(format!("Expected column=name, got {}", s)),
+    }
 }
 
-impl PaymentEngine {
-    fn new() -> PaymentEngine {
-        PaymentEngine {
-            accounts: HashMap::new(),
-            transactions: HashMap::new(),
+impl ProcessArgs {
+    fn engine_spec(&self) -> EngineSpec {
+        EngineSpec {
+            threads: self.threads,
+            parallel_parse: self.parallel_parse,
+            mmap: self.mmap,
+            vectorized: self.vectorized,
+            resources: self.resources.clone().into(),
+            low_memory: self.low_memory.clone(),
+            compact_store: self.compact_store,
+            load_state: self.load_state.clone(),
+            opening_balances: self.opening_balances.clone(),
+            state_format: self.state_format,
+            compat_level: self.compat_level,
+            account_creation: self.account_creation,
+            audit: self.audit.clone(),
+            audit_compression: self.output_compression,
+            wal: self.wal.clone(),
+            webhooks: self.webhook.iter().cloned().map(WebhookSpec::new).collect(),
+            idempotent: self.idempotent,
+            overdraft_limit: self.overdraft_limit,
+            overdraft_limits: self.overdraft_limits.clone(),
+            limits: self.limits.clone().into(),
+            blocklist: self.blocklist.clone(),
+            blocklist_mode: self.blocklist_mode,
+            accounts_meta: self.accounts_meta.clone(),
+            tier_limits: self.tier_limits.clone(),
+        }
+    }
+
+    // The first flag given that a Kafka consumer can't honour: it runs until
+    // it fails, there's no end of the run to write anything at
+    fn unused_by_kafka(&self) -> Option<&'static str> {
+        [
+            ("--save-state", self.save_state.is_some()),
+            ("--output-partition", self.output_partition.is_some()),
+            ("--stream-accounts", self.stream_accounts),
+            ("--output-db", self.output_db.is_some()),
+            ("--disputes-report", self.disputes_report.is_some()),
+            ("--dedupe-report", self.dedupe_report.is_some()),
+            ("--anomaly-report", self.anomaly_report.is_some()),
+            ("--locked-report", self.locked_report.is_some()),
+            ("--categories-report", self.categories_report.is_some()),
+            ("--double-entry", self.double_entry.is_some()),
+            ("--blocked-report", self.blocked_report.is_some()),
+            ("--stats", self.stats.is_some()),
+            ("--reconcile", self.reconcile.is_some()),
+            ("--checkpoint", self.checkpoint.is_some()),
+            ("--threads", self.threads > 1),
+            ("--parallel-parse", self.parallel_parse),
+            ("--mmap", self.mmap),
+            ("--vectorized", self.vectorized),
+        ]
+        .iter()
+        .find(|(_, given)| *given)
+        .map(|(flag, _)| *flag)
+    }
+
+    // The flags are just a shorthand for a single source pipeline
+    fn into_pipeline(self) -> Pipeline {
+        let engine = self.engine_spec();
+        let mut outputs = Vec::new();
+        if let Some(path) = self.save_state {
+            outputs.push(OutputSpec::State {
+                path,
+                format: self.state_format,
+            });
+        }
+        let filter = AccountFilter {
+            clients: self.clients,
+            only_locked: self.only_locked,
+        };
+        outputs.push(match self.output_partition {
+            _ if self.stream_accounts => OutputSpec::StreamedAccounts {
+                path: None,
+                columns: self.columns,
+            },
+            Some(partitions) => OutputSpec::PartitionedAccounts {
+                dir: self.output_dir,
+                partitions,
+                by: self.partition_by,
+                sort: self.sort,
+                columns: self.columns,
+                filter,
+                compression: self.output_compression,
+            },
+            None => OutputSpec::Accounts {
+                path: None,
+                format: self.output_format,
+                sort: self.sort,
+                columns: self.columns,
+                filter,
+                commodity: self.commodity,
+                compression: self.output_compression,
+            },
+        });
+        if let Some(path) = self.output_db {
+            outputs.push(OutputSpec::Sqlite {
+                path,
+                ledger: self.ledger,
+            });
+        }
+        if let Some(path) = self.disputes_report {
+            outputs.push(OutputSpec::Disputes { path });
+        }
+        if let Some(path) = self.dedupe_report {
+            outputs.push(OutputSpec::Conflicts { path });
+        }
+        if let Some(path) = self.anomaly_report {
+            outputs.push(OutputSpec::Anomalies {
+                path,
+                rules: AnomalyRules {
+                    chargeback_ratio: self.chargeback_ratio,
+                    structuring_threshold: self.structuring_threshold,
+                    structuring_count: self.structuring_count,
+                    ..AnomalyRules::default()
+                },
+            });
         }
+        if let Some(path) = self.locked_report {
+            outputs.push(OutputSpec::Locked { path });
+        }
+        if let Some(path) = self.categories_report {
+            outputs.push(OutputSpec::Categories { path });
+        }
+        if let Some(path) = self.double_entry {
+            outputs.push(OutputSpec::DoubleEntry { path });
+        }
+        if let Some(path) = self.blocked_report {
+            outputs.push(OutputSpec::Blocked { path });
+        }
+        if let Some(path) = self.stats {
+            outputs.push(OutputSpec::Stats {
+                path: Some(path).filter(|path| path.as_os_str() != "-"),
+            });
+        }
+        // Last, failing doesn't keep the other outputs from being written
+        if let Some(expected) = self.reconcile {
+            outputs.push(OutputSpec::Reconcile {
+                expected,
+                report: self.reconcile_report,
+                tolerance: self.reconcile_tolerance,
+            });
+        }
+        let format = self.input_format;
+        let (layout, client, sheet) = (self.layout, self.statement_client, self.sheet);
+        let dialect = CsvDialect::from(self.dialect);
+        let (every, resume) = (self.checkpoint_every, self.resume);
+        Pipeline {
+            sources: self
+                .input
+                .into_iter()
+                .map(|path| SourceSpec {
+                    id: None,
+                    path,
+                    format,
+                    dialect: dialect.clone(),
+                    layout: layout.clone(),
+                    client,
+                    sheet: sheet.clone(),
+                })
+                .collect(),
+            transforms: Vec::new(),
+            engine,
+            outputs,
+            hooks: Vec::new(),
+            concurrent: false,
+            metrics: self.metrics_addr,
+            checkpoint: self.checkpoint.map(|path| CheckpointSpec {
+                path,
+                every,
+                resume,
+            }),
+            progress: self.progress,
+            timings: self.timings,
+        }
+    }
+}
+
+#[cfg(feature = "alloc-counter")]
+#[global_allocator]
+static ALLOCATOR: payments_engine::timings::CountingAllocator =
+    payments_engine::timings::CountingAllocator;
+
+fn main() -> ExitCode {
+    let cli = Cli::load();
+    logging::init(cli.log_format);
+    let error_json = cli.error_json.clone();
+    match run(cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(failure) => failure.report(error_json.as_deref()),
     }
+}
 
-    fn process_transaction(&mut self, transaction: Transaction) {
-        let account_ref = self.accounts.get_mut(&transaction.client_id).unwrap();
-        account_ref.num_transactions += 1;
-        debug!(
-            "client transactions now, num_transactions: {}",
-            account_ref.num_transactions
-        );
-        debug!(
-            "Processing transaction {:?}, {:?}",
-            account_ref, transaction
-        );
-        match transaction.tx_type {
-            TransactionType::Deposit => {
-                assert!(!self.transactions.contains_key(&transaction.tx_id));
-                let amount = transaction.amount.unwrap();
-                account_ref.funds_available += amount;
-                account_ref.funds_total += amount;
-                debug!("Funds added!");
-                // Assumption here: Only Deposits can be disputed so we don't store the rest
-                self.transactions.insert(transaction.tx_id, transaction); // Adding it at the end avoid ownership BS
+fn run(cli: Cli) -> Result<(), Failure> {
+    let pipeline = match cli.command {
+        Some(Command::Run { pipeline }) => {
+            Pipeline::from_yaml(&pipeline).map_err(|e| PaymentErrors::ReadPipeline.because(e))?
+        }
+        Some(Command::Generate {
+            rows,
+            clients,
+            dispute_rate,
+            seed,
+            adversarial,
+            output,
+        }) => {
+            let config = GeneratorConfig {
+                rows,
+                clients,
+                dispute_rate,
+                seed,
+                adversarial,
+            };
+            return File::create(output)
+                .and_then(|file| generate::generate(file, &config))
+                .map_err(|e| PaymentErrors::Generate.because(e));
+        }
+        Some(Command::Serve {
+            listen,
+            shards,
+            engine,
+        }) => {
+            let engine = EngineSpec::from(engine).build()?;
+            let server =
+                Server::with_shards(engine, shards).map_err(|e| PaymentErrors::Serve.because(e))?;
+            return TcpListener::bind(listen)
+                .and_then(|listener| server.run(listener))
+                .map_err(|e| PaymentErrors::Serve.because(e));
+        }
+        Some(Command::Replay {
+            journal,
+            load_state,
+            opening_balances,
+            save_state,
+            state_format,
+            compat_level,
+            account_creation,
+            output_format,
+        }) => {
+            let started = Instant::now();
+            let mut engine = EngineSpec {
+                load_state,
+                opening_balances,
+                state_format,
+                compat_level,
+                account_creation,
+                ..Default::default()
             }
-            TransactionType::Withdrawal => {
-                let amount = transaction.amount.unwrap();
-                if account_ref.funds_available >= amount {
-                    account_ref.funds_available -= amount;
-                    account_ref.funds_total -= amount;
-                    debug!("Funds withdrawn!");
-                } else {
-                    debug!(
-                        "   (transaction declined, not enough funds ({} < {})!",
-                        account_ref.funds_available, amount
-                    );
-                }
+            .build()?;
+            if output_format == OutputFormat::Beancount {
+                engine.track_double_entry();
             }
-            TransactionType::Dispute => {
-                let maybe_orig_txt = self.transactions.get_mut(&transaction.tx_id);
-                if let Some(mut orig_txt) = maybe_orig_txt {
-                    debug!("Found disputed transaction {:?}", orig_txt);
-                    if orig_txt.status == TransactionStatus::OK
-                        && orig_txt.client_id == transaction.client_id
-                    {
-                        debug!(" OK, it can be disputed.");
-                        orig_txt.status = TransactionStatus::Disputed;
-                        let amount = orig_txt.amount.unwrap();
-                        account_ref.funds_available -= amount;
-                        account_ref.funds_held += amount;
-                    }
-                }
+            wal::replay(&mut engine, &journal).map_err(|e| PaymentErrors::Replay.because(e))?;
+            let mut outputs = Vec::new();
+            if let Some(path) = save_state {
+                outputs.push(OutputSpec::State {
+                    path,
+                    format: state_format,
+                });
             }
-            TransactionType::Resolve => {
-                let maybe_orig_txt = self.transactions.get_mut(&transaction.tx_id);
-                if let Some(mut orig_txt) = maybe_orig_txt {
-                    debug!("Found disputed transaction {:?}", orig_txt);
-                    if orig_txt.status == TransactionStatus::Disputed
-                        && orig_txt.client_id == transaction.client_id
-                    {
-                        debug!(" OK, it can be resolved.");
-                        orig_txt.status = TransactionStatus::OK;
-                        let amount = orig_txt.amount.unwrap();
-                        account_ref.funds_available += amount;
-                        account_ref.funds_held -= amount;
-                    }
-                }
+            outputs.push(OutputSpec::Accounts {
+                path: None,
+                format: output_format,
+                sort: AccountOrder::default(),
+                columns: output::default_columns(),
+                filter: AccountFilter::default(),
+                commodity: output::default_commodity(),
+                compression: Compression::None,
+            });
+            for output in &outputs {
+                output.write(&engine, None, started)?;
+            }
+            return Ok(());
+        }
+        Some(Command::Query {
+            journal,
+            client,
+            at,
+            load_state,
+            opening_balances,
+            state_format,
+            compat_level,
+            account_creation,
+        }) => {
+            let mut engine = EngineSpec {
+                load_state,
+                opening_balances,
+                state_format,
+                compat_level,
+                account_creation,
+                ..Default::default()
             }
-            TransactionType::Chargeback => {
-                let maybe_orig_txt = self.transactions.get_mut(&transaction.tx_id);
-                if let Some(mut orig_txt) = maybe_orig_txt {
-                    debug!("Found disputed transaction {:?}", orig_txt);
-                    if orig_txt.status == TransactionStatus::Disputed
-                        && orig_txt.client_id == transaction.client_id
-                    {
-                        debug!(" OK, it can be chargedback.");
-                        orig_txt.status = TransactionStatus::Chargedback;
-                        let amount = orig_txt.amount.unwrap();
-                        account_ref.funds_available += amount;
-                        account_ref.funds_held -= amount;
-                        account_ref.locked = true; // If a chargeback occurs the client's account should be immediately frozen.
+            .build()?;
+            let replayed = wal::replay_until(&mut engine, &journal, at)
+                .map_err(|e| PaymentErrors::Replay.because(e))?;
+            eprintln!("After {} transactions", replayed);
+            let exported = match client {
+                None => engine.export_accounts(std::io::stdout().lock()),
+                Some(client) => engine.account(client).map(|account| {
+                    println!("client,available,held,total,locked");
+                    if let Some(account) = account {
+                        println!("{}", account.to_csv_row());
                     }
-                }
+                }),
+            };
+            return exported.map_err(|e| PaymentErrors::ExportAccounts.because(e));
+        }
+        Some(Command::Validate {
+            input,
+            compat_level,
+            dialect,
+        }) => {
+            let validation = File::open(&input)
+                .map_err(|e| e.into())
+                .and_then(|file| validate::validate(file, &dialect.into(), compat_level))
+                .map_err(|e| PaymentErrors::ImportCsv.because(e))?;
+            for problem in &validation.problems {
+                println!("{}", problem);
             }
-        };
-        debug!("Account status after this transaction: {:?}", account_ref);
-    }
-
-    fn import_csv(&mut self, filename: &str) -> Result<(), Box<dyn Error>> {
-        let mut rdr = Reader::from_path(filename)?;
-        for result in rdr.records() {
-            let record = result?;
-            debug!("{:?}", record);
-            assert!(record.len() ==  3 || record.len() == 4); // It should always be 4 but since amount is optional maybe the comma also is
-
-            let transaction = Transaction::try_from(record).unwrap(); // Assuming non-fail since input is guaranteed to be sane
-            debug!("Transaction: {:?}", transaction);
-
-            if !self.accounts.contains_key(&transaction.client_id) {
-                let account = Account {
-                    client_id: transaction.client_id,
-                    num_transactions: 0,
-                    funds_available: Decimal::new(0, 0),
-                    funds_held: Decimal::new(0, 0),
-                    funds_total: Decimal::new(0, 0),
-                    locked: false,
-                };
-                self.accounts.insert(transaction.client_id, account);
-                debug!("Account created for new client");
+            eprintln!(
+                "{} rows, {} problems",
+                validation.rows,
+                validation.problems.len()
+            );
+            return match validation.is_ok() {
+                true => Ok(()),
+                false => Err(PaymentErrors::Invalid.into()),
+            };
+        }
+        Some(Command::Inspect {
+            input,
+            tx,
+            compat_level,
+            dialect,
+        }) => {
+            let inspection = File::open(&input)
+                .map_err(|e| e.into())
+                .and_then(|file| inspect::inspect(file, &dialect.into(), compat_level, tx))
+                .map_err(|e| PaymentErrors::ImportCsv.because(e))?;
+            if !inspection.is_found() {
+                return Err(PaymentErrors::Inspect.because(format!("No tx {} in {:?}", tx, input)));
             }
-            self.process_transaction(transaction);
+            print!("{}", inspection);
+            return Ok(());
         }
-        Ok(())
-    }
-
-    fn export_accounts(&self) {
-        println!("client,available,held,total,locked");
-        for client_id in self.accounts.keys() {
-            let account_ref = self.accounts.get(client_id).unwrap();
-            println!(
-                "{},{},{},{},{}",
-                client_id,
-                account_ref.funds_available,
-                account_ref.funds_held,
-                account_ref.funds_total,
-                account_ref.locked
-            );
+        Some(Command::Stats {
+            input,
+            json,
+            dialect,
+        }) => {
+            let profile = File::open(&input)
+                .map_err(|e| e.into())
+                .and_then(|file| profile::profile(file, &dialect.into()))
+                .map_err(|e| PaymentErrors::ImportCsv.because(e))?;
+            match json {
+                true => println!(
+                    "{}",
+                    serde_json::to_string_pretty(&profile).unwrap_or_default()
+                ),
+                false => print!("{}", profile),
+            }
+            return Ok(());
         }
-    }
+        Some(Command::Repl {
+            load_state,
+            state_format,
+            compat_level,
+            account_creation,
+        }) => {
+            let mut engine = EngineSpec {
+                load_state,
+                state_format,
+                compat_level,
+                account_creation,
+                ..Default::default()
+            }
+            .build()?;
+            let stdin = io::stdin();
+            let prompt = stdin.is_terminal();
+            return repl::run(&mut engine, stdin.lock(), io::stdout().lock(), prompt)
+                .map_err(|e| PaymentErrors::Repl.because(e));
+        }
+        Some(Command::Completions { shell }) => {
+            let mut command = Cli::command();
+            let name = command.get_name().to_string();
+            // Into memory first, writing it straight out panics if that fails
+            let mut script = Vec::new();
+            clap_complete::generate(shell, &mut command, name, &mut script);
+            return io::stdout()
+                .write_all(&script)
+                .map_err(|e| PaymentErrors::Docs.because(e));
+        }
+        Some(Command::Man) => {
+            return clap_mangen::Man::new(Cli::command())
+                .render(&mut io::stdout())
+                .map_err(|e| PaymentErrors::Docs.because(e));
+        }
+        Some(Command::Config {
+            command: ConfigCommand::PrintDefault,
+        }) => {
+            return config::print_default(&Cli::command(), std::io::stdout())
+                .map_err(|e| PaymentErrors::Config.because(e));
+        }
+        Some(Command::Diff { old, new }) => {
+            return File::open(&old)
+                .and_then(|old| Ok((old, File::open(&new)?)))
+                .map_err(|e| e.into())
+                .and_then(|(old, new)| diff::diff(old, new))
+                .and_then(|diffs| {
+                    eprintln!("{} clients differ", diffs.len());
+                    diff::write(&diffs, std::io::stdout())
+                })
+                .map_err(|e| PaymentErrors::Diff.because(e));
+        }
+        Some(Command::Merge {
+            states,
+            state_format,
+            save_state,
+        }) => {
+            let started = Instant::now();
+            let mut merged = PaymentEngine::new();
+            merged.set_idempotent(true);
+            for path in &states {
+                let mut engine = PaymentEngine::new();
+                engine.set_idempotent(true);
+                File::open(path)
+                    .map_err(|e| e.into())
+                    .and_then(|file| engine.restore_state(BufReader::new(file), state_format))
+                    .map_err(|e| {
+                        PaymentErrors::LoadState.because(format!("Loading {:?}: {}", path, e))
+                    })?;
+                merged.merge(engine).map_err(|e| {
+                    PaymentErrors::Merge.because(format!("Merging {:?}: {}", path, e))
+                })?;
+            }
+            let mut outputs = Vec::new();
+            if let Some(path) = save_state {
+                outputs.push(OutputSpec::State {
+                    path,
+                    format: state_format,
+                });
+            }
+            outputs.push(OutputSpec::Accounts {
+                path: None,
+                format: OutputFormat::Csv,
+                sort: AccountOrder::default(),
+                columns: output::default_columns(),
+                filter: AccountFilter::default(),
+                commodity: output::default_commodity(),
+                compression: Compression::None,
+            });
+            for output in &outputs {
+                output.write(&merged, None, started)?;
+            }
+            return Ok(());
+        }
+        Some(Command::Watch {
+            dir,
+            tail,
+            interval,
+            export,
+            export_every,
+            export_format,
+            dialect,
+            engine,
+        }) => {
+            let mut engine = EngineSpec::from(engine).build()?;
+            let spec = WatchSpec {
+                dir,
+                tail,
+                dialect: dialect.into(),
+                interval: Duration::from_secs_f64(interval),
+                export,
+                export_every: Duration::from_secs_f64(export_every),
+                export_format,
+            };
+            return Watcher::new(&spec)
+                .and_then(|mut watcher| watcher.run(&mut engine))
+                .map_err(|e| PaymentErrors::Watch.because(e));
+        }
+        Some(Command::Grpc {
+            listen,
+            shards,
+            engine,
+        }) => {
+            let engine = EngineSpec::from(engine).build()?;
+            return serve_grpc(listen, engine, shards);
+        }
+        None if cli.process.source == SourceKind::Kafka => {
+            if let Some(flag) = cli.process.unused_by_kafka() {
+                Cli::command()
+                    .error(
+                        ErrorKind::ArgumentConflict,
+                        format!("{} can't be used with --source kafka", flag),
+                    )
+                    .exit()
+            }
+            let mut engine = cli.process.engine_spec().build()?;
+            if let Some(addr) = cli.process.metrics_addr {
+                let metrics = Arc::new(Metrics::default());
+                metrics::serve(addr, metrics.clone())
+                    .map_err(|e| PaymentErrors::Metrics.because(e))?;
+                engine.set_metrics(metrics);
+            }
+            return consume_kafka(&mut engine, cli.process);
+        }
+        None if cli.process.input.is_none() => Cli::command()
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                "an input file is required unless --source kafka",
+            )
+            .exit(),
+        None if cli.process.threads > 1 && cli.process.compat_level == CompatLevel::V2 => {
+            Cli::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    "--compat-level v2 can't be used with --threads: a tx id repeated by clients of different threads wouldn't be ignored",
+                )
+                .exit()
+        }
+        None => cli.process.into_pipeline(),
+    };
+    pipeline.run()?;
+    Ok(())
 }
 
-fn main() -> Result<(), PaymentErrors> {
-    env_logger::init();
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        return Err(PaymentErrors::WrongArgumentCount);
-    }
-    let mut engine = PaymentEngine::new();
-    engine
-        .import_csv(args[1].as_str())
-        .map_err(|_| -> PaymentErrors { PaymentErrors::ImportCsv })?;
-    engine.export_accounts();
-    Ok(())
+#[cfg(feature = "kafka")]
+fn consume_kafka(engine: &mut PaymentEngine, args: ProcessArgs) -> Result<(), Failure> {
+    use payments_engine::input::kafka::{self, KafkaSpec};
+
+    let spec = KafkaSpec {
+        brokers: args.brokers,
+        topic: args.topic,
+        group: args.group,
+        actors: args.actors,
+    };
+    kafka::consume(engine, &spec).map_err(|e| PaymentErrors::Kafka.because(e))
+}
+
+#[cfg(not(feature = "kafka"))]
+fn consume_kafka(_engine: &mut PaymentEngine, _args: ProcessArgs) -> Result<(), Failure> {
+    Err(PaymentErrors::Kafka.because("Built without Kafka support, rebuild with --features kafka"))
+}
+
+#[cfg(feature = "grpc")]
+fn serve_grpc(addr: SocketAddr, engine: PaymentEngine, shards: usize) -> Result<(), Failure> {
+    payments_engine::grpc::serve(addr, engine, shards).map_err(|e| PaymentErrors::Grpc.because(e))
+}
+
+#[cfg(not(feature = "grpc"))]
+fn serve_grpc(_addr: SocketAddr, _engine: PaymentEngine, _shards: usize) -> Result<(), Failure> {
+    Err(PaymentErrors::Grpc.because("Built without gRPC support, rebuild with --features grpc"))
 }
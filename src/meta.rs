@@ -0,0 +1,142 @@
+use crate::transaction::ClientId;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::Path;
+
+/*
+What we know about the clients besides their balances, from a CSV sidecar
+file kept by the back office:
+
+  client,name,currency,risk_tier,locked
+  1,Alice Smith,EUR,low,false
+  2,"Bob Jones, Jr.",USD,high,
+  3,,,,true
+
+Every column but client can be left out or empty. The clients in the file
+get an account before the first transaction, with a zero balance, locked if
+the file says so (an account that already exists is locked too, but never
+unlocked). The risk tier picks the tier limits (see limits.rs), and name,
+currency and risk_tier can be added as columns to the account export.
+*/
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct AccountMeta {
+    pub name: Option<String>,
+    /// Home currency, informative only: balances have no currency
+    pub currency: Option<String>,
+    pub risk_tier: Option<String>,
+    pub locked: bool,
+}
+
+// Not flattened, the csv crate guesses the types of flattened fields
+#[derive(Deserialize)]
+struct MetaRow {
+    client: ClientId,
+    name: Option<String>,
+    currency: Option<String>,
+    risk_tier: Option<String>,
+    locked: Option<bool>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct AccountsMeta {
+    clients: BTreeMap<ClientId, AccountMeta>,
+}
+
+impl AccountsMeta {
+    pub fn load(path: &Path) -> Result<AccountsMeta, Box<dyn Error>> {
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_path(path)?;
+        let mut clients = BTreeMap::new();
+        for row in reader.deserialize() {
+            let row: MetaRow = row?;
+            let meta = AccountMeta {
+                name: row.name,
+                currency: row.currency,
+                risk_tier: row.risk_tier,
+                locked: row.locked.unwrap_or(false),
+            };
+            if clients.insert(row.client, meta).is_some() {
+                return Err(format!("Client {} is in {:?} twice", row.client, path).into());
+            }
+        }
+        Ok(AccountsMeta { clients })
+    }
+
+    pub fn get(&self, client_id: ClientId) -> Option<&AccountMeta> {
+        self.clients.get(&client_id)
+    }
+
+    pub fn risk_tier(&self, client_id: ClientId) -> Option<&str> {
+        self.get(client_id)?.risk_tier.as_deref()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (ClientId, &AccountMeta)> {
+        self.clients.iter().map(|(client, meta)| (*client, meta))
+    }
+
+    pub fn len(&self) -> usize {
+        self.clients.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.clients.is_empty()
+    }
+}
+
+#[test]
+fn test_accounts_meta() {
+    use crate::output::AccountColumn;
+    use crate::PaymentEngine;
+    use std::fs;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("meta.csv");
+    fs::write(
+        &path,
+        "client,name,currency,risk_tier,locked
+1,Alice Smith,EUR,low,false
+2,\"Bob Jones, Jr.\",USD,high,
+3,,,,true
+",
+    )
+    .unwrap();
+    let meta = AccountsMeta::load(&path).unwrap();
+    assert_eq!(meta.risk_tier(2), Some("high"));
+    assert_eq!(meta.risk_tier(3), None);
+
+    let mut engine = PaymentEngine::new();
+    engine.set_accounts_meta(meta).unwrap();
+    engine
+        .import_reader(&b"type,client,tx,amount\ndeposit,1,1,10\n"[..])
+        .unwrap();
+    let mut exported = Vec::new();
+    engine
+        .export_accounts_with(
+            &mut exported,
+            Default::default(),
+            &[
+                AccountColumn::Client,
+                AccountColumn::Name,
+                AccountColumn::Currency,
+                AccountColumn::RiskTier,
+                AccountColumn::Total,
+                AccountColumn::Locked,
+            ],
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(exported).unwrap(),
+        "client,name,currency,risk_tier,total,locked
+1,Alice Smith,EUR,low,10,false
+2,\"Bob Jones, Jr.\",USD,high,0,false
+3,,,,0,true
+"
+    );
+
+    fs::write(&path, "client,name\n1,a\n1,b\n").unwrap();
+    assert!(AccountsMeta::load(&path).is_err());
+}
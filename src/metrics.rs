@@ -0,0 +1,240 @@
+use crate::http;
+use crate::transaction::TransactionType;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{self, BufReader};
+use std::net::{SocketAddr, TcpListener};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+const TYPES: [&str; 8] = [
+    "deposit",
+    "withdrawal",
+    "dispute",
+    "resolve",
+    "chargeback",
+    "hold",
+    "capture",
+    "release",
+];
+// Scrapes served at once, more wait to be accepted
+const MAX_CONNECTIONS: usize = 16;
+
+const RESULTS: [&str; 4] = ["applied", "declined", "ignored", "failed"];
+// Processing a transaction in memory takes about a microsecond, the rest of
+// the buckets are for slow stores
+const BUCKETS: [f64; 9] = [1e-6, 5e-6, 1e-5, 5e-5, 1e-4, 5e-4, 1e-3, 1e-2, 1e-1];
+
+/// Result of a processed transaction, as far as metrics are concerned.
+#[derive(Debug, Clone, Copy)]
+pub enum Outcome {
+    Applied,
+    Declined(&'static str),
+    Ignored(&'static str),
+    Failed,
+}
+
+/// Counters shared by the engine (all its shards) and the /metrics endpoint.
+#[derive(Default)]
+pub struct Metrics {
+    transactions: [[AtomicU64; 4]; 8],
+    rejects: Mutex<BTreeMap<&'static str, u64>>,
+    accounts_locked: AtomicU64,
+    latency_buckets: [AtomicU64; 9],
+    latency_count: AtomicU64,
+    latency_nanos: AtomicU64,
+}
+
+fn type_index(tx_type: TransactionType) -> usize {
+    match tx_type {
+        TransactionType::Deposit => 0,
+        TransactionType::Withdrawal => 1,
+        TransactionType::Dispute => 2,
+        TransactionType::Resolve => 3,
+        TransactionType::Chargeback => 4,
+        TransactionType::Hold => 5,
+        TransactionType::Capture => 6,
+        TransactionType::Release => 7,
+    }
+}
+
+impl Metrics {
+    pub(crate) fn record(
+        &self,
+        tx_type: TransactionType,
+        outcome: Outcome,
+        newly_locked: bool,
+        elapsed: Duration,
+    ) {
+        let (result, reason) = match outcome {
+            Outcome::Applied => (0, None),
+            Outcome::Declined(reason) => (1, Some(reason)),
+            Outcome::Ignored(reason) => (2, Some(reason)),
+            Outcome::Failed => (3, Some("error")),
+        };
+        self.transactions[type_index(tx_type)][result].fetch_add(1, Ordering::Relaxed);
+        if let Some(reason) = reason {
+            if let Ok(mut rejects) = self.rejects.lock() {
+                *rejects.entry(reason).or_insert(0) += 1;
+            }
+        }
+        if newly_locked {
+            self.accounts_locked.fetch_add(1, Ordering::Relaxed);
+        }
+        let seconds = elapsed.as_secs_f64();
+        for (bucket, upper) in self.latency_buckets.iter().zip(BUCKETS.iter()) {
+            if seconds <= *upper {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.latency_count.fetch_add(1, Ordering::Relaxed);
+        self.latency_nanos
+            .fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// How many transactions were processed so far, and how many of them
+    /// weren't applied (declined, ignored or failed).
+    pub fn processed(&self) -> (u64, u64) {
+        let (mut processed, mut rejected) = (0, 0);
+        for counts in &self.transactions {
+            for (result, count) in counts.iter().enumerate() {
+                let count = count.load(Ordering::Relaxed);
+                processed += count;
+                if result > 0 {
+                    rejected += count;
+                }
+            }
+        }
+        (processed, rejected)
+    }
+
+    /// Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        out.push_str(
+            "# HELP payments_transactions_total Transactions processed, by type and result.\n",
+        );
+        out.push_str("# TYPE payments_transactions_total counter\n");
+        for (tx_type, counts) in TYPES.iter().zip(self.transactions.iter()) {
+            for (result, count) in RESULTS.iter().zip(counts.iter()) {
+                let _ = writeln!(
+                    out,
+                    "payments_transactions_total{{type=\"{}\",result=\"{}\"}} {}",
+                    tx_type,
+                    result,
+                    count.load(Ordering::Relaxed)
+                );
+            }
+        }
+        out.push_str("# HELP payments_rejects_total Transactions not applied, by reason.\n");
+        out.push_str("# TYPE payments_rejects_total counter\n");
+        if let Ok(rejects) = self.rejects.lock() {
+            for (reason, count) in rejects.iter() {
+                let _ = writeln!(
+                    out,
+                    "payments_rejects_total{{reason=\"{}\"}} {}",
+                    reason, count
+                );
+            }
+        }
+        out.push_str(
+            "# HELP payments_accounts_locked_total Accounts locked by a chargeback or a limit.\n",
+        );
+        out.push_str("# TYPE payments_accounts_locked_total counter\n");
+        let _ = writeln!(
+            out,
+            "payments_accounts_locked_total {}",
+            self.accounts_locked.load(Ordering::Relaxed)
+        );
+        out.push_str("# HELP payments_transaction_seconds Time to process a transaction.\n");
+        out.push_str("# TYPE payments_transaction_seconds histogram\n");
+        for (bucket, upper) in self.latency_buckets.iter().zip(BUCKETS.iter()) {
+            let _ = writeln!(
+                out,
+                "payments_transaction_seconds_bucket{{le=\"{}\"}} {}",
+                upper,
+                bucket.load(Ordering::Relaxed)
+            );
+        }
+        let count = self.latency_count.load(Ordering::Relaxed);
+        let _ = writeln!(
+            out,
+            "payments_transaction_seconds_bucket{{le=\"+Inf\"}} {}",
+            count
+        );
+        let _ = writeln!(
+            out,
+            "payments_transaction_seconds_sum {}",
+            self.latency_nanos.load(Ordering::Relaxed) as f64 / 1e9
+        );
+        let _ = writeln!(out, "payments_transaction_seconds_count {}", count);
+        out
+    }
+}
+
+/// Serves GET /metrics on `addr` from a background thread, for as long as the
+/// process lives.
+pub fn serve(addr: SocketAddr, metrics: Arc<Metrics>) -> io::Result<SocketAddr> {
+    let listener = TcpListener::bind(addr)?;
+    let local = listener.local_addr()?;
+    debug!("Serving metrics on {}", local);
+    // A connection each, so an idle one doesn't hold up the scrapes
+    thread::spawn(move || {
+        http::serve_connections(listener, MAX_CONNECTIONS, move |stream| {
+            let result = (|| -> io::Result<()> {
+                stream.set_read_timeout(Some(http::READ_TIMEOUT))?;
+                let request = http::read_request(&mut BufReader::new(&stream))?;
+                let mut stream = &stream;
+                match (request.method.as_str(), request.path.as_str()) {
+                    ("GET", "/metrics") => http::write_response(
+                        &mut stream,
+                        200,
+                        "text/plain; version=0.0.4",
+                        metrics.render().as_bytes(),
+                    ),
+                    _ => http::write_response(&mut stream, 404, "text/plain", b"Not found\n"),
+                }
+            })();
+            if let Err(e) = result {
+                warn!("Metrics request failed: {}", e);
+            }
+        })
+    });
+    Ok(local)
+}
+
+#[test]
+fn test_metrics_endpoint() {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+
+    let metrics = Arc::new(Metrics::default());
+    let mut engine = crate::PaymentEngine::new();
+    engine.set_metrics(metrics.clone());
+    engine
+        .import_csv("test_files/a_bit_of_everything.csv")
+        .unwrap();
+
+    let addr = serve("127.0.0.1:0".parse().unwrap(), metrics).unwrap();
+    // Doesn't hold up the scrape
+    let _idle = TcpStream::connect(addr).unwrap();
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    stream
+        .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK"));
+    assert!(
+        response.contains("payments_transactions_total{type=\"deposit\",result=\"applied\"} 10\n")
+    );
+    assert!(response.contains("payments_rejects_total{reason=\"not enough funds\"} 1\n"));
+    assert!(response.contains("payments_accounts_locked_total 2\n"));
+    assert!(response.contains("payments_transaction_seconds_count 20\n"));
+}
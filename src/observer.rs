@@ -0,0 +1,79 @@
+use crate::engine::Account;
+use crate::transaction::{Transaction, TransactionId, TxOutcome};
+
+/// Gets told what the engine does, transaction by transaction, for whatever
+/// doesn't belong in the core (alerting, webhooks, custom metrics...).
+/// Called from the thread processing the transaction, and shared by all the
+/// shards in parallel mode, so keep it quick and do the slow work elsewhere.
+/// Transactions that fail (malformed, say) are errors of process_transaction
+/// and aren't reported here.
+pub trait EngineObserver: Send + Sync {
+    /// The transaction changed the account, which is passed as it is now.
+    fn on_applied(&self, _transaction: &Transaction, _account: &Account) {}
+
+    /// The transaction was declined or ignored, `outcome` says why.
+    fn on_rejected(&self, _transaction: &Transaction, _outcome: TxOutcome) {}
+
+    /// A chargeback locked the account, after on_applied, or a transaction
+    /// over a limit with lock_on_violation did, after on_rejected. `tx` is
+    /// the one that locked it.
+    fn on_account_locked(&self, _account: &Account, _tx: TransactionId) {}
+}
+
+#[test]
+fn test_observer() {
+    use crate::PaymentEngine;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<String>>);
+
+    impl EngineObserver for Recorder {
+        fn on_applied(&self, transaction: &Transaction, account: &Account) {
+            self.0.lock().unwrap().push(format!(
+                "applied {} {} {}",
+                transaction.tx_type,
+                transaction.tx_id,
+                account.total()
+            ));
+        }
+
+        fn on_rejected(&self, transaction: &Transaction, outcome: TxOutcome) {
+            self.0.lock().unwrap().push(format!(
+                "rejected {} {}",
+                transaction.tx_id,
+                outcome.reason().unwrap_or_default()
+            ));
+        }
+
+        fn on_account_locked(&self, account: &Account, chargeback: TransactionId) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("locked {} {}", account.client_id(), chargeback));
+        }
+    }
+
+    let recorder = Arc::new(Recorder::default());
+    let mut engine = PaymentEngine::new().with_observer(recorder.clone());
+    engine
+        .import_reader(
+            &b"type,client,tx,amount
+deposit,1,1,10
+withdrawal,1,2,20
+dispute,1,1,
+chargeback,1,1,
+"[..],
+        )
+        .unwrap();
+    assert_eq!(
+        *recorder.0.lock().unwrap(),
+        vec![
+            "applied deposit 1 10",
+            "rejected 2 not enough funds",
+            "applied dispute 1 10",
+            "applied chargeback 1 10",
+            "locked 1 1",
+        ]
+    );
+}
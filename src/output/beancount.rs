@@ -0,0 +1,129 @@
+use crate::double_entry::{DoubleEntryLedger, LedgerAccount};
+use std::collections::BTreeSet;
+use std::io::{self, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/*
+The double-entry ledger (see double_entry.rs) as a beancount file, for
+plain-text accounting tools (bean-check, fava, or hledger after a
+conversion):
+
+  2024-01-31 open Assets:Cash
+  2024-01-31 open Liabilities:Clients:1:Available
+
+  2024-01-31 * "deposit, client 1, tx 1"
+    Assets:Cash                              10 USD
+    Liabilities:Clients:1:Available          -10 USD
+
+Transactions have no date, so they're all dated on the day of the run, in
+the order they were processed. Balances have no currency either: the
+commodity is whatever the caller says.
+*/
+
+fn account_name(account: LedgerAccount) -> String {
+    match account {
+        LedgerAccount::Cash => "Assets:Cash".to_string(),
+        LedgerAccount::ChargebackLosses => "Expenses:ChargebackLosses".to_string(),
+        LedgerAccount::Available(client) => format!("Liabilities:Clients:{}:Available", client),
+        LedgerAccount::Held(client) => format!("Liabilities:Clients:{}:Held", client),
+    }
+}
+
+/// YYYY-MM-DD of a number of days since 1970-01-01, proleptic Gregorian
+/// (Howard Hinnant's civil_from_days).
+fn civil_date(days: i64) -> String {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Today, UTC
+pub fn today() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default();
+    civil_date((secs / 86_400) as i64)
+}
+
+pub fn write<W: Write>(
+    ledger: &DoubleEntryLedger,
+    date: &str,
+    commodity: &str,
+    mut writer: W,
+) -> io::Result<()> {
+    let accounts: BTreeSet<LedgerAccount> = ledger
+        .postings()
+        .iter()
+        .map(|posting| posting.account)
+        .collect();
+    for account in &accounts {
+        writeln!(writer, "{} open {}", date, account_name(*account))?;
+    }
+    let mut entry = None;
+    for posting in ledger.postings() {
+        if entry != Some(posting.entry) {
+            entry = Some(posting.entry);
+            writeln!(writer)?;
+            writeln!(
+                writer,
+                "{} * \"{}, client {}, tx {}\"",
+                date, posting.tx_type, posting.client, posting.tx
+            )?;
+        }
+        writeln!(
+            writer,
+            "  {:<40} {} {}",
+            account_name(posting.account),
+            posting.amount,
+            commodity
+        )?;
+    }
+    writer.flush()
+}
+
+#[test]
+fn test_beancount() {
+    use crate::PaymentEngine;
+
+    assert_eq!(civil_date(0), "1970-01-01");
+    assert_eq!(civil_date(19_723), "2024-01-01");
+    assert_eq!(civil_date(19_782), "2024-02-29");
+    assert_eq!(civil_date(-1), "1969-12-31");
+
+    let mut engine = PaymentEngine::new();
+    engine.track_double_entry();
+    engine
+        .import_reader(&b"type,client,tx,amount\ndeposit,1,1,10\ndispute,1,1,\n"[..])
+        .unwrap();
+    let mut out = Vec::new();
+    write(
+        engine.double_entry().unwrap(),
+        "2024-01-31",
+        "EUR",
+        &mut out,
+    )
+    .unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "2024-01-31 open Assets:Cash
+2024-01-31 open Liabilities:Clients:1:Available
+2024-01-31 open Liabilities:Clients:1:Held
+
+2024-01-31 * \"deposit, client 1, tx 1\"
+  Assets:Cash                              10 EUR
+  Liabilities:Clients:1:Available          -10 EUR
+
+2024-01-31 * \"dispute, client 1, tx 1\"
+  Liabilities:Clients:1:Available          10 EUR
+  Liabilities:Clients:1:Held               -10 EUR
+"
+    );
+}
@@ -0,0 +1,193 @@
+use flate2::write::GzEncoder;
+use std::error::Error;
+use std::io::{self, Write};
+use std::mem;
+use std::str::FromStr;
+
+/*
+Output files compressed as they're written, for exports that get archived and
+shipped around: gzip or zstd, at their default levels. Nothing is buffered
+beyond what the encoder needs, so a big export never sits uncompressed in
+memory or on disk.
+
+A stream can also be ended and picked up again (end_member), for files that
+stay open for the whole run like the audit log: every flush of it ends a gzip
+member or zstd frame, so the file is complete whenever it has been flushed,
+and the members of a run, or of runs appending to the same file, decompress
+as one.
+*/
+
+#[derive(Debug, Default, PartialEq, Copy, Clone)]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Compression, String> {
+        match s {
+            "none" => Ok(Compression::None),
+            "gzip" | "gz" => Ok(Compression::Gzip),
+            "zstd" => Ok(Compression::Zstd),
+            _ => Err(format!("Unknown compression: {}", s)),
+        }
+    }
+}
+
+deserialize_from_str!(Compression);
+
+impl Compression {
+    /// What goes at the end of the name of a file compressed this way
+    pub fn extension(self) -> &'static str {
+        match self {
+            Compression::None => "",
+            Compression::Gzip => ".gz",
+            Compression::Zstd => ".zst",
+        }
+    }
+
+    pub fn writer<W: Write>(self, writer: W) -> Compressed<W> {
+        Compressed {
+            compression: self,
+            state: State::Idle(writer),
+        }
+    }
+
+    /// Lets write write through the compression and finishes the stream
+    pub fn write_with<W, F>(self, writer: W, write: F) -> Result<(), Box<dyn Error>>
+    where
+        W: Write,
+        F: FnOnce(&mut Compressed<W>) -> Result<(), Box<dyn Error>>,
+    {
+        let mut writer = self.writer(writer);
+        write(&mut writer)?;
+        writer.finish()?;
+        Ok(())
+    }
+}
+
+pub struct Compressed<W: Write> {
+    compression: Compression,
+    state: State<W>,
+}
+
+enum State<W: Write> {
+    // Between members, or not compressed at all
+    Idle(W),
+    Gzip(GzEncoder<W>),
+    Zstd(zstd::Encoder<'static, W>),
+    // Ending the member failed
+    Broken,
+}
+
+impl<W: Write> Compressed<W> {
+    /// Ends the stream, which must be done for it to be complete
+    pub fn finish(mut self) -> io::Result<W> {
+        self.end_member()?;
+        match mem::replace(&mut self.state, State::Broken) {
+            State::Idle(mut writer) => {
+                writer.flush()?;
+                Ok(writer)
+            }
+            _ => Err(broken()),
+        }
+    }
+
+    /// Ends the current gzip member or zstd frame, the next write starts
+    /// another one
+    pub fn end_member(&mut self) -> io::Result<()> {
+        let writer = match mem::replace(&mut self.state, State::Broken) {
+            State::Idle(writer) => writer,
+            State::Gzip(encoder) => encoder.finish()?,
+            State::Zstd(encoder) => encoder.finish()?,
+            State::Broken => return Err(broken()),
+        };
+        self.state = State::Idle(writer);
+        Ok(())
+    }
+
+    fn encoder(&mut self) -> io::Result<&mut dyn Write> {
+        // A new member starts with the first write after the last one ended
+        if self.compression != Compression::None && matches!(self.state, State::Idle(_)) {
+            if let State::Idle(writer) = mem::replace(&mut self.state, State::Broken) {
+                self.state = match self.compression {
+                    Compression::Gzip => {
+                        State::Gzip(GzEncoder::new(writer, flate2::Compression::default()))
+                    }
+                    _ => State::Zstd(zstd::Encoder::new(writer, 0)?),
+                };
+            }
+        }
+        match &mut self.state {
+            State::Idle(writer) => Ok(writer),
+            State::Gzip(encoder) => Ok(encoder),
+            State::Zstd(encoder) => Ok(encoder),
+            State::Broken => Err(broken()),
+        }
+    }
+}
+
+// Best effort, like BufWriter: finish to know whether it worked
+impl<W: Write> Drop for Compressed<W> {
+    fn drop(&mut self) {
+        if let State::Gzip(_) | State::Zstd(_) = self.state {
+            let _ = self.end_member();
+        }
+    }
+}
+
+fn broken() -> io::Error {
+    io::Error::other("The compressed stream is broken")
+}
+
+impl<W: Write> Write for Compressed<W> {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.encoder()?.write(bytes)
+    }
+
+    // Whatever has been compressed so far, without ending the member
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.state {
+            State::Idle(writer) => writer.flush(),
+            State::Gzip(encoder) => encoder.flush(),
+            State::Zstd(encoder) => encoder.flush(),
+            State::Broken => Err(broken()),
+        }
+    }
+}
+
+#[test]
+fn test_compressed() {
+    use std::io::Read;
+
+    for compression in [Compression::None, Compression::Gzip, Compression::Zstd] {
+        let mut writer = compression.writer(Vec::new());
+        writer.write_all(b"client,total\n1,5\n").unwrap();
+        writer.end_member().unwrap();
+        writer.end_member().unwrap();
+        writer.write_all(b"2,3\n").unwrap();
+        let written = writer.finish().unwrap();
+        let mut read = String::new();
+        match compression {
+            Compression::None => read = String::from_utf8(written).unwrap(),
+            Compression::Gzip => {
+                flate2::read::MultiGzDecoder::new(written.as_slice())
+                    .read_to_string(&mut read)
+                    .unwrap();
+            }
+            Compression::Zstd => {
+                zstd::Decoder::new(written.as_slice())
+                    .unwrap()
+                    .read_to_string(&mut read)
+                    .unwrap();
+            }
+        }
+        assert_eq!(read, "client,total\n1,5\n2,3\n", "{:?}", compression);
+    }
+    assert_eq!(Compression::from_str("zstd"), Ok(Compression::Zstd));
+    assert!(Compression::from_str("lz4").is_err());
+}
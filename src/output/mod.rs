@@ -0,0 +1,275 @@
+pub mod beancount;
+pub mod compress;
+#[cfg(feature = "parquet")]
+pub mod parquet;
+pub mod partition;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod stream;
+
+use crate::engine::Account;
+use crate::meta::AccountMeta;
+use crate::state::StateFormat;
+use crate::transaction::ClientId;
+use serde::Deserialize;
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+#[derive(Debug, Default, PartialEq, Copy, Clone)]
+pub enum OutputFormat {
+    #[default]
+    Csv,
+    State(StateFormat), // Account snapshot in one of the binary state formats
+    Parquet,            // Needs the parquet feature, see parquet.rs
+    Beancount,          // Every transaction rather than the accounts, see beancount.rs
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<OutputFormat, String> {
+        match s {
+            "csv" => Ok(OutputFormat::Csv),
+            "parquet" => Ok(OutputFormat::Parquet),
+            "beancount" => Ok(OutputFormat::Beancount),
+            _ => StateFormat::from_str(s)
+                .map(OutputFormat::State)
+                .map_err(|_| format!("Unknown output format: {}", s)),
+        }
+    }
+}
+
+deserialize_from_str!(OutputFormat);
+
+/// Order of the accounts in the CSV and Parquet exports. By client id unless
+/// asked otherwise, so the same input always gives the same file.
+#[derive(Debug, Default, PartialEq, Copy, Clone)]
+pub enum AccountOrder {
+    #[default]
+    Client,
+    Total, // Biggest first, then by client id
+    None,  // Whatever order the store has them in, without collecting them first
+}
+
+impl FromStr for AccountOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<AccountOrder, String> {
+        match s {
+            "client" => Ok(AccountOrder::Client),
+            "total" => Ok(AccountOrder::Total),
+            "none" => Ok(AccountOrder::None),
+            _ => Err(format!("Unknown account order: {}", s)),
+        }
+    }
+}
+
+deserialize_from_str!(AccountOrder);
+
+/// Which accounts the CSV and Parquet exports have: all of them unless
+/// narrowed down to some clients, or to the locked accounts (e.g. for the
+/// fraud queue). Binary snapshots always have them all.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccountFilter {
+    pub clients: Option<ClientRanges>,
+    pub only_locked: bool,
+}
+
+impl AccountFilter {
+    pub fn is_all(&self) -> bool {
+        self.clients.is_none() && !self.only_locked
+    }
+
+    pub fn matches(&self, account: &Account) -> bool {
+        (!self.only_locked || account.locked)
+            && self
+                .clients
+                .as_ref()
+                .is_none_or(|clients| clients.contains(account.client_id))
+    }
+}
+
+/// Client ids and ranges of them, e.g. 1,2,100-200
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientRanges(Vec<RangeInclusive<ClientId>>);
+
+impl ClientRanges {
+    pub fn contains(&self, client_id: ClientId) -> bool {
+        self.0.iter().any(|range| range.contains(&client_id))
+    }
+}
+
+impl FromStr for ClientRanges {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ClientRanges, String> {
+        let id = |id: &str| {
+            id.trim()
+                .parse::<ClientId>()
+                .map_err(|_| format!("Invalid client id: {}", id))
+        };
+        s.split(',')
+            .map(|part| match part.split_once('-') {
+                Some((start, end)) if id(start)? <= id(end)? => Ok(id(start)?..=id(end)?),
+                Some(_) => Err(format!("Empty client range: {}", part)),
+                None => id(part).map(|id| id..=id),
+            })
+            .collect::<Result<_, _>>()
+            .map(ClientRanges)
+    }
+}
+
+deserialize_from_str!(ClientRanges);
+
+/// A column of the CSV account export.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum AccountColumn {
+    Client,
+    Available,
+    Held,
+    Total,
+    Locked,
+    NumTransactions,
+    /// Whether the available funds are below zero, see OverdraftLimits
+    Overdrawn,
+    /// From the accounts metadata (see meta.rs), empty without
+    Name,
+    Currency,
+    RiskTier,
+}
+
+impl AccountColumn {
+    /// What the spec asks for
+    pub const DEFAULT: [AccountColumn; 5] = [
+        AccountColumn::Client,
+        AccountColumn::Available,
+        AccountColumn::Held,
+        AccountColumn::Total,
+        AccountColumn::Locked,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            AccountColumn::Client => "client",
+            AccountColumn::Available => "available",
+            AccountColumn::Held => "held",
+            AccountColumn::Total => "total",
+            AccountColumn::Locked => "locked",
+            AccountColumn::NumTransactions => "num_transactions",
+            AccountColumn::Overdrawn => "overdrawn",
+            AccountColumn::Name => "name",
+            AccountColumn::Currency => "currency",
+            AccountColumn::RiskTier => "risk_tier",
+        }
+    }
+
+    pub fn write<W: Write>(
+        self,
+        account: &Account,
+        meta: Option<&AccountMeta>,
+        mut writer: W,
+    ) -> io::Result<()> {
+        let text = |field: fn(&AccountMeta) -> &Option<String>| {
+            meta.and_then(|meta| field(meta).as_deref())
+                .unwrap_or_default()
+        };
+        match self {
+            AccountColumn::Client => write!(writer, "{}", account.client_id),
+            AccountColumn::Available => write!(writer, "{}", account.funds_available),
+            AccountColumn::Held => write!(writer, "{}", account.funds_held),
+            AccountColumn::Total => write!(writer, "{}", account.funds_total),
+            AccountColumn::Locked => write!(writer, "{}", account.locked),
+            AccountColumn::NumTransactions => write!(writer, "{}", account.num_transactions),
+            AccountColumn::Overdrawn => write!(writer, "{}", account.is_overdrawn()),
+            AccountColumn::Name => write_text(writer, text(|meta| &meta.name)),
+            AccountColumn::Currency => write_text(writer, text(|meta| &meta.currency)),
+            AccountColumn::RiskTier => write_text(writer, text(|meta| &meta.risk_tier)),
+        }
+    }
+}
+
+impl FromStr for AccountColumn {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<AccountColumn, String> {
+        match s {
+            "client" => Ok(AccountColumn::Client),
+            "available" => Ok(AccountColumn::Available),
+            "held" => Ok(AccountColumn::Held),
+            "total" => Ok(AccountColumn::Total),
+            "locked" => Ok(AccountColumn::Locked),
+            "num_transactions" => Ok(AccountColumn::NumTransactions),
+            "overdrawn" => Ok(AccountColumn::Overdrawn),
+            "name" => Ok(AccountColumn::Name),
+            "currency" => Ok(AccountColumn::Currency),
+            "risk_tier" => Ok(AccountColumn::RiskTier),
+            _ => Err(format!("Unknown account column: {}", s)),
+        }
+    }
+}
+
+deserialize_from_str!(AccountColumn);
+
+// Quoted if it has to be, names have commas
+fn write_text<W: Write>(mut writer: W, text: &str) -> io::Result<()> {
+    if text.contains([',', '"', '\n', '\r']) {
+        write!(writer, "\"{}\"", text.replace('"', "\"\""))
+    } else {
+        writer.write_all(text.as_bytes())
+    }
+}
+
+pub fn default_columns() -> Vec<AccountColumn> {
+    AccountColumn::DEFAULT.to_vec()
+}
+
+/// Balances have no currency, the beancount export needs one
+pub fn default_commodity() -> String {
+    "USD".to_string()
+}
+
+#[test]
+fn test_account_filter() {
+    use crate::PaymentEngine;
+
+    let mut engine = PaymentEngine::new();
+    engine
+        .import_reader(
+            &b"type,client,tx,amount
+deposit,1,1,5
+deposit,2,2,5
+deposit,150,3,5
+deposit,300,4,5
+dispute,150,3,
+chargeback,150,3,
+"[..],
+        )
+        .unwrap();
+    let clients = |filter: AccountFilter| {
+        engine
+            .filtered_accounts(AccountOrder::Client, &filter)
+            .unwrap()
+            .map(|account| account.unwrap().client_id)
+            .collect::<Vec<_>>()
+    };
+    let ranges: ClientRanges = "1, 100-200".parse().unwrap();
+    assert_eq!(
+        clients(AccountFilter {
+            clients: Some(ranges.clone()),
+            only_locked: false,
+        }),
+        vec![1, 150]
+    );
+    assert_eq!(
+        clients(AccountFilter {
+            clients: None,
+            only_locked: true,
+        }),
+        vec![150]
+    );
+    assert_eq!(clients(AccountFilter::default()), vec![1, 2, 150, 300]);
+    assert!("5-1".parse::<ClientRanges>().is_err());
+    assert!("1,x".parse::<ClientRanges>().is_err());
+}
@@ -0,0 +1,173 @@
+use crate::output::{AccountFilter, AccountOrder};
+use crate::pipeline::JournalEntry;
+use crate::PaymentEngine;
+use arrow_array::builder::{
+    BooleanBuilder, Decimal128Builder, StringBuilder, UInt16Builder, UInt32Builder,
+};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use parquet::arrow::ArrowWriter;
+use rust_decimal::Decimal;
+use std::error::Error;
+use std::io::Write;
+use std::sync::Arc;
+
+/*
+Amounts are DECIMAL(38, 4): four decimals is what the input has, and 38
+digits is more than a Decimal can hold. An amount with more decimals (say,
+after a currency transform) is an error rather than being rounded.
+
+  accounts: client (uint16), available, held, total (decimal), locked (bool)
+  ledger:   source, type (string), client (uint16), tx (uint32), amount (decimal, null if none)
+*/
+
+const PRECISION: u8 = 38;
+const SCALE: i8 = 4;
+const BATCH_ROWS: usize = 64 * 1024;
+
+fn amount_type() -> DataType {
+    DataType::Decimal128(PRECISION, SCALE)
+}
+
+fn mantissa(amount: Decimal) -> Result<i128, Box<dyn Error>> {
+    let mut rescaled = amount;
+    rescaled.rescale(SCALE as u32);
+    if rescaled != amount || rescaled.scale() != SCALE as u32 {
+        return Err(format!("{} doesn't fit in {} decimals", amount, SCALE).into());
+    }
+    Ok(rescaled.mantissa())
+}
+
+fn decimal_builder() -> Decimal128Builder {
+    Decimal128Builder::new().with_data_type(amount_type())
+}
+
+pub fn write_accounts<W: Write + Send>(
+    engine: &PaymentEngine,
+    order: AccountOrder,
+    filter: &AccountFilter,
+    writer: W,
+) -> Result<(), Box<dyn Error>> {
+    let schema: SchemaRef = Arc::new(Schema::new(vec![
+        Field::new("client", DataType::UInt16, false),
+        Field::new("available", amount_type(), false),
+        Field::new("held", amount_type(), false),
+        Field::new("total", amount_type(), false),
+        Field::new("locked", DataType::Boolean, false),
+    ]));
+    let mut writer = ArrowWriter::try_new(writer, schema.clone(), None)?;
+    let mut accounts = engine.filtered_accounts(order, filter)?.peekable();
+    while accounts.peek().is_some() {
+        let mut client = UInt16Builder::new();
+        let mut available = decimal_builder();
+        let mut held = decimal_builder();
+        let mut total = decimal_builder();
+        let mut locked = BooleanBuilder::new();
+        for account in accounts.by_ref().take(BATCH_ROWS) {
+            let account = account?;
+            client.append_value(account.client_id);
+            available.append_value(mantissa(account.funds_available)?);
+            held.append_value(mantissa(account.funds_held)?);
+            total.append_value(mantissa(account.funds_total)?);
+            locked.append_value(account.locked);
+        }
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(client.finish()),
+            Arc::new(available.finish()),
+            Arc::new(held.finish()),
+            Arc::new(total.finish()),
+            Arc::new(locked.finish()),
+        ];
+        writer.write(&RecordBatch::try_new(schema.clone(), columns)?)?;
+    }
+    writer.close()?;
+    Ok(())
+}
+
+pub fn write_ledger<W: Write + Send>(
+    journal: &[JournalEntry],
+    writer: W,
+) -> Result<(), Box<dyn Error>> {
+    let schema: SchemaRef = Arc::new(Schema::new(vec![
+        Field::new("source", DataType::Utf8, false),
+        Field::new("type", DataType::Utf8, false),
+        Field::new("client", DataType::UInt16, false),
+        Field::new("tx", DataType::UInt32, false),
+        Field::new("amount", amount_type(), true),
+        Field::new("description", DataType::Utf8, true),
+        Field::new("category", DataType::Utf8, true),
+    ]));
+    let mut writer = ArrowWriter::try_new(writer, schema.clone(), None)?;
+    for chunk in journal.chunks(BATCH_ROWS) {
+        let mut source = StringBuilder::new();
+        let mut tx_type = StringBuilder::new();
+        let mut client = UInt16Builder::new();
+        let mut tx = UInt32Builder::new();
+        let mut amount = decimal_builder();
+        let mut description = StringBuilder::new();
+        let mut category = StringBuilder::new();
+        for entry in chunk {
+            let transaction = &entry.transaction;
+            source.append_value(&entry.source);
+            tx_type.append_value(transaction.tx_type.to_string());
+            client.append_value(transaction.client_id);
+            tx.append_value(transaction.tx_id);
+            amount.append_option(transaction.amount.map(mantissa).transpose()?);
+            description.append_option(transaction.description.as_deref());
+            category.append_option(transaction.category.as_deref());
+        }
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(source.finish()),
+            Arc::new(tx_type.finish()),
+            Arc::new(client.finish()),
+            Arc::new(tx.finish()),
+            Arc::new(amount.finish()),
+            Arc::new(description.finish()),
+            Arc::new(category.finish()),
+        ];
+        writer.write(&RecordBatch::try_new(schema.clone(), columns)?)?;
+    }
+    writer.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_parquet_output() {
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::Decimal128Type;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use std::fs::File;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("accounts.parquet");
+    let mut engine = PaymentEngine::new();
+    engine
+        .import_csv("test_files/a_bit_of_everything.csv")
+        .unwrap();
+    write_accounts(
+        &engine,
+        AccountOrder::Client,
+        &AccountFilter::default(),
+        File::create(&path).unwrap(),
+    )
+    .unwrap();
+
+    let mut batches = ParquetRecordBatchReaderBuilder::try_new(File::open(&path).unwrap())
+        .unwrap()
+        .build()
+        .unwrap();
+    let batch = batches.next().unwrap().unwrap();
+    assert!(batches.next().is_none());
+    assert_eq!(batch.num_rows(), engine.accounts.len());
+    let totals = batch.column(3).as_primitive::<Decimal128Type>();
+    assert_eq!((totals.precision(), totals.scale()), (PRECISION, SCALE));
+    let expected: Vec<i128> = engine
+        .ordered_accounts(AccountOrder::Client)
+        .unwrap()
+        .map(|account| mantissa(account.unwrap().funds_total).unwrap())
+        .collect();
+    assert_eq!(totals.values().to_vec(), expected);
+
+    assert_eq!(mantissa(Decimal::new(15, 1)).unwrap(), 15000);
+    assert!(mantissa(Decimal::new(1, 5)).is_err());
+}
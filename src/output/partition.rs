@@ -0,0 +1,156 @@
+use crate::engine::PaymentEngine;
+use crate::output::compress::Compression;
+use crate::output::{AccountColumn, AccountFilter, AccountOrder};
+use crate::transaction::ClientId;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/*
+The CSV account export split over several files, for account sets too big to
+load in one go: accounts_00.csv to accounts_15.csv with 16 partitions, each
+with the header and its share of the accounts in the usual order, so they can
+be loaded in parallel. Every file is written, even if no account falls in it,
+so loaders can count on them. Compressed, they're accounts_00.csv.gz or
+accounts_00.csv.zst.
+
+By client id modulo the number of partitions (the default, the same split as
+--threads), or by range: the client id space cut in equal consecutive
+ranges, so every file has neighbouring ids but possibly very different
+sizes.
+*/
+
+#[derive(Debug, Default, PartialEq, Copy, Clone)]
+pub enum PartitionBy {
+    #[default]
+    Modulo,
+    Range,
+}
+
+impl FromStr for PartitionBy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<PartitionBy, String> {
+        match s {
+            "modulo" => Ok(PartitionBy::Modulo),
+            "range" => Ok(PartitionBy::Range),
+            _ => Err(format!("Unknown partitioning: {}", s)),
+        }
+    }
+}
+
+deserialize_from_str!(PartitionBy);
+
+impl PartitionBy {
+    /// Which of the partitions the client's account goes in
+    pub fn partition(self, client_id: ClientId, partitions: u16) -> usize {
+        let (client_id, partitions) = (usize::from(client_id), usize::from(partitions));
+        match self {
+            PartitionBy::Modulo => client_id % partitions,
+            PartitionBy::Range => client_id * partitions / (usize::from(ClientId::MAX) + 1),
+        }
+    }
+}
+
+/// accounts_NN.csv, with as many digits as the last one needs, at least two
+pub fn file_name(partition: usize, partitions: u16, compression: Compression) -> String {
+    let width = partitions.saturating_sub(1).to_string().len().max(2);
+    format!(
+        "accounts_{:0width$}.csv{}",
+        partition,
+        compression.extension(),
+        width = width
+    )
+}
+
+/// Writes the partitions into dir, which has to exist
+#[allow(clippy::too_many_arguments)]
+pub fn write(
+    engine: &PaymentEngine,
+    dir: &Path,
+    partitions: u16,
+    by: PartitionBy,
+    order: AccountOrder,
+    columns: &[AccountColumn],
+    filter: &AccountFilter,
+    compression: Compression,
+) -> Result<(), Box<dyn Error>> {
+    if partitions == 0 {
+        return Err("No partitions".into());
+    }
+    let header: Vec<&str> = columns.iter().map(|column| column.name()).collect();
+    let mut writers = Vec::with_capacity(partitions.into());
+    for partition in 0..usize::from(partitions) {
+        let path: PathBuf = dir.join(file_name(partition, partitions, compression));
+        let file = File::create(&path).map_err(|e| format!("Creating {:?}: {}", path, e))?;
+        let mut writer = compression.writer(BufWriter::new(file));
+        writeln!(writer, "{}", header.join(","))?;
+        writers.push(writer);
+    }
+    for account in engine.filtered_accounts(order, filter)? {
+        let account = account?;
+        let writer = &mut writers[by.partition(account.client_id, partitions)];
+        engine.write_account_row(&account, columns, writer)?;
+    }
+    for writer in writers {
+        writer.finish()?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_partitioned_export() {
+    let mut engine = PaymentEngine::new();
+    engine
+        .import_reader(
+            &b"type,client,tx,amount
+deposit,1,1,5
+deposit,2,2,5
+deposit,17,3,1
+deposit,65535,4,2
+"[..],
+        )
+        .unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let columns = [AccountColumn::Client, AccountColumn::Total];
+    let read = |partition, partitions| {
+        let name = file_name(partition, partitions, Compression::None);
+        std::fs::read_to_string(dir.path().join(name)).unwrap()
+    };
+    write(
+        &engine,
+        dir.path(),
+        16,
+        PartitionBy::Modulo,
+        AccountOrder::Client,
+        &columns,
+        &AccountFilter::default(),
+        Compression::None,
+    )
+    .unwrap();
+    assert_eq!(read(1, 16), "client,total\n1,5\n17,1\n");
+    assert_eq!(read(2, 16), "client,total\n2,5\n");
+    assert_eq!(read(0, 16), "client,total\n");
+    assert_eq!(read(15, 16), "client,total\n65535,2\n");
+
+    write(
+        &engine,
+        dir.path(),
+        4,
+        PartitionBy::Range,
+        AccountOrder::Client,
+        &columns,
+        &AccountFilter::default(),
+        Compression::None,
+    )
+    .unwrap();
+    assert_eq!(read(0, 4), "client,total\n1,5\n2,5\n17,1\n");
+    assert_eq!(read(3, 4), "client,total\n65535,2\n");
+
+    assert_eq!(file_name(7, 100, Compression::None), "accounts_07.csv");
+    assert_eq!(file_name(7, 101, Compression::Zstd), "accounts_007.csv.zst");
+    assert_eq!(PartitionBy::Range.partition(32767, 2), 0);
+    assert_eq!(PartitionBy::Range.partition(32768, 2), 1);
+}
@@ -0,0 +1,139 @@
+use crate::pipeline::JournalEntry;
+use crate::PaymentEngine;
+use rusqlite::{params, Connection};
+use std::error::Error;
+use std::path::Path;
+
+/*
+Tables, replaced on every run:
+
+  accounts(client INTEGER PRIMARY KEY, available TEXT, held TEXT, total TEXT, locked INTEGER)
+  ledger(seq INTEGER PRIMARY KEY, source TEXT, type TEXT, client INTEGER, tx INTEGER, amount TEXT,
+         description TEXT, category TEXT)
+
+The ledger is every transaction that went into the engine, in order, and is
+only written if asked for. Amounts are text so no precision is lost; CAST
+them to REAL for sums that don't need to be exact.
+*/
+
+pub fn write(
+    engine: &PaymentEngine,
+    journal: Option<&[JournalEntry]>,
+    path: &Path,
+) -> Result<(), Box<dyn Error>> {
+    let mut connection = Connection::open(path)?;
+    let db = connection.transaction()?;
+    db.execute_batch(
+        "DROP TABLE IF EXISTS accounts;
+         CREATE TABLE accounts (
+             client INTEGER PRIMARY KEY,
+             available TEXT NOT NULL,
+             held TEXT NOT NULL,
+             total TEXT NOT NULL,
+             locked INTEGER NOT NULL
+         );",
+    )?;
+    {
+        let mut insert = db.prepare("INSERT INTO accounts VALUES (?1, ?2, ?3, ?4, ?5)")?;
+        for account in engine.iter_accounts() {
+            let account = account?;
+            insert.execute(params![
+                account.client_id,
+                account.funds_available.to_string(),
+                account.funds_held.to_string(),
+                account.funds_total.to_string(),
+                account.locked,
+            ])?;
+        }
+    }
+    if let Some(journal) = journal {
+        db.execute_batch(
+            "DROP TABLE IF EXISTS ledger;
+             CREATE TABLE ledger (
+                 seq INTEGER PRIMARY KEY,
+                 source TEXT NOT NULL,
+                 type TEXT NOT NULL,
+                 client INTEGER NOT NULL,
+                 tx INTEGER NOT NULL,
+                 amount TEXT,
+                 description TEXT,
+                 category TEXT
+             );
+             CREATE INDEX ledger_client ON ledger (client);",
+        )?;
+        let mut insert = db.prepare(
+            "INSERT INTO ledger (source, type, client, tx, amount, description, category)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        )?;
+        for JournalEntry {
+            source,
+            transaction,
+        } in journal
+        {
+            insert.execute(params![
+                source.as_ref(),
+                transaction.tx_type.to_string(),
+                transaction.client_id,
+                transaction.tx_id,
+                transaction.amount.map(|amount| amount.to_string()),
+                transaction.description.as_deref(),
+                transaction.category.as_deref(),
+            ])?;
+        }
+    }
+    db.commit()?;
+    Ok(())
+}
+
+#[test]
+fn test_sqlite_output() {
+    use crate::input::InputFormat;
+    use crate::pipeline::{OutputSpec, Pipeline, SourceSpec};
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("accounts.sqlite");
+    let pipeline = Pipeline {
+        sources: vec![SourceSpec {
+            id: None,
+            path: "test_files/input.csv".into(),
+            format: InputFormat::Csv,
+            dialect: Default::default(),
+            layout: None,
+            client: None,
+            sheet: None,
+        }],
+        transforms: Vec::new(),
+        engine: Default::default(),
+        outputs: vec![OutputSpec::Sqlite {
+            path: path.clone(),
+            ledger: true,
+        }],
+        hooks: Vec::new(),
+        concurrent: false,
+        metrics: None,
+        checkpoint: None,
+        progress: false,
+        timings: false,
+    };
+    // Twice, to check the tables are replaced
+    pipeline.run().unwrap();
+    pipeline.run().unwrap();
+
+    let db = Connection::open(&path).unwrap();
+    let (available, locked): (String, bool) = db
+        .query_row(
+            "SELECT available, locked FROM accounts WHERE client = 1",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .unwrap();
+    assert_eq!((available.as_str(), locked), ("1.5", false));
+    let (rows, withdrawals): (u64, u64) = db
+        .query_row(
+            "SELECT COUNT(*), SUM(type = 'withdrawal') FROM ledger",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .unwrap();
+    assert_eq!((rows, withdrawals), (5, 2));
+}
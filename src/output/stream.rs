@@ -0,0 +1,153 @@
+use crate::output::AccountColumn;
+use crate::transaction::{ClientId, Transaction};
+use crate::PaymentEngine;
+use std::error::Error;
+use std::io::Write;
+
+/*
+The CSV account export written while the input is processed, for input
+sorted by client (every client's transactions together): once a transaction
+of another client comes, the previous client's account can't change anymore,
+so its row is written and flushed right away and the account is dropped from
+the engine. Pipelines downstream get the accounts as the run goes rather than
+all at the end, and only one account is kept at a time.
+
+The disputable transactions are still kept, tx ids are unique across
+clients. A client that comes back after its account was written is an error,
+the input isn't sorted. As the accounts are gone from the engine, the stats,
+saved state and other account outputs of the run don't have them.
+*/
+
+pub struct AccountStream {
+    writer: Box<dyn Write + Send>,
+    columns: Vec<AccountColumn>,
+    // The client of the last transaction, whose account is still open
+    current: Option<ClientId>,
+    written: Vec<bool>,
+}
+
+impl AccountStream {
+    /// Writes the header right away
+    pub fn new(
+        mut writer: Box<dyn Write + Send>,
+        columns: Vec<AccountColumn>,
+    ) -> Result<AccountStream, Box<dyn Error>> {
+        let header: Vec<&str> = columns.iter().map(|column| column.name()).collect();
+        writeln!(writer, "{}", header.join(","))?;
+        writer.flush()?;
+        Ok(AccountStream {
+            writer,
+            columns,
+            current: None,
+            written: vec![false; usize::from(ClientId::MAX) + 1],
+        })
+    }
+
+    /// Like PaymentEngine::import_transactions, writing the accounts as their
+    /// clients are done. Sources can follow one another, the last account is
+    /// only written by finish.
+    pub fn import<I>(
+        &mut self,
+        engine: &mut PaymentEngine,
+        transactions: I,
+    ) -> Result<(), Box<dyn Error>>
+    where
+        I: IntoIterator<Item = Result<Transaction, Box<dyn Error>>>,
+    {
+        for transaction in transactions {
+            let transaction = transaction?;
+            let client_id = transaction.client_id;
+            if self.current != Some(client_id) {
+                self.write_current(engine)?;
+                if self.written[usize::from(client_id)] {
+                    return Err(format!(
+                        "Client {} comes back after its account was written, the input isn't sorted by client",
+                        client_id
+                    )
+                    .into());
+                }
+                self.current = Some(client_id);
+            }
+            engine.process_transaction(transaction)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the last account
+    pub fn finish(mut self, engine: &mut PaymentEngine) -> Result<(), Box<dyn Error>> {
+        self.write_current(engine)?;
+        Ok(self.writer.flush()?)
+    }
+
+    fn write_current(&mut self, engine: &mut PaymentEngine) -> Result<(), Box<dyn Error>> {
+        let client_id = match self.current.take() {
+            Some(client_id) => client_id,
+            None => return Ok(()),
+        };
+        self.written[usize::from(client_id)] = true;
+        // None if none of its transactions opened it
+        if let Some(account) = engine.accounts.remove(client_id)? {
+            engine.write_account_row(&account, &self.columns, &mut self.writer)?;
+            self.writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_account_stream() {
+    use crate::output::default_columns;
+    use std::sync::{Arc, Mutex};
+
+    // What has been written so far, while the stream still has the writer
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(bytes)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let parse = |input: &'static str| {
+        input
+            .lines()
+            .map(|line| Ok(Transaction::from_byte_record(&line.split(',').collect())?))
+            .collect::<Vec<Result<Transaction, Box<dyn Error>>>>()
+    };
+    let output = Shared::default();
+    let written = || String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+    let mut engine = PaymentEngine::new();
+    let mut stream = AccountStream::new(Box::new(output.clone()), default_columns()).unwrap();
+    stream
+        .import(
+            &mut engine,
+            parse("deposit,2,1,5\ndispute,2,1,\ndeposit,1,2,3\nwithdrawal,3,3,1"),
+        )
+        .unwrap();
+    assert_eq!(
+        written(),
+        "client,available,held,total,locked\n2,0,5,5,false\n1,3,0,3,false\n"
+    );
+    // Client 3's declined withdrawal still opened an account
+    stream
+        .import(&mut engine, parse("deposit,4,4,1\nresolve,4,1,"))
+        .unwrap();
+    stream.finish(&mut engine).unwrap();
+    assert!(written().ends_with("1,3,0,3,false\n3,0,0,0,false\n4,1,0,1,false\n"));
+    assert!(engine.is_empty());
+
+    let mut stream = AccountStream::new(Box::new(Shared::default()), default_columns()).unwrap();
+    let unsorted = stream.import(
+        &mut PaymentEngine::new(),
+        parse("deposit,1,1,1\ndeposit,2,2,1\ndeposit,1,3,1"),
+    );
+    assert!(unsorted
+        .unwrap_err()
+        .to_string()
+        .starts_with("Client 1 comes back"));
+}
@@ -0,0 +1,224 @@
+use crate::compat::CompatLevel;
+use crate::engine::PaymentEngine;
+use crate::input::dialect::CsvDialect;
+use crate::transaction::{ClientId, Transaction};
+use csv::ByteRecord;
+use std::error::Error;
+use std::fs::File;
+use std::io::Read;
+use std::mem;
+use std::sync::mpsc::{sync_channel, SyncSender};
+use std::thread;
+use tracing::debug;
+
+// Items are sent to the workers in batches to keep channel overhead down
+const BATCH_SIZE: usize = 1024;
+const BATCHES_IN_FLIGHT: usize = 16;
+
+type BoxedError = Box<dyn Error + Send + Sync>;
+
+/// Processes the input with one engine per worker thread. Every item is routed
+/// by `client_id % threads`, so all the transactions of a client go, in order,
+/// to the same worker and the result is the same as a sequential run: a client's
+/// disputes can only ever refer to its own transactions. A tx id stored by
+/// two shards fails the import once they're put back together, the way a
+/// sequential run fails on it right away. An idempotent engine can't be
+/// split, see PaymentEngine::set_idempotent, and neither can a v2 one, which
+/// ignores a repeated tx id instead of failing.
+///
+/// Returns how many items were imported.
+fn import_sharded<T, I>(
+    engine: &mut PaymentEngine,
+    items: I,
+    threads: usize,
+    route: fn(&T) -> Result<ClientId, BoxedError>,
+    parse: fn(T) -> Result<Transaction, BoxedError>,
+) -> Result<u64, Box<dyn Error>>
+where
+    T: Send + 'static,
+    I: IntoIterator<Item = Result<T, BoxedError>>,
+{
+    let threads = threads.max(1);
+    if threads > 1 && engine.is_idempotent() {
+        return Err("Idempotent mode needs a single engine, it can't be sharded".into());
+    }
+    // v2 ignores a repeated tx id, keeping the first in input order, which
+    // shards working on different clients at once can't tell
+    if threads > 1 && engine.compat_level() == CompatLevel::V2 {
+        return Err("Compat level v2 needs a single thread, it can't be sharded".into());
+    }
+    let shards = mem::take(engine).split(threads)?;
+
+    let mut senders: Vec<SyncSender<Vec<T>>> = Vec::with_capacity(threads);
+    let mut workers = Vec::with_capacity(threads);
+    for (worker, mut shard) in shards.into_iter().enumerate() {
+        let (sender, receiver) = sync_channel::<Vec<T>>(BATCHES_IN_FLIGHT);
+        senders.push(sender);
+        workers.push(thread::spawn(move || -> Result<PaymentEngine, String> {
+            shard
+                .import_transactions(
+                    receiver
+                        .iter()
+                        .flatten()
+                        .map(|item| parse(item).map_err(|e| e as Box<dyn Error>)),
+                )
+                .map_err(|e| e.to_string())?;
+            debug!("Worker {} done", worker);
+            Ok(shard)
+        }));
+    }
+
+    let mut batches: Vec<Vec<T>> = (0..threads)
+        .map(|_| Vec::with_capacity(BATCH_SIZE))
+        .collect();
+    let mut routing_result: Result<(), Box<dyn Error>> = Ok(());
+    let mut imported = 0;
+    for item in items {
+        let routed = item.and_then(|item| route(&item).map(|client_id| (client_id, item)));
+        let (client_id, item) = match routed {
+            Ok(routed) => routed,
+            Err(e) => {
+                routing_result = Err(e);
+                break;
+            }
+        };
+        let shard = client_id as usize % threads;
+        imported += 1;
+        batches[shard].push(item);
+        if batches[shard].len() == BATCH_SIZE {
+            let batch = mem::replace(&mut batches[shard], Vec::with_capacity(BATCH_SIZE));
+            // Only fails if the worker stopped early, its error is collected below
+            if senders[shard].send(batch).is_err() {
+                break;
+            }
+        }
+    }
+    for (sender, batch) in senders.into_iter().zip(batches) {
+        let _ = sender.send(batch);
+    }
+
+    let mut worker_result: Result<(), Box<dyn Error>> = Ok(());
+    let mut shards = Vec::with_capacity(threads);
+    for worker in workers {
+        match worker.join() {
+            Ok(Ok(shard)) => shards.push(shard),
+            Ok(Err(e)) => worker_result = Err(e.into()),
+            Err(_) => worker_result = Err("Worker thread panicked".into()),
+        }
+    }
+    // Shard 0 owns the original store
+    let mut shards = shards.into_iter();
+    if let Some(first) = shards.next() {
+        *engine = first;
+    }
+    for shard in shards {
+        engine.absorb(shard)?;
+    }
+    routing_result.and(worker_result)?;
+    Ok(imported)
+}
+
+fn route_record(record: &ByteRecord) -> Result<ClientId, BoxedError> {
+    let client_id = record.get(1).ok_or("Missing client column")?;
+    Ok(std::str::from_utf8(client_id)?.trim().parse::<ClientId>()?)
+}
+
+fn parse_record(record: ByteRecord) -> Result<Transaction, BoxedError> {
+    Ok(Transaction::from_byte_record(&record)?)
+}
+
+pub fn import_csv(
+    engine: &mut PaymentEngine,
+    filename: &str,
+    dialect: &CsvDialect,
+    threads: usize,
+) -> Result<u64, Box<dyn Error>> {
+    import_csv_reader(engine, File::open(filename)?, dialect, threads)
+}
+
+/// Same as import_csv for a file that's already open.
+pub fn import_csv_reader<R: Read>(
+    engine: &mut PaymentEngine,
+    reader: R,
+    dialect: &CsvDialect,
+    threads: usize,
+) -> Result<u64, Box<dyn Error>> {
+    let mut rdr = dialect.reader(reader)?;
+    let records = std::iter::from_fn(move || {
+        let mut record = ByteRecord::new();
+        match rdr.read_byte_record(&mut record) {
+            Ok(true) => Some(Ok(record)),
+            Ok(false) => None,
+            Err(e) => Some(Err(e.to_string().into())),
+        }
+    });
+    import_sharded(engine, records, threads, route_record, parse_record)
+}
+
+/// Same as import_csv but for inputs that come already parsed, so only the
+/// processing is parallel.
+pub fn import_transactions<I>(
+    engine: &mut PaymentEngine,
+    transactions: I,
+    threads: usize,
+) -> Result<u64, Box<dyn Error>>
+where
+    I: IntoIterator<Item = Result<Transaction, Box<dyn Error>>>,
+{
+    let transactions = transactions
+        .into_iter()
+        .map(|transaction| transaction.map_err(|e| -> BoxedError { e.to_string().into() }));
+    import_sharded(engine, transactions, threads, |t| Ok(t.client_id), Ok)
+}
+
+#[test]
+fn test_parallel_matches_sequential() {
+    let filename = "test_files/a_bit_of_everything.csv";
+    let mut sequential = PaymentEngine::new();
+    sequential.import_csv(filename).unwrap();
+    for threads in 1..4 {
+        let mut parallel = PaymentEngine::new();
+        import_csv(&mut parallel, filename, &Default::default(), threads).unwrap();
+        assert_eq!(parallel.sorted_accounts(), sequential.sorted_accounts());
+        assert_eq!(
+            parallel.sorted_transactions(),
+            sequential.sorted_transactions()
+        );
+    }
+
+    let mut sequential = PaymentEngine::new();
+    sequential.set_idempotent(true);
+    sequential.import_csv(filename).unwrap();
+    let mut parallel = PaymentEngine::new();
+    parallel.set_idempotent(true);
+    import_csv(&mut parallel, filename, &Default::default(), 1).unwrap();
+    assert_eq!(parallel.sorted_accounts(), sequential.sorted_accounts());
+    // A tx id already used by a client of another shard wouldn't be skipped
+    let mut parallel = PaymentEngine::new();
+    parallel.set_idempotent(true);
+    assert!(import_csv(&mut parallel, filename, &Default::default(), 2).is_err());
+
+    let mut sequential = PaymentEngine::new();
+    sequential.set_compat_level(CompatLevel::V2);
+    sequential.import_csv(filename).unwrap();
+    let mut parallel = PaymentEngine::new();
+    parallel.set_compat_level(CompatLevel::V2);
+    import_csv(&mut parallel, filename, &Default::default(), 1).unwrap();
+    assert_eq!(parallel.sorted_accounts(), sequential.sorted_accounts());
+    assert_eq!(
+        parallel.sorted_transactions(),
+        sequential.sorted_transactions()
+    );
+    // Nor would a tx id repeated by a client of another shard be ignored
+    let mut parallel = PaymentEngine::new();
+    parallel.set_compat_level(CompatLevel::V2);
+    assert!(import_csv(&mut parallel, filename, &Default::default(), 2).is_err());
+}
+
+#[test]
+fn test_tx_id_repeated_across_shards() {
+    let transactions = ["deposit,1,1,1", "deposit,2,1,5"]
+        .map(|line| Ok(Transaction::from_byte_record(&line.split(',').collect())?));
+    let mut engine = PaymentEngine::new();
+    assert!(import_transactions(&mut engine, transactions, 2).is_err());
+}
@@ -0,0 +1,1420 @@
+use crate::anomalies::AnomalyRules;
+use crate::audit::AuditLog;
+use crate::blocklist::{BlockMode, Blocklist};
+use crate::checkpoint::{self, CheckpointSpec};
+use crate::compat::CompatLevel;
+use crate::hook::{self, Hook, HookSpec};
+use crate::input::binary::RecordReader;
+use crate::input::dialect::CsvDialect;
+use crate::input::fixed::{FixedReader, Layout};
+use crate::input::mmap::Mmap;
+use crate::input::protobuf::{self, ProtobufReader};
+use crate::input::InputFormat;
+use crate::input::{batch, chunked};
+use crate::limits::LimitsSpec;
+use crate::locks;
+use crate::meta::AccountsMeta;
+use crate::metrics::{self, Metrics};
+use crate::output::compress::Compression;
+use crate::output::partition::{self, PartitionBy};
+use crate::output::stream::AccountStream;
+use crate::output::{self, beancount, AccountColumn, AccountFilter, AccountOrder, OutputFormat};
+use crate::parallel;
+use crate::policy::{AccountCreation, OverdraftLimits};
+use crate::progress::Progress;
+use crate::reconcile::{self, Discrepancies};
+use crate::resources::{Bounded, ResourceLimits};
+use crate::state::StateFormat;
+use crate::stats::RunSummary;
+use crate::store::{
+    CompactTransactionStore, DiskTransactionStore, MemoryTransactionStore, TransactionStore,
+};
+use crate::timings::Timings;
+use crate::transaction::{ClientId, Transaction};
+use crate::transform::{self, Transform, TransformSpec};
+use crate::wal::Wal;
+use crate::webhook::WebhookSpec;
+use crate::PaymentEngine;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::cell::Cell;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::sync_channel;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{debug, info};
+
+/*
+A whole run described as a document instead of flags, e.g.
+
+sources:
+  - path: bank.csv
+  - id: mobile
+    path: mobile.bin
+    format: protobuf
+transforms:
+  - type: client_remap
+    map: {7: 70}
+engine:
+  threads: 4
+  load_state: yesterday.state
+outputs:
+  - type: accounts
+    path: accounts.csv
+  - type: state
+    path: today.state
+hooks:
+  - type: command
+    command: ./monthly_report.sh
+
+Sources are processed in order, every transaction going through the
+transforms (see transform.rs) in the order they're listed. Without outputs the
+accounts go to stdout as CSV. Hooks (see hook.rs) run last, once the outputs
+are written.
+
+With `concurrent: true` the sources are read at the same time, each in its
+own thread with its own copy of the transforms, and interleaved. Only for
+sources that don't depend on each other (no disputes of another source's
+deposits), as which one goes first is up to the scheduler.
+*/
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Pipeline {
+    pub sources: Vec<SourceSpec>,
+    #[serde(default)]
+    pub transforms: Vec<TransformSpec>,
+    #[serde(default)]
+    pub engine: EngineSpec,
+    #[serde(default = "default_outputs")]
+    pub outputs: Vec<OutputSpec>,
+    #[serde(default)]
+    pub hooks: Vec<HookSpec>,
+    /// Read all the sources at the same time instead of one after the other
+    #[serde(default)]
+    pub concurrent: bool,
+    /// Serve Prometheus metrics on this address while the pipeline runs
+    #[serde(default)]
+    pub metrics: Option<SocketAddr>,
+    /// Checkpoint now and then to be able to resume, see checkpoint.rs. Only
+    /// for a single CSV source without transforms, in one thread.
+    #[serde(default)]
+    pub checkpoint: Option<CheckpointSpec>,
+    /// Progress bar on stderr, see progress.rs
+    #[serde(default)]
+    pub progress: bool,
+    /// Time (and allocations) per phase on stderr, see timings.rs
+    #[serde(default)]
+    pub timings: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SourceSpec {
+    pub id: Option<String>,
+    pub path: PathBuf,
+    #[serde(default)]
+    pub format: InputFormat,
+    /// For CSV sources
+    #[serde(default)]
+    pub dialect: CsvDialect,
+    /// For fixed-width sources, where the fields are, see input/fixed.rs
+    #[serde(default)]
+    pub layout: Option<PathBuf>,
+    /// For bank statements, whose they are: they don't say
+    #[serde(default)]
+    pub client: Option<ClientId>,
+    /// For XLSX sources, the sheet to read if not the first one
+    #[serde(default)]
+    pub sheet: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EngineSpec {
+    pub threads: usize,
+    /// With one thread, parse CSV sources on every core all the same, see
+    /// input/chunked.rs
+    pub parallel_parse: bool,
+    /// Memory-map CSV sources instead of reading them, see input/mmap.rs.
+    /// Only for the sequential reader: threads and parallel_parse read the
+    /// file themselves.
+    pub mmap: bool,
+    /// Apply deposits and withdrawals a batch at a time, see vectorized.rs.
+    /// Only with one thread.
+    pub vectorized: bool,
+    /// Caps on record sizes, accounts, stored transactions and memory, see
+    /// resources.rs
+    pub resources: ResourceLimits,
+    /// On-disk transaction index instead of memory, see store.rs
+    pub low_memory: Option<PathBuf>,
+    /// Packed in-memory transaction store, see store.rs
+    pub compact_store: bool,
+    pub load_state: Option<PathBuf>,
+    /// Balances to start from (CSV, like the accounts export), see
+    /// PaymentEngine::load_opening_balances
+    pub opening_balances: Option<PathBuf>,
+    pub state_format: StateFormat,
+    pub compat_level: CompatLevel,
+    /// When unknown clients get an account, see policy.rs
+    pub account_creation: AccountCreation,
+    /// JSON lines record of every balance change, see audit.rs
+    pub audit: Option<PathBuf>,
+    pub audit_compression: Compression,
+    /// Write-ahead log of every transaction, see wal.rs
+    pub wal: Option<PathBuf>,
+    /// Notified of chargebacks and locked accounts, see webhook.rs
+    pub webhooks: Vec<WebhookSpec>,
+    /// Skip deposits and withdrawals already processed, according to the
+    /// loaded state, see seen.rs
+    pub idempotent: bool,
+    /// How far below zero withdrawals can take any account
+    pub overdraft_limit: Decimal,
+    /// Per client overdraft limits, CSV or TOML, see policy.rs
+    pub overdraft_limits: Option<PathBuf>,
+    /// Fraud checks, see limits.rs
+    pub limits: Option<LimitsSpec>,
+    /// Clients whose transactions are rejected, see blocklist.rs
+    pub blocklist: Option<PathBuf>,
+    pub blocklist_mode: BlockMode,
+    /// Names, currencies, risk tiers and initial locks of the clients (CSV,
+    /// see meta.rs)
+    pub accounts_meta: Option<PathBuf>,
+    /// Limits by risk tier (TOML), added to the ones in limits
+    pub tier_limits: Option<PathBuf>,
+}
+
+impl Default for EngineSpec {
+    fn default() -> Self {
+        EngineSpec {
+            threads: 1,
+            parallel_parse: false,
+            mmap: false,
+            vectorized: false,
+            resources: ResourceLimits::default(),
+            low_memory: None,
+            compact_store: false,
+            load_state: None,
+            opening_balances: None,
+            state_format: StateFormat::default(),
+            compat_level: CompatLevel::default(),
+            account_creation: AccountCreation::default(),
+            audit: None,
+            audit_compression: Compression::None,
+            wal: None,
+            webhooks: Vec::new(),
+            idempotent: false,
+            overdraft_limit: Decimal::ZERO,
+            overdraft_limits: None,
+            limits: None,
+            blocklist: None,
+            blocklist_mode: BlockMode::default(),
+            accounts_meta: None,
+            tier_limits: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum OutputSpec {
+    /// Final balances, to stdout if there's no path. Or, in the beancount
+    /// format, every transaction as an accounting entry.
+    Accounts {
+        path: Option<PathBuf>,
+        #[serde(default)]
+        format: OutputFormat,
+        /// Of the CSV and Parquet formats; binary snapshots are in no order
+        #[serde(default)]
+        sort: AccountOrder,
+        /// Of the CSV format
+        #[serde(default = "output::default_columns")]
+        columns: Vec<AccountColumn>,
+        /// Of the CSV and Parquet formats, e.g. {clients: 1-100, only_locked: true}
+        #[serde(default)]
+        filter: AccountFilter,
+        /// Of the beancount format
+        #[serde(default = "output::default_commodity")]
+        commodity: String,
+        /// Of all the formats but Parquet, see output/compress.rs
+        #[serde(default)]
+        compression: Compression,
+    },
+    /// The CSV accounts split over files in dir, by client id (see
+    /// output/partition.rs)
+    PartitionedAccounts {
+        dir: PathBuf,
+        partitions: u16,
+        #[serde(default)]
+        by: PartitionBy,
+        #[serde(default)]
+        sort: AccountOrder,
+        #[serde(default = "output::default_columns")]
+        columns: Vec<AccountColumn>,
+        #[serde(default)]
+        filter: AccountFilter,
+        /// Of every file, which gets .gz or .zst at the end of its name
+        #[serde(default)]
+        compression: Compression,
+    },
+    /// The CSV accounts written as the input is processed, to stdout if
+    /// there's no path. For input sorted by client, see output/stream.rs.
+    StreamedAccounts {
+        path: Option<PathBuf>,
+        #[serde(default = "output::default_columns")]
+        columns: Vec<AccountColumn>,
+    },
+    /// End of run summary (JSON), to stderr if there's no path
+    Stats { path: Option<PathBuf> },
+    /// Whole engine state, to be picked up by a later run with load_state
+    State {
+        path: PathBuf,
+        #[serde(default)]
+        format: StateFormat,
+    },
+    /// Every transaction that went into the engine, with its source. CSV
+    /// (the journal format in hook.rs) or Parquet.
+    Ledger {
+        path: PathBuf,
+        #[serde(default)]
+        format: OutputFormat,
+        /// Of the CSV format
+        #[serde(default)]
+        compression: Compression,
+    },
+    /// Every transaction disputed during the run and what became of it
+    /// (CSV, see disputes.rs)
+    Disputes { path: PathBuf },
+    /// Repeated tx ids, and disputes, resolves and chargebacks the engine
+    /// passed over for referring to unknown, undisputed or other clients'
+    /// transactions (CSV, see conflicts.rs)
+    Conflicts { path: PathBuf },
+    /// Clients whose transactions look like a bust-out, too many
+    /// chargebacks or structuring, scored (CSV, see anomalies.rs)
+    Anomalies {
+        path: PathBuf,
+        #[serde(default)]
+        rules: AnomalyRules,
+    },
+    /// Every locked account with the chargeback that locked it (CSV, see
+    /// locks.rs)
+    Locked { path: PathBuf },
+    /// What went in and out of every client's account per category (CSV,
+    /// see categories.rs)
+    Categories { path: PathBuf },
+    /// Every applied transaction as balanced debit and credit postings (CSV,
+    /// see double_entry.rs)
+    DoubleEntry { path: PathBuf },
+    /// Every transaction of a blocked client and what was done with it
+    /// (CSV, see blocklist.rs)
+    Blocked { path: PathBuf },
+    /// Accounts, and with ledger every transaction, into an SQLite database
+    /// (see output/sqlite.rs). Needs the sqlite feature.
+    Sqlite {
+        path: PathBuf,
+        #[serde(default)]
+        ledger: bool,
+    },
+    /// The accounts checked against the balances in a control file (CSV):
+    /// the differences, to stderr if there's no path, fail the run. See
+    /// reconcile.rs.
+    Reconcile {
+        expected: PathBuf,
+        report: Option<PathBuf>,
+        /// How far apart amounts can be and still match
+        #[serde(default)]
+        tolerance: Decimal,
+    },
+}
+
+fn default_outputs() -> Vec<OutputSpec> {
+    vec![OutputSpec::Accounts {
+        path: None,
+        format: OutputFormat::Csv,
+        sort: AccountOrder::default(),
+        columns: output::default_columns(),
+        filter: AccountFilter::default(),
+        commodity: output::default_commodity(),
+        compression: Compression::None,
+    }]
+}
+
+/// Which stage of the run failed, along with the underlying error.
+#[derive(Debug)]
+pub enum PipelineError {
+    CreateStore(Box<dyn Error>),
+    Audit(Box<dyn Error>),
+    Wal(Box<dyn Error>),
+    Webhook(Box<dyn Error>),
+    Overdraft(Box<dyn Error>),
+    Blocklist(Box<dyn Error>),
+    Limits(Box<dyn Error>),
+    AccountsMeta(Box<dyn Error>),
+    Metrics(Box<dyn Error>),
+    Transform(Box<dyn Error>),
+    LoadState(Box<dyn Error>),
+    OpeningBalances(Box<dyn Error>),
+    Import(InputFormat, Box<dyn Error>),
+    SaveState(Box<dyn Error>),
+    ExportAccounts(Box<dyn Error>),
+    WriteStats(Box<dyn Error>),
+    Hook(Box<dyn Error>),
+    Checkpoint(Box<dyn Error>),
+    /// The control file can't be read, or reconcile::Discrepancies
+    Reconcile(Box<dyn Error>),
+}
+
+/// A transaction that went into the engine and the source it came from.
+#[derive(Debug, Clone)]
+pub struct JournalEntry {
+    pub source: Arc<str>,
+    pub transaction: Transaction,
+}
+
+/// How many transactions were read from a source.
+#[derive(Debug, Clone)]
+pub struct SourceCounter {
+    pub id: Arc<str>,
+    pub transactions: u64,
+    /// Time spent reading and parsing it, with timings, unless that happened
+    /// off the engine's thread
+    pub parse_time: Option<Duration>,
+}
+impl Pipeline {
+    pub fn from_yaml(path: &Path) -> Result<Pipeline, Box<dyn Error>> {
+        let pipeline = serde_yaml::from_reader(BufReader::new(File::open(path)?))?;
+        debug!("Pipeline: {:?}", pipeline);
+        Ok(pipeline)
+    }
+
+    pub fn run(&self) -> Result<PaymentEngine, PipelineError> {
+        self.run_with_hooks(Vec::new())
+    }
+
+    /// Like run, with hooks written in Rust running before the ones in the
+    /// definition.
+    pub fn run_with_hooks(
+        &self,
+        mut hooks: Vec<Box<dyn Hook>>,
+    ) -> Result<PaymentEngine, PipelineError> {
+        hooks.extend(self.hooks.iter().map(|spec| spec.build()));
+        // Only worth keeping if someone is going to look at it
+        let needs_journal = self.outputs.iter().any(|output| {
+            matches!(
+                output,
+                OutputSpec::Ledger { .. } | OutputSpec::Sqlite { ledger: true, .. }
+            )
+        });
+        let mut journal = if hooks.is_empty() && !needs_journal {
+            None
+        } else {
+            Some(Vec::new())
+        };
+        let mut counters: Vec<SourceCounter> = self
+            .sources
+            .iter()
+            .map(|source| SourceCounter {
+                id: source.id().into(),
+                transactions: 0,
+                parse_time: self.timings.then_some(Duration::ZERO),
+            })
+            .collect();
+        let started = Instant::now();
+        let mut timings = self.timings.then(Timings::new);
+        let mut engine = self.engine.build()?;
+        let mut progress = None;
+        if self.metrics.is_some() || self.progress {
+            let metrics = Arc::new(Metrics::default());
+            if let Some(addr) = self.metrics {
+                metrics::serve(addr, metrics.clone())
+                    .map_err(|e| PipelineError::Metrics(e.into()))?;
+            }
+            engine.set_metrics(metrics.clone());
+            if self.progress {
+                progress = Some(Arc::new(Progress::new(self.input_size(), metrics)));
+            }
+        }
+        let display = progress.clone().map(Progress::show);
+        if self
+            .outputs
+            .iter()
+            .any(|output| matches!(output, OutputSpec::Disputes { .. }))
+        {
+            engine.track_disputes();
+        }
+        if self
+            .outputs
+            .iter()
+            .any(|output| matches!(output, OutputSpec::Conflicts { .. }))
+        {
+            engine.track_conflicts();
+        }
+        if let Some(rules) = self.outputs.iter().find_map(|output| match output {
+            OutputSpec::Anomalies { rules, .. } => Some(rules),
+            _ => None,
+        }) {
+            engine.track_anomalies(rules.clone());
+        }
+        if self
+            .outputs
+            .iter()
+            .any(|output| matches!(output, OutputSpec::Locked { .. }))
+        {
+            engine.track_locks();
+        }
+        if self
+            .outputs
+            .iter()
+            .any(|output| matches!(output, OutputSpec::Categories { .. }))
+        {
+            engine.track_categories();
+        }
+        if self
+            .outputs
+            .iter()
+            .any(|output| output.needs_double_entry())
+        {
+            engine.track_double_entry();
+        }
+        let mut stream = self.account_stream()?;
+        if let Some(timings) = &mut timings {
+            timings.phase("setup");
+        }
+        if self.checkpoint.is_some() || (self.concurrent && self.sources.len() > 1) {
+            for counter in counters.iter_mut() {
+                counter.parse_time = None;
+            }
+        }
+        if let Some(spec) = &self.checkpoint {
+            self.import_checkpointed(&mut engine, spec, &mut counters[0], journal.as_mut())?;
+        } else if self.concurrent && self.sources.len() > 1 {
+            self.import_concurrently(
+                &mut engine,
+                &mut counters,
+                journal.as_mut(),
+                progress.as_ref(),
+            )?;
+        } else {
+            let mut transforms =
+                build_transforms(&self.transforms).map_err(PipelineError::Transform)?;
+            for (source, counter) in self.sources.iter().zip(counters.iter_mut()) {
+                debug!("Importing {:?}", source.path);
+                source
+                    .import(
+                        &mut engine,
+                        &self.engine,
+                        &mut transforms,
+                        counter,
+                        journal.as_mut(),
+                        progress.as_ref(),
+                        stream.as_mut(),
+                    )
+                    .map_err(|e| PipelineError::Import(source.format, e))?;
+            }
+        }
+        if let Some(stream) = stream {
+            stream
+                .finish(&mut engine)
+                .map_err(PipelineError::ExportAccounts)?;
+        }
+        if let Some(display) = display {
+            display.finish();
+        }
+        if let Some(timings) = &mut timings {
+            let import = timings.phase("import");
+            let parse = counters
+                .iter()
+                .map(|counter| counter.parse_time)
+                .sum::<Option<Duration>>();
+            if let Some(parse) = parse {
+                let engine = import.elapsed.saturating_sub(parse);
+                import.parts = vec![("parse", parse), ("engine", engine)];
+            }
+        }
+        if let Some(audit) = engine.audit_log() {
+            audit.flush().map_err(|e| PipelineError::Audit(e.into()))?;
+        }
+        if let Some(wal) = engine.wal() {
+            wal.sync().map_err(|e| PipelineError::Wal(e.into()))?;
+        }
+        for counter in &counters {
+            info!("{}: {} transactions", counter.id, counter.transactions);
+        }
+        for output in &self.outputs {
+            output.write(&engine, journal.as_deref(), started)?;
+        }
+        if let Some(timings) = &mut timings {
+            timings.phase("export");
+        }
+        let journal = journal.unwrap_or_default();
+        for hook in hooks.iter_mut() {
+            hook.run(&engine, &journal, &counters)
+                .map_err(PipelineError::Hook)?;
+        }
+        if let Some(mut timings) = timings {
+            timings.phase("hooks");
+            timings
+                .write(io::stderr())
+                .map_err(|e| PipelineError::WriteStats(e.into()))?;
+        }
+        Ok(engine)
+    }
+
+    // Only with a single thread importing the sources one after the other,
+    // so the accounts come in the order of the input
+    fn account_stream(&self) -> Result<Option<AccountStream>, PipelineError> {
+        let (path, columns) = match self.outputs.iter().find_map(|output| match output {
+            OutputSpec::StreamedAccounts { path, columns } => Some((path, columns)),
+            _ => None,
+        }) {
+            Some(output) => output,
+            None => return Ok(None),
+        };
+        if self.engine.threads > 1
+            || self.checkpoint.is_some()
+            || (self.concurrent && self.sources.len() > 1)
+            || self
+                .sources
+                .iter()
+                .any(|source| source.dialect.batches.is_some())
+        {
+            return Err(PipelineError::ExportAccounts(
+                "Streamed accounts need a single thread, no checkpoints, no concurrent sources and no batches".into(),
+            ));
+        }
+        create_output(path)
+            .map_err(|e| e.into())
+            .and_then(|writer| AccountStream::new(writer, columns.clone()))
+            .map(Some)
+            .map_err(PipelineError::ExportAccounts)
+    }
+
+    // Bytes the progress can count: the checkpointed import and columnar
+    // sources read their files themselves
+    fn input_size(&self) -> u64 {
+        if self.checkpoint.is_some() {
+            return 0;
+        }
+        self.sources
+            .iter()
+            .filter(|source| {
+                matches!(
+                    source.format,
+                    InputFormat::Csv
+                        | InputFormat::Protobuf
+                        | InputFormat::Fixed
+                        | InputFormat::Ofx
+                        | InputFormat::Camt
+                        | InputFormat::Nacha
+                        | InputFormat::Mt940
+                        | InputFormat::Xlsx
+                        | InputFormat::MessagePack
+                        | InputFormat::Cbor
+                )
+            })
+            .filter_map(|source| std::fs::metadata(&source.path).ok())
+            .map(|metadata| metadata.len())
+            .sum()
+    }
+
+    // One reader thread per source, all feeding the engine through the same
+    // channel. Each source keeps its order but they're interleaved however
+    // the threads happen to run, and each source gets its own transforms.
+    fn import_concurrently(
+        &self,
+        engine: &mut PaymentEngine,
+        counters: &mut [SourceCounter],
+        mut journal: Option<&mut Vec<JournalEntry>>,
+        progress: Option<&Arc<Progress>>,
+    ) -> Result<(), PipelineError> {
+        if let Some(source) = self
+            .sources
+            .iter()
+            .find(|source| source.dialect.batches.is_some())
+        {
+            return Err(PipelineError::Import(
+                source.format,
+                "Atomic batches can't be imported concurrently".into(),
+            ));
+        }
+        let (sender, receiver) = sync_channel::<(usize, Result<Transaction, String>)>(1024);
+        thread::scope(|scope| {
+            for (index, source) in self.sources.iter().enumerate() {
+                let sender = sender.clone();
+                scope.spawn(move || {
+                    debug!("Importing {:?}", source.path);
+                    let result = build_transforms(&self.transforms).and_then(|mut transforms| {
+                        for transaction in
+                            source.open(&mut transforms, progress, &self.engine.resources)?
+                        {
+                            // The engine stopped, no point in reading further
+                            if sender
+                                .send((index, transaction.map_err(|e| e.to_string())))
+                                .is_err()
+                            {
+                                break;
+                            }
+                        }
+                        Ok(())
+                    });
+                    if let Err(e) = result {
+                        let _ = sender.send((index, Err(e.to_string())));
+                    }
+                });
+            }
+            drop(sender);
+
+            let failed = Cell::new(0);
+            let transactions = receiver
+                .into_iter()
+                .map(|(index, transaction)| match transaction {
+                    Ok(transaction) => {
+                        tally(&mut counters[index], &mut journal, &transaction);
+                        Ok(transaction)
+                    }
+                    Err(e) => {
+                        failed.set(index);
+                        Err(format!("{}: {}", counters[index].id, e).into())
+                    }
+                });
+            // Dropping the receiver when this returns stops the readers
+            import(engine, self.engine.threads, transactions)
+                .map_err(|e| PipelineError::Import(self.sources[failed.get()].format, e))
+        })
+    }
+}
+
+type Transactions<'a> = Box<dyn Iterator<Item = Result<Transaction, Box<dyn Error>>> + 'a>;
+
+fn build_transforms(specs: &[TransformSpec]) -> Result<Vec<Box<dyn Transform>>, Box<dyn Error>> {
+    specs.iter().map(|spec| spec.build()).collect()
+}
+
+impl EngineSpec {
+    pub fn build(&self) -> Result<PaymentEngine, PipelineError> {
+        let store: Box<dyn TransactionStore> = match &self.low_memory {
+            Some(path) => Box::new(
+                DiskTransactionStore::create(path.clone())
+                    .map_err(|e| PipelineError::CreateStore(e.into()))?,
+            ),
+            None if self.compact_store => Box::new(CompactTransactionStore::default()),
+            None => Box::new(MemoryTransactionStore::default()),
+        };
+        let mut engine = PaymentEngine::with_transaction_store(store);
+        engine.set_compat_level(self.compat_level);
+        engine.set_account_creation(self.account_creation);
+        engine.set_idempotent(self.idempotent);
+        engine.set_resource_limits(self.resources.clone());
+        let mut overdraft = OverdraftLimits::new(self.overdraft_limit);
+        if let Some(path) = &self.overdraft_limits {
+            overdraft.load(path).map_err(PipelineError::Overdraft)?;
+        }
+        engine.set_overdraft_limits(overdraft);
+        let mut limits = self.limits.clone();
+        if let Some(path) = &self.tier_limits {
+            limits
+                .get_or_insert_with(LimitsSpec::default)
+                .load_tiers(path)
+                .map_err(PipelineError::Limits)?;
+        }
+        if let Some(limits) = limits {
+            engine.set_limits(limits);
+        }
+        if let Some(path) = &self.blocklist {
+            let blocklist =
+                Blocklist::load(path, self.blocklist_mode).map_err(PipelineError::Blocklist)?;
+            engine.set_blocklist(blocklist);
+        }
+        if let Some(path) = &self.audit {
+            let audit = AuditLog::open_compressed(path, self.audit_compression)
+                .map_err(|e| PipelineError::Audit(e.into()))?;
+            engine.set_audit_log(Arc::new(audit));
+        }
+        if let Some(path) = &self.wal {
+            let wal = Wal::open(path).map_err(|e| PipelineError::Wal(e.into()))?;
+            engine.set_wal(Arc::new(wal));
+        }
+        for webhook in &self.webhooks {
+            engine.add_observer(webhook.build().map_err(PipelineError::Webhook)?);
+        }
+        if let Some(path) = &self.load_state {
+            File::open(path)
+                .map_err(|e| e.into())
+                .and_then(|file| engine.restore_state(BufReader::new(file), self.state_format))
+                .map_err(PipelineError::LoadState)?;
+        }
+        if let Some(path) = &self.opening_balances {
+            File::open(path)
+                .map_err(|e| e.into())
+                .and_then(|file| engine.load_opening_balances(BufReader::new(file)))
+                .map_err(PipelineError::OpeningBalances)?;
+        }
+        // After the state, for the locks to apply to the loaded accounts too
+        if let Some(path) = &self.accounts_meta {
+            AccountsMeta::load(path)
+                .and_then(|meta| Ok(engine.set_accounts_meta(meta)?))
+                .map_err(PipelineError::AccountsMeta)?;
+        }
+        Ok(engine)
+    }
+}
+
+impl Pipeline {
+    fn import_checkpointed(
+        &self,
+        engine: &mut PaymentEngine,
+        spec: &CheckpointSpec,
+        counter: &mut SourceCounter,
+        mut journal: Option<&mut Vec<JournalEntry>>,
+    ) -> Result<(), PipelineError> {
+        let source = match self.sources.as_slice() {
+            [source] if source.format == InputFormat::Csv => source,
+            _ => {
+                return Err(PipelineError::Checkpoint(
+                    "Needs a single CSV source".into(),
+                ))
+            }
+        };
+        if !self.transforms.is_empty()
+            || self.engine.threads > 1
+            || source.dialect.batches.is_some()
+        {
+            return Err(PipelineError::Checkpoint(
+                "Doesn't work with transforms, threads or atomic batches".into(),
+            ));
+        }
+        let format = self.engine.state_format;
+        let offset = if spec.resume {
+            checkpoint::restore(engine, &spec.path, format).map_err(PipelineError::Checkpoint)?
+        } else {
+            None
+        };
+        checkpoint::import_csv(
+            engine,
+            &source.path,
+            &source.dialect,
+            spec,
+            format,
+            offset.unwrap_or(0),
+            |transaction| tally(counter, &mut journal, transaction),
+        )
+        .map_err(|e| PipelineError::Import(source.format, e))
+    }
+}
+
+impl SourceSpec {
+    /// What the source is called in the journal and the counters: its id, or
+    /// its path if it doesn't have one.
+    pub fn id(&self) -> String {
+        match &self.id {
+            Some(id) => id.clone(),
+            None => self.path.display().to_string(),
+        }
+    }
+
+    fn statement_client(&self) -> Result<ClientId, &'static str> {
+        self.client
+            .ok_or("A bank statement needs the client it is of (--statement-client)")
+    }
+
+    // The file (or for protobuf, maybe a socket), counted towards the
+    // progress if there's one, and checked against the record limits if it's
+    // CSV
+    fn file(
+        &self,
+        progress: Option<&Arc<Progress>>,
+        resources: &ResourceLimits,
+    ) -> io::Result<Box<dyn Read + Send>> {
+        let socket = self
+            .path
+            .to_str()
+            .and_then(|path| path.strip_prefix("tcp://"));
+        let inner: Box<dyn Read + Send> = match (self.format, socket) {
+            (InputFormat::Protobuf, Some(address)) => Box::new(protobuf::listen(address)?),
+            _ => Box::new(File::open(&self.path)?),
+        };
+        let file: Box<dyn Read + Send> = match progress {
+            Some(progress) => Box::new(progress.reader(inner)),
+            None => inner,
+        };
+        Ok(match self.format {
+            InputFormat::Csv => Box::new(self.bounded(file, resources)),
+            _ => file,
+        })
+    }
+
+    fn bounded<R: Read>(&self, inner: R, resources: &ResourceLimits) -> Bounded<R> {
+        resources.csv(
+            inner,
+            self.dialect.delimiter as u8,
+            self.dialect.quote as u8,
+        )
+    }
+
+    fn open<'a>(
+        &self,
+        transforms: &'a mut [Box<dyn Transform>],
+        progress: Option<&Arc<Progress>>,
+        resources: &ResourceLimits,
+    ) -> Result<Transactions<'a>, Box<dyn Error>> {
+        let path = self.path.to_str().ok_or("Non UTF-8 path")?;
+        Ok(match self.format {
+            InputFormat::Csv => Box::new(transform::records(
+                self.dialect.reader(self.file(progress, resources)?)?,
+                transforms,
+            )),
+            InputFormat::Protobuf => Box::new(transform::transactions(
+                ProtobufReader::new(BufReader::new(self.file(progress, resources)?)),
+                transforms,
+            )),
+            InputFormat::Fixed => {
+                let layout = self
+                    .layout
+                    .as_ref()
+                    .ok_or("Fixed-width input needs a layout")?;
+                Box::new(transform::transactions(
+                    FixedReader::new(
+                        BufReader::new(self.file(progress, resources)?),
+                        Layout::load(layout)?,
+                    ),
+                    transforms,
+                ))
+            }
+            InputFormat::Ofx => Box::new(transform::transactions(
+                crate::input::ofx::read(self.file(progress, resources)?, self.statement_client()?)?,
+                transforms,
+            )),
+            InputFormat::Camt => Box::new(transform::transactions(
+                crate::input::camt::read(
+                    self.file(progress, resources)?,
+                    self.statement_client()?,
+                )?,
+                transforms,
+            )),
+            InputFormat::Nacha => Box::new(transform::transactions(
+                crate::input::nacha::read(self.file(progress, resources)?, self.client)?,
+                transforms,
+            )),
+            InputFormat::Mt940 => Box::new(transform::transactions(
+                crate::input::mt940::read(
+                    self.file(progress, resources)?,
+                    self.statement_client()?,
+                )?,
+                transforms,
+            )),
+            InputFormat::Xlsx => Box::new(transform::transactions(
+                open_xlsx(self, self.file(progress, resources)?)?,
+                transforms,
+            )),
+            InputFormat::MessagePack | InputFormat::Cbor => Box::new(transform::transactions(
+                RecordReader::new(BufReader::new(self.file(progress, resources)?), self.format),
+                transforms,
+            )),
+            InputFormat::Parquet | InputFormat::Arrow => Box::new(transform::transactions(
+                open_columnar(path, self.format)?,
+                transforms,
+            )),
+        })
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn import(
+        &self,
+        engine: &mut PaymentEngine,
+        spec: &EngineSpec,
+        transforms: &mut [Box<dyn Transform>],
+        counter: &mut SourceCounter,
+        mut journal: Option<&mut Vec<JournalEntry>>,
+        progress: Option<&Arc<Progress>>,
+        stream: Option<&mut AccountStream>,
+    ) -> Result<(), Box<dyn Error>> {
+        let threads = spec.threads;
+        let resources = &spec.resources;
+        let map = match (self.format, spec.mmap) {
+            // SAFETY: --mmap is documented as for files that don't change
+            // while they're processed, the watch mode, whose files can
+            // still be growing, never sets it
+            (InputFormat::Csv, true) => Some(unsafe { Mmap::open(&self.path) }?),
+            _ => None,
+        };
+        let reader = || match (&map, progress) {
+            (Some(map), Some(progress)) => self
+                .dialect
+                .reader(self.bounded(progress.reader(&map[..]), resources)),
+            (Some(map), None) => self.dialect.reader(self.bounded(&map[..], resources)),
+            (None, _) => self.dialect.reader(self.file(progress, resources)?),
+        };
+        if let (InputFormat::Csv, Some(rollback)) = (self.format, self.dialect.batches) {
+            if threads > 1 || !transforms.is_empty() {
+                return Err("Atomic batches need a single thread and no transforms".into());
+            }
+            let reader = reader()?;
+            counter.parse_time = None;
+            return batch::import(engine, reader, rollback, |transaction| {
+                tally(counter, &mut journal, transaction)
+            });
+        }
+        // Without transforms or journal CSV rows can be parsed in the worker threads
+        if self.format == InputFormat::Csv
+            && transforms.is_empty()
+            && journal.is_none()
+            && threads > 1
+        {
+            counter.parse_time = None;
+            counter.transactions += parallel::import_csv_reader(
+                engine,
+                self.file(progress, resources)?,
+                &self.dialect,
+                threads,
+            )?;
+            return Ok(());
+        }
+        if self.format == InputFormat::Csv
+            && transforms.is_empty()
+            && spec.parallel_parse
+            && stream.is_none()
+            && chunked::supports(&self.dialect)
+        {
+            counter.parse_time = None;
+            chunked::import_csv(
+                engine,
+                self.file(progress, resources)?,
+                &self.dialect,
+                |transaction| tally(counter, &mut journal, transaction),
+            )?;
+            return Ok(());
+        }
+        let transactions: Transactions = match map {
+            Some(_) => Box::new(transform::records(reader()?, transforms)),
+            None => self.open(transforms, progress, resources)?,
+        };
+        // Parsing happens in next()
+        let parsing = Cell::new(Duration::ZERO);
+        let timed = counter.parse_time.is_some() && threads == 1;
+        let mut transactions = transactions;
+        let transactions = std::iter::from_fn(|| {
+            if !timed {
+                return transactions.next();
+            }
+            let started = Instant::now();
+            let next = transactions.next();
+            parsing.set(parsing.get() + started.elapsed());
+            next
+        })
+        .inspect(|transaction| {
+            if let Ok(transaction) = transaction {
+                tally(counter, &mut journal, transaction);
+            }
+        });
+        match stream {
+            Some(stream) => stream.import(engine, transactions)?,
+            None if spec.vectorized && threads == 1 => import_vectorized(engine, transactions)?,
+            None => import(engine, threads, transactions)?,
+        }
+        counter.parse_time = match timed {
+            true => counter.parse_time.map(|time| time + parsing.get()),
+            false => None,
+        };
+        Ok(())
+    }
+}
+
+fn tally(
+    counter: &mut SourceCounter,
+    journal: &mut Option<&mut Vec<JournalEntry>>,
+    transaction: &Transaction,
+) {
+    counter.transactions += 1;
+    if let Some(journal) = journal {
+        journal.push(JournalEntry {
+            source: counter.id.clone(),
+            transaction: transaction.clone(),
+        });
+    }
+}
+
+fn import<I>(
+    engine: &mut PaymentEngine,
+    threads: usize,
+    transactions: I,
+) -> Result<(), Box<dyn Error>>
+where
+    I: IntoIterator<Item = Result<Transaction, Box<dyn Error>>>,
+{
+    if threads > 1 {
+        parallel::import_transactions(engine, transactions, threads)?;
+        Ok(())
+    } else {
+        engine.import_transactions(transactions)
+    }
+}
+
+const PARQUET_COMPRESSED: &str = "Parquet files are compressed already, leave out the compression";
+
+// Stdout unless there's a path
+fn create_output(path: &Option<PathBuf>) -> io::Result<Box<dyn Write + Send>> {
+    Ok(match path {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(io::stdout()),
+    })
+}
+
+impl OutputSpec {
+    fn needs_double_entry(&self) -> bool {
+        matches!(
+            self,
+            OutputSpec::DoubleEntry { .. }
+                | OutputSpec::Accounts {
+                    format: OutputFormat::Beancount,
+                    ..
+                }
+        )
+    }
+
+    /// The journal is only needed for a ledger.
+    pub fn write(
+        &self,
+        engine: &PaymentEngine,
+        journal: Option<&[JournalEntry]>,
+        started: Instant,
+    ) -> Result<(), PipelineError> {
+        match self {
+            OutputSpec::Stats { path } => engine
+                .count_accounts()
+                .map(|accounts| RunSummary::new(engine.stats(), accounts, started.elapsed()))
+                .and_then(|summary| match path {
+                    Some(path) => summary.write(BufWriter::new(File::create(path)?)),
+                    None => summary.write(io::stderr()),
+                })
+                .map_err(|e| PipelineError::WriteStats(e.into())),
+            OutputSpec::Accounts {
+                path,
+                format,
+                sort,
+                columns,
+                filter,
+                commodity,
+                compression,
+            } => {
+                let writer =
+                    create_output(path).map_err(|e| PipelineError::ExportAccounts(e.into()))?;
+                match format {
+                    OutputFormat::Csv => compression.write_with(writer, |writer| {
+                        Ok(engine.export_accounts_with(writer, *sort, columns, filter)?)
+                    }),
+                    OutputFormat::State(format) => compression
+                        .write_with(writer, |writer| engine.save_accounts(writer, *format)),
+                    OutputFormat::Parquet if *compression == Compression::None => {
+                        write_parquet(engine, None, *sort, filter, writer)
+                    }
+                    OutputFormat::Parquet => Err(PARQUET_COMPRESSED.into()),
+                    OutputFormat::Beancount => engine
+                        .double_entry()
+                        .ok_or_else(|| "The double-entry ledger wasn't kept".into())
+                        .and_then(|ledger| {
+                            compression.write_with(writer, |writer| {
+                                Ok(beancount::write(
+                                    ledger,
+                                    &beancount::today(),
+                                    commodity,
+                                    writer,
+                                )?)
+                            })
+                        }),
+                }
+                .map_err(PipelineError::ExportAccounts)
+            }
+            OutputSpec::PartitionedAccounts {
+                dir,
+                partitions,
+                by,
+                sort,
+                columns,
+                filter,
+                compression,
+            } => partition::write(
+                engine,
+                dir,
+                *partitions,
+                *by,
+                *sort,
+                columns,
+                filter,
+                *compression,
+            )
+            .map_err(PipelineError::ExportAccounts),
+            // Already written
+            OutputSpec::StreamedAccounts { .. } => Ok(()),
+            OutputSpec::Ledger {
+                path,
+                format,
+                compression,
+            } => {
+                let journal = journal.unwrap_or_default();
+                File::create(path)
+                    .map_err(|e| e.into())
+                    .and_then(|file| match format {
+                        OutputFormat::Csv => compression
+                            .write_with(file, |writer| hook::write_journal(writer, journal)),
+                        OutputFormat::Parquet if *compression != Compression::None => {
+                            Err(PARQUET_COMPRESSED.into())
+                        }
+                        OutputFormat::Parquet => write_parquet(
+                            engine,
+                            Some(journal),
+                            AccountOrder::None,
+                            &AccountFilter::default(),
+                            Box::new(BufWriter::new(file)),
+                        ),
+                        OutputFormat::State(_) => Err("No binary ledger format".into()),
+                        OutputFormat::Beancount => {
+                            Err("Use an accounts output for beancount".into())
+                        }
+                    })
+                    .map_err(PipelineError::ExportAccounts)
+            }
+            OutputSpec::Disputes { path } => engine
+                .disputes()
+                .ok_or_else(|| "Disputes weren't tracked".into())
+                .and_then(|history| Ok(history.write(BufWriter::new(File::create(path)?))?))
+                .map_err(PipelineError::ExportAccounts),
+            OutputSpec::Conflicts { path } => engine
+                .conflicts()
+                .ok_or_else(|| "Conflicts weren't tracked".into())
+                .and_then(|report| Ok(report.write(BufWriter::new(File::create(path)?))?))
+                .map_err(PipelineError::ExportAccounts),
+            OutputSpec::Anomalies { path, .. } => engine
+                .anomalies()
+                .ok_or_else(|| "Anomalies weren't tracked".into())
+                .and_then(|report| Ok(report.write(BufWriter::new(File::create(path)?))?))
+                .map_err(PipelineError::ExportAccounts),
+            OutputSpec::Locked { path } => engine
+                .locks()
+                .ok_or_else(|| "Locks weren't tracked".into())
+                .and_then(|history| {
+                    locks::write_report(engine, history, BufWriter::new(File::create(path)?))
+                })
+                .map_err(PipelineError::ExportAccounts),
+            OutputSpec::Categories { path } => engine
+                .categories()
+                .ok_or_else(|| "Categories weren't tracked".into())
+                .and_then(|totals| Ok(totals.write(BufWriter::new(File::create(path)?))?))
+                .map_err(PipelineError::ExportAccounts),
+            OutputSpec::DoubleEntry { path } => engine
+                .double_entry()
+                .ok_or_else(|| "The double-entry ledger wasn't kept".into())
+                .and_then(|ledger| Ok(ledger.write(BufWriter::new(File::create(path)?))?))
+                .map_err(PipelineError::ExportAccounts),
+            OutputSpec::Blocked { path } => engine
+                .blocklist()
+                .ok_or_else(|| "No blocklist".into())
+                .and_then(|blocklist| {
+                    Ok(blocklist.write_report(BufWriter::new(File::create(path)?))?)
+                })
+                .map_err(PipelineError::ExportAccounts),
+            OutputSpec::State { path, format } => engine
+                .save_state_to(path, *format)
+                .map_err(PipelineError::SaveState),
+            OutputSpec::Sqlite { path, ledger } => {
+                write_sqlite(engine, journal.filter(|_| *ledger), path)
+                    .map_err(PipelineError::ExportAccounts)
+            }
+            OutputSpec::Reconcile {
+                expected,
+                report,
+                tolerance,
+            } => File::open(expected)
+                .map_err(|e| e.into())
+                .and_then(|control| {
+                    reconcile::reconcile(engine, BufReader::new(control), *tolerance)
+                })
+                .and_then(|discrepancies| {
+                    match report {
+                        Some(path) => {
+                            reconcile::write(&discrepancies, BufWriter::new(File::create(path)?))?
+                        }
+                        None if !discrepancies.is_empty() => {
+                            reconcile::write(&discrepancies, io::stderr())?
+                        }
+                        None => {}
+                    }
+                    match discrepancies.len() {
+                        0 => Ok(()),
+                        n => Err(Discrepancies(n).into()),
+                    }
+                })
+                .map_err(PipelineError::Reconcile),
+        }
+    }
+}
+
+#[cfg(feature = "parquet")]
+fn open_columnar(
+    path: &str,
+    format: InputFormat,
+) -> Result<crate::input::arrow::Transactions, Box<dyn Error>> {
+    crate::input::arrow::open(path, format)
+}
+
+#[cfg(not(feature = "parquet"))]
+fn open_columnar(
+    path: &str,
+    _format: InputFormat,
+) -> Result<Transactions<'static>, Box<dyn Error>> {
+    Err(format!(
+        "Can't read {}: built without Parquet support, rebuild with --features parquet",
+        path
+    )
+    .into())
+}
+
+#[cfg(feature = "xlsx")]
+fn open_xlsx(
+    source: &SourceSpec,
+    file: impl Read,
+) -> Result<Transactions<'static>, Box<dyn Error>> {
+    let rows = crate::input::xlsx::read(file, source.sheet.as_deref(), &source.dialect)?;
+    Ok(Box::new(rows.into_iter()))
+}
+
+#[cfg(not(feature = "xlsx"))]
+fn open_xlsx(
+    source: &SourceSpec,
+    _file: impl Read,
+) -> Result<Transactions<'static>, Box<dyn Error>> {
+    Err(format!(
+        "Can't read {}: built without XLSX support, rebuild with --features xlsx",
+        source.path.display()
+    )
+    .into())
+}
+
+#[cfg(feature = "parquet")]
+fn import_vectorized<I>(engine: &mut PaymentEngine, transactions: I) -> Result<(), Box<dyn Error>>
+where
+    I: IntoIterator<Item = Result<Transaction, Box<dyn Error>>>,
+{
+    crate::vectorized::import_transactions(engine, transactions)
+}
+
+#[cfg(not(feature = "parquet"))]
+fn import_vectorized<I>(_engine: &mut PaymentEngine, _transactions: I) -> Result<(), Box<dyn Error>>
+where
+    I: IntoIterator<Item = Result<Transaction, Box<dyn Error>>>,
+{
+    Err("Built without Arrow support for --vectorized, rebuild with --features parquet".into())
+}
+
+// The accounts, or the ledger if there's a journal
+#[cfg(feature = "parquet")]
+fn write_parquet(
+    engine: &PaymentEngine,
+    journal: Option<&[JournalEntry]>,
+    order: AccountOrder,
+    filter: &AccountFilter,
+    writer: Box<dyn Write + Send>,
+) -> Result<(), Box<dyn Error>> {
+    use crate::output::parquet;
+
+    match journal {
+        Some(journal) => parquet::write_ledger(journal, writer),
+        None => parquet::write_accounts(engine, order, filter, writer),
+    }
+}
+
+#[cfg(not(feature = "parquet"))]
+fn write_parquet(
+    _engine: &PaymentEngine,
+    _journal: Option<&[JournalEntry]>,
+    _order: AccountOrder,
+    _filter: &AccountFilter,
+    _writer: Box<dyn Write + Send>,
+) -> Result<(), Box<dyn Error>> {
+    Err("Built without Parquet support, rebuild with --features parquet".into())
+}
+
+#[cfg(feature = "sqlite")]
+fn write_sqlite(
+    engine: &PaymentEngine,
+    journal: Option<&[JournalEntry]>,
+    path: &Path,
+) -> Result<(), Box<dyn Error>> {
+    crate::output::sqlite::write(engine, journal, path)
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn write_sqlite(
+    _engine: &PaymentEngine,
+    _journal: Option<&[JournalEntry]>,
+    _path: &Path,
+) -> Result<(), Box<dyn Error>> {
+    Err("Built without SQLite support, rebuild with --features sqlite".into())
+}
+
+#[test]
+fn test_run_pipeline_from_yaml() {
+    let dir = tempfile::tempdir().unwrap();
+    let accounts = dir.path().join("accounts.csv");
+    let state = dir.path().join("state.cbor");
+    let yaml = format!(
+        "sources:
+  - path: test_files/input.csv
+    format: csv
+engine:
+  threads: 2
+outputs:
+  - type: accounts
+    path: {:?}
+  - type: state
+    path: {:?}
+    format: cbor
+  - type: partitioned_accounts
+    dir: {:?}
+    partitions: 2
+    columns: [client, total]
+  - type: ledger
+    path: {:?}
+    compression: gzip
+",
+        accounts,
+        state,
+        dir.path(),
+        dir.path().join("ledger.csv.gz")
+    );
+    let definition = dir.path().join("pipeline.yaml");
+    std::fs::write(&definition, yaml).unwrap();
+
+    let pipeline = Pipeline::from_yaml(&definition).unwrap();
+    assert_eq!(pipeline.engine.threads, 2);
+    pipeline.run().unwrap();
+
+    let exported = std::fs::read_to_string(accounts).unwrap();
+    let mut lines: Vec<&str> = exported.lines().collect();
+    lines.sort_unstable();
+    assert_eq!(
+        lines,
+        vec![
+            "1,1.5,0,1.5,false",
+            "2,2.0,0,2.0,false",
+            "client,available,held,total,locked"
+        ]
+    );
+    let restored =
+        PaymentEngine::load_state(File::open(state).unwrap(), StateFormat::Cbor).unwrap();
+    assert_eq!(restored.accounts.len(), 2);
+    let odd = std::fs::read_to_string(dir.path().join("accounts_01.csv")).unwrap();
+    assert_eq!(odd, "client,total\n1,1.5\n");
+    let mut ledger = String::new();
+    flate2::read::GzDecoder::new(File::open(dir.path().join("ledger.csv.gz")).unwrap())
+        .read_to_string(&mut ledger)
+        .unwrap();
+    assert_eq!(ledger.lines().count(), 6);
+
+    /* Typos shouldn't be silently ignored */
+    std::fs::write(&definition, "sources: []\nengine:\n  thread: 2\n").unwrap();
+    assert!(Pipeline::from_yaml(&definition).is_err());
+}
@@ -0,0 +1,159 @@
+use crate::transaction::{ClientId, TransactionType};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+/*
+When the engine opens an account for a client it hasn't seen:
+
+  any      on the client's first transaction, whatever it is (the default,
+           what the spec asks for)
+  deposit  only on a deposit; anything else from an unknown client is
+           ignored, so a stray dispute or withdrawal doesn't leave an empty
+           account in the export
+  never    never; only the accounts loaded with --load-state take
+           transactions
+*/
+#[derive(Debug, Default, PartialEq, Copy, Clone)]
+pub enum AccountCreation {
+    #[default]
+    Any,
+    Deposit,
+    Never,
+}
+
+impl AccountCreation {
+    /// Whether a transaction of this type opens an account for an unknown
+    /// client.
+    pub fn creates(self, tx_type: TransactionType) -> bool {
+        match self {
+            AccountCreation::Any => true,
+            AccountCreation::Deposit => tx_type == TransactionType::Deposit,
+            AccountCreation::Never => false,
+        }
+    }
+}
+
+impl FromStr for AccountCreation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<AccountCreation, String> {
+        match s {
+            "any" => Ok(AccountCreation::Any),
+            "deposit" => Ok(AccountCreation::Deposit),
+            "never" => Ok(AccountCreation::Never),
+            _ => Err(format!("Unknown account creation policy {}", s)),
+        }
+    }
+}
+
+deserialize_from_str!(AccountCreation);
+
+/*
+How far below zero a withdrawal can take the available funds: the default
+for every client, unless the client has its own limit. Limits files are
+either CSV:
+
+  client,limit
+  7,100
+
+or TOML:
+
+  default = "50"
+  [clients]
+  7 = "100"
+*/
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct OverdraftLimits {
+    pub default: Decimal,
+    pub clients: HashMap<ClientId, Decimal>,
+}
+
+// TOML keys are always strings
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LimitsFile {
+    default: Option<Decimal>,
+    #[serde(default)]
+    clients: HashMap<String, Decimal>,
+}
+
+#[derive(Deserialize)]
+struct LimitRow {
+    client: ClientId,
+    limit: Decimal,
+}
+
+impl OverdraftLimits {
+    pub fn new(default: Decimal) -> OverdraftLimits {
+        OverdraftLimits {
+            default,
+            clients: HashMap::new(),
+        }
+    }
+
+    pub fn limit(&self, client_id: ClientId) -> Decimal {
+        self.clients
+            .get(&client_id)
+            .copied()
+            .unwrap_or(self.default)
+    }
+
+    /// Adds the limits in the file to these ones, TOML if it ends in .toml,
+    /// CSV otherwise. A TOML default replaces this one.
+    pub fn load(&mut self, path: &Path) -> Result<(), Box<dyn Error>> {
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "toml")
+        {
+            let loaded: LimitsFile = toml::from_str(&fs::read_to_string(path)?)?;
+            if let Some(default) = loaded.default {
+                self.default = default;
+            }
+            for (client, limit) in loaded.clients {
+                let client = client
+                    .parse()
+                    .map_err(|_| format!("Invalid client id {}", client))?;
+                self.clients.insert(client, limit);
+            }
+        } else {
+            let mut reader = csv::ReaderBuilder::new()
+                .trim(csv::Trim::All)
+                .from_path(path)?;
+            for row in reader.deserialize() {
+                let row: LimitRow = row?;
+                self.clients.insert(row.client, row.limit);
+            }
+        }
+        let mut limits = self.clients.values().chain(std::iter::once(&self.default));
+        if let Some(negative) = limits.find(|limit| limit.is_sign_negative()) {
+            return Err(format!("Negative overdraft limit {}", negative).into());
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_overdraft_limits() {
+    let dir = tempfile::tempdir().unwrap();
+    let csv = dir.path().join("limits.csv");
+    fs::write(&csv, "client,limit\n7,100\n8, 0.5\n").unwrap();
+    let toml = dir.path().join("limits.toml");
+    fs::write(&toml, "default = \"50\"\n[clients]\n8 = \"20\"\n").unwrap();
+
+    let mut limits = OverdraftLimits::new(Decimal::new(10, 0));
+    limits.load(&csv).unwrap();
+    assert_eq!(limits.limit(7), Decimal::new(100, 0));
+    assert_eq!(limits.limit(8), Decimal::new(5, 1));
+    assert_eq!(limits.limit(9), Decimal::new(10, 0));
+    limits.load(&toml).unwrap();
+    assert_eq!(limits.limit(8), Decimal::new(20, 0));
+    assert_eq!(limits.limit(9), Decimal::new(50, 0));
+
+    fs::write(&csv, "client,limit\n7,-1\n").unwrap();
+    assert!(OverdraftLimits::default().load(&csv).is_err());
+}
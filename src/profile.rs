@@ -0,0 +1,174 @@
+use crate::input::dialect::CsvDialect;
+use crate::transaction::{ClientId, Transaction, TransactionId, TransactionType};
+use csv::ByteRecord;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::io::Read;
+
+/*
+`stats input.csv`: what's in a file, without running it through the engine.
+For sizing a new feed and checking it looks like what was promised:
+
+  rows                 3000000
+  malformed            0
+  clients              1000
+  tx ids               2400000
+  deposit              1500000
+  withdrawal           900000
+  ...
+  amount min           0.0001
+  amount max           9999.9999
+  amount sum           7500123456.7891
+  dispute family       20.0%
+
+Rows that can't be read are counted as malformed rather than stopping it.
+The dispute family is disputes, resolves and chargebacks, out of the rows
+that could be read. The amount sum is missing if it overflows.
+*/
+
+const TYPES: [TransactionType; 8] = [
+    TransactionType::Deposit,
+    TransactionType::Withdrawal,
+    TransactionType::Dispute,
+    TransactionType::Resolve,
+    TransactionType::Chargeback,
+    TransactionType::Hold,
+    TransactionType::Capture,
+    TransactionType::Release,
+];
+
+#[derive(Debug, Default, Serialize)]
+pub struct Profile {
+    pub rows: u64,
+    pub malformed: u64,
+    pub clients: usize,
+    pub tx_ids: usize,
+    /// Rows of each type, by name
+    pub types: BTreeMap<String, u64>,
+    pub amount_min: Option<Decimal>,
+    pub amount_max: Option<Decimal>,
+    pub amount_sum: Option<Decimal>,
+    /// Share of the readable rows that are disputes, resolves or chargebacks
+    pub dispute_ratio: f64,
+}
+
+/// Only fails if the input can't be read at all.
+pub fn profile<R: Read>(reader: R, dialect: &CsvDialect) -> Result<Profile, Box<dyn Error>> {
+    let mut rdr = dialect.reader(reader)?;
+    let mut profile = Profile::default();
+    let mut clients = vec![false; ClientId::MAX as usize + 1];
+    let mut tx_ids: HashSet<TransactionId> = HashSet::new();
+    let mut types = [0u64; TYPES.len()];
+    let mut sum = Some(Decimal::ZERO);
+    let mut record = ByteRecord::new();
+    loop {
+        match rdr.read_byte_record(&mut record) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(e) => {
+                let fatal = match e.downcast_ref::<csv::Error>() {
+                    Some(e) => e.is_io_error(),
+                    None => e.is::<std::io::Error>(),
+                };
+                if fatal {
+                    return Err(e);
+                }
+                profile.rows += 1;
+                profile.malformed += 1;
+                continue;
+            }
+        }
+        profile.rows += 1;
+        let transaction = match Transaction::from_byte_record(&record) {
+            Ok(transaction) => transaction,
+            Err(_) => {
+                profile.malformed += 1;
+                continue;
+            }
+        };
+        clients[transaction.client_id as usize] = true;
+        tx_ids.insert(transaction.tx_id);
+        types[transaction.tx_type as usize] += 1;
+        if let Some(amount) = transaction.amount {
+            profile.amount_min = Some(profile.amount_min.map_or(amount, |min| min.min(amount)));
+            profile.amount_max = Some(profile.amount_max.map_or(amount, |max| max.max(amount)));
+            sum = sum.and_then(|sum| sum.checked_add(amount));
+        }
+    }
+    profile.clients = clients.iter().filter(|&&seen| seen).count();
+    profile.tx_ids = tx_ids.len();
+    profile.types = TYPES
+        .iter()
+        .map(|tx_type| (tx_type.to_string(), types[*tx_type as usize]))
+        .collect();
+    profile.amount_sum = sum.filter(|_| profile.amount_min.is_some());
+    let disputes: u64 = [
+        TransactionType::Dispute,
+        TransactionType::Resolve,
+        TransactionType::Chargeback,
+    ]
+    .iter()
+    .map(|tx_type| types[*tx_type as usize])
+    .sum();
+    let readable = profile.rows - profile.malformed;
+    if readable > 0 {
+        profile.dispute_ratio = disputes as f64 / readable as f64;
+    }
+    Ok(profile)
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let amount = |amount: Option<Decimal>| match amount {
+            Some(amount) => amount.normalize().to_string(),
+            None => "-".to_string(),
+        };
+        writeln!(f, "{:<20} {}", "rows", self.rows)?;
+        writeln!(f, "{:<20} {}", "malformed", self.malformed)?;
+        writeln!(f, "{:<20} {}", "clients", self.clients)?;
+        writeln!(f, "{:<20} {}", "tx ids", self.tx_ids)?;
+        for tx_type in TYPES.iter() {
+            let name = tx_type.to_string();
+            writeln!(f, "{:<20} {}", name, self.types.get(&name).unwrap_or(&0))?;
+        }
+        writeln!(f, "{:<20} {}", "amount min", amount(self.amount_min))?;
+        writeln!(f, "{:<20} {}", "amount max", amount(self.amount_max))?;
+        writeln!(f, "{:<20} {}", "amount sum", amount(self.amount_sum))?;
+        writeln!(
+            f,
+            "{:<20} {:.1}%",
+            "dispute family",
+            self.dispute_ratio * 100.0
+        )
+    }
+}
+
+#[test]
+fn test_profile() {
+    let input = b"type,client,tx,amount
+deposit,1,1,10.5
+deposit,2,2,0.25
+withdrawal,1,3,3
+dispute,1,1,
+resolve,1,1,
+bogus,1,4,1
+deposit,3,5,100
+";
+    let profile = profile(&input[..], &CsvDialect::default()).unwrap();
+    assert_eq!(profile.rows, 7);
+    assert_eq!(profile.malformed, 1);
+    assert_eq!(profile.clients, 3);
+    assert_eq!(profile.tx_ids, 4);
+    assert_eq!(profile.types["deposit"], 3);
+    assert_eq!(profile.types["chargeback"], 0);
+    assert_eq!(profile.amount_min, Some(Decimal::new(25, 2)));
+    assert_eq!(profile.amount_max, Some(Decimal::new(100, 0)));
+    assert_eq!(profile.amount_sum, Some(Decimal::new(11375, 2)));
+    assert!((profile.dispute_ratio - 2.0 / 6.0).abs() < 1e-9);
+    let text = profile.to_string();
+    assert!(text.contains("amount sum           113.75\n"), "{}", text);
+    assert!(text.ends_with("dispute family       33.3%\n"), "{}", text);
+}
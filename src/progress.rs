@@ -0,0 +1,199 @@
+use crate::metrics::Metrics;
+use std::io::{self, IsTerminal, Read, Write};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/*
+--progress: a progress line on stderr, so stdout can still be the accounts.
+
+  [##########----------]  51.3%  1.3 GiB/2.6 GiB  412034 rows/s  ETA 3m12s  1523 rejected
+
+The bytes come from the sources read through reader() (the total is the
+size of the input files), the rows and rejects (transactions declined,
+ignored or failed) from the engine's metrics. On a terminal the line is
+redrawn in place a few times a second; otherwise, e.g. when stderr goes to a
+log file, a line is printed every ten seconds. Inputs that aren't counted
+(Parquet, Arrow) only show rows.
+*/
+
+const BAR_WIDTH: usize = 20;
+
+pub struct Progress {
+    total: u64,
+    read: AtomicU64,
+    metrics: Arc<Metrics>,
+    started: Instant,
+}
+
+/// A reader that counts what goes through it towards the progress.
+pub struct Counted<R> {
+    inner: R,
+    progress: Arc<Progress>,
+}
+
+impl<R: Read> Read for Counted<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.progress.read.fetch_add(read as u64, Ordering::Relaxed);
+        Ok(read)
+    }
+}
+
+/// Draws the progress until it's finished.
+pub struct ProgressDisplay {
+    progress: Arc<Progress>,
+    done: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Progress {
+    /// `total` bytes to read, the transactions counted by `metrics`, which
+    /// the engine has to be given.
+    pub fn new(total: u64, metrics: Arc<Metrics>) -> Progress {
+        Progress {
+            total,
+            read: AtomicU64::new(0),
+            metrics,
+            started: Instant::now(),
+        }
+    }
+
+    pub fn reader<R: Read>(self: &Arc<Self>, inner: R) -> Counted<R> {
+        Counted {
+            inner,
+            progress: Arc::clone(self),
+        }
+    }
+
+    /// The progress line as of now.
+    pub fn line(&self) -> String {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let (rows, rejected) = self.metrics.processed();
+        let rate = match elapsed > 0.0 {
+            true => (rows as f64 / elapsed) as u64,
+            false => 0,
+        };
+        let read = self.read.load(Ordering::Relaxed).min(self.total);
+        if self.total == 0 {
+            return format!("{} rows  {} rows/s  {} rejected", rows, rate, rejected);
+        }
+        let fraction = read as f64 / self.total as f64;
+        let filled = (fraction * BAR_WIDTH as f64) as usize;
+        let eta = match read {
+            0 => "?".to_string(),
+            _ => duration(elapsed / fraction - elapsed),
+        };
+        format!(
+            "[{}{}] {:5.1}%  {}/{}  {} rows/s  ETA {}  {} rejected",
+            "#".repeat(filled),
+            "-".repeat(BAR_WIDTH - filled),
+            fraction * 100.0,
+            size(read),
+            size(self.total),
+            rate,
+            eta,
+            rejected
+        )
+    }
+
+    /// Starts drawing on stderr.
+    pub fn show(self: Arc<Self>) -> ProgressDisplay {
+        let done = Arc::new(AtomicBool::new(false));
+        let progress = Arc::clone(&self);
+        let stop = Arc::clone(&done);
+        let thread = thread::spawn(move || {
+            let terminal = io::stderr().is_terminal();
+            let every = match terminal {
+                true => Duration::from_millis(200),
+                false => Duration::from_secs(10),
+            };
+            let mut last = Instant::now();
+            while !stop.load(Ordering::Relaxed) {
+                thread::sleep(Duration::from_millis(100));
+                if last.elapsed() < every {
+                    continue;
+                }
+                last = Instant::now();
+                let mut stderr = io::stderr().lock();
+                let _ = match terminal {
+                    true => write!(stderr, "\r{}\x1b[K", progress.line()),
+                    false => writeln!(stderr, "{}", progress.line()),
+                };
+            }
+        });
+        ProgressDisplay {
+            progress: self,
+            done,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl ProgressDisplay {
+    /// Stops drawing and leaves the final numbers on their own line.
+    pub fn finish(mut self) {
+        self.stop();
+        let mut stderr = io::stderr().lock();
+        let line = self.progress.line();
+        let _ = match stderr.is_terminal() {
+            true => writeln!(stderr, "\r{}\x1b[K", line),
+            false => writeln!(stderr, "{}", line),
+        };
+    }
+
+    fn stop(&mut self) {
+        self.done.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+// Stops drawing on errors too
+impl Drop for ProgressDisplay {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+pub(crate) fn size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+fn duration(seconds: f64) -> String {
+    let seconds = seconds.max(0.0) as u64;
+    match seconds {
+        0..=59 => format!("{}s", seconds),
+        60..=3599 => format!("{}m{:02}s", seconds / 60, seconds % 60),
+        _ => format!("{}h{:02}m", seconds / 3600, seconds % 3600 / 60),
+    }
+}
+
+#[test]
+fn test_progress() {
+    let metrics = Arc::new(Metrics::default());
+    let progress = Arc::new(Progress::new(4096, metrics.clone()));
+    let mut engine = crate::PaymentEngine::new();
+    engine.set_metrics(metrics);
+    let input = b"type,client,tx,amount\ndeposit,1,1,1\nwithdrawal,1,2,5\n";
+    engine.import_reader(progress.reader(&input[..])).unwrap();
+
+    let line = progress.line();
+    assert!(line.starts_with("[----"), "{}", line);
+    assert!(line.contains("  1.3%  53 B/4.0 KiB"), "{}", line);
+    assert!(line.ends_with("  1 rejected"), "{}", line);
+    assert_eq!(duration(3725.0), "1h02m");
+    assert_eq!(size(3 << 30), "3.0 GiB");
+}
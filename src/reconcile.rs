@@ -0,0 +1,245 @@
+use crate::engine::Account;
+use crate::output::AccountOrder;
+use crate::transaction::ClientId;
+use crate::PaymentEngine;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::io::{Read, Write};
+
+/*
+Checks the final balances against a control file, e.g. the balances the
+ledger system of record has, before the run's output goes anywhere. The
+control file is an account export (columns found by name, others ignored),
+where available, held, total and locked can be left out or empty to not
+check them:
+
+  client,total
+  1,10.5
+  2,0
+
+Every difference is reported, by client id, with the delta from expected to
+actual:
+
+  client,issue,expected,actual,delta
+  1,total,10.5,10,-0.5
+  2,locked,false,true,
+  3,missing,7,,-7
+  8,unexpected,,1,1
+
+missing is a client of the control file the engine has no account of, and
+unexpected an account the control file hasn't got, with their totals. Amounts
+within the tolerance of each other are taken as equal.
+*/
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+struct Expected {
+    client: ClientId,
+    #[serde(default)]
+    available: Option<Decimal>,
+    #[serde(default)]
+    held: Option<Decimal>,
+    #[serde(default)]
+    total: Option<Decimal>,
+    #[serde(default)]
+    locked: Option<bool>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Issue {
+    Missing,
+    Unexpected,
+    Available,
+    Held,
+    Total,
+    Locked,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Issue::Missing => "missing",
+            Issue::Unexpected => "unexpected",
+            Issue::Available => "available",
+            Issue::Held => "held",
+            Issue::Total => "total",
+            Issue::Locked => "locked",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Discrepancy {
+    pub client: ClientId,
+    pub issue: Issue,
+    /// Empty for an unexpected account
+    pub expected: String,
+    /// Empty for a missing one
+    pub actual: String,
+    /// Actual minus expected, None for locked
+    pub delta: Option<Decimal>,
+}
+
+/// Why the run fails when the balances don't match
+#[derive(Debug)]
+pub struct Discrepancies(pub usize);
+
+impl fmt::Display for Discrepancies {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Differences with the control file: {}", self.0)
+    }
+}
+
+impl Error for Discrepancies {}
+
+fn read<R: Read>(reader: R) -> Result<BTreeMap<ClientId, Expected>, Box<dyn Error>> {
+    let mut expected = BTreeMap::new();
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(reader);
+    for (balances, line) in reader.deserialize().zip(2..) {
+        let balances: Expected = balances.map_err(|e| format!("Line {}: {}", line, e))?;
+        if expected.insert(balances.client, balances).is_some() {
+            return Err(format!("Line {}: Client repeated in the control file", line).into());
+        }
+    }
+    Ok(expected)
+}
+
+/// The differences between the engine's accounts and the control file, by
+/// client id.
+pub fn reconcile<R: Read>(
+    engine: &PaymentEngine,
+    control: R,
+    tolerance: Decimal,
+) -> Result<Vec<Discrepancy>, Box<dyn Error>> {
+    let mut expected = read(control)?;
+    let mut discrepancies = Vec::new();
+    for account in engine.ordered_accounts(AccountOrder::Client)? {
+        let account = account?;
+        match expected.remove(&account.client_id) {
+            Some(expected) => compare(&expected, &account, tolerance, &mut discrepancies),
+            None => discrepancies.push(Discrepancy {
+                client: account.client_id,
+                issue: Issue::Unexpected,
+                expected: String::new(),
+                actual: account.funds_total.to_string(),
+                delta: Some(account.funds_total),
+            }),
+        }
+    }
+    for (client, expected) in expected {
+        discrepancies.push(Discrepancy {
+            client,
+            issue: Issue::Missing,
+            expected: expected
+                .total
+                .map(|total| total.to_string())
+                .unwrap_or_default(),
+            actual: String::new(),
+            delta: expected.total.map(|total| -total),
+        });
+    }
+    discrepancies.sort_by_key(|discrepancy| discrepancy.client);
+    Ok(discrepancies)
+}
+
+fn compare(
+    expected: &Expected,
+    account: &Account,
+    tolerance: Decimal,
+    discrepancies: &mut Vec<Discrepancy>,
+) {
+    let amounts = [
+        (
+            Issue::Available,
+            expected.available,
+            account.funds_available,
+        ),
+        (Issue::Held, expected.held, account.funds_held),
+        (Issue::Total, expected.total, account.funds_total),
+    ];
+    for (issue, expected, actual) in amounts {
+        let expected = match expected {
+            Some(expected) if (actual - expected).abs() > tolerance => expected,
+            _ => continue,
+        };
+        discrepancies.push(Discrepancy {
+            client: account.client_id,
+            issue,
+            expected: expected.to_string(),
+            actual: actual.to_string(),
+            delta: Some(actual - expected),
+        });
+    }
+    if let Some(locked) = expected.locked.filter(|locked| *locked != account.locked) {
+        discrepancies.push(Discrepancy {
+            client: account.client_id,
+            issue: Issue::Locked,
+            expected: locked.to_string(),
+            actual: account.locked.to_string(),
+            delta: None,
+        });
+    }
+}
+
+pub fn write<W: Write>(discrepancies: &[Discrepancy], mut writer: W) -> Result<(), Box<dyn Error>> {
+    writeln!(writer, "client,issue,expected,actual,delta")?;
+    for discrepancy in discrepancies {
+        write!(
+            writer,
+            "{},{},{},{},",
+            discrepancy.client, discrepancy.issue, discrepancy.expected, discrepancy.actual
+        )?;
+        if let Some(delta) = discrepancy.delta {
+            write!(writer, "{}", delta)?;
+        }
+        writeln!(writer)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[test]
+fn test_reconcile() {
+    let mut engine = PaymentEngine::new();
+    engine
+        .import_reader(
+            &b"type,client,tx,amount
+deposit,1,1,10
+deposit,2,2,5
+dispute,2,2,
+chargeback,2,2,
+deposit,4,3,3.004
+deposit,8,4,1
+"[..],
+        )
+        .unwrap();
+    let control = b"client,total,locked,name
+1,10.5,,Alice
+2,5,false,Bob
+3,7,,
+4,3,false,
+";
+    let discrepancies = reconcile(&engine, &control[..], Decimal::new(1, 2)).unwrap();
+    let mut report = Vec::new();
+    write(&discrepancies, &mut report).unwrap();
+    assert_eq!(
+        String::from_utf8(report).unwrap(),
+        "client,issue,expected,actual,delta
+1,total,10.5,10,-0.5
+2,locked,false,true,
+3,missing,7,,-7
+8,unexpected,,1,1
+"
+    );
+    // 3.004 is within the tolerance, not without
+    let strict = reconcile(&engine, &control[..], Decimal::ZERO).unwrap();
+    assert!(strict
+        .iter()
+        .any(|discrepancy| discrepancy.client == 4 && discrepancy.issue == Issue::Total));
+
+    assert!(reconcile(&engine, &b"client,total\n1,1\n1,2\n"[..], Decimal::ZERO).is_err());
+}
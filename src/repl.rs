@@ -0,0 +1,129 @@
+use crate::transaction::{ClientId, Transaction};
+use crate::PaymentEngine;
+use csv::StringRecord;
+use std::convert::TryFrom;
+use std::io::{self, BufRead, Write};
+
+/*
+`repl [--load-state state.bin]`: the engine one command at a time, to try out
+how it behaves or reproduce a bug report without writing a CSV file for it.
+A transaction is its CSV row with spaces instead of commas, anything after
+the amount being its description:
+
+  > deposit 1 99 5.00
+  applied
+  > dispute 1 99
+  applied
+  > account 1
+  client 1: available 0.00, held 5.00, total 5.00, not locked
+  > export
+  client,available,held,total,locked
+  1,0.00,5.00,5.00,false
+
+`help` lists the commands, `quit` or end of input leaves. Mistakes are
+reported and the session goes on, nothing but failing to write the answers
+stops it.
+*/
+
+const HELP: &str = "\
+<type> <client> <tx> [amount [description]]
+                               process a transaction, e.g. deposit 1 99 5.00
+account <client>               one client's account
+export                         every account, as CSV
+help                           this
+quit                           leave (so does end of input)";
+
+/// Reads commands from `input` until it ends or says quit, answering on
+/// `output`. With `prompt` it asks for each one with "> ", for terminals.
+pub fn run<R: BufRead, W: Write>(
+    engine: &mut PaymentEngine,
+    input: R,
+    mut output: W,
+    prompt: bool,
+) -> io::Result<()> {
+    let mut lines = input.lines();
+    loop {
+        if prompt {
+            write!(output, "> ")?;
+            output.flush()?;
+        }
+        let line = match lines.next() {
+            Some(line) => line?,
+            None => break,
+        };
+        let mut words: Vec<&str> = line.split_whitespace().collect();
+        let description = words.get(4..).map(|rest| rest.join(" "));
+        if let Some(description) = &description {
+            words.truncate(4);
+            words.push(description);
+        }
+        match words.as_slice() {
+            [] => {}
+            ["quit"] | ["exit"] => break,
+            ["help"] => writeln!(output, "{}", HELP)?,
+            ["export"] => engine.export_accounts(&mut output)?,
+            ["account", client] => match client.parse::<ClientId>() {
+                Ok(client) => match engine.account(client)? {
+                    Some(account) => writeln!(
+                        output,
+                        "client {}: available {}, held {}, total {}, {}",
+                        account.client_id,
+                        account.funds_available,
+                        account.funds_held,
+                        account.funds_total,
+                        match account.locked {
+                            true => "locked",
+                            false => "not locked",
+                        }
+                    )?,
+                    None => writeln!(output, "no account for client {}", client)?,
+                },
+                Err(_) => writeln!(output, "error: Invalid client id")?,
+            },
+            _ => match Transaction::try_from(StringRecord::from(words)) {
+                Ok(transaction) => match engine.process_transaction(transaction) {
+                    Ok(outcome) => match outcome.reason() {
+                        Some(reason) => writeln!(output, "{} ({})", outcome.kind(), reason)?,
+                        None => writeln!(output, "{}", outcome.kind())?,
+                    },
+                    Err(e) => writeln!(output, "error: {}", e)?,
+                },
+                Err(e) => writeln!(output, "error: {}, try help", e)?,
+            },
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_repl() {
+    let mut engine = PaymentEngine::new();
+    let input = b"deposit 1 99 5.00 Coffee, beans
+dispute 1 99
+
+account 1
+withdrawal 1 100 1
+deposit 1 99 1
+account 2
+transfer 1 2 3
+export
+quit
+deposit 2 1 1
+";
+    let mut output = Vec::new();
+    run(&mut engine, &input[..], &mut output, false).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "applied
+applied
+client 1: available 0.00, held 5.00, total 5.00, not locked
+declined (not enough funds)
+error: Repeated transaction id
+no account for client 2
+error: Unknown transaction type, try help
+client,available,held,total,locked
+1,0.00,5.00,5.00,false
+"
+    );
+    assert!(engine.account(2).unwrap().is_none());
+}
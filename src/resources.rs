@@ -0,0 +1,198 @@
+use crate::store::{AccountStore, TransactionStore};
+use serde::Deserialize;
+use std::io::{self, Read};
+
+/*
+Limits on what the input can make the process use, for files and requests
+that can't be trusted: a corrupted or malicious input gets an error instead
+of running the process out of memory. All of them are off by default.
+
+  max_record_length  bytes in one CSV record
+  max_columns        fields in one CSV record
+  max_accounts       accounts in the engine: a new client past it fails
+  max_transactions   stored transactions: a deposit past it fails
+  max_memory_mb      resident memory, looked at every CHECK_EVERY
+                     transactions (only where /proc has it, i.e. Linux):
+                     transactions fail while the process is over it
+
+The CSV limits are checked on the raw bytes, before the CSV reader buffers
+them, so a file without a single newline fails after max_record_length bytes
+instead of being read into memory whole. With threads, shards or actors the
+engine limits are per shard. The HTTP server also always caps its request
+lines and headers and drops connections that stop sending, see http.rs.
+*/
+
+const CHECK_EVERY: u64 = 1024;
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ResourceLimits {
+    pub max_record_length: Option<usize>,
+    pub max_columns: Option<usize>,
+    pub max_accounts: Option<usize>,
+    pub max_transactions: Option<usize>,
+    pub max_memory_mb: Option<u64>,
+}
+
+impl ResourceLimits {
+    /// The CSV input, failing at the first record over the limits.
+    /// `delimiter` and `quote` are the dialect's.
+    pub fn csv<R: Read>(&self, inner: R, delimiter: u8, quote: u8) -> Bounded<R> {
+        Bounded {
+            inner,
+            max_length: self.max_record_length,
+            max_columns: self.max_columns,
+            delimiter,
+            quote,
+            quoted: false,
+            length: 0,
+            columns: 1,
+        }
+    }
+
+    /// Fails if a new account would be one too many.
+    pub(crate) fn room_for_account(&self, accounts: &dyn AccountStore) -> Result<(), &'static str> {
+        match self.max_accounts {
+            Some(max) if accounts.len() >= max => Err("Too many accounts"),
+            _ => Ok(()),
+        }
+    }
+
+    /// Fails if storing another transaction would be one too many.
+    pub(crate) fn room_for_transaction(
+        &self,
+        transactions: &dyn TransactionStore,
+    ) -> Result<(), &'static str> {
+        match self.max_transactions {
+            Some(max) if transactions.len() >= max => Err("Too many stored transactions"),
+            _ => Ok(()),
+        }
+    }
+
+    /// Fails if the process is over the memory limit. Only looks every
+    /// CHECK_EVERY transactions, `processed` is how many there were so far.
+    pub(crate) fn check_memory(&self, processed: u64) -> Result<(), &'static str> {
+        match (self.max_memory_mb, processed % CHECK_EVERY) {
+            (Some(max), 0) if resident_memory().is_some_and(|used| used > max << 20) => {
+                Err("Memory limit reached")
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Resident memory of the process in bytes, where /proc has it.
+pub fn resident_memory() -> Option<u64> {
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+    Some(pages * page_size())
+}
+
+#[cfg(unix)]
+fn page_size() -> u64 {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as u64 }
+}
+
+#[cfg(not(unix))]
+fn page_size() -> u64 {
+    4096
+}
+
+/// A reader that fails once a CSV record goes over the limits. It follows
+/// the quotes, so delimiters and newlines in quoted fields don't count.
+pub struct Bounded<R> {
+    inner: R,
+    max_length: Option<usize>,
+    max_columns: Option<usize>,
+    delimiter: u8,
+    quote: u8,
+    quoted: bool,
+    // Of the current record
+    length: usize,
+    columns: usize,
+}
+
+impl<R: Read> Read for Bounded<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        if self.max_length.is_none() && self.max_columns.is_none() {
+            return Ok(read);
+        }
+        for &byte in &buf[..read] {
+            self.length += 1;
+            match byte {
+                _ if byte == self.quote => self.quoted = !self.quoted,
+                _ if self.quoted => {}
+                b'\n' => {
+                    self.length = 0;
+                    self.columns = 1;
+                }
+                _ if byte == self.delimiter => self.columns += 1,
+                _ => {}
+            }
+            match (self.max_length, self.max_columns) {
+                (Some(max), _) if self.length > max => {
+                    return Err(too_big(format!("Record longer than {} bytes", max)))
+                }
+                (_, Some(max)) if self.columns > max => {
+                    return Err(too_big(format!("Record with more than {} columns", max)))
+                }
+                _ => {}
+            }
+        }
+        Ok(read)
+    }
+}
+
+fn too_big(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[test]
+fn test_resource_limits() {
+    use crate::PaymentEngine;
+
+    let limits = ResourceLimits {
+        max_record_length: Some(24),
+        max_columns: Some(4),
+        ..ResourceLimits::default()
+    };
+    let read = |input: &str| {
+        let mut out = Vec::new();
+        limits
+            .csv(input.as_bytes(), b',', b'"')
+            .read_to_end(&mut out)
+            .map(|_| out.len())
+    };
+    assert_eq!(
+        read("type,client,tx,amount\ndeposit,1,1,\"1,5\"\n").unwrap(),
+        40
+    );
+    let error = read("type,client,tx,amount\ndeposit,1,1,1,5\n").unwrap_err();
+    assert_eq!(error.to_string(), "Record with more than 4 columns");
+    let error = read("type,client,tx,amount\ndeposit,1,1,1.000000000000\n").unwrap_err();
+    assert_eq!(error.to_string(), "Record longer than 24 bytes");
+
+    let mut engine = PaymentEngine::new();
+    engine.set_resource_limits(ResourceLimits {
+        max_accounts: Some(2),
+        max_transactions: Some(2),
+        ..ResourceLimits::default()
+    });
+    let input = b"type,client,tx,amount\ndeposit,1,1,1\nwithdrawal,2,2,1\ndeposit,3,3,1\n";
+    let error = engine.import_reader(&input[..]).unwrap_err();
+    assert_eq!(error.to_string(), "Too many accounts");
+    let input = b"type,client,tx,amount\ndeposit,2,4,1\ndeposit,1,5,1\n";
+    let error = engine.import_reader(&input[..]).unwrap_err();
+    assert_eq!(error.to_string(), "Too many stored transactions");
+    assert_eq!(engine.len(), 2);
+
+    let limits = ResourceLimits {
+        max_memory_mb: Some(1),
+        ..ResourceLimits::default()
+    };
+    if resident_memory().is_some() {
+        assert!(limits.check_memory(CHECK_EVERY).is_err());
+        assert!(limits.check_memory(CHECK_EVERY + 1).is_ok());
+    }
+}
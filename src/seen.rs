@@ -0,0 +1,129 @@
+use crate::transaction::TransactionId;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/*
+The deposit and withdrawal tx ids already processed, saved with the state so
+a later run that gets some of the same rows again (overlapping daily files, a
+file retried after a crash...) skips them instead of applying them twice.
+
+Most lookups are of new ids, so a bloom filter answers those without touching
+the exact set, which only settles the maybes. Only the exact set is saved, the
+filter is rebuilt from it on load.
+*/
+
+const BLOOM_BITS: usize = 1 << 23; // 1 MiB, a few % false positives at a million ids
+const BLOOM_HASHES: u32 = 3;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "Vec<TransactionId>", into = "Vec<TransactionId>")]
+pub struct SeenTxIds {
+    bloom: Vec<u64>,
+    exact: HashSet<TransactionId>,
+}
+
+impl Default for SeenTxIds {
+    fn default() -> Self {
+        SeenTxIds {
+            bloom: vec![0; BLOOM_BITS / 64],
+            exact: HashSet::new(),
+        }
+    }
+}
+
+// Bit positions for the filter, from multiplicative hashes with different odd
+// constants
+fn bits(tx_id: TransactionId) -> impl Iterator<Item = usize> {
+    const SEEDS: [u64; BLOOM_HASHES as usize] = [
+        0x9E37_79B9_7F4A_7C15,
+        0xC2B2_AE3D_27D4_EB4F,
+        0x1656_67B1_9E37_79F9,
+    ];
+    SEEDS.iter().map(move |seed| {
+        let hash = (u64::from(tx_id) + 1).wrapping_mul(*seed);
+        (hash >> 40) as usize % BLOOM_BITS
+    })
+}
+
+impl SeenTxIds {
+    pub fn contains(&self, tx_id: TransactionId) -> bool {
+        bits(tx_id).all(|bit| self.bloom[bit / 64] & (1 << (bit % 64)) != 0)
+            && self.exact.contains(&tx_id)
+    }
+
+    pub fn insert(&mut self, tx_id: TransactionId) {
+        for bit in bits(tx_id) {
+            self.bloom[bit / 64] |= 1 << (bit % 64);
+        }
+        self.exact.insert(tx_id);
+    }
+
+    // The filter keeps its bits, the exact set has the last word anyway
+    pub(crate) fn remove(&mut self, tx_id: TransactionId) {
+        self.exact.remove(&tx_id);
+    }
+
+    pub(crate) fn merge(&mut self, other: SeenTxIds) {
+        for tx_id in other.exact {
+            self.insert(tx_id);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.exact.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.exact.is_empty()
+    }
+}
+
+impl From<Vec<TransactionId>> for SeenTxIds {
+    fn from(tx_ids: Vec<TransactionId>) -> SeenTxIds {
+        let mut seen = SeenTxIds::default();
+        for tx_id in tx_ids {
+            seen.insert(tx_id);
+        }
+        seen
+    }
+}
+
+impl From<SeenTxIds> for Vec<TransactionId> {
+    fn from(seen: SeenTxIds) -> Vec<TransactionId> {
+        let mut tx_ids: Vec<TransactionId> = seen.exact.into_iter().collect();
+        tx_ids.sort_unstable();
+        tx_ids
+    }
+}
+
+#[test]
+fn test_reprocessing() {
+    use crate::state::StateFormat;
+    use crate::PaymentEngine;
+
+    let monday = &b"type,client,tx,amount\ndeposit,1,1,10\nwithdrawal,1,2,3\n"[..];
+    let tuesday = &b"type,client,tx,amount\ndeposit,1,1,10\nwithdrawal,1,2,3\ndeposit,1,3,1\n"[..];
+
+    let mut engine = PaymentEngine::new();
+    engine.set_idempotent(true);
+    engine.import_reader(monday).unwrap();
+    let mut state = Vec::new();
+    engine
+        .save_state(&mut state, StateFormat::MessagePack)
+        .unwrap();
+
+    let mut engine = PaymentEngine::new();
+    engine.set_idempotent(true);
+    engine
+        .restore_state(&state[..], StateFormat::MessagePack)
+        .unwrap();
+    engine.import_reader(tuesday).unwrap();
+    let account = engine.account(1).unwrap().unwrap();
+    assert_eq!(account.total().to_string(), "8");
+    assert_eq!(engine.stats().replayed, 2);
+
+    let seen = SeenTxIds::from(vec![5, 7]);
+    assert!(seen.contains(5) && seen.contains(7));
+    assert!(!seen.contains(6));
+    assert_eq!(Vec::from(seen), vec![5, 7]);
+}
@@ -0,0 +1,228 @@
+use crate::concurrent::ConcurrentPaymentEngine;
+use crate::engine::Account;
+use crate::http::{self, Request};
+use crate::input::json::JsonTransaction;
+use crate::metrics::Metrics;
+use crate::transaction::*;
+use crate::PaymentEngine;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::error::Error;
+use std::io::{self, BufReader};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use tracing::{debug, info, warn};
+
+/*
+Online mode: the same engine behind a small JSON API.
+
+  POST /transactions         {"type": "deposit", "client": 1, "tx": 1, "amount": "1.5"}
+                             or an array of them, processed in order
+  GET  /accounts/<client id> {"client": 1, "available": "1.5", "held": "0", ...}
+  GET  /metrics              Prometheus metrics, see metrics.rs
+
+See input/json.rs for the transaction format. POST answers
+with one result per transaction: applied, declined or ignored (with the
+reason) or failed (with the error). A failed transaction doesn't stop the
+rest of a batch. Every connection gets a thread, up to MAX_CONNECTIONS at
+once, and with shards (see concurrent.rs) requests for clients of different
+shards don't wait for each other.
+*/
+
+// Connections handled at once, more wait to be accepted
+const MAX_CONNECTIONS: usize = 256;
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum TransactionsRequest {
+    One(JsonTransaction),
+    Batch(Vec<JsonTransaction>),
+}
+
+#[derive(Debug, Serialize)]
+struct TransactionResult {
+    tx: TransactionId,
+    result: &'static str,
+    reason: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct AccountResponse {
+    client: ClientId,
+    available: Decimal,
+    held: Decimal,
+    total: Decimal,
+    locked: bool,
+}
+
+impl From<Account> for AccountResponse {
+    fn from(account: Account) -> AccountResponse {
+        AccountResponse {
+            client: account.client_id,
+            available: account.funds_available,
+            held: account.funds_held,
+            total: account.funds_total,
+            locked: account.locked,
+        }
+    }
+}
+
+pub struct Server {
+    engine: ConcurrentPaymentEngine,
+    metrics: Arc<Metrics>,
+}
+
+type Response = (u16, &'static str, Vec<u8>);
+
+fn json<T: Serialize>(status: u16, value: &T) -> Response {
+    match serde_json::to_vec(value) {
+        Ok(body) => (status, "application/json", body),
+        Err(e) => (500, "text/plain", e.to_string().into_bytes()),
+    }
+}
+
+fn error(status: u16, message: &str) -> Response {
+    (status, "text/plain", format!("{}\n", message).into_bytes())
+}
+
+impl Server {
+    pub fn new(mut engine: PaymentEngine) -> Server {
+        let metrics = Arc::new(Metrics::default());
+        engine.set_metrics(metrics.clone());
+        Server {
+            engine: engine.into(),
+            metrics,
+        }
+    }
+
+    /// With the engine split in `shards`, see concurrent.rs
+    pub fn with_shards(mut engine: PaymentEngine, shards: usize) -> Result<Server, Box<dyn Error>> {
+        let metrics = Arc::new(Metrics::default());
+        engine.set_metrics(metrics.clone());
+        Ok(Server {
+            engine: ConcurrentPaymentEngine::new(engine, shards)?,
+            metrics,
+        })
+    }
+
+    /// Serves connections for as long as the process lives, one thread
+    /// each, up to MAX_CONNECTIONS at once.
+    pub fn run(self, listener: TcpListener) -> io::Result<()> {
+        info!("Listening on {}", listener.local_addr()?);
+        let server = Arc::new(self);
+        http::serve_connections(listener, MAX_CONNECTIONS, move |stream| {
+            if let Err(e) = server.serve_connection(&stream) {
+                warn!("Request failed: {}", e);
+            }
+        });
+        Ok(())
+    }
+
+    fn serve_connection(&self, stream: &TcpStream) -> io::Result<()> {
+        stream.set_read_timeout(Some(http::READ_TIMEOUT))?;
+        let response = match http::read_request(&mut BufReader::new(stream)) {
+            Ok(request) => self.handle(&request),
+            Err(e) => error(400, &e.to_string()),
+        };
+        let mut writer = stream;
+        http::write_response(&mut writer, response.0, response.1, &response.2)
+    }
+
+    fn handle(&self, request: &Request) -> Response {
+        debug!("{} {}", request.method, request.path);
+        match (request.method.as_str(), request.path.as_str()) {
+            ("POST", "/transactions") => {
+                let transactions = match serde_json::from_slice(&request.body) {
+                    Ok(TransactionsRequest::One(one)) => vec![one],
+                    Ok(TransactionsRequest::Batch(batch)) => batch,
+                    Err(e) => return error(400, &e.to_string()),
+                };
+                let results: Vec<TransactionResult> = transactions
+                    .into_iter()
+                    .map(|transaction| process(&self.engine, transaction))
+                    .collect();
+                json(200, &results)
+            }
+            ("GET", "/metrics") => (
+                200,
+                "text/plain; version=0.0.4",
+                self.metrics.render().into_bytes(),
+            ),
+            ("GET", path) if path.starts_with("/accounts/") => {
+                let client_id = match path["/accounts/".len()..].parse::<ClientId>() {
+                    Ok(client_id) => client_id,
+                    Err(_) => return error(400, "Invalid client id"),
+                };
+                match self.engine.account(client_id) {
+                    Ok(Some(account)) => json(200, &AccountResponse::from(account)),
+                    Ok(None) => error(404, "No such account"),
+                    Err(e) => error(500, &e.to_string()),
+                }
+            }
+            (_, "/transactions") => error(405, "Use POST"),
+            _ => error(404, "Not found"),
+        }
+    }
+}
+
+fn process(engine: &ConcurrentPaymentEngine, request: JsonTransaction) -> TransactionResult {
+    let tx = request.tx;
+    let result = Transaction::try_from(request)
+        .map_err(|e| e.into())
+        .and_then(|transaction| engine.process_transaction(transaction));
+    let (result, reason) = match result {
+        Ok(outcome) => (outcome.kind(), outcome.reason().map(|r| r.to_string())),
+        Err(e) => ("failed", Some(e.to_string())),
+    };
+    TransactionResult { tx, result, reason }
+}
+
+#[test]
+fn test_server() {
+    let server = Server::with_shards(PaymentEngine::new(), 2).unwrap();
+    let request = |method: &str, path: &str, body: &str| {
+        let (status, _, body) = server.handle(&Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            body: body.as_bytes().to_vec(),
+        });
+        (status, String::from_utf8(body).unwrap())
+    };
+
+    let (status, body) = request(
+        "POST",
+        "/transactions",
+        r#"{"type": "deposit", "client": 1, "tx": 1, "amount": "10.5"}"#,
+    );
+    assert_eq!(status, 200);
+    assert_eq!(body, r#"[{"tx":1,"result":"applied","reason":null}]"#);
+
+    let (status, body) = request(
+        "POST",
+        "/transactions",
+        r#"[{"type": "withdrawal", "client": 1, "tx": 2, "amount": "20"},
+            {"type": "dispute", "client": 1, "tx": 1},
+            {"type": "deposit", "client": 1, "tx": 1, "amount": "1"},
+            {"type": "refund", "client": 1, "tx": 3}]"#,
+    );
+    assert_eq!(status, 200);
+    let results: Vec<serde_json::Value> = serde_json::from_str(&body).unwrap();
+    let results: Vec<&str> = results
+        .iter()
+        .map(|r| r["result"].as_str().unwrap())
+        .collect();
+    assert_eq!(results, vec!["declined", "applied", "failed", "failed"]);
+
+    let (status, body) = request("GET", "/accounts/1", "");
+    assert_eq!(status, 200);
+    assert_eq!(
+        body,
+        r#"{"client":1,"available":"0.0","held":"10.5","total":"10.5","locked":false}"#
+    );
+    assert_eq!(request("GET", "/accounts/2", "").0, 404);
+    assert_eq!(request("POST", "/transactions", "{").0, 400);
+    assert!(request("GET", "/metrics", "")
+        .1
+        .contains("payments_transactions_total"));
+}
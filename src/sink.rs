@@ -0,0 +1,313 @@
+use crate::spool::{MemorySpool, Spool};
+use serde::Deserialize;
+use std::error::Error;
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+/// Something we push records to over the network. A payload is an already
+/// encoded record. The webhooks (see webhook.rs) are the only network output
+/// there is, and the only sink; the other outputs are local files and the
+/// Kafka source only consumes.
+pub trait Sink {
+    fn name(&self) -> &str;
+    fn send(&mut self, payload: &[u8]) -> Result<(), Box<dyn Error>>;
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff_ms: 100,
+            max_backoff_ms: 5000,
+        }
+    }
+}
+
+impl RetryPolicy {
+    // Exponential, doubling after every failed attempt
+    fn backoff(&self, attempt: u32) -> Duration {
+        let ms = self
+            .initial_backoff_ms
+            .saturating_mul(1u64.checked_shl(attempt).unwrap_or(u64::MAX));
+        Duration::from_millis(ms.min(self.max_backoff_ms))
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CircuitBreakerConfig {
+    /// Consecutive failed sends (after retries) before we stop trying
+    pub failure_threshold: u32,
+    /// How long to wait before letting a probe send through again
+    pub cooldown_ms: u64,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        CircuitBreakerConfig {
+            failure_threshold: 5,
+            cooldown_ms: 30_000,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+enum BreakerState {
+    Closed,
+    Open(Instant),
+    HalfOpen,
+}
+
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    config: CircuitBreakerConfig,
+    state: BreakerState,
+    consecutive_failures: u32,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: CircuitBreakerConfig) -> CircuitBreaker {
+        CircuitBreaker {
+            config,
+            state: BreakerState::Closed,
+            consecutive_failures: 0,
+        }
+    }
+
+    pub fn allows_request(&mut self) -> bool {
+        match self.state {
+            BreakerState::Closed | BreakerState::HalfOpen => true,
+            BreakerState::Open(since) => {
+                if since.elapsed() >= Duration::from_millis(self.config.cooldown_ms) {
+                    self.state = BreakerState::HalfOpen;
+                    true
+                } else {
+                    false
+                }
+            }
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        matches!(self.state, BreakerState::Open(_))
+    }
+
+    pub fn record_success(&mut self) {
+        self.consecutive_failures = 0;
+        self.state = BreakerState::Closed;
+    }
+
+    pub fn record_failure(&mut self) {
+        self.consecutive_failures += 1;
+        // A failed probe reopens straight away
+        if self.state == BreakerState::HalfOpen
+            || self.consecutive_failures >= self.config.failure_threshold
+        {
+            self.state = BreakerState::Open(Instant::now());
+        }
+    }
+}
+
+/// Wraps a sink with retries and a circuit breaker. Whatever can't be
+/// delivered is spooled locally and drained, in order, once the sink is back,
+/// so a sink blip never fails the batch run. The spool is in memory unless a
+/// durable one is given with `with_spool`.
+pub struct ResilientSink<S: Sink> {
+    inner: S,
+    retry: RetryPolicy,
+    breaker: CircuitBreaker,
+    spool: Box<dyn Spool + Send>,
+}
+
+impl<S: Sink> ResilientSink<S> {
+    pub fn new(inner: S, retry: RetryPolicy, breaker: CircuitBreakerConfig) -> ResilientSink<S> {
+        ResilientSink {
+            inner,
+            retry,
+            breaker: CircuitBreaker::new(breaker),
+            spool: Box::new(MemorySpool::default()),
+        }
+    }
+
+    /// Anything left in the spool (e.g. by a previous run) is delivered before
+    /// new records.
+    pub fn with_spool(mut self, spool: Box<dyn Spool + Send>) -> ResilientSink<S> {
+        self.spool = spool;
+        self
+    }
+
+    pub fn spooled(&self) -> usize {
+        self.spool.len()
+    }
+
+    fn send_with_retries(&mut self, payload: &[u8]) -> Result<(), Box<dyn Error>> {
+        let mut attempt = 0;
+        loop {
+            match self.inner.send(payload) {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    attempt += 1;
+                    if attempt >= self.retry.max_attempts {
+                        return Err(e);
+                    }
+                    debug!(
+                        "{}: attempt {} failed ({}), retrying",
+                        self.inner.name(),
+                        attempt,
+                        e
+                    );
+                    thread::sleep(self.retry.backoff(attempt - 1));
+                }
+            }
+        }
+    }
+
+    /// Tries to deliver everything in the spool. Returns whether it's empty now.
+    pub fn drain(&mut self) -> Result<bool, Box<dyn Error>> {
+        while let Some(payload) = self.spool.peek()? {
+            if !self.breaker.allows_request() {
+                return Ok(false);
+            }
+            match self.send_with_retries(&payload) {
+                Ok(()) => {
+                    self.breaker.record_success();
+                    self.spool.pop()?;
+                }
+                Err(e) => {
+                    self.breaker.record_failure();
+                    warn!(
+                        "{}: still down ({}), {} records spooled",
+                        self.inner.name(),
+                        e,
+                        self.spool.len()
+                    );
+                    return Ok(false);
+                }
+            }
+        }
+        Ok(true)
+    }
+}
+
+impl<S: Sink> Sink for ResilientSink<S> {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn send(&mut self, payload: &[u8]) -> Result<(), Box<dyn Error>> {
+        // Healthy with nothing waiting: straight to the sink, a durable spool
+        // costs a sync or two per record
+        if self.spool.is_empty() && self.breaker.state == BreakerState::Closed {
+            match self.send_with_retries(payload) {
+                Ok(()) => {
+                    self.breaker.record_success();
+                    return Ok(());
+                }
+                Err(e) => {
+                    self.breaker.record_failure();
+                    self.spool.push(payload)?;
+                    warn!("{}: down ({}), spooling", self.inner.name(), e);
+                    return Ok(());
+                }
+            }
+        }
+        // Anything already spooled has to go first to keep the ordering
+        self.spool.push(payload)?;
+        if self.breaker.is_open() {
+            debug!("{}: circuit open, spooling", self.inner.name());
+        }
+        // Only failing to spool is an error, the sink being down isn't
+        self.drain()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+struct FlakySink {
+    failures_left: u32,
+    received: Vec<Vec<u8>>,
+}
+
+#[cfg(test)]
+impl Sink for FlakySink {
+    fn name(&self) -> &str {
+        "flaky"
+    }
+
+    fn send(&mut self, payload: &[u8]) -> Result<(), Box<dyn Error>> {
+        if self.failures_left > 0 {
+            self.failures_left -= 1;
+            return Err("connection refused".into());
+        }
+        self.received.push(payload.to_vec());
+        Ok(())
+    }
+}
+
+#[test]
+fn test_resilient_sink() {
+    let retry = RetryPolicy {
+        max_attempts: 2,
+        initial_backoff_ms: 0,
+        max_backoff_ms: 0,
+    };
+    let breaker = CircuitBreakerConfig {
+        failure_threshold: 1,
+        cooldown_ms: 60_000,
+    };
+
+    /* A single failure is absorbed by the retry */
+    let flaky = FlakySink {
+        failures_left: 1,
+        received: Vec::new(),
+    };
+    let mut sink = ResilientSink::new(flaky, retry.clone(), breaker.clone());
+    sink.send(b"1").unwrap();
+    assert_eq!(sink.inner.received, vec![b"1".to_vec()]);
+
+    /* Healthy, nothing touches the spool */
+    struct NoSpool;
+    impl Spool for NoSpool {
+        fn push(&mut self, _payload: &[u8]) -> Result<(), Box<dyn Error>> {
+            panic!("spooled while healthy")
+        }
+        fn peek(&mut self) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+            Ok(None)
+        }
+        fn pop(&mut self) -> Result<(), Box<dyn Error>> {
+            panic!("spooled while healthy")
+        }
+        fn len(&self) -> usize {
+            0
+        }
+    }
+    let mut sink = sink.with_spool(Box::new(NoSpool));
+    sink.send(b"2").unwrap();
+    assert_eq!(sink.inner.received, vec![b"1".to_vec(), b"2".to_vec()]);
+
+    /* Sink down: the breaker opens and everything gets spooled */
+    let flaky = FlakySink {
+        failures_left: 2,
+        received: Vec::new(),
+    };
+    let mut sink = ResilientSink::new(flaky, retry, breaker);
+    sink.send(b"1").unwrap();
+    sink.send(b"2").unwrap();
+    assert!(sink.breaker.is_open());
+    assert_eq!(sink.spooled(), 2);
+    assert!(sink.inner.received.is_empty());
+
+    /* Recovery drains the spool in order */
+    sink.breaker.config.cooldown_ms = 0;
+    assert!(sink.drain().unwrap());
+    assert_eq!(sink.inner.received, vec![b"1".to_vec(), b"2".to_vec()]);
+}
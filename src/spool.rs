@@ -0,0 +1,427 @@
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+/// FIFO queue where sinks park whatever they couldn't deliver.
+pub trait Spool {
+    fn push(&mut self, payload: &[u8]) -> Result<(), Box<dyn Error>>;
+    /// Oldest payload, left in the spool until `pop` confirms it was delivered
+    fn peek(&mut self) -> Result<Option<Vec<u8>>, Box<dyn Error>>;
+    fn pop(&mut self) -> Result<(), Box<dyn Error>>;
+    fn len(&self) -> usize;
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[derive(Default)]
+pub struct MemorySpool {
+    queue: VecDeque<Vec<u8>>,
+}
+
+impl Spool for MemorySpool {
+    fn push(&mut self, payload: &[u8]) -> Result<(), Box<dyn Error>> {
+        self.queue.push_back(payload.to_vec());
+        Ok(())
+    }
+
+    fn peek(&mut self) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        Ok(self.queue.front().cloned())
+    }
+
+    fn pop(&mut self) -> Result<(), Box<dyn Error>> {
+        self.queue.pop_front();
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        self.queue.len()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DiskSpoolConfig {
+    pub dir: PathBuf,
+    /// Start a new segment file once the current one is this big
+    pub segment_bytes: u64,
+    /// Log a warning every time the spool grows past this size
+    pub alert_bytes: u64,
+}
+
+impl Default for DiskSpoolConfig {
+    fn default() -> Self {
+        DiskSpoolConfig {
+            dir: PathBuf::from("spool"),
+            segment_bytes: 64 * 1024 * 1024,
+            alert_bytes: 1024 * 1024 * 1024,
+        }
+    }
+}
+
+// Every record is [length: u32 LE][crc32 of payload: u32 LE][payload]
+const RECORD_HEADER: u64 = 8;
+const CURSOR_FILE: &str = "cursor";
+
+struct Segment {
+    id: u64,
+    records: usize,
+    bytes: u64,
+}
+
+/// Spool that survives restarts: records are appended to numbered segment
+/// files in a directory and a cursor file remembers how far we've delivered.
+/// Opening a directory with leftovers picks them up so they get replayed, in
+/// order, before anything new. Appends and cursor updates are synced to disk
+/// before they return, so what push took survives the machine going down.
+pub struct DiskSpool {
+    config: DiskSpoolConfig,
+    segments: VecDeque<Segment>, // Oldest first, the last one is the one we write to
+    read_offset: u64,            // In the oldest segment
+    writer: Option<File>,
+    alerted: bool,
+}
+
+impl DiskSpool {
+    pub fn open(config: DiskSpoolConfig) -> Result<DiskSpool, Box<dyn Error>> {
+        fs::create_dir_all(&config.dir)?;
+        let mut ids: Vec<u64> = fs::read_dir(&config.dir)?
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let name = entry.file_name().into_string().ok()?;
+                name.strip_prefix("spool-")?
+                    .strip_suffix(".seg")?
+                    .parse()
+                    .ok()
+            })
+            .collect();
+        ids.sort_unstable();
+
+        let (cursor_id, cursor_offset) = read_cursor(&config.dir)?;
+        let mut spool = DiskSpool {
+            config,
+            segments: VecDeque::new(),
+            read_offset: 0,
+            writer: None,
+            alerted: false,
+        };
+        for id in ids {
+            if id < cursor_id {
+                // Fully delivered but not deleted before we went down
+                fs::remove_file(spool.segment_path(id))?;
+                continue;
+            }
+            let offset = if id == cursor_id { cursor_offset } else { 0 };
+            if spool.segments.is_empty() {
+                spool.read_offset = offset;
+            }
+            let (records, bytes) = spool.scan_segment(id, offset)?;
+            spool.segments.push_back(Segment { id, records, bytes });
+        }
+        if !spool.is_empty() {
+            warn!(
+                "Spool {:?} has {} undelivered records from a previous run",
+                spool.config.dir,
+                spool.len()
+            );
+        }
+        Ok(spool)
+    }
+
+    fn segment_path(&self, id: u64) -> PathBuf {
+        self.config.dir.join(format!("spool-{:010}.seg", id))
+    }
+
+    // Counts the valid records from `offset` on. A torn or corrupted record
+    // (say we crashed mid-write) is skipped: the scan picks up again at the
+    // next header whose payload checks out, and the segment is rewritten
+    // without the bad bytes so the records after them are still read in
+    // order. A torn write at the end just goes away.
+    fn scan_segment(&self, id: u64, offset: u64) -> Result<(usize, u64), Box<dyn Error>> {
+        let path = self.segment_path(id);
+        let data = fs::read(&path)?;
+        let start = (offset as usize).min(data.len());
+        let mut kept = data[..start].to_vec();
+        let mut records = 0;
+        let mut skipped = 0;
+        let mut position = start;
+        while position < data.len() {
+            match record_length(&data[position..]) {
+                Some(length) => {
+                    kept.extend_from_slice(&data[position..position + length]);
+                    records += 1;
+                    position += length;
+                }
+                None => {
+                    skipped += 1;
+                    position += 1;
+                }
+            }
+        }
+        if skipped > 0 {
+            warn!(
+                "Spool segment {:?} has {} corrupted bytes, skipping them",
+                path, skipped
+            );
+            let tmp = path.with_extension("tmp");
+            let mut file = File::create(&tmp)?;
+            file.write_all(&kept)?;
+            file.sync_all()?;
+            fs::rename(tmp, &path)?;
+            sync_dir(&self.config.dir)?;
+        }
+        Ok((records, kept.len() as u64))
+    }
+
+    fn write_cursor(&self) -> Result<(), Box<dyn Error>> {
+        let id = self.segments.front().map(|s| s.id).unwrap_or(0);
+        let tmp = self.config.dir.join("cursor.tmp");
+        let mut file = File::create(&tmp)?;
+        write!(file, "{} {}", id, self.read_offset)?;
+        file.sync_all()?;
+        fs::rename(tmp, self.config.dir.join(CURSOR_FILE))?;
+        sync_dir(&self.config.dir)?;
+        Ok(())
+    }
+
+    pub fn size_bytes(&self) -> u64 {
+        self.segments.iter().map(|s| s.bytes).sum::<u64>() - self.read_offset
+    }
+}
+
+fn read_cursor(dir: &Path) -> Result<(u64, u64), Box<dyn Error>> {
+    let cursor = match fs::read_to_string(dir.join(CURSOR_FILE)) {
+        Ok(cursor) => cursor,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok((0, 0)),
+        Err(e) => return Err(e.into()),
+    };
+    let mut fields = cursor.split_whitespace().map(|f| f.parse::<u64>());
+    match (fields.next(), fields.next()) {
+        (Some(Ok(id)), Some(Ok(offset))) => Ok((id, offset)),
+        _ => Err("Corrupted spool cursor".into()),
+    }
+}
+
+// Makes a new, renamed or removed file of the directory durable
+pub(crate) fn sync_dir(dir: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    File::open(dir)?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = dir;
+    Ok(())
+}
+
+// How long the record at the start of `data` is, if there's a whole one that
+// checks out
+fn record_length(data: &[u8]) -> Option<usize> {
+    let header = data.get(..RECORD_HEADER as usize)?;
+    let length = u32::from_le_bytes([header[0], header[1], header[2], header[3]]) as usize;
+    let checksum = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+    let payload = data.get(RECORD_HEADER as usize..)?.get(..length)?;
+    match crc32fast::hash(payload) == checksum {
+        true => Some(RECORD_HEADER as usize + length),
+        false => None,
+    }
+}
+
+// None on a clean end of segment or on a record that doesn't check out.
+// `left` is how many bytes the segment has from here: a corrupt length can't
+// be trusted with the allocation before the checksum is checked.
+fn read_record<R: Read>(reader: &mut R, left: u64) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+    let mut header = [0u8; RECORD_HEADER as usize];
+    match reader.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let length = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+    let checksum = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+    if RECORD_HEADER + u64::from(length) > left {
+        return Ok(None);
+    }
+    let mut payload = vec![0u8; length as usize];
+    match reader.read_exact(&mut payload) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    if crc32fast::hash(&payload) != checksum {
+        return Ok(None);
+    }
+    Ok(Some(payload))
+}
+
+impl Spool for DiskSpool {
+    fn push(&mut self, payload: &[u8]) -> Result<(), Box<dyn Error>> {
+        let rotate = match self.segments.back() {
+            None => true,
+            Some(segment) => segment.bytes >= self.config.segment_bytes,
+        };
+        if rotate || self.writer.is_none() {
+            let id = match self.segments.back() {
+                Some(segment) if !rotate => segment.id,
+                Some(segment) => segment.id + 1,
+                None => 0,
+            };
+            if rotate {
+                self.segments.push_back(Segment {
+                    id,
+                    records: 0,
+                    bytes: 0,
+                });
+            }
+            let path = self.segment_path(id);
+            self.writer = Some(OpenOptions::new().create(true).append(true).open(path)?);
+            sync_dir(&self.config.dir)?;
+        }
+
+        let mut record = Vec::with_capacity(RECORD_HEADER as usize + payload.len());
+        record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        record.extend_from_slice(&crc32fast::hash(payload).to_le_bytes());
+        record.extend_from_slice(payload);
+        let writer = self.writer.as_mut().unwrap();
+        writer.write_all(&record)?;
+        writer.sync_data()?;
+        let segment = self.segments.back_mut().unwrap();
+        segment.records += 1;
+        segment.bytes += record.len() as u64;
+
+        let size = self.size_bytes();
+        if size > self.config.alert_bytes && !self.alerted {
+            warn!(
+                "Spool {:?} is over the alert threshold: {} bytes, {} records",
+                self.config.dir,
+                size,
+                self.len()
+            );
+            self.alerted = true;
+        }
+        Ok(())
+    }
+
+    fn peek(&mut self) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        let segment = match self.segments.iter().find(|s| s.records > 0) {
+            None => return Ok(None),
+            Some(segment) => segment,
+        };
+        let offset = if segment.id == self.segments[0].id {
+            self.read_offset
+        } else {
+            0
+        };
+        let mut file = File::open(self.segment_path(segment.id))?;
+        let left = file.metadata()?.len().saturating_sub(offset);
+        file.seek(SeekFrom::Start(offset))?;
+        match read_record(&mut file, left)? {
+            Some(payload) => Ok(Some(payload)),
+            None => Err("Spool record went missing".into()),
+        }
+    }
+
+    fn pop(&mut self) -> Result<(), Box<dyn Error>> {
+        // Empty segments at the front only happen for the one being written
+        while self.segments.len() > 1 && self.segments[0].records == 0 {
+            let segment = self.segments.pop_front().unwrap();
+            fs::remove_file(self.segment_path(segment.id))?;
+            self.read_offset = 0;
+        }
+        let payload = match self.peek()? {
+            None => return Ok(()),
+            Some(payload) => payload,
+        };
+        self.read_offset += RECORD_HEADER + payload.len() as u64;
+        self.segments[0].records -= 1;
+        if self.segments[0].records == 0 && self.segments.len() > 1 {
+            let segment = self.segments.pop_front().unwrap();
+            fs::remove_file(self.segment_path(segment.id))?;
+            self.read_offset = 0;
+        }
+        if self.size_bytes() <= self.config.alert_bytes {
+            self.alerted = false;
+        }
+        self.write_cursor()
+    }
+
+    fn len(&self) -> usize {
+        self.segments.iter().map(|s| s.records).sum()
+    }
+}
+
+#[test]
+fn test_disk_spool_survives_restart() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = DiskSpoolConfig {
+        dir: dir.path().to_path_buf(),
+        segment_bytes: 20, // Two records per segment
+        alert_bytes: 1024,
+    };
+
+    let mut spool = DiskSpool::open(config.clone()).unwrap();
+    for payload in [b"one", b"two", b"six", b"ten", b"end"].iter() {
+        spool.push(*payload).unwrap();
+    }
+    assert_eq!(spool.len(), 5);
+    assert_eq!(spool.peek().unwrap(), Some(b"one".to_vec()));
+    spool.pop().unwrap();
+    spool.pop().unwrap();
+    spool.pop().unwrap();
+    drop(spool);
+
+    /* Restart: only what wasn't delivered comes back, in order */
+    let mut spool = DiskSpool::open(config.clone()).unwrap();
+    assert_eq!(spool.len(), 2);
+    assert_eq!(spool.peek().unwrap(), Some(b"ten".to_vec()));
+    spool.pop().unwrap();
+    spool.push(b"new").unwrap();
+    drop(spool);
+
+    /* A torn write at the end gets dropped */
+    let last = dir.path().join("spool-0000000002.seg");
+    let mut file = OpenOptions::new().append(true).open(last).unwrap();
+    file.write_all(&[42, 0, 0, 0, 1, 2]).unwrap();
+    drop(file);
+    let mut spool = DiskSpool::open(config).unwrap();
+    assert_eq!(spool.len(), 2);
+    assert_eq!(spool.peek().unwrap(), Some(b"end".to_vec()));
+    spool.pop().unwrap();
+    assert_eq!(spool.peek().unwrap(), Some(b"new".to_vec()));
+    spool.pop().unwrap();
+    assert!(spool.is_empty());
+    drop(spool);
+
+    /* A corrupted record in the middle is skipped, not what follows it */
+    let dir = tempfile::tempdir().unwrap();
+    let config = DiskSpoolConfig {
+        dir: dir.path().to_path_buf(),
+        segment_bytes: 1024,
+        alert_bytes: 1024,
+    };
+    let mut spool = DiskSpool::open(config.clone()).unwrap();
+    spool.push(b"aaaaaaaaaaaaaaaaaaaa").unwrap();
+    let second = spool.size_bytes() as usize + RECORD_HEADER as usize;
+    spool.push(b"bbb").unwrap();
+    spool.push(b"ccc").unwrap();
+    drop(spool);
+    let first = dir.path().join("spool-0000000000.seg");
+    let mut bytes = fs::read(&first).unwrap();
+    bytes[second] ^= 0xff;
+    fs::write(&first, bytes).unwrap();
+    let mut spool = DiskSpool::open(config).unwrap();
+    assert_eq!(spool.len(), 2);
+    spool.pop().unwrap();
+    assert_eq!(spool.peek().unwrap(), Some(b"ccc".to_vec()));
+}
+
+#[test]
+fn test_read_record_with_corrupt_length() {
+    let mut record = Vec::new();
+    record.extend_from_slice(&u32::MAX.to_le_bytes());
+    record.extend_from_slice(&crc32fast::hash(b"abc").to_le_bytes());
+    record.extend_from_slice(b"abc");
+    let left = record.len() as u64;
+    assert_eq!(read_record(&mut record.as_slice(), left).unwrap(), None);
+}
@@ -0,0 +1,88 @@
+use crate::spool::sync_dir;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+/// Compact binary encodings used to move engine state and account snapshots
+/// between hosts.
+#[derive(Debug, Default, PartialEq, Copy, Clone)]
+pub enum StateFormat {
+    #[default]
+    MessagePack,
+    Cbor,
+}
+
+impl FromStr for StateFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<StateFormat, String> {
+        match s {
+            "msgpack" => Ok(StateFormat::MessagePack),
+            "cbor" => Ok(StateFormat::Cbor),
+            _ => Err(format!("Unknown state format: {}", s)),
+        }
+    }
+}
+
+pub fn write<W: Write, T: Serialize>(
+    mut writer: W,
+    format: StateFormat,
+    value: &T,
+) -> Result<(), Box<dyn Error>> {
+    match format {
+        // Named fields so snapshots survive fields being added or reordered
+        StateFormat::MessagePack => rmp_serde::encode::write_named(&mut writer, value)?,
+        StateFormat::Cbor => ciborium::ser::into_writer(value, &mut writer)?,
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Writes to a temporary file next to path and renames it over path, so
+/// whatever was there survives if we die halfway. The file is on disk before
+/// the rename, and the rename before we return, so a crash can't leave an
+/// empty file behind either.
+pub fn write_atomically<F>(path: &Path, write: F) -> Result<(), Box<dyn Error>>
+where
+    F: FnOnce(&mut BufWriter<File>) -> Result<(), Box<dyn Error>>,
+{
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    let result = File::create(&temporary)
+        .map_err(|e| e.into())
+        .and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            write(&mut writer)?;
+            writer.flush()?;
+            writer.get_ref().sync_all()?;
+            Ok(())
+        })
+        .and_then(|_| Ok(fs::rename(&temporary, path)?))
+        .and_then(|_| {
+            let dir = match path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            };
+            Ok(sync_dir(dir)?)
+        });
+    if result.is_err() {
+        let _ = fs::remove_file(&temporary);
+    }
+    result
+}
+
+pub fn read<R: Read, T: DeserializeOwned>(
+    reader: R,
+    format: StateFormat,
+) -> Result<T, Box<dyn Error>> {
+    Ok(match format {
+        StateFormat::MessagePack => rmp_serde::decode::from_read(reader)?,
+        StateFormat::Cbor => ciborium::de::from_reader(reader)?,
+    })
+}
+
+deserialize_from_str!(StateFormat);
@@ -0,0 +1,191 @@
+use crate::transaction::{TransactionType, TxOutcome};
+use serde::Serialize;
+use std::error::Error;
+use std::io::{self, Write};
+use std::time::Duration;
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct TypeCounts {
+    pub applied: u64,
+    /// Withdrawals without enough funds
+    pub declined: u64,
+    /// Valid rows that had no effect: disputes of unknown transactions...
+    pub ignored: u64,
+    /// Rows the engine couldn't process at all
+    pub failed: u64,
+}
+
+impl TypeCounts {
+    fn merge(&mut self, other: &TypeCounts) {
+        self.applied += other.applied;
+        self.declined += other.declined;
+        self.ignored += other.ignored;
+        self.failed += other.failed;
+    }
+
+    fn total(&self) -> u64 {
+        self.applied + self.declined + self.ignored + self.failed
+    }
+}
+
+/// Counters kept by the engine as it goes.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct Stats {
+    pub deposit: TypeCounts,
+    pub withdrawal: TypeCounts,
+    pub dispute: TypeCounts,
+    pub resolve: TypeCounts,
+    pub chargeback: TypeCounts,
+    pub hold: TypeCounts,
+    pub capture: TypeCounts,
+    pub release: TypeCounts,
+    /// Deposits and withdrawals skipped (and counted as ignored) because they
+    /// had already been processed, in idempotent mode
+    pub replayed: u64,
+    /// Transactions rejected (and counted as ignored) by the fraud limits
+    pub limit_violations: u64,
+    /// Transactions undone (and counted under their type as well) with the
+    /// atomic batch they were in
+    pub rolled_back: u64,
+    pub rolled_back_batches: u64,
+    /// Tx ids undone by an operator
+    pub undone: u64,
+}
+
+impl Stats {
+    fn of(&mut self, tx_type: TransactionType) -> &mut TypeCounts {
+        match tx_type {
+            TransactionType::Deposit => &mut self.deposit,
+            TransactionType::Withdrawal => &mut self.withdrawal,
+            TransactionType::Dispute => &mut self.dispute,
+            TransactionType::Resolve => &mut self.resolve,
+            TransactionType::Chargeback => &mut self.chargeback,
+            TransactionType::Hold => &mut self.hold,
+            TransactionType::Capture => &mut self.capture,
+            TransactionType::Release => &mut self.release,
+        }
+    }
+
+    pub(crate) fn count(
+        &mut self,
+        tx_type: TransactionType,
+        result: &Result<TxOutcome, Box<dyn Error>>,
+    ) {
+        match result {
+            Ok(TxOutcome::SkippedReplayed) => self.replayed += 1,
+            Ok(TxOutcome::RejectedOverLimit | TxOutcome::RejectedVelocity) => {
+                self.limit_violations += 1
+            }
+            _ => {}
+        }
+        let counts = self.of(tx_type);
+        match result {
+            Ok(TxOutcome::Applied) => counts.applied += 1,
+            Ok(TxOutcome::DeclinedInsufficientFunds) => counts.declined += 1,
+            Ok(_) => counts.ignored += 1,
+            Err(_) => counts.failed += 1,
+        }
+    }
+
+    pub(crate) fn merge(&mut self, other: &Stats) {
+        self.deposit.merge(&other.deposit);
+        self.withdrawal.merge(&other.withdrawal);
+        self.dispute.merge(&other.dispute);
+        self.resolve.merge(&other.resolve);
+        self.chargeback.merge(&other.chargeback);
+        self.hold.merge(&other.hold);
+        self.capture.merge(&other.capture);
+        self.release.merge(&other.release);
+        self.replayed += other.replayed;
+        self.limit_violations += other.limit_violations;
+        self.rolled_back += other.rolled_back;
+        self.rolled_back_batches += other.rolled_back_batches;
+        self.undone += other.undone;
+    }
+
+    pub fn total(&self) -> u64 {
+        self.deposit.total()
+            + self.withdrawal.total()
+            + self.dispute.total()
+            + self.resolve.total()
+            + self.chargeback.total()
+            + self.hold.total()
+            + self.capture.total()
+            + self.release.total()
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Disputes {
+    pub opened: u64,
+    pub resolved: u64,
+    pub charged_back: u64,
+}
+
+/// End of run summary, for a quick sanity check of a run.
+#[derive(Debug, Serialize)]
+pub struct RunSummary {
+    pub transactions: Stats,
+    pub disputes: Disputes,
+    pub accounts: u64,
+    pub locked_accounts: u64,
+    pub seconds: f64,
+    pub transactions_per_second: f64,
+}
+
+impl RunSummary {
+    pub fn new(stats: &Stats, accounts: (u64, u64), elapsed: Duration) -> RunSummary {
+        let seconds = elapsed.as_secs_f64();
+        RunSummary {
+            transactions: stats.clone(),
+            disputes: Disputes {
+                opened: stats.dispute.applied,
+                resolved: stats.resolve.applied,
+                charged_back: stats.chargeback.applied,
+            },
+            accounts: accounts.0,
+            locked_accounts: accounts.1,
+            seconds,
+            transactions_per_second: if seconds > 0.0 {
+                stats.total() as f64 / seconds
+            } else {
+                0.0
+            },
+        }
+    }
+
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writeln!(writer)?;
+        writer.flush()
+    }
+}
+
+#[test]
+fn test_stats() {
+    let mut engine = crate::PaymentEngine::new();
+    engine
+        .import_csv("test_files/a_bit_of_everything.csv")
+        .unwrap();
+    let stats = engine.stats();
+    assert_eq!(stats.total(), 20);
+    assert_eq!(
+        stats.withdrawal,
+        TypeCounts {
+            applied: 1,
+            declined: 1,
+            ignored: 0,
+            failed: 0
+        }
+    );
+    assert_eq!(stats.dispute.applied, 4);
+    assert_eq!(stats.dispute.ignored, 1);
+    let summary = RunSummary::new(
+        stats,
+        engine.count_accounts().unwrap(),
+        Duration::from_secs(2),
+    );
+    assert_eq!(summary.disputes.charged_back, 2);
+    assert_eq!(summary.locked_accounts, 2);
+    assert_eq!(summary.transactions_per_second, 10.0);
+}
@@ -0,0 +1,706 @@
+use crate::engine::Account;
+use crate::transaction::*;
+use rust_decimal::Decimal;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+/// Where the engine keeps the client accounts. There's at most 65536 of them
+/// so memory is rarely the problem; this is for sharing them with other
+/// systems (a database, say).
+pub trait AccountStore: Send {
+    fn get(&self, client_id: ClientId) -> io::Result<Option<Account>>;
+    /// The stored account, to be changed in place: processing a transaction
+    /// of a known client is then a single lookup instead of a get and a put.
+    fn get_mut(&mut self, client_id: ClientId) -> io::Result<Option<&mut Account>>;
+    /// Inserts the account or replaces the one with the same client id
+    fn put(&mut self, account: Account) -> io::Result<()>;
+    fn remove(&mut self, client_id: ClientId) -> io::Result<Option<Account>>;
+    fn len(&self) -> usize;
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// All accounts, in no particular order
+    fn iter(&self) -> Box<dyn Iterator<Item = io::Result<Account>> + '_>;
+    /// An empty store of the same kind, for the `shard`th worker in parallel mode
+    fn new_shard(&self, shard: usize) -> io::Result<Box<dyn AccountStore>>;
+}
+
+#[derive(Default)]
+pub struct MemoryAccountStore {
+    accounts: HashMap<ClientId, Account>,
+}
+
+impl AccountStore for MemoryAccountStore {
+    fn get(&self, client_id: ClientId) -> io::Result<Option<Account>> {
+        Ok(self.accounts.get(&client_id).cloned())
+    }
+
+    fn get_mut(&mut self, client_id: ClientId) -> io::Result<Option<&mut Account>> {
+        Ok(self.accounts.get_mut(&client_id))
+    }
+
+    fn put(&mut self, account: Account) -> io::Result<()> {
+        self.accounts.insert(account.client_id, account);
+        Ok(())
+    }
+
+    fn remove(&mut self, client_id: ClientId) -> io::Result<Option<Account>> {
+        Ok(self.accounts.remove(&client_id))
+    }
+
+    fn len(&self) -> usize {
+        self.accounts.len()
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = io::Result<Account>> + '_> {
+        Box::new(self.accounts.values().cloned().map(Ok))
+    }
+
+    fn new_shard(&self, _shard: usize) -> io::Result<Box<dyn AccountStore>> {
+        Ok(Box::new(MemoryAccountStore::default()))
+    }
+}
+
+/// Where the engine keeps the transactions that can still be disputed.
+pub trait TransactionStore: Send {
+    fn contains(&self, tx_id: TransactionId) -> io::Result<bool>;
+    fn get(&self, tx_id: TransactionId) -> io::Result<Option<Transaction>>;
+    fn insert(&mut self, transaction: Transaction) -> io::Result<()>;
+    /// Inserts the transaction unless its tx id is already stored, and says
+    /// whether it did. The in-memory stores do it in one lookup.
+    fn insert_new(&mut self, transaction: Transaction) -> io::Result<bool> {
+        if self.contains(transaction.tx_id)? {
+            return Ok(false);
+        }
+        self.insert(transaction)?;
+        Ok(true)
+    }
+    fn remove(&mut self, tx_id: TransactionId) -> io::Result<()>;
+    fn set_status(&mut self, tx_id: TransactionId, status: TransactionStatus) -> io::Result<()>;
+    fn len(&self) -> usize;
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// All stored transactions, in no particular order
+    fn iter(&self) -> Box<dyn Iterator<Item = io::Result<Transaction>> + '_>;
+    /// An empty store of the same kind, for the `shard`th worker in parallel mode
+    fn new_shard(&self, shard: usize) -> io::Result<Box<dyn TransactionStore>>;
+}
+
+#[derive(Default)]
+pub struct MemoryTransactionStore {
+    transactions: HashMap<TransactionId, Transaction>,
+}
+
+impl TransactionStore for MemoryTransactionStore {
+    fn contains(&self, tx_id: TransactionId) -> io::Result<bool> {
+        Ok(self.transactions.contains_key(&tx_id))
+    }
+
+    fn get(&self, tx_id: TransactionId) -> io::Result<Option<Transaction>> {
+        Ok(self.transactions.get(&tx_id).cloned())
+    }
+
+    fn insert(&mut self, transaction: Transaction) -> io::Result<()> {
+        self.transactions.insert(transaction.tx_id, transaction);
+        Ok(())
+    }
+
+    fn insert_new(&mut self, transaction: Transaction) -> io::Result<bool> {
+        Ok(match self.transactions.entry(transaction.tx_id) {
+            Entry::Occupied(_) => false,
+            Entry::Vacant(slot) => {
+                slot.insert(transaction);
+                true
+            }
+        })
+    }
+
+    fn remove(&mut self, tx_id: TransactionId) -> io::Result<()> {
+        self.transactions.remove(&tx_id);
+        Ok(())
+    }
+
+    fn set_status(&mut self, tx_id: TransactionId, status: TransactionStatus) -> io::Result<()> {
+        if let Some(transaction) = self.transactions.get_mut(&tx_id) {
+            transaction.status = status;
+        }
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        self.transactions.len()
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = io::Result<Transaction>> + '_> {
+        Box::new(self.transactions.values().cloned().map(Ok))
+    }
+
+    fn new_shard(&self, _shard: usize) -> io::Result<Box<dyn TransactionStore>> {
+        Ok(Box::new(MemoryTransactionStore::default()))
+    }
+}
+
+/*
+A Transaction takes 64 bytes, 68 with its key, and most of it is the
+Option<Decimal>, the description and the category. Packed, a transaction is
+12 bytes:
+  client id
+  kind      type (bits 0..3), has an amount (bit 3), the amount's scale (4..8)
+  status
+  amount    the Decimal's mantissa as an i64, in two halves so the struct
+            only needs 4 byte alignment
+Amounts with a bigger mantissa (more than 18 digits) or scale than that, and
+transactions with a description or a category, are kept whole on the side.
+The tx id is the key.
+*/
+#[derive(Debug, Clone, Copy)]
+struct Packed {
+    client_id: ClientId,
+    kind: u8,
+    status: u8,
+    mantissa: [u32; 2],
+}
+
+impl Packed {
+    fn pack(transaction: &Transaction) -> Option<Packed> {
+        if transaction.description.is_some() || transaction.category.is_some() {
+            return None;
+        }
+        let mut kind = encode_type(transaction.tx_type);
+        let mut mantissa = 0i64;
+        if let Some(amount) = transaction.amount {
+            if amount.scale() > 15 {
+                return None;
+            }
+            mantissa = i64::try_from(amount.mantissa()).ok()?;
+            kind |= 1 << 3 | (amount.scale() as u8) << 4;
+        }
+        Some(Packed {
+            client_id: transaction.client_id,
+            kind,
+            status: encode_status(transaction.status),
+            mantissa: [mantissa as u32, (mantissa >> 32) as u32],
+        })
+    }
+
+    fn unpack(&self, tx_id: TransactionId) -> Transaction {
+        let mantissa = i64::from(self.mantissa[0]) | i64::from(self.mantissa[1]) << 32;
+        Transaction {
+            // Only ever built by pack
+            tx_type: decode_type(self.kind & 0b111).unwrap_or(TransactionType::Deposit),
+            client_id: self.client_id,
+            tx_id,
+            amount: match self.kind & 1 << 3 {
+                0 => None,
+                _ => Some(Decimal::new(mantissa, u32::from(self.kind >> 4))),
+            },
+            status: decode_status(self.status).unwrap_or(TransactionStatus::OK),
+            description: None,
+            category: None,
+        }
+    }
+}
+
+/// In memory like MemoryTransactionStore, in about half the memory, for
+/// inputs with many deposits. Slightly slower, as every lookup unpacks.
+#[derive(Default)]
+pub struct CompactTransactionStore {
+    packed: HashMap<TransactionId, Packed>,
+    // The ones that don't fit
+    whole: HashMap<TransactionId, Transaction>,
+}
+
+impl TransactionStore for CompactTransactionStore {
+    fn contains(&self, tx_id: TransactionId) -> io::Result<bool> {
+        Ok(self.packed.contains_key(&tx_id) || self.whole.contains_key(&tx_id))
+    }
+
+    fn get(&self, tx_id: TransactionId) -> io::Result<Option<Transaction>> {
+        Ok(match self.packed.get(&tx_id) {
+            Some(packed) => Some(packed.unpack(tx_id)),
+            None => self.whole.get(&tx_id).cloned(),
+        })
+    }
+
+    fn insert_new(&mut self, transaction: Transaction) -> io::Result<bool> {
+        if self.whole.contains_key(&transaction.tx_id) {
+            return Ok(false);
+        }
+        Ok(match Packed::pack(&transaction) {
+            Some(packed) => match self.packed.entry(transaction.tx_id) {
+                Entry::Occupied(_) => false,
+                Entry::Vacant(slot) => {
+                    slot.insert(packed);
+                    true
+                }
+            },
+            None if self.packed.contains_key(&transaction.tx_id) => false,
+            None => {
+                self.whole.insert(transaction.tx_id, transaction);
+                true
+            }
+        })
+    }
+
+    fn insert(&mut self, transaction: Transaction) -> io::Result<()> {
+        match Packed::pack(&transaction) {
+            Some(packed) => {
+                self.whole.remove(&transaction.tx_id);
+                self.packed.insert(transaction.tx_id, packed);
+            }
+            None => {
+                self.packed.remove(&transaction.tx_id);
+                self.whole.insert(transaction.tx_id, transaction);
+            }
+        }
+        Ok(())
+    }
+
+    fn remove(&mut self, tx_id: TransactionId) -> io::Result<()> {
+        self.packed.remove(&tx_id);
+        self.whole.remove(&tx_id);
+        Ok(())
+    }
+
+    fn set_status(&mut self, tx_id: TransactionId, status: TransactionStatus) -> io::Result<()> {
+        if let Some(packed) = self.packed.get_mut(&tx_id) {
+            packed.status = encode_status(status);
+        } else if let Some(transaction) = self.whole.get_mut(&tx_id) {
+            transaction.status = status;
+        }
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        self.packed.len() + self.whole.len()
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = io::Result<Transaction>> + '_> {
+        Box::new(
+            self.packed
+                .iter()
+                .map(|(tx_id, packed)| packed.unpack(*tx_id))
+                .chain(self.whole.values().cloned())
+                .map(Ok),
+        )
+    }
+
+    fn new_shard(&self, _shard: usize) -> io::Result<Box<dyn TransactionStore>> {
+        Ok(Box::new(CompactTransactionStore::default()))
+    }
+}
+
+/*
+Fixed size record per tx id, at offset tx_id * RECORD_SIZE:
+  0     1 if there's a transaction stored here
+  1     status
+  2     type
+  3     1 if there's an amount
+  4..6  client id (LE)
+  6..8  unused
+  8..24 amount (Decimal::serialize)
+  24..32 where the description and category are in the text file, plus one
+        (LE), 0 if there's neither
+The file is sparse so only the pages we actually touch use disk space, and
+the memory used doesn't depend on the number of transactions. Descriptions
+and categories don't fit in a fixed size record, they're appended to a second
+file next to it (the path with .text added), each as its length plus one
+(u32 LE, 0 if there's none) and its bytes. What a removed transaction had
+there stays until the store is dropped.
+*/
+const RECORD_SIZE: u64 = 32;
+
+/// On-disk index keyed by tx id, for inputs too big to keep every deposit in
+/// memory. Lookups are a seek and a read, so it's a lot slower than the
+/// in-memory store; the OS page cache does the caching.
+pub struct DiskTransactionStore {
+    path: PathBuf,
+    file: File,
+    text: File,
+    text_len: u64,
+    len: usize,
+    max_tx_id: Option<TransactionId>, // So iterating doesn't scan the whole 4G id space
+    temporary: bool,
+}
+
+impl DiskTransactionStore {
+    /// Creates (or truncates) the index at `path`, and the file for the
+    /// descriptions and categories next to it.
+    pub fn create(path: PathBuf) -> io::Result<DiskTransactionStore> {
+        let open = |path: &Path| {
+            OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(path)
+        };
+        let file = open(&path)?;
+        let text = open(&text_path(&path))?;
+        debug!("Transaction index at {:?}", path);
+        Ok(DiskTransactionStore {
+            path,
+            file,
+            text,
+            text_len: 0,
+            len: 0,
+            max_tx_id: None,
+            temporary: false,
+        })
+    }
+
+    fn read_record(&self, tx_id: TransactionId) -> io::Result<[u8; RECORD_SIZE as usize]> {
+        let mut record = [0u8; RECORD_SIZE as usize];
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(u64::from(tx_id) * RECORD_SIZE))?;
+        // Past the end of the file is the same as a hole: nothing stored
+        let mut read = 0;
+        while read < record.len() {
+            match file.read(&mut record[read..])? {
+                0 => break,
+                n => read += n,
+            }
+        }
+        Ok(record)
+    }
+
+    fn write_at(&mut self, tx_id: TransactionId, offset: u64, bytes: &[u8]) -> io::Result<()> {
+        self.file
+            .seek(SeekFrom::Start(u64::from(tx_id) * RECORD_SIZE + offset))?;
+        self.file.write_all(bytes)
+    }
+
+    // Where they went, plus one, or 0 if there's neither
+    fn append_text(&mut self, transaction: &Transaction) -> io::Result<u64> {
+        if transaction.description.is_none() && transaction.category.is_none() {
+            return Ok(0);
+        }
+        let mut bytes = Vec::new();
+        for text in [&transaction.description, &transaction.category] {
+            match text {
+                None => bytes.extend_from_slice(&0u32.to_le_bytes()),
+                Some(text) => {
+                    let length = u32::try_from(text.len() + 1).map_err(|_| {
+                        io::Error::new(io::ErrorKind::InvalidInput, "Text too long to store")
+                    })?;
+                    bytes.extend_from_slice(&length.to_le_bytes());
+                    bytes.extend_from_slice(text.as_bytes());
+                }
+            }
+        }
+        let offset = self.text_len;
+        self.text.seek(SeekFrom::Start(offset))?;
+        self.text.write_all(&bytes)?;
+        self.text_len += bytes.len() as u64;
+        Ok(offset + 1)
+    }
+
+    fn read_text(&self, transaction: &mut Transaction, offset: u64) -> io::Result<()> {
+        let mut file = &self.text;
+        file.seek(SeekFrom::Start(offset - 1))?;
+        let mut read = || -> io::Result<Option<Box<str>>> {
+            let mut length = [0u8; 4];
+            file.read_exact(&mut length)?;
+            let length = u32::from_le_bytes(length) as u64;
+            if length == 0 {
+                return Ok(None);
+            }
+            // A corrupt length can't be trusted with the allocation
+            if length - 1 > self.text_len {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Corrupted transaction index",
+                ));
+            }
+            let mut text = vec![0u8; (length - 1) as usize];
+            file.read_exact(&mut text)?;
+            String::from_utf8(text)
+                .map(|text| Some(text.into_boxed_str()))
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        };
+        transaction.description = read()?;
+        transaction.category = read()?;
+        Ok(())
+    }
+}
+
+fn text_path(path: &Path) -> PathBuf {
+    let mut path = path.to_path_buf().into_os_string();
+    path.push(".text");
+    PathBuf::from(path)
+}
+
+impl Drop for DiskTransactionStore {
+    fn drop(&mut self) {
+        if self.temporary {
+            let _ = fs::remove_file(&self.path);
+            let _ = fs::remove_file(text_path(&self.path));
+        }
+    }
+}
+
+fn encode_status(status: TransactionStatus) -> u8 {
+    match status {
+        TransactionStatus::OK => 0,
+        TransactionStatus::Disputed => 1,
+        TransactionStatus::Chargedback => 2,
+        TransactionStatus::Captured => 3,
+        TransactionStatus::Released => 4,
+    }
+}
+
+fn decode_status(status: u8) -> Option<TransactionStatus> {
+    match status {
+        0 => Some(TransactionStatus::OK),
+        1 => Some(TransactionStatus::Disputed),
+        2 => Some(TransactionStatus::Chargedback),
+        3 => Some(TransactionStatus::Captured),
+        4 => Some(TransactionStatus::Released),
+        _ => None,
+    }
+}
+
+pub(crate) fn encode_type(tx_type: TransactionType) -> u8 {
+    match tx_type {
+        TransactionType::Deposit => 0,
+        TransactionType::Withdrawal => 1,
+        TransactionType::Dispute => 2,
+        TransactionType::Resolve => 3,
+        TransactionType::Chargeback => 4,
+        TransactionType::Hold => 5,
+        TransactionType::Capture => 6,
+        TransactionType::Release => 7,
+    }
+}
+
+pub(crate) fn decode_type(tx_type: u8) -> Option<TransactionType> {
+    match tx_type {
+        0 => Some(TransactionType::Deposit),
+        1 => Some(TransactionType::Withdrawal),
+        2 => Some(TransactionType::Dispute),
+        3 => Some(TransactionType::Resolve),
+        4 => Some(TransactionType::Chargeback),
+        5 => Some(TransactionType::Hold),
+        6 => Some(TransactionType::Capture),
+        7 => Some(TransactionType::Release),
+        _ => None,
+    }
+}
+
+fn encode(transaction: &Transaction, text: u64) -> [u8; RECORD_SIZE as usize] {
+    let mut record = [0u8; RECORD_SIZE as usize];
+    record[0] = 1;
+    record[1] = encode_status(transaction.status);
+    record[2] = encode_type(transaction.tx_type);
+    record[4..6].copy_from_slice(&transaction.client_id.to_le_bytes());
+    if let Some(amount) = transaction.amount {
+        record[3] = 1;
+        record[8..24].copy_from_slice(&amount.serialize());
+    }
+    record[24..32].copy_from_slice(&text.to_le_bytes());
+    record
+}
+
+fn decode(
+    tx_id: TransactionId,
+    record: &[u8; RECORD_SIZE as usize],
+) -> io::Result<Option<Transaction>> {
+    if record[0] == 0 {
+        return Ok(None);
+    }
+    let corrupted = || io::Error::new(io::ErrorKind::InvalidData, "Corrupted transaction index");
+    let status = decode_status(record[1]).ok_or_else(corrupted)?;
+    let tx_type = decode_type(record[2]).ok_or_else(corrupted)?;
+    let mut amount = [0u8; 16];
+    amount.copy_from_slice(&record[8..24]);
+    Ok(Some(Transaction {
+        tx_type,
+        client_id: ClientId::from_le_bytes([record[4], record[5]]),
+        tx_id,
+        amount: if record[3] == 1 {
+            Some(Decimal::deserialize(amount))
+        } else {
+            None
+        },
+        status,
+        description: None,
+        category: None,
+    }))
+}
+
+impl TransactionStore for DiskTransactionStore {
+    fn contains(&self, tx_id: TransactionId) -> io::Result<bool> {
+        Ok(self.read_record(tx_id)?[0] == 1)
+    }
+
+    fn get(&self, tx_id: TransactionId) -> io::Result<Option<Transaction>> {
+        let record = self.read_record(tx_id)?;
+        let mut transaction = decode(tx_id, &record)?;
+        let mut text = [0u8; 8];
+        text.copy_from_slice(&record[24..32]);
+        match (&mut transaction, u64::from_le_bytes(text)) {
+            (Some(transaction), offset) if offset > 0 => self.read_text(transaction, offset)?,
+            _ => {}
+        }
+        Ok(transaction)
+    }
+
+    fn insert(&mut self, transaction: Transaction) -> io::Result<()> {
+        if !self.contains(transaction.tx_id)? {
+            self.len += 1;
+        }
+        self.max_tx_id = self.max_tx_id.max(Some(transaction.tx_id));
+        let text = self.append_text(&transaction)?;
+        self.write_at(transaction.tx_id, 0, &encode(&transaction, text))
+    }
+
+    fn remove(&mut self, tx_id: TransactionId) -> io::Result<()> {
+        if self.contains(tx_id)? {
+            self.len -= 1;
+            self.write_at(tx_id, 0, &[0u8; RECORD_SIZE as usize])?;
+        }
+        Ok(())
+    }
+
+    fn set_status(&mut self, tx_id: TransactionId, status: TransactionStatus) -> io::Result<()> {
+        if self.contains(tx_id)? {
+            self.write_at(tx_id, 1, &[encode_status(status)])?;
+        }
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = io::Result<Transaction>> + '_> {
+        let end = self.max_tx_id.map(|max| u64::from(max) + 1).unwrap_or(0);
+        Box::new(
+            (0..end).filter_map(move |tx_id| match self.get(tx_id as TransactionId) {
+                Ok(None) => None,
+                Ok(Some(transaction)) => Some(Ok(transaction)),
+                Err(e) => Some(Err(e)),
+            }),
+        )
+    }
+
+    fn new_shard(&self, shard: usize) -> io::Result<Box<dyn TransactionStore>> {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".shard{}", shard));
+        let mut store = DiskTransactionStore::create(PathBuf::from(path))?;
+        store.temporary = true;
+        Ok(Box::new(store))
+    }
+}
+
+#[test]
+fn test_disk_transaction_store() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut store = DiskTransactionStore::create(dir.path().join("txs")).unwrap();
+    let deposit = |tx_id, amount: &str| Transaction {
+        tx_type: TransactionType::Deposit,
+        client_id: 513,
+        tx_id,
+        amount: Some(amount.parse().unwrap()),
+        status: TransactionStatus::OK,
+        description: None,
+        category: None,
+    };
+    store.insert(deposit(7, "1.2345")).unwrap();
+    store.insert(deposit(1_000_000, "-3")).unwrap();
+    assert_eq!(store.len(), 2);
+    assert!(!store.contains(8).unwrap());
+    assert_eq!(store.get(u32::MAX).unwrap(), None);
+    assert_eq!(store.get(7).unwrap(), Some(deposit(7, "1.2345")));
+
+    store.set_status(7, TransactionStatus::Disputed).unwrap();
+    assert_eq!(
+        store.get(7).unwrap().unwrap().status,
+        TransactionStatus::Disputed
+    );
+
+    let described = Transaction {
+        description: Some("Refund, order 7".into()),
+        category: Some("refund".into()),
+        ..deposit(9, "5")
+    };
+    let categorized = Transaction {
+        category: Some("payroll".into()),
+        ..deposit(10, "1")
+    };
+    store.insert(described.clone()).unwrap();
+    store.insert(categorized.clone()).unwrap();
+    store.set_status(9, TransactionStatus::Disputed).unwrap();
+    assert_eq!(
+        store.get(9).unwrap(),
+        Some(Transaction {
+            status: TransactionStatus::Disputed,
+            ..described
+        })
+    );
+    assert_eq!(store.get(10).unwrap(), Some(categorized));
+
+    store.remove(1_000_000).unwrap();
+    store.remove(9).unwrap();
+    store.remove(10).unwrap();
+    let all: Vec<Transaction> = store.iter().map(|t| t.unwrap()).collect();
+    assert_eq!(all.len(), 1);
+    assert_eq!(all[0].tx_id, 7);
+}
+
+#[test]
+fn test_compact_transaction_store() {
+    assert_eq!(std::mem::size_of::<Packed>(), 12);
+    let mut store = CompactTransactionStore::default();
+    let transaction = |tx_id, amount: Option<&str>| Transaction {
+        tx_type: TransactionType::Withdrawal,
+        client_id: 65535,
+        tx_id,
+        amount: amount.map(|amount| amount.parse().unwrap()),
+        status: TransactionStatus::OK,
+        description: None,
+        category: None,
+    };
+    let all = [
+        transaction(1, Some("1.5")),
+        transaction(2, Some("-922337203685477.5807")),
+        transaction(3, None),
+        // Too many digits to pack
+        transaction(4, Some("79228162514264337593543950335")),
+        transaction(5, Some("0.0000000000000000000000000001")),
+    ];
+    for transaction in all.iter() {
+        store.insert(transaction.clone()).unwrap();
+    }
+    assert_eq!((store.packed.len(), store.whole.len()), (3, 2));
+    for transaction in all.iter() {
+        let stored = store.get(transaction.tx_id).unwrap().unwrap();
+        assert_eq!(stored, *transaction);
+        // Same scale too, so it's written out the same
+        assert_eq!(
+            stored.amount.map(|a| a.to_string()),
+            transaction.amount.map(|a| a.to_string())
+        );
+    }
+
+    store.set_status(1, TransactionStatus::Chargedback).unwrap();
+    store.set_status(4, TransactionStatus::Disputed).unwrap();
+    assert_eq!(
+        store.get(1).unwrap().unwrap().status,
+        TransactionStatus::Chargedback
+    );
+    assert_eq!(
+        store.get(4).unwrap().unwrap().status,
+        TransactionStatus::Disputed
+    );
+    store.remove(4).unwrap();
+    assert!(!store.contains(4).unwrap());
+    assert_eq!(store.len(), 4);
+    assert_eq!(store.iter().count(), 4);
+}
@@ -0,0 +1,225 @@
+use crate::progress::size;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+/*
+--timings: where a run spends its time, on stderr once it's done.
+
+  phase          seconds   share  allocations     allocated
+  setup            0.000    0.0%            2          96 B
+  import           1.759  100.0%           54     264.2 MiB
+    parse          0.490   27.9%
+    engine         1.269   72.1%
+  export           0.001    0.0%           17     167.7 KiB
+  hooks            0.000    0.0%            0           0 B
+  total            1.760                   73     264.4 MiB
+
+Parse is the time spent reading and parsing the sources on the engine's
+thread, and engine the rest of the import. With --threads, --parallel-parse
+or concurrent sources both happen at the same time on different threads and
+the import isn't split.
+
+The allocation columns need a build with the alloc-counter feature, whose
+binary counts every allocation with CountingAllocator.
+*/
+
+/// Allocations so far, and the bytes they asked for.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Allocations {
+    pub count: u64,
+    pub bytes: u64,
+}
+
+impl Allocations {
+    fn since(self, earlier: Allocations) -> Allocations {
+        Allocations {
+            count: self.count - earlier.count,
+            bytes: self.bytes - earlier.bytes,
+        }
+    }
+}
+
+#[cfg(feature = "alloc-counter")]
+mod counter {
+    use super::Allocations;
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    static COUNT: AtomicU64 = AtomicU64::new(0);
+    static BYTES: AtomicU64 = AtomicU64::new(0);
+
+    /// The system allocator, counting. For the binary's #[global_allocator].
+    pub struct CountingAllocator;
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            COUNT.fetch_add(1, Ordering::Relaxed);
+            BYTES.fetch_add(layout.size() as u64, Ordering::Relaxed);
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            COUNT.fetch_add(1, Ordering::Relaxed);
+            BYTES.fetch_add(new_size as u64, Ordering::Relaxed);
+            System.realloc(ptr, layout, new_size)
+        }
+    }
+
+    pub fn allocations() -> Option<Allocations> {
+        Some(Allocations {
+            count: COUNT.load(Ordering::Relaxed),
+            bytes: BYTES.load(Ordering::Relaxed),
+        })
+    }
+}
+
+#[cfg(feature = "alloc-counter")]
+pub use counter::{allocations, CountingAllocator};
+
+/// None without the alloc-counter feature.
+#[cfg(not(feature = "alloc-counter"))]
+pub fn allocations() -> Option<Allocations> {
+    None
+}
+
+#[derive(Debug, Clone)]
+pub struct Phase {
+    pub name: &'static str,
+    pub elapsed: Duration,
+    pub allocations: Option<Allocations>,
+    /// What the phase is made of, when it's known
+    pub parts: Vec<(&'static str, Duration)>,
+}
+
+#[derive(Debug)]
+pub struct Timings {
+    phases: Vec<Phase>,
+    mark: Instant,
+    mark_allocations: Option<Allocations>,
+}
+
+impl Default for Timings {
+    fn default() -> Self {
+        Timings::new()
+    }
+}
+
+impl Timings {
+    /// Starts the first phase.
+    pub fn new() -> Timings {
+        Timings {
+            phases: Vec::new(),
+            mark: Instant::now(),
+            mark_allocations: allocations(),
+        }
+    }
+
+    /// Ends the current phase, calling it `name`, and starts the next one.
+    pub fn phase(&mut self, name: &'static str) -> &mut Phase {
+        let now = allocations();
+        self.phases.push(Phase {
+            name,
+            elapsed: self.mark.elapsed(),
+            allocations: now
+                .zip(self.mark_allocations)
+                .map(|(now, mark)| now.since(mark)),
+            parts: Vec::new(),
+        });
+        self.mark = Instant::now();
+        self.mark_allocations = now;
+        self.phases.last_mut().unwrap_or_else(|| unreachable!())
+    }
+
+    pub fn phases(&self) -> &[Phase] {
+        &self.phases
+    }
+
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let total: Duration = self.phases.iter().map(|phase| phase.elapsed).sum();
+        let share = |elapsed: Duration| match total.is_zero() {
+            true => 0.0,
+            false => elapsed.as_secs_f64() / total.as_secs_f64() * 100.0,
+        };
+        writeln!(
+            writer,
+            "{:<12} {:>9} {:>7} {:>12} {:>13}",
+            "phase", "seconds", "share", "allocations", "allocated"
+        )?;
+        for phase in &self.phases {
+            write!(
+                writer,
+                "{:<12} {:>9.3} {:>6.1}%",
+                phase.name,
+                phase.elapsed.as_secs_f64(),
+                share(phase.elapsed)
+            )?;
+            write_allocations(&mut writer, phase.allocations)?;
+            for (name, elapsed) in &phase.parts {
+                writeln!(
+                    writer,
+                    "  {:<10} {:>9.3} {:>6.1}%",
+                    name,
+                    elapsed.as_secs_f64(),
+                    share(*elapsed)
+                )?;
+            }
+        }
+        write!(
+            writer,
+            "{:<12} {:>9.3} {:>7}",
+            "total",
+            total.as_secs_f64(),
+            ""
+        )?;
+        let allocated = self
+            .phases
+            .iter()
+            .map(|phase| phase.allocations)
+            .collect::<Option<Vec<Allocations>>>()
+            .map(|all| Allocations {
+                count: all.iter().map(|a| a.count).sum(),
+                bytes: all.iter().map(|a| a.bytes).sum(),
+            });
+        write_allocations(&mut writer, allocated)
+    }
+}
+
+fn write_allocations<W: Write>(writer: &mut W, allocations: Option<Allocations>) -> io::Result<()> {
+    match allocations {
+        Some(allocations) => writeln!(
+            writer,
+            " {:>12} {:>13}",
+            allocations.count,
+            size(allocations.bytes)
+        ),
+        None => writeln!(writer),
+    }
+}
+
+#[test]
+fn test_timings() {
+    let mut timings = Timings::new();
+    timings.phase("setup");
+    std::thread::sleep(Duration::from_millis(20));
+    timings.phase("import").parts = vec![
+        ("parse", Duration::from_millis(5)),
+        ("engine", Duration::from_millis(15)),
+    ];
+    timings.phase("export");
+    assert_eq!(timings.phases().len(), 3);
+    assert!(timings.phases()[1].elapsed >= Duration::from_millis(20));
+
+    let mut report = Vec::new();
+    timings.write(&mut report).unwrap();
+    let report = String::from_utf8(report).unwrap();
+    let lines: Vec<&str> = report.lines().collect();
+    assert_eq!(lines.len(), 7, "{}", report);
+    assert!(lines[0].starts_with("phase"));
+    assert!(lines[3].starts_with("  parse"));
+    assert!(lines[6].starts_with("total"));
+    assert_eq!(size(1536), "1.5 KiB");
+}
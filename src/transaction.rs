@@ -0,0 +1,379 @@
+use csv::{ByteRecord, StringRecord};
+use rust_decimal::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fmt;
+
+pub type ClientId = u16; // client column is a valid u16 client ID
+pub type TransactionId = u32; // the tx is a valid u32 transaction ID
+
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
+pub enum TransactionStatus {
+    OK,
+    Disputed,
+    Chargedback,
+    /// A hold that was turned into a withdrawal
+    Captured,
+    /// A hold whose funds went back to available
+    Released,
+}
+
+/// What processing a transaction did. Anything but Applied left the
+/// balances as they were.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum TxOutcome {
+    Applied,
+    /// A withdrawal of more than the available funds
+    DeclinedInsufficientFunds,
+    /// A dispute, resolve, chargeback, capture or release of a transaction
+    /// we don't have
+    IgnoredUnknownTx,
+    /// A dispute, resolve, chargeback, capture or release of another
+    /// client's transaction
+    IgnoredWrongClient,
+    /// A dispute of a transaction that's already disputed or charged back,
+    /// a resolve or chargeback of one that isn't disputed, a capture or
+    /// release of one that isn't a pending hold...
+    IgnoredWrongStatus,
+    /// A deposit or withdrawal with the tx id of an earlier one (v2, v1
+    /// fails on a repeated deposit)
+    IgnoredDuplicateTx,
+    /// A deposit or withdrawal on a locked account (v2)
+    RejectedLocked,
+    /// A transaction of a client without an account, that the account
+    /// creation policy doesn't let open one
+    IgnoredUnknownAccount,
+    /// A deposit or withdrawal already processed, in this run or an earlier
+    /// one (idempotent mode)
+    SkippedReplayed,
+    /// Over the single transaction or the daily withdrawal limit
+    RejectedOverLimit,
+    /// Too many transactions of the client in the velocity window
+    RejectedVelocity,
+    /// A transaction of a client on the blocklist
+    RejectedBlocked,
+    /// A hold of zero or a negative amount
+    IgnoredInvalidAmount,
+}
+
+impl TxOutcome {
+    pub fn is_applied(self) -> bool {
+        self == TxOutcome::Applied
+    }
+
+    /// applied, declined or ignored, as the servers, the audit log and the
+    /// stats group them.
+    pub fn kind(self) -> &'static str {
+        match self {
+            TxOutcome::Applied => "applied",
+            TxOutcome::DeclinedInsufficientFunds => "declined",
+            _ => "ignored",
+        }
+    }
+
+    /// Why it had no effect, None if it was applied.
+    pub fn reason(self) -> Option<&'static str> {
+        match self {
+            TxOutcome::Applied => None,
+            TxOutcome::DeclinedInsufficientFunds => Some("not enough funds"),
+            TxOutcome::IgnoredUnknownTx => Some("unknown transaction"),
+            TxOutcome::IgnoredWrongClient => Some("wrong client"),
+            TxOutcome::IgnoredWrongStatus => Some("wrong status"),
+            TxOutcome::IgnoredDuplicateTx => Some("repeated tx id"),
+            TxOutcome::RejectedLocked => Some("account is locked"),
+            TxOutcome::IgnoredUnknownAccount => Some("unknown account"),
+            TxOutcome::SkippedReplayed => Some("already processed"),
+            TxOutcome::RejectedOverLimit => Some("over the limit"),
+            TxOutcome::RejectedVelocity => Some("too many transactions"),
+            TxOutcome::RejectedBlocked => Some("blocked client"),
+            TxOutcome::IgnoredInvalidAmount => Some("invalid amount"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
+pub enum TransactionType {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+    /// Card-style authorization: moves the amount from available to held
+    /// until it's captured or released
+    Hold,
+    /// Turns the hold with the same tx id into a withdrawal
+    Capture,
+    /// Gives the funds of the hold with the same tx id back
+    Release,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Transaction {
+    pub tx_type: TransactionType,
+    pub client_id: ClientId,
+    pub tx_id: TransactionId,
+    pub amount: Option<Decimal>,
+    pub status: TransactionStatus,
+    /// Free text from the optional description column, kept only for the
+    /// humans reading the store, audit log and ledger
+    #[serde(default)]
+    pub description: Option<Box<str>>,
+    /// From the optional category column (payroll, refund...), for the
+    /// totals per category, see categories.rs
+    #[serde(default)]
+    pub category: Option<Box<str>>,
+}
+
+impl FromStr for TransactionType {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<TransactionType, &'static str> {
+        match s {
+            "deposit" => Ok(TransactionType::Deposit),
+            "withdrawal" => Ok(TransactionType::Withdrawal),
+            "dispute" => Ok(TransactionType::Dispute),
+            "resolve" => Ok(TransactionType::Resolve),
+            "chargeback" => Ok(TransactionType::Chargeback),
+            "hold" => Ok(TransactionType::Hold),
+            "capture" => Ok(TransactionType::Capture),
+            "release" => Ok(TransactionType::Release),
+            _ => Err("Unknown transaction type"),
+        }
+    }
+}
+
+impl fmt::Display for TransactionType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            TransactionType::Deposit => "deposit",
+            TransactionType::Withdrawal => "withdrawal",
+            TransactionType::Dispute => "dispute",
+            TransactionType::Resolve => "resolve",
+            TransactionType::Chargeback => "chargeback",
+            TransactionType::Hold => "hold",
+            TransactionType::Capture => "capture",
+            TransactionType::Release => "release",
+        })
+    }
+}
+
+/*
+columns type, client, tx, and amount. You can assume the type is a string, the
+client column is a valid u16 client ID, the tx is a valid u32 transaction ID, and
+the amount is a decimal value with a precision of up to four places past the decimal.
+*/
+impl TryFrom<StringRecord> for Transaction {
+    type Error = &'static str;
+
+    fn try_from(record: StringRecord) -> Result<Transaction, &'static str> {
+        let field = |column| record.get(column).map(|f| f.trim());
+        let tx_type = TransactionType::from_str(field(0).ok_or("Wrong number of columns")?)?;
+        let client_id = field(1)
+            .and_then(|id| id.parse::<ClientId>().ok())
+            .ok_or("Invalid client id")?;
+        let tx_id = field(2)
+            .and_then(|id| id.parse::<TransactionId>().ok())
+            .ok_or("Invalid transaction id")?;
+        let amount = match field(3) {
+            None | Some("") => None,
+            Some(something) => Some(Decimal::from_str(something).map_err(|_| "Invalid amount")?),
+        };
+
+        Ok(Transaction {
+            tx_type,
+            client_id,
+            tx_id,
+            amount,
+            status: TransactionStatus::OK,
+            description: field(4).filter(|d| !d.is_empty()).map(Box::from),
+            category: field(5).filter(|c| !c.is_empty()).map(Box::from),
+        })
+    }
+}
+
+// Digits only (after trimming), no sign
+fn parse_uint(field: &[u8]) -> Option<u64> {
+    let field = field.trim_ascii();
+    if field.is_empty() || field.len() > 19 {
+        return None;
+    }
+    field.iter().try_fold(0u64, |acc, c| match c {
+        b'0'..=b'9' => Some(acc * 10 + u64::from(c - b'0')),
+        _ => None,
+    })
+}
+
+// Plain [-]digits[.digits] straight into a Decimal. Anything fancier (exponents,
+// too many digits) goes through Decimal::from_str.
+fn parse_amount(field: &[u8]) -> Option<Decimal> {
+    let (negative, digits) = match field.split_first() {
+        Some((b'-', rest)) => (true, rest),
+        _ => (false, field),
+    };
+    let mut mantissa: i128 = 0;
+    let mut scale: Option<u32> = None;
+    let mut num_digits = 0;
+    for c in digits {
+        match c {
+            b'0'..=b'9' => {
+                mantissa = mantissa * 10 + i128::from(c - b'0');
+                num_digits += 1;
+                scale = scale.map(|s| s + 1);
+            }
+            b'.' if scale.is_none() => scale = Some(0),
+            _ => return None,
+        }
+    }
+    if num_digits == 0 || num_digits > 28 {
+        return None;
+    }
+    if negative {
+        mantissa = -mantissa;
+    }
+    Decimal::try_from_i128_with_scale(mantissa, scale.unwrap_or(0)).ok()
+}
+
+impl Transaction {
+    /// Same as the TryFrom<StringRecord> but straight from the raw bytes, which
+    /// saves validating UTF-8 and allocating for every row. Only the amount
+    /// is looked at as text, and only if it's not a plain decimal.
+    pub fn from_byte_record(record: &ByteRecord) -> Result<Transaction, &'static str> {
+        if !(3..=6).contains(&record.len()) {
+            return Err("Wrong number of columns"); // Amount is optional, maybe the comma also is
+        }
+        let tx_type = match record[0].trim_ascii() {
+            b"deposit" => TransactionType::Deposit,
+            b"withdrawal" => TransactionType::Withdrawal,
+            b"dispute" => TransactionType::Dispute,
+            b"resolve" => TransactionType::Resolve,
+            b"chargeback" => TransactionType::Chargeback,
+            b"hold" => TransactionType::Hold,
+            b"capture" => TransactionType::Capture,
+            b"release" => TransactionType::Release,
+            _ => return Err("Unknown transaction type"),
+        };
+        let client_id = parse_uint(&record[1])
+            .and_then(|id| ClientId::try_from(id).ok())
+            .ok_or("Invalid client id")?;
+        let tx_id = parse_uint(&record[2])
+            .and_then(|id| TransactionId::try_from(id).ok())
+            .ok_or("Invalid transaction id")?;
+        let amount = match record.get(3).map(|a| a.trim_ascii()) {
+            None | Some(b"") => None,
+            Some(something) => match parse_amount(something) {
+                Some(amount) => Some(amount),
+                None => std::str::from_utf8(something)
+                    .ok()
+                    .and_then(|a| Decimal::from_str(a).ok())
+                    .map(Some)
+                    .ok_or("Invalid amount")?,
+            },
+        };
+        // Only ever looked at as text, so they're the only columns decoded here
+        let text = |column: usize, invalid| match record.get(column).map(|t| t.trim_ascii()) {
+            None | Some(b"") => Ok(None),
+            Some(something) => std::str::from_utf8(something)
+                .map(|text| Some(text.into()))
+                .map_err(|_| invalid),
+        };
+        let description = text(4, "Invalid description")?;
+        let category = text(5, "Invalid category")?;
+
+        Ok(Transaction {
+            tx_type,
+            client_id,
+            tx_id,
+            amount,
+            status: TransactionStatus::OK,
+            description,
+            category,
+        })
+    }
+}
+
+/// Free text (a description) as a CSV field, quoted if it needs to be.
+pub(crate) fn quoted(text: &str) -> String {
+    match text.contains(['"', ',', '\n', '\r']) {
+        true => format!("\"{}\"", text.replace('"', "\"\"")),
+        false => text.to_string(),
+    }
+}
+
+#[test]
+fn test_description() {
+    let record = |fields: Vec<&str>| {
+        let from_bytes = Transaction::from_byte_record(&ByteRecord::from(fields.clone()));
+        assert_eq!(
+            from_bytes,
+            Transaction::try_from(StringRecord::from(fields))
+        );
+        from_bytes
+    };
+    let deposit = record(vec!["deposit", "1", "1", "1.0", " Top-up, card "]).unwrap();
+    assert_eq!(deposit.description.as_deref(), Some("Top-up, card"));
+    let deposit = record(vec!["deposit", "1", "1", "1.0", "", "refund"]).unwrap();
+    assert_eq!(deposit.description, None);
+    assert_eq!(deposit.category.as_deref(), Some("refund"));
+    assert_eq!(quoted("Top-up, \"card\""), "\"Top-up, \"\"card\"\"\"");
+    assert_eq!(quoted("Top-up"), "Top-up");
+}
+
+#[test]
+fn test_record_to_transaction() {
+    /* Deposits */
+    let tx_deposit =
+        Transaction::try_from(StringRecord::from(vec!["deposit", "1", "1", "1.0"])).unwrap();
+    assert_eq!(
+        tx_deposit,
+        Transaction {
+            tx_type: TransactionType::Deposit,
+            client_id: 1,
+            tx_id: 1,
+            amount: Some(Decimal::from_str("1.0").unwrap()),
+            status: TransactionStatus::OK,
+            description: None,
+            category: None,
+        }
+    );
+
+    /* Transaction inequality */
+    assert_ne!(
+        tx_deposit,
+        Transaction {
+            tx_type: TransactionType::Withdrawal,
+            client_id: 1,
+            tx_id: 1,
+            amount: Some(Decimal::from_str("1.0").unwrap()),
+            status: TransactionStatus::OK,
+            description: None,
+            category: None,
+        }
+    );
+}
+
+#[test]
+fn test_byte_record_matches_string_record() {
+    let rows = vec![
+        vec!["deposit", " 1", " 1", " 1.0"],
+        vec!["withdrawal", "65535", "4294967295", "-0.1234"],
+        vec!["dispute", "2", "3", ""],
+        vec!["resolve", "2", "3"],
+        vec!["chargeback", "7 ", "3  ", "+1.5"],
+        vec!["deposit", "1", "1", "0.0000000000000000000000000001"],
+    ];
+    for row in rows {
+        let from_string = Transaction::try_from(StringRecord::from(row.clone())).unwrap();
+        let from_bytes = Transaction::from_byte_record(&ByteRecord::from(row)).unwrap();
+        assert_eq!(from_bytes, from_string);
+    }
+
+    for bad in [
+        vec!["deposit", "65536", "1", "1.0"],
+        vec!["deposit", "-1", "1", "1.0"],
+        vec!["deposit", "1", "1", "1.0.0"],
+        vec!["refund", "1", "1", "1.0"],
+        vec!["deposit", "1"],
+    ] {
+        assert!(Transaction::from_byte_record(&ByteRecord::from(bad)).is_err());
+    }
+}
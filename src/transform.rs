@@ -0,0 +1,312 @@
+use crate::input::dialect::{CsvDialect, CsvReader};
+use crate::transaction::*;
+use csv::ByteRecord;
+use rust_decimal::prelude::*;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs::File;
+use std::io::Read;
+use tracing::debug;
+
+/// A fixup stage between the input and the engine, for the usual problems of
+/// feeds that don't quite match what we expect.
+pub trait Transform: Send {
+    /// Raw CSV row, before it's parsed. Returning false drops the row.
+    /// Only CSV inputs go through this.
+    fn apply_record(&mut self, _record: &mut ByteRecord) -> bool {
+        true
+    }
+    /// Parsed transaction, for every input format. None drops it, an error
+    /// is one of the input, like a row that can't be parsed.
+    fn apply(&mut self, transaction: Transaction) -> Result<Option<Transaction>, Box<dyn Error>> {
+        Ok(Some(transaction))
+    }
+}
+
+/// For files that don't have the columns in the type, client, tx, amount order
+/// and no header to find them by (see CsvDialect::columns). Positions are
+/// after the header mapping, if there was one.
+pub struct ColumnRemap {
+    /// Position in the file of each of type, client, tx and amount (and
+    /// description and category)
+    pub columns: Vec<usize>,
+}
+
+impl Transform for ColumnRemap {
+    fn apply_record(&mut self, record: &mut ByteRecord) -> bool {
+        let mut remapped = ByteRecord::with_capacity(record.as_slice().len(), self.columns.len());
+        for column in &self.columns {
+            // A missing column is empty, like a missing amount
+            remapped.push_field(record.get(*column).unwrap_or_default());
+        }
+        *record = remapped;
+        true
+    }
+}
+
+/// Converts every amount by a fixed rate, e.g. 0.01 for feeds in cents.
+pub struct CurrencyNormalize {
+    pub rate: Decimal,
+    pub decimals: u32,
+}
+
+impl Transform for CurrencyNormalize {
+    fn apply(
+        &mut self,
+        mut transaction: Transaction,
+    ) -> Result<Option<Transaction>, Box<dyn Error>> {
+        if let Some(amount) = transaction.amount {
+            let converted = amount
+                .checked_mul(self.rate)
+                .ok_or_else(|| format!("Amount {} overflows converted at {}", amount, self.rate))?;
+            transaction.amount = Some(converted.round_dp(self.decimals));
+        }
+        Ok(Some(transaction))
+    }
+}
+
+pub struct ClientRemap {
+    pub map: HashMap<ClientId, ClientId>,
+}
+
+impl Transform for ClientRemap {
+    fn apply(
+        &mut self,
+        mut transaction: Transaction,
+    ) -> Result<Option<Transaction>, Box<dyn Error>> {
+        if let Some(client_id) = self.map.get(&transaction.client_id) {
+            transaction.client_id = *client_id;
+        }
+        Ok(Some(transaction))
+    }
+}
+
+/// Keeps only the transactions of the given types and/or clients.
+pub struct Filter {
+    pub types: Option<Vec<TransactionType>>,
+    pub clients: Option<HashSet<ClientId>>,
+}
+
+impl Transform for Filter {
+    fn apply(&mut self, transaction: Transaction) -> Result<Option<Transaction>, Box<dyn Error>> {
+        let type_ok = match &self.types {
+            None => true,
+            Some(types) => types.contains(&transaction.tx_type),
+        };
+        let client_ok = match &self.clients {
+            None => true,
+            Some(clients) => clients.contains(&transaction.client_id),
+        };
+        if type_ok && client_ok {
+            Ok(Some(transaction))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// Drops rows we've already seen, for feeds that resend. Two rows are the
+/// same if they have the same type and tx id, so a dispute of a deposit isn't
+/// a duplicate of the deposit.
+#[derive(Default)]
+pub struct Dedup {
+    seen: HashSet<(u8, TransactionId)>,
+}
+
+impl Transform for Dedup {
+    fn apply(&mut self, transaction: Transaction) -> Result<Option<Transaction>, Box<dyn Error>> {
+        if self
+            .seen
+            .insert((transaction.tx_type as u8, transaction.tx_id))
+        {
+            Ok(Some(transaction))
+        } else {
+            debug!("Dropping duplicate {:?}", transaction);
+            Ok(None)
+        }
+    }
+}
+
+/// How transforms are written in a pipeline definition.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum TransformSpec {
+    ColumnRemap {
+        columns: Vec<usize>,
+    },
+    Currency {
+        rate: Decimal,
+        #[serde(default = "default_decimals")]
+        decimals: u32,
+    },
+    ClientRemap {
+        map: HashMap<ClientId, ClientId>,
+    },
+    Filter {
+        types: Option<Vec<String>>,
+        clients: Option<HashSet<ClientId>>,
+    },
+    Dedup,
+}
+
+fn default_decimals() -> u32 {
+    4
+}
+
+impl TransformSpec {
+    pub fn build(&self) -> Result<Box<dyn Transform>, Box<dyn Error>> {
+        Ok(match self {
+            TransformSpec::ColumnRemap { columns } => Box::new(ColumnRemap {
+                columns: columns.clone(),
+            }),
+            TransformSpec::Currency { rate, decimals } => Box::new(CurrencyNormalize {
+                rate: *rate,
+                decimals: *decimals,
+            }),
+            TransformSpec::ClientRemap { map } => Box::new(ClientRemap { map: map.clone() }),
+            TransformSpec::Filter { types, clients } => {
+                let types = match types {
+                    None => None,
+                    Some(types) => Some(
+                        types
+                            .iter()
+                            .map(|t| TransactionType::from_str(t))
+                            .collect::<Result<Vec<TransactionType>, &'static str>>()?,
+                    ),
+                };
+                Box::new(Filter {
+                    types,
+                    clients: clients.clone(),
+                })
+            }
+            TransformSpec::Dedup => Box::new(Dedup::default()),
+        })
+    }
+}
+
+fn apply_all(
+    transforms: &mut [Box<dyn Transform>],
+    transaction: Transaction,
+) -> Result<Option<Transaction>, Box<dyn Error>> {
+    let mut transaction = Some(transaction);
+    for transform in transforms.iter_mut() {
+        transaction = match transaction {
+            Some(transaction) => transform.apply(transaction)?,
+            None => break,
+        };
+    }
+    Ok(transaction)
+}
+
+/// Already parsed transactions (any input format) with the transforms applied.
+pub fn transactions<'a, I>(
+    transactions: I,
+    transforms: &'a mut [Box<dyn Transform>],
+) -> impl Iterator<Item = Result<Transaction, Box<dyn Error>>> + 'a
+where
+    I: IntoIterator<Item = Result<Transaction, Box<dyn Error>>> + 'a,
+{
+    transactions
+        .into_iter()
+        .filter_map(move |transaction| match transaction {
+            Ok(transaction) => apply_all(transforms, transaction).transpose(),
+            Err(e) => Some(Err(e)),
+        })
+}
+
+/// Transactions from a CSV file, with the transforms applied to both the raw
+/// rows and the parsed transactions.
+pub fn csv<'a>(
+    filename: &str,
+    dialect: &CsvDialect,
+    transforms: &'a mut [Box<dyn Transform>],
+) -> Result<impl Iterator<Item = Result<Transaction, Box<dyn Error>>> + 'a, Box<dyn Error>> {
+    Ok(records(dialect.reader(File::open(filename)?)?, transforms))
+}
+
+/// Same as csv for a reader that's already open.
+pub fn records<'a, R: Read + 'a>(
+    mut rdr: CsvReader<R>,
+    transforms: &'a mut [Box<dyn Transform>],
+) -> impl Iterator<Item = Result<Transaction, Box<dyn Error>>> + 'a {
+    let mut record = ByteRecord::new();
+    std::iter::from_fn(move || loop {
+        match rdr.read_byte_record(&mut record) {
+            Ok(true) => {}
+            Ok(false) => return None,
+            Err(e) => return Some(Err(e)),
+        }
+        if !transforms.iter_mut().all(|t| t.apply_record(&mut record)) {
+            continue;
+        }
+        let transaction = match Transaction::from_byte_record(&record) {
+            Ok(transaction) => transaction,
+            Err(e) => return Some(Err(e.into())),
+        };
+        if let Some(transaction) = apply_all(transforms, transaction).transpose() {
+            return Some(transaction);
+        }
+    })
+}
+
+#[test]
+fn test_transforms() {
+    let mut transforms: Vec<Box<dyn Transform>> = vec![
+        Box::new(ColumnRemap {
+            columns: vec![1, 0, 2, 3],
+        }),
+        Box::new(ClientRemap {
+            map: vec![(1, 100)].into_iter().collect(),
+        }),
+        Box::new(CurrencyNormalize {
+            rate: Decimal::from_str("0.01").unwrap(),
+            decimals: 4,
+        }),
+        Box::new(Filter {
+            types: Some(vec![TransactionType::Deposit, TransactionType::Dispute]),
+            clients: None,
+        }),
+        Box::new(Dedup::default()),
+    ];
+    let mut record = ByteRecord::from(vec!["1", "deposit", "1", "150"]);
+    assert!(transforms[0].apply_record(&mut record));
+    assert_eq!(record, ByteRecord::from(vec!["deposit", "1", "1", "150"]));
+
+    let transaction =
+        |tx_type, tx_id, amount: Option<&str>| -> Result<Transaction, Box<dyn Error>> {
+            Ok(Transaction {
+                tx_type,
+                client_id: 1,
+                tx_id,
+                amount: amount.map(|a| Decimal::from_str(a).unwrap()),
+                status: TransactionStatus::OK,
+                description: None,
+                category: None,
+            })
+        };
+    let input = vec![
+        transaction(TransactionType::Deposit, 1, Some("150")),
+        transaction(TransactionType::Deposit, 1, Some("150")),
+        transaction(TransactionType::Withdrawal, 2, Some("50")),
+        transaction(TransactionType::Dispute, 1, None),
+    ];
+    let output: Vec<Transaction> = transactions(input, &mut transforms[1..])
+        .map(|t| t.unwrap())
+        .collect();
+    assert_eq!(output.len(), 2);
+    assert_eq!(output[0].client_id, 100);
+    assert_eq!(output[0].amount, Some(Decimal::from_str("1.5").unwrap()));
+    assert_eq!(output[1].tx_type, TransactionType::Dispute);
+
+    let mut currency: Vec<Box<dyn Transform>> = vec![Box::new(CurrencyNormalize {
+        rate: Decimal::new(100, 0),
+        decimals: 4,
+    })];
+    let huge = Transaction {
+        amount: Some(Decimal::MAX),
+        ..transaction(TransactionType::Deposit, 3, None).unwrap()
+    };
+    let mut converted = transactions(vec![Ok(huge)], &mut currency);
+    assert!(converted.next().unwrap().is_err());
+}
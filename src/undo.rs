@@ -0,0 +1,135 @@
+use crate::double_entry::LedgerAccount;
+use crate::transaction::{ClientId, TransactionId, TransactionType};
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+
+/*
+What every applied transaction did to its client's balances, by tx id, so an
+operator can take a transaction back (a deposit booked to the wrong client,
+say):
+
+  engine.track_undo();
+  engine.import_csv("day.csv")?;
+  engine.undo(17)?;
+
+Undoing a tx id reverses everything applied under it, the transaction itself
+and its disputes, resolves and chargebacks (an account its chargeback locked
+is unlocked), takes them off the account's num_transactions, and forgets it:
+it's gone from the stored transactions and the seen tx ids, so the corrected
+one can be sent. What came after isn't looked at: undoing a deposit whose
+funds were withdrawn since leaves the account with negative funds. The
+double-entry ledger gets reversing entries.
+
+The log is in memory only, it isn't saved with the state.
+*/
+
+/// The change in balances of one applied event.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Delta {
+    pub tx_type: TransactionType,
+    pub client: ClientId,
+    pub available: Decimal,
+    pub held: Decimal,
+    /// It locked the account
+    pub locked: bool,
+    pub(crate) counterpart: LedgerAccount,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct UndoLog {
+    deltas: HashMap<TransactionId, Vec<Delta>>,
+}
+
+impl UndoLog {
+    pub(crate) fn record(&mut self, tx_id: TransactionId, delta: Delta) {
+        self.deltas.entry(tx_id).or_default().push(delta);
+    }
+
+    /// Everything applied under a tx id, oldest first.
+    pub fn deltas(&self, tx_id: TransactionId) -> Option<&[Delta]> {
+        self.deltas.get(&tx_id).map(Vec::as_slice)
+    }
+
+    pub(crate) fn take(&mut self, tx_id: TransactionId) -> Option<Vec<Delta>> {
+        self.deltas.remove(&tx_id)
+    }
+
+    // Back to what deltas() said, for batch rollbacks
+    pub(crate) fn restore(&mut self, tx_id: TransactionId, deltas: Option<Vec<Delta>>) {
+        match deltas {
+            Some(deltas) => self.deltas.insert(tx_id, deltas),
+            None => self.deltas.remove(&tx_id),
+        };
+    }
+
+    pub(crate) fn merge(&mut self, other: UndoLog) {
+        for (tx_id, deltas) in other.deltas {
+            self.deltas.entry(tx_id).or_default().extend(deltas);
+        }
+    }
+
+    /// How many tx ids can be undone
+    pub fn len(&self) -> usize {
+        self.deltas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deltas.is_empty()
+    }
+}
+
+#[test]
+fn test_undo() {
+    use crate::compat::CompatLevel;
+    use crate::PaymentEngine;
+
+    let mut engine = PaymentEngine::new();
+    engine.set_compat_level(CompatLevel::V2);
+    engine.set_idempotent(true);
+    engine.track_undo();
+    engine.track_double_entry();
+    engine
+        .import_reader(
+            &b"type,client,tx,amount
+deposit,1,1,10
+deposit,1,2,5
+withdrawal,1,3,3
+deposit,2,4,7
+dispute,2,4,
+chargeback,2,4,
+withdrawal,1,5,100
+"[..],
+        )
+        .unwrap();
+    assert_eq!(engine.undo_log().unwrap().deltas(4).unwrap().len(), 3);
+    assert!(engine.undo(5).is_err(), "declined, nothing to undo");
+
+    engine.undo(2).unwrap();
+    let account = engine.account(1).unwrap().unwrap();
+    assert_eq!(account.available(), Decimal::new(7, 0));
+    assert_eq!(account.total(), Decimal::new(7, 0));
+    assert_eq!(account.num_transactions(), 3);
+    assert!(engine.transactions.get(2).unwrap().is_none());
+    assert!(engine.undo(2).is_err());
+
+    engine.undo(4).unwrap();
+    let account = engine.account(2).unwrap().unwrap();
+    assert_eq!(
+        (account.available(), account.held(), account.is_locked()),
+        (Decimal::ZERO, Decimal::ZERO, false)
+    );
+    assert_eq!(account.num_transactions(), 0);
+    assert_eq!(engine.stats().undone, 2);
+    let balances = engine.double_entry().unwrap().trial_balance();
+    assert_eq!(balances[&LedgerAccount::Available(1)], -Decimal::new(7, 0));
+    assert!(balances[&LedgerAccount::Available(2)].is_zero());
+
+    // Forgotten, so it can be sent again
+    engine
+        .import_reader(&b"type,client,tx,amount\ndeposit,1,2,6\n"[..])
+        .unwrap();
+    assert_eq!(
+        engine.account(1).unwrap().unwrap().total(),
+        Decimal::new(13, 0)
+    );
+}
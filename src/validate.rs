@@ -0,0 +1,202 @@
+use crate::compat::CompatLevel;
+use crate::input::dialect::CsvDialect;
+use crate::transaction::{ClientId, Transaction, TransactionId, TransactionType};
+use crate::PaymentEngine;
+use csv::ByteRecord;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io::Read;
+
+/*
+A dry run: the file is parsed and fed to a throwaway engine, and whatever
+looks wrong is reported instead of stopping at the first problem. What the
+engine does on its own (a withdrawal without funds, a resolve of something
+that isn't disputed) isn't a problem with the file and isn't reported.
+*/
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum ProblemKind {
+    /// Not a row we can read: wrong number of columns, bad ids or amounts
+    Malformed,
+    UnknownType,
+    /// A deposit or withdrawal with the tx id of an earlier one
+    DuplicateTx,
+    /// A dispute, resolve or chargeback of a tx that isn't in the file
+    MissingReference,
+    /// A dispute, resolve or chargeback of another client's tx
+    CrossClient,
+    /// The engine failed on it, e.g. an overflowing balance
+    Rejected,
+}
+
+impl fmt::Display for ProblemKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ProblemKind::Malformed => "malformed",
+            ProblemKind::UnknownType => "unknown type",
+            ProblemKind::DuplicateTx => "duplicate tx",
+            ProblemKind::MissingReference => "missing reference",
+            ProblemKind::CrossClient => "cross-client",
+            ProblemKind::Rejected => "rejected",
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Problem {
+    /// In the file, counting the header
+    pub line: u64,
+    pub kind: ProblemKind,
+    pub message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}: {}", self.line, self.kind, self.message)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Validation {
+    pub rows: u64,
+    pub problems: Vec<Problem>,
+}
+
+impl Validation {
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Only fails if the input can't be read at all.
+pub fn validate<R: Read>(
+    reader: R,
+    dialect: &CsvDialect,
+    compat: CompatLevel,
+) -> Result<Validation, Box<dyn Error>> {
+    let mut rdr = dialect.reader(reader)?;
+    let mut engine = PaymentEngine::new();
+    engine.set_compat_level(compat);
+    let mut validation = Validation::default();
+    let mut problems = Vec::new();
+    // Line and client of every deposit and withdrawal
+    let mut seen: HashMap<TransactionId, (u64, ClientId)> = HashMap::new();
+    let mut record = ByteRecord::new();
+    loop {
+        let line = rdr.inner_mut().position().line();
+        let mut problem = |kind, message: String| {
+            problems.push(Problem {
+                line,
+                kind,
+                message,
+            })
+        };
+        match rdr.read_byte_record(&mut record) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(e) => {
+                let fatal = match e.downcast_ref::<csv::Error>() {
+                    Some(e) => e.is_io_error(),
+                    None => e.is::<std::io::Error>(),
+                };
+                if fatal {
+                    return Err(e);
+                }
+                validation.rows += 1;
+                problem(ProblemKind::Malformed, e.to_string());
+                continue;
+            }
+        }
+        validation.rows += 1;
+        let transaction = match Transaction::from_byte_record(&record) {
+            Ok(transaction) => transaction,
+            Err("Unknown transaction type") => {
+                let tx_type = String::from_utf8_lossy(record.get(0).unwrap_or_default());
+                problem(ProblemKind::UnknownType, format!("{:?}", tx_type.trim()));
+                continue;
+            }
+            Err(e) => {
+                problem(ProblemKind::Malformed, e.to_string());
+                continue;
+            }
+        };
+        let (tx, client) = (transaction.tx_id, transaction.client_id);
+        match transaction.tx_type {
+            TransactionType::Deposit | TransactionType::Withdrawal | TransactionType::Hold => {
+                if let Some((first, _)) = seen.get(&tx) {
+                    problem(
+                        ProblemKind::DuplicateTx,
+                        format!("tx {} is already on line {}", tx, first),
+                    );
+                    // v1 would stop the whole run on it
+                    continue;
+                }
+                seen.insert(tx, (line, client));
+            }
+            tx_type => match seen.get(&tx) {
+                None => problem(
+                    ProblemKind::MissingReference,
+                    format!("{} of tx {}, which isn't in the file", tx_type, tx),
+                ),
+                Some((first, owner)) if *owner != client => problem(
+                    ProblemKind::CrossClient,
+                    format!(
+                        "{} by client {} of tx {} of client {} (line {})",
+                        tx_type, client, tx, owner, first
+                    ),
+                ),
+                Some(_) => {}
+            },
+        }
+        if let Err(e) = engine.process_transaction(transaction) {
+            problem(ProblemKind::Rejected, e.to_string());
+        }
+    }
+    validation.problems = problems;
+    Ok(validation)
+}
+
+#[test]
+fn test_validate() {
+    let input = b"type,client,tx,amount
+deposit,1,1,10
+deposit,1,1,5
+withdrawal,2,2,100
+refund,1,3,1
+deposit,1,x,1
+dispute,2,1,
+resolve,1,9,
+deposit,1,4
+withdrawal,1,5,1,extra
+deposit,3,6,79228162514264337593543950335
+deposit,3,7,1
+";
+    let validation = validate(&input[..], &CsvDialect::default(), CompatLevel::V1).unwrap();
+    assert_eq!(validation.rows, 11);
+    let found: Vec<(u64, ProblemKind)> = validation
+        .problems
+        .iter()
+        .map(|problem| (problem.line, problem.kind))
+        .collect();
+    assert_eq!(
+        found,
+        vec![
+            (3, ProblemKind::DuplicateTx),
+            (5, ProblemKind::UnknownType),
+            (6, ProblemKind::Malformed),
+            (7, ProblemKind::CrossClient),
+            (8, ProblemKind::MissingReference),
+            (9, ProblemKind::Malformed),
+            (10, ProblemKind::Malformed),
+            (12, ProblemKind::Rejected),
+        ]
+    );
+
+    let clean = b"type,client,tx,amount\ndeposit,1,1,10\nwithdrawal,1,2,20\n";
+    assert!(
+        validate(&clean[..], &CsvDialect::default(), CompatLevel::V1)
+            .unwrap()
+            .is_ok()
+    );
+}
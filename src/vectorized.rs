@@ -0,0 +1,634 @@
+use crate::dispute_state::DisputeStateMachine;
+use crate::engine::{move_disputed, Account};
+use crate::stats::Stats;
+use crate::store::{decode_type, encode_type};
+use crate::transaction::{
+    ClientId, Transaction, TransactionId, TransactionStatus, TransactionType, TxOutcome,
+};
+use crate::PaymentEngine;
+use arrow_array::cast::AsArray;
+use arrow_array::types::{Decimal128Type, UInt16Type, UInt32Type, UInt8Type};
+use arrow_array::{Array, Decimal128Array, RecordBatch, UInt16Array, UInt32Array, UInt8Array};
+use arrow_schema::{DataType, Field, Schema};
+use arrow_select::take::take_record_batch;
+use rust_decimal::Decimal;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::error::Error;
+use std::ops::Range;
+use std::sync::Arc;
+use tracing::debug;
+
+/*
+--vectorized: for reprocessing large files that are mostly deposits and
+withdrawals. The transactions are decoded into Arrow record batches of
+BATCH_ROWS rows: type, client, tx, the amount as a Decimal128 at the largest
+scale in the batch, and the scale each amount came with. Then, a batch at a
+time:
+
+1. The deposits go in the transaction store, in their order.
+2. The batch is reordered by client (stable, each client keeps its order).
+3. Each client's slice of the columns is run through in one pass, in i128 at
+   the batch's scale, from one lookup of its account. Disputes, resolves and
+   chargebacks go through the engine's own state machine on the way, and see
+   a deposit of the batch only if it came before them.
+4. The accounts, the new statuses and the counts are written back, one write
+   per client instead of one per row.
+
+The result is the same as a sequential run, down to the scale of the
+balances. A batch the kernel can't reproduce exactly (a repeated tx id, a
+deposit without an amount, a balance that doesn't fit in a Decimal, holds,
+descriptions, categories)
+is undone and given to the row engine instead, which fails or not where it
+would have. So is everything when the engine isn't a plain one: v2, account
+creation policies, limits, a blocklist, audit, metrics (and so --progress),
+observers, the WAL or any of the histories need every transaction to go
+through process_transaction. Transactions aren't logged one by one.
+
+What's saved is the per row work of process_transaction and the account
+lookups. Storing the deposits, so they can be disputed, costs the same as in
+the row engine and is most of what's left: on 3M generated rows with 0.1%
+disputes the engine's time goes down by about a tenth.
+*/
+
+const BATCH_ROWS: usize = 64 * 1024;
+
+// Decimal's mantissa is 96 bits. A balance that doesn't fit, even at a
+// smaller scale, is left to the row engine, where Decimal would round it.
+const MAX_MANTISSA: i128 = (1 << 96) - 1;
+
+const TYPE: usize = 0;
+const CLIENT: usize = 1;
+const TX: usize = 2;
+const AMOUNT: usize = 3;
+const SCALE: usize = 4;
+
+/// Imports the transactions a batch at a time, see above. The first error
+/// stops it, with every transaction before it applied.
+pub fn import_transactions<I>(
+    engine: &mut PaymentEngine,
+    transactions: I,
+) -> Result<(), Box<dyn Error>>
+where
+    I: IntoIterator<Item = Result<Transaction, Box<dyn Error>>>,
+{
+    if !engine.is_plain() {
+        debug!("Not a plain engine, processing row by row");
+        return engine.import_transactions(transactions);
+    }
+    let mut transactions = transactions.into_iter();
+    loop {
+        let mut rows = Vec::with_capacity(BATCH_ROWS);
+        let mut failed = None;
+        for transaction in transactions.by_ref().take(BATCH_ROWS) {
+            match transaction {
+                Ok(transaction) => rows.push(transaction),
+                Err(e) => {
+                    failed = Some(e);
+                    break;
+                }
+            }
+        }
+        let last = rows.len() < BATCH_ROWS;
+        match record_batch(&rows)? {
+            Some(batch) => apply_batch(engine, &batch)?,
+            None => {
+                debug!("Batch of {} rows goes row by row", rows.len());
+                engine.import_transactions(rows.into_iter().map(Ok))?;
+            }
+        }
+        if let Some(e) = failed {
+            return Err(e);
+        }
+        if last {
+            return Ok(());
+        }
+    }
+}
+
+/// The rows as columns. None if an amount doesn't fit in i128 at the
+/// largest scale of the batch, or a row has a description or a category,
+/// which the columns have no room for.
+pub(crate) fn record_batch(rows: &[Transaction]) -> Result<Option<RecordBatch>, Box<dyn Error>> {
+    let scale = rows
+        .iter()
+        .filter_map(|transaction| transaction.amount)
+        .map(|amount| amount.scale())
+        .max()
+        .unwrap_or(0);
+    if rows
+        .iter()
+        .any(|transaction| transaction.description.is_some() || transaction.category.is_some())
+    {
+        return Ok(None);
+    }
+    let mut amounts = Vec::with_capacity(rows.len());
+    for transaction in rows {
+        amounts.push(match transaction.amount {
+            Some(amount) => match rescale(amount.mantissa(), amount.scale(), scale) {
+                Some(amount) => Some(amount),
+                None => return Ok(None),
+            },
+            None => None,
+        });
+    }
+    let schema = Schema::new(vec![
+        Field::new("type", DataType::UInt8, false),
+        Field::new("client", DataType::UInt16, false),
+        Field::new("tx", DataType::UInt32, false),
+        Field::new("amount", DataType::Decimal128(38, scale as i8), true),
+        Field::new("scale", DataType::UInt8, true),
+    ]);
+    let batch = RecordBatch::try_new(
+        Arc::new(schema),
+        vec![
+            Arc::new(UInt8Array::from_iter_values(
+                rows.iter()
+                    .map(|transaction| encode_type(transaction.tx_type)),
+            )),
+            Arc::new(UInt16Array::from_iter_values(
+                rows.iter().map(|transaction| transaction.client_id),
+            )),
+            Arc::new(UInt32Array::from_iter_values(
+                rows.iter().map(|transaction| transaction.tx_id),
+            )),
+            Arc::new(Decimal128Array::from(amounts).with_precision_and_scale(38, scale as i8)?),
+            Arc::new(
+                rows.iter()
+                    .map(|transaction| transaction.amount.map(|amount| amount.scale() as u8))
+                    .collect::<UInt8Array>(),
+            ),
+        ],
+    )?;
+    Ok(Some(batch))
+}
+
+// The value, now with `to` decimals, if it fits
+fn rescale(mantissa: i128, from: u32, to: u32) -> Option<i128> {
+    10i128
+        .checked_pow(to.checked_sub(from)?)
+        .and_then(|factor| mantissa.checked_mul(factor))
+}
+
+// The columns of a batch, downcast once
+struct Columns<'a> {
+    types: &'a UInt8Array,
+    clients: &'a UInt16Array,
+    txs: &'a UInt32Array,
+    amounts: &'a Decimal128Array,
+    scales: &'a UInt8Array,
+    // Of the amounts
+    scale: u32,
+}
+
+impl<'a> Columns<'a> {
+    fn new(batch: &'a RecordBatch) -> Columns<'a> {
+        let amounts = batch.column(AMOUNT).as_primitive::<Decimal128Type>();
+        Columns {
+            types: batch.column(TYPE).as_primitive::<UInt8Type>(),
+            clients: batch.column(CLIENT).as_primitive::<UInt16Type>(),
+            txs: batch.column(TX).as_primitive::<UInt32Type>(),
+            scale: amounts.scale() as u32,
+            amounts,
+            scales: batch.column(SCALE).as_primitive::<UInt8Type>(),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.types.len()
+    }
+
+    fn transaction(&self, row: usize) -> Result<Transaction, Box<dyn Error>> {
+        let amount = match self.amounts.is_valid(row) {
+            // Back to the scale it came with
+            true => {
+                let scale = u32::from(self.scales.value(row));
+                let mut value = self.amounts.value(row);
+                if scale < self.scale {
+                    value /= 10i128.pow(self.scale - scale);
+                }
+                Some(Decimal::try_from_i128_with_scale(value, scale)?)
+            }
+            false => None,
+        };
+        Ok(Transaction {
+            tx_type: decode_type(self.types.value(row)).ok_or("Unknown transaction type")?,
+            client_id: self.clients.value(row),
+            tx_id: self.txs.value(row),
+            amount,
+            status: TransactionStatus::OK,
+            description: None,
+            category: None,
+        })
+    }
+}
+
+fn apply_rows(engine: &mut PaymentEngine, batch: &RecordBatch) -> Result<(), Box<dyn Error>> {
+    let columns = Columns::new(batch);
+    for row in 0..columns.len() {
+        engine.process_transaction(columns.transaction(row)?)?;
+    }
+    Ok(())
+}
+
+fn apply_batch(engine: &mut PaymentEngine, batch: &RecordBatch) -> Result<(), Box<dyn Error>> {
+    let holds = [
+        TransactionType::Hold,
+        TransactionType::Capture,
+        TransactionType::Release,
+    ]
+    .map(encode_type);
+    if Columns::new(batch)
+        .types
+        .values()
+        .iter()
+        .any(|tx_type| holds.contains(tx_type))
+    {
+        debug!("Batch with holds goes row by row");
+        return apply_rows(engine, batch);
+    }
+    let inserted = match insert_deposits(engine, batch)? {
+        Some(inserted) => inserted,
+        None => {
+            debug!("Batch of {} rows goes row by row", batch.num_rows());
+            return apply_rows(engine, batch);
+        }
+    };
+    match run(engine, batch)? {
+        Some(done) => done.commit(engine),
+        None => {
+            debug!("Batch of {} rows goes row by row", batch.num_rows());
+            for tx_id in inserted {
+                engine.transactions.remove(tx_id)?;
+            }
+            apply_rows(engine, batch)
+        }
+    }
+}
+
+// Step 1. None, with nothing inserted, if a deposit or a withdrawal doesn't
+// have an amount or a deposit's tx id was seen before.
+fn insert_deposits(
+    engine: &mut PaymentEngine,
+    batch: &RecordBatch,
+) -> Result<Option<Vec<TransactionId>>, Box<dyn Error>> {
+    let columns = Columns::new(batch);
+    let deposit = encode_type(TransactionType::Deposit);
+    let withdrawal = encode_type(TransactionType::Withdrawal);
+    let mut inserted = Vec::new();
+    for row in 0..columns.len() {
+        let tx_type = columns.types.value(row);
+        let payment = tx_type == deposit || tx_type == withdrawal;
+        let refused = match (payment && columns.amounts.is_null(row), tx_type == deposit) {
+            (true, _) => true,
+            (false, true) => {
+                let transaction = columns.transaction(row)?;
+                let tx_id = transaction.tx_id;
+                let new = engine.transactions.insert_new(transaction)?;
+                if new {
+                    inserted.push(tx_id);
+                }
+                !new
+            }
+            (false, false) => false,
+        };
+        if refused {
+            for tx_id in inserted {
+                engine.transactions.remove(tx_id)?;
+            }
+            return Ok(None);
+        }
+    }
+    Ok(Some(inserted))
+}
+
+// What a batch does, computed but not written yet
+struct Done {
+    accounts: Vec<Account>,
+    statuses: HashMap<TransactionId, TransactionStatus>,
+    stats: Stats,
+}
+
+impl Done {
+    // Step 4
+    fn commit(self, engine: &mut PaymentEngine) -> Result<(), Box<dyn Error>> {
+        for (tx_id, status) in self.statuses {
+            engine.transactions.set_status(tx_id, status)?;
+        }
+        for account in self.accounts {
+            engine.accounts.put(account)?;
+        }
+        engine.stats.merge(&self.stats);
+        Ok(())
+    }
+}
+
+// Steps 2 and 3. None if a client's balances can't be computed exactly.
+fn run(engine: &PaymentEngine, batch: &RecordBatch) -> Result<Option<Done>, Box<dyn Error>> {
+    let columns = Columns::new(batch);
+    let mut order: Vec<u32> = (0..columns.len() as u32).collect();
+    order.sort_by_key(|&row| columns.clients.value(row as usize));
+    let sorted = take_record_batch(batch, &UInt32Array::from(order.clone()))?;
+
+    let mut scan = Scan {
+        engine,
+        columns: Columns::new(&sorted),
+        positions: &order,
+        deposited: deposited(&columns),
+        done: Done {
+            accounts: Vec::new(),
+            statuses: HashMap::new(),
+            stats: Stats::default(),
+        },
+    };
+    let clients = scan.columns.clients;
+    let mut start = 0;
+    while start < clients.len() {
+        let client_id = clients.value(start);
+        let end = (start..clients.len())
+            .find(|&row| clients.value(row) != client_id)
+            .unwrap_or(clients.len());
+        match scan.client(client_id, start..end)? {
+            Some(account) => scan.done.accounts.push(account),
+            None => return Ok(None),
+        }
+        start = end;
+    }
+    Ok(Some(scan.done))
+}
+
+// Where in the batch the deposits disputed in the batch are
+fn deposited(columns: &Columns) -> HashMap<TransactionId, usize> {
+    let (types, txs) = (columns.types, columns.txs);
+    let deposit = encode_type(TransactionType::Deposit);
+    let withdrawal = encode_type(TransactionType::Withdrawal);
+    let disputed: HashSet<TransactionId> = (0..columns.len())
+        .filter(|&row| types.value(row) != deposit && types.value(row) != withdrawal)
+        .map(|row| txs.value(row))
+        .collect();
+    if disputed.is_empty() {
+        return HashMap::new();
+    }
+    (0..columns.len())
+        .filter(|&row| types.value(row) == deposit && disputed.contains(&txs.value(row)))
+        .map(|row| (txs.value(row), row))
+        .collect()
+}
+
+// A balance in i128 at the scale of the client's run, and the scale Decimal
+// would have given it
+#[derive(Clone, Copy)]
+struct Funds {
+    value: i128,
+    scale: u32,
+}
+
+impl Funds {
+    fn widen(amount: Decimal, to: u32) -> Option<Funds> {
+        fits(Funds {
+            value: rescale(amount.mantissa(), amount.scale(), to)?,
+            scale: amount.scale(),
+        })
+    }
+
+    fn narrow(self, from: u32) -> Option<Decimal> {
+        let value = self.value / 10i128.pow(from - self.scale);
+        Decimal::try_from_i128_with_scale(value, self.scale).ok()
+    }
+
+    fn add(self, amount: i128, scale: u32) -> Option<Funds> {
+        fits(Funds {
+            value: self.value.checked_add(amount)?,
+            scale: self.scale.max(scale),
+        })
+    }
+
+    fn sub(self, amount: i128, scale: u32) -> Option<Funds> {
+        self.add(amount.checked_neg()?, scale)
+    }
+}
+
+fn fits(funds: Funds) -> Option<Funds> {
+    (funds.value.abs() <= MAX_MANTISSA).then_some(funds)
+}
+
+struct Scan<'a> {
+    engine: &'a PaymentEngine,
+    // Sorted by client
+    columns: Columns<'a>,
+    // Where each row of the sorted batch was in the batch
+    positions: &'a [u32],
+    deposited: HashMap<TransactionId, usize>,
+    done: Done,
+}
+
+impl Scan<'_> {
+    // The kernel: one client's rows in one pass
+    fn client(
+        &mut self,
+        client_id: ClientId,
+        rows: Range<usize>,
+    ) -> Result<Option<Account>, Box<dyn Error>> {
+        let mut account = self.engine.accounts.get(client_id)?.unwrap_or(Account {
+            client_id,
+            num_transactions: 0,
+            funds_available: Decimal::ZERO,
+            funds_held: Decimal::ZERO,
+            funds_total: Decimal::ZERO,
+            locked: false,
+        });
+        let overdraft = self.engine.overdraft_limit(client_id);
+        let (types, amounts, scales) = (
+            self.columns.types,
+            self.columns.amounts,
+            self.columns.scales,
+        );
+        let batch_scale = self.columns.scale;
+        let scale = batch_scale
+            .max(account.funds_available.scale())
+            .max(account.funds_held.scale())
+            .max(account.funds_total.scale())
+            .max(overdraft.scale());
+        let (Some(mut available), Some(mut held), Some(mut total), Some(overdraft)) = (
+            Funds::widen(account.funds_available, scale),
+            Funds::widen(account.funds_held, scale),
+            Funds::widen(account.funds_total, scale),
+            Funds::widen(overdraft, scale),
+        ) else {
+            return Ok(None);
+        };
+        for row in rows.clone() {
+            let tx_type = decode_type(types.value(row)).ok_or("Unknown transaction type")?;
+            let outcome = match tx_type {
+                TransactionType::Deposit | TransactionType::Withdrawal => {
+                    let Some(amount) = rescale(amounts.value(row), batch_scale, scale) else {
+                        return Ok(None);
+                    };
+                    let amount_scale = u32::from(scales.value(row));
+                    // What the row engine compares the amount with
+                    let Some(limit) = available.add(overdraft.value, 0) else {
+                        return Ok(None);
+                    };
+                    let (after, outcome) = match tx_type {
+                        TransactionType::Deposit => (
+                            available
+                                .add(amount, amount_scale)
+                                .zip(total.add(amount, amount_scale)),
+                            TxOutcome::Applied,
+                        ),
+                        _ if limit.value >= amount => (
+                            available
+                                .sub(amount, amount_scale)
+                                .zip(total.sub(amount, amount_scale)),
+                            TxOutcome::Applied,
+                        ),
+                        _ => (
+                            Some((available, total)),
+                            TxOutcome::DeclinedInsufficientFunds,
+                        ),
+                    };
+                    match after {
+                        Some(after) => (available, total) = after,
+                        None => return Ok(None),
+                    }
+                    outcome
+                }
+                _ => {
+                    // The same as the row engine, on the account as of now
+                    let (Some(funds_available), Some(funds_held), Some(funds_total)) = (
+                        available.narrow(scale),
+                        held.narrow(scale),
+                        total.narrow(scale),
+                    ) else {
+                        return Ok(None);
+                    };
+                    account.funds_available = funds_available;
+                    account.funds_held = funds_held;
+                    account.funds_total = funds_total;
+                    let Some(outcome) = self.dispute(&mut account, row)? else {
+                        return Ok(None);
+                    };
+                    let (Some(after_available), Some(after_held), Some(after_total)) = (
+                        Funds::widen(account.funds_available, scale),
+                        Funds::widen(account.funds_held, scale),
+                        Funds::widen(account.funds_total, scale),
+                    ) else {
+                        return Ok(None);
+                    };
+                    (available, held, total) = (after_available, after_held, after_total);
+                    outcome
+                }
+            };
+            self.done.stats.count(tx_type, &Ok(outcome));
+        }
+        let (Some(funds_available), Some(funds_held), Some(funds_total)) = (
+            available.narrow(scale),
+            held.narrow(scale),
+            total.narrow(scale),
+        ) else {
+            return Ok(None);
+        };
+        account.funds_available = funds_available;
+        account.funds_held = funds_held;
+        account.funds_total = funds_total;
+        account.num_transactions = account
+            .num_transactions
+            .saturating_add(u32::try_from(rows.len()).unwrap_or(u32::MAX));
+        Ok(Some(account))
+    }
+
+    // A dispute, resolve or chargeback. None if the row engine would fail.
+    fn dispute(
+        &mut self,
+        account: &mut Account,
+        row: usize,
+    ) -> Result<Option<TxOutcome>, Box<dyn Error>> {
+        let event = self.columns.transaction(row)?;
+        let position = self.positions[row] as usize;
+        let stored = match self.deposited.get(&event.tx_id) {
+            // Not yet
+            Some(&deposited) if deposited > position => None,
+            _ => self.engine.transactions.get(event.tx_id)?,
+        };
+        let Some(mut disputed) = stored else {
+            return Ok(Some(TxOutcome::IgnoredUnknownTx));
+        };
+        if let Some(&status) = self.done.statuses.get(&event.tx_id) {
+            disputed.status = status;
+        }
+        let status = match DisputeStateMachine::transition(&disputed, &event) {
+            Ok(status) => status,
+            Err(refused) => return Ok(Some(refused)),
+        };
+        let Some(amount) = disputed.amount else {
+            return Ok(None);
+        };
+        if move_disputed(account, &disputed, event.tx_type, amount, false).is_err() {
+            return Ok(None);
+        }
+        self.done.statuses.insert(event.tx_id, status);
+        Ok(Some(TxOutcome::Applied))
+    }
+}
+
+#[test]
+fn test_vectorized_matches_rows() {
+    let mut input = std::fs::read_to_string("test_files/a_bit_of_everything.csv").unwrap();
+    // Mixed scales, a withdrawal from an unknown client, overdrawn clients,
+    // disputes before their deposit and of other clients' deposits
+    input.push_str("deposit, 3, 50, 0.12345\nwithdrawal, 3, 51, 0.1\nwithdrawal, 4, 52, 1\n");
+    input.push_str("deposit, 5, 53, 10\nwithdrawal, 5, 54, 10.5\nwithdrawal, 5, 55, 9.99\n");
+    input.push_str("dispute, 3, 50,\ndeposit, 3, 56, 7\ndispute, 6, 57,\ndeposit, 6, 57, 1\n");
+    input.push_str("dispute, 6, 57,\ndispute, 5, 56,\nchargeback, 3, 50,\ndeposit, 3, 58, 1\n");
+    let parse = |input: &str| -> Vec<Result<Transaction, Box<dyn Error>>> {
+        csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .flexible(true)
+            .from_reader(input.as_bytes())
+            .byte_records()
+            .map(|record| Ok(Transaction::from_byte_record(&record?)?))
+            .collect()
+    };
+    let accounts = |engine: &PaymentEngine| {
+        let mut out = Vec::new();
+        engine.export_accounts(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    };
+
+    let mut rows = PaymentEngine::new();
+    rows.import_transactions(parse(&input)).unwrap();
+    let mut vectorized = PaymentEngine::new();
+    import_transactions(&mut vectorized, parse(&input)).unwrap();
+    assert_eq!(vectorized.sorted_accounts(), rows.sorted_accounts());
+    assert_eq!(vectorized.sorted_transactions(), rows.sorted_transactions());
+    assert_eq!(vectorized.stats(), rows.stats());
+    // Same scales too
+    assert_eq!(accounts(&vectorized), accounts(&rows));
+
+    // Errors where the row engine has them
+    let bad =
+        "type,client,tx,amount\ndeposit,7,60,1\ndeposit,8,61,1\ndeposit,7,60,2\ndeposit,9,62,1\n";
+    let mut rows = PaymentEngine::new();
+    assert!(rows.import_transactions(parse(bad)).is_err());
+    let mut vectorized = PaymentEngine::new();
+    assert!(import_transactions(&mut vectorized, parse(bad)).is_err());
+    assert_eq!(accounts(&vectorized), accounts(&rows));
+    assert_eq!(vectorized.sorted_transactions(), rows.sorted_transactions());
+    assert_eq!(vectorized.len(), 2);
+
+    let batch = record_batch(
+        &parse("type,client,tx,amount\ndeposit,1,1,1.5\nwithdrawal,1,2,0.25\n")
+            .into_iter()
+            .map(Result::unwrap)
+            .collect::<Vec<_>>(),
+    )
+    .unwrap()
+    .unwrap();
+    assert_eq!(
+        batch
+            .column(AMOUNT)
+            .as_primitive::<Decimal128Type>()
+            .values(),
+        &[150, 25]
+    );
+}
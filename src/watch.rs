@@ -0,0 +1,280 @@
+use crate::input::dialect::CsvDialect;
+use crate::input::encoding::Encoding;
+use crate::output::OutputFormat;
+use crate::state;
+use crate::PaymentEngine;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{error, info, warn};
+
+/*
+The batch tool as a continuous processor. Every interval:
+
+- CSV files that landed in the directory are imported once their size stops
+  changing between two polls (so one still being copied isn't read halfway),
+  then moved into processed/ or, if they failed, failed/ under it. What's
+  left in the directory after a restart is what hasn't been imported yet.
+- The tailed file, if any, is read from where we stopped up to its last
+  complete line. A file that got shorter was rotated and is read from the
+  start again.
+
+The accounts are exported, replacing the file atomically, whenever something
+was imported and the last export is older than export_every. It's plain
+polling, no inotify and friends, so it works the same everywhere including
+network filesystems.
+*/
+
+pub struct WatchSpec {
+    pub dir: Option<PathBuf>,
+    pub tail: Option<PathBuf>,
+    pub dialect: CsvDialect,
+    pub interval: Duration,
+    pub export: Option<PathBuf>,
+    pub export_every: Duration,
+    pub export_format: OutputFormat,
+}
+
+struct Tail {
+    path: PathBuf,
+    offset: u64,
+    header: Vec<u8>,
+}
+
+pub struct Watcher<'s> {
+    spec: &'s WatchSpec,
+    // Size of each pending file at the previous poll
+    pending: HashMap<PathBuf, u64>,
+    tail: Option<Tail>,
+    last_export: Option<Instant>,
+    dirty: bool,
+}
+
+impl<'s> Watcher<'s> {
+    pub fn new(spec: &'s WatchSpec) -> Result<Watcher<'s>, Box<dyn Error>> {
+        if let Some(dir) = &spec.dir {
+            fs::create_dir_all(dir.join("processed"))?;
+            fs::create_dir_all(dir.join("failed"))?;
+        }
+        // Lines are found by looking for \n bytes
+        if spec.tail.is_some() && spec.dialect.encoding == Encoding::Utf16Le {
+            return Err("Can't tail UTF-16 files".into());
+        }
+        Ok(Watcher {
+            spec,
+            pending: HashMap::new(),
+            tail: spec.tail.clone().map(|path| Tail {
+                path,
+                offset: 0,
+                header: Vec::new(),
+            }),
+            last_export: None,
+            dirty: false,
+        })
+    }
+
+    /// Polls forever. Only errors it can't carry on from (e.g. the directory
+    /// went away, or the engine itself failed) stop it.
+    pub fn run(&mut self, engine: &mut PaymentEngine) -> Result<(), Box<dyn Error>> {
+        loop {
+            self.poll(engine)?;
+            thread::sleep(self.spec.interval);
+        }
+    }
+
+    /// A single round: imports whatever is ready and exports if it's time.
+    /// Returns the number of files imported.
+    pub fn poll(&mut self, engine: &mut PaymentEngine) -> Result<usize, Box<dyn Error>> {
+        let mut imported = 0;
+        if let Some(dir) = &self.spec.dir {
+            for path in self.ready(dir)? {
+                let result = File::open(&path)
+                    .map_err(|e| e.into())
+                    .and_then(|file| engine.import_reader_with_dialect(file, &self.spec.dialect));
+                let to = match result {
+                    Ok(()) => {
+                        info!("Imported {:?}", path);
+                        imported += 1;
+                        "processed"
+                    }
+                    Err(e) => {
+                        error!("Importing {:?}: {}", path, e);
+                        "failed"
+                    }
+                };
+                // Even a failed file may have been imported up to the bad row
+                self.dirty = true;
+                fs::rename(
+                    &path,
+                    dir.join(to).join(path.file_name().unwrap_or_default()),
+                )?;
+            }
+        }
+        if let Some(tail) = &mut self.tail {
+            match tail.read(engine, &self.spec.dialect) {
+                Ok(true) => self.dirty = true,
+                Ok(false) => {}
+                Err(e) => {
+                    error!("Tailing {:?}: {}", tail.path, e);
+                    self.dirty = true;
+                }
+            }
+        }
+        let due = self
+            .last_export
+            .is_none_or(|last| last.elapsed() >= self.spec.export_every);
+        if self.dirty && due {
+            if let Some(path) = &self.spec.export {
+                export(engine, path, self.spec.export_format)?;
+            }
+            self.last_export = Some(Instant::now());
+            self.dirty = false;
+        }
+        Ok(imported)
+    }
+
+    /// Call when stopping, so the export isn't left behind.
+    pub fn flush(&mut self, engine: &PaymentEngine) -> Result<(), Box<dyn Error>> {
+        if let (true, Some(path)) = (self.dirty, &self.spec.export) {
+            export(engine, path, self.spec.export_format)?;
+            self.dirty = false;
+        }
+        Ok(())
+    }
+
+    // CSV files in the directory whose size is the same as at the last poll,
+    // oldest name first
+    fn ready(&mut self, dir: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+        let mut sizes = HashMap::new();
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            let csv = path
+                .extension()
+                .is_some_and(|extension| extension.eq_ignore_ascii_case("csv"));
+            if csv && entry.file_type()?.is_file() {
+                sizes.insert(path, entry.metadata()?.len());
+            }
+        }
+        let mut ready: Vec<PathBuf> = sizes
+            .iter()
+            .filter(|(path, size)| self.pending.get(*path) == Some(size))
+            .map(|(path, _)| path.clone())
+            .collect();
+        ready.sort();
+        for path in &ready {
+            sizes.remove(path);
+        }
+        self.pending = sizes;
+        Ok(ready)
+    }
+}
+
+impl Tail {
+    // Whether anything was read
+    fn read(
+        &mut self,
+        engine: &mut PaymentEngine,
+        dialect: &CsvDialect,
+    ) -> Result<bool, Box<dyn Error>> {
+        let mut file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e.into()),
+        };
+        let len = file.metadata()?.len();
+        if len < self.offset {
+            warn!("{:?} got shorter, reading it from the start", self.path);
+            self.offset = 0;
+            self.header.clear();
+        }
+        file.seek(SeekFrom::Start(self.offset))?;
+        let mut chunk = Vec::new();
+        file.take(len - self.offset).read_to_end(&mut chunk)?;
+        let complete = match chunk.iter().rposition(|&b| b == b'\n') {
+            Some(last) => last + 1,
+            None => return Ok(false),
+        };
+        chunk.truncate(complete);
+        self.offset += complete as u64;
+        // Every chunk goes in with the header, for the column names
+        if dialect.header && self.header.is_empty() {
+            let end = chunk.iter().position(|&b| b == b'\n').unwrap_or(0) + 1;
+            self.header = chunk.drain(..end).collect();
+        }
+        engine.import_reader_with_dialect(self.header.as_slice().chain(&chunk[..]), dialect)?;
+        Ok(true)
+    }
+}
+
+fn export(engine: &PaymentEngine, path: &Path, format: OutputFormat) -> Result<(), Box<dyn Error>> {
+    state::write_atomically(path, |writer| match format {
+        OutputFormat::Csv => Ok(engine.export_accounts(writer)?),
+        OutputFormat::State(format) => engine.save_accounts(writer, format),
+        OutputFormat::Parquet | OutputFormat::Beancount => {
+            Err("Use csv, msgpack or cbor to export while watching".into())
+        }
+    })?;
+    info!("Exported the accounts to {:?}", path);
+    Ok(())
+}
+
+#[test]
+fn test_watch() {
+    use std::io::Write;
+
+    let dir = tempfile::tempdir().unwrap();
+    let incoming = dir.path().join("incoming");
+    let tailed = dir.path().join("live.csv");
+    let spec = WatchSpec {
+        dir: Some(incoming.clone()),
+        tail: Some(tailed.clone()),
+        dialect: CsvDialect::default(),
+        interval: Duration::from_millis(0),
+        export: Some(dir.path().join("accounts.csv")),
+        export_every: Duration::from_secs(0),
+        export_format: OutputFormat::Csv,
+    };
+    let mut engine = PaymentEngine::new();
+    let mut watcher = Watcher::new(&spec).unwrap();
+
+    fs::write(
+        incoming.join("1.csv"),
+        "type,client,tx,amount\ndeposit,1,1,10\n",
+    )
+    .unwrap();
+    fs::write(
+        incoming.join("bad.csv"),
+        "type,client,tx,amount\nrefund,1,2,1\n",
+    )
+    .unwrap();
+    fs::write(incoming.join("notes.txt"), "not a CSV").unwrap();
+    // Seen once, not ready yet
+    assert_eq!(watcher.poll(&mut engine).unwrap(), 0);
+    assert_eq!(watcher.poll(&mut engine).unwrap(), 1);
+    assert!(incoming.join("processed/1.csv").exists());
+    assert!(incoming.join("failed/bad.csv").exists());
+    assert!(incoming.join("notes.txt").exists());
+
+    let mut live = File::create(&tailed).unwrap();
+    live.write_all(b"type,client,tx,amount\ndeposit,2,3,5\nwithdrawal,2,4")
+        .unwrap();
+    watcher.poll(&mut engine).unwrap();
+    live.write_all(b",1\ndeposit,1,5,1\n").unwrap();
+    watcher.poll(&mut engine).unwrap();
+
+    let mut expected = PaymentEngine::new();
+    expected
+        .import_reader(
+            &b"type,client,tx,amount\ndeposit,1,1,10\ndeposit,2,3,5\nwithdrawal,2,4,1\ndeposit,1,5,1\n"[..],
+        )
+        .unwrap();
+    assert_eq!(engine.sorted_accounts(), expected.sorted_accounts());
+    let mut exported = Vec::new();
+    expected.export_accounts(&mut exported).unwrap();
+    assert_eq!(fs::read(spec.export.unwrap()).unwrap(), exported);
+}
@@ -0,0 +1,244 @@
+use crate::engine::Account;
+use crate::http;
+use crate::observer::EngineObserver;
+use crate::sink::{CircuitBreakerConfig, ResilientSink, RetryPolicy, Sink};
+use crate::spool::{DiskSpool, DiskSpoolConfig};
+use crate::transaction::{ClientId, Transaction, TransactionId, TransactionType};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::sync::mpsc::{sync_channel, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tracing::warn;
+
+/*
+POSTs a JSON object to a URL when a chargeback is applied and when an account
+gets locked, for the fraud team:
+
+  {"event":"chargeback","client":1,"tx":7,"available":"0","held":"0","total":"0","locked":true}
+  {"event":"account_locked","client":1,"tx":7,...}
+
+tx is the charged back transaction (for account_locked, the chargeback or
+the transaction over a limit that locked it), the balances are the account's
+right after. Deliveries go through a ResilientSink (see sink.rs): a webhook that's
+down gets retried and its events spooled, never fails the run. The spool is in
+memory unless one is configured, events still spooled when the process exits
+are lost then.
+
+The engine doesn't wait for deliveries: it's holding its lock when it
+notifies, in the servers too. Events go in a queue of QUEUE_SIZE and a thread
+of the webhook's delivers them; when the queue is full (the webhook has been
+slow for that many events) the new ones are dropped, with a warning. Once the
+engine is gone the thread delivers (or spools) what's left in the queue
+before it ends.
+*/
+
+const QUEUE_SIZE: usize = 1024;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookSpec {
+    /// http:// only
+    pub url: String,
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+    #[serde(default)]
+    pub retry: RetryPolicy,
+    #[serde(default)]
+    pub circuit_breaker: CircuitBreakerConfig,
+    /// Keep undelivered events on disk, across runs
+    pub spool: Option<DiskSpoolConfig>,
+}
+
+fn default_timeout_ms() -> u64 {
+    5000
+}
+
+impl WebhookSpec {
+    /// Just the URL, the rest at the defaults, for --webhook.
+    pub fn new(url: String) -> WebhookSpec {
+        WebhookSpec {
+            url,
+            timeout_ms: default_timeout_ms(),
+            retry: RetryPolicy::default(),
+            circuit_breaker: CircuitBreakerConfig::default(),
+            spool: None,
+        }
+    }
+
+    pub fn build(&self) -> Result<Arc<Webhook>, Box<dyn Error>> {
+        let sink = WebhookSink {
+            url: self.url.clone(),
+            timeout: Duration::from_millis(self.timeout_ms),
+        };
+        let mut sink = ResilientSink::new(sink, self.retry.clone(), self.circuit_breaker.clone());
+        if let Some(spool) = &self.spool {
+            sink = sink.with_spool(Box::new(DiskSpool::open(spool.clone())?));
+        }
+        let (queue, events) = sync_channel::<Vec<u8>>(QUEUE_SIZE);
+        let worker = thread::Builder::new()
+            .name("webhook".to_string())
+            .spawn(move || {
+                for payload in events {
+                    // Only spooling can fail, and the run goes on without the event
+                    if let Err(e) = sink.send(&payload) {
+                        warn!(url = sink.name(), "Webhook event dropped: {}", e);
+                    }
+                }
+            })?;
+        Ok(Arc::new(Webhook {
+            queue: Some(queue),
+            worker: Some(worker),
+        }))
+    }
+}
+
+pub struct WebhookSink {
+    url: String,
+    timeout: Duration,
+}
+
+impl Sink for WebhookSink {
+    fn name(&self) -> &str {
+        &self.url
+    }
+
+    fn send(&mut self, payload: &[u8]) -> Result<(), Box<dyn Error>> {
+        match http::post(&self.url, "application/json", payload, self.timeout)? {
+            200..=299 => Ok(()),
+            status => Err(format!("HTTP {}", status).into()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Event {
+    event: &'static str,
+    client: ClientId,
+    tx: TransactionId,
+    available: Decimal,
+    held: Decimal,
+    total: Decimal,
+    locked: bool,
+}
+
+/// The observer that sends the events, register it on the engine.
+pub struct Webhook {
+    // Both only None once dropped
+    queue: Option<SyncSender<Vec<u8>>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl Webhook {
+    fn notify(&self, event: &'static str, account: &Account, tx: TransactionId) {
+        let event = Event {
+            event,
+            client: account.client_id(),
+            tx,
+            available: account.available(),
+            held: account.held(),
+            total: account.total(),
+            locked: account.is_locked(),
+        };
+        let result = serde_json::to_vec(&event)
+            .map_err(|e| e.to_string())
+            .and_then(|payload| match &self.queue {
+                Some(queue) => queue.try_send(payload).map_err(|e| match e {
+                    TrySendError::Full(_) => "the queue is full".to_string(),
+                    TrySendError::Disconnected(_) => "the webhook thread stopped".to_string(),
+                }),
+                None => Err("the webhook was dropped".to_string()),
+            });
+        if let Err(e) = result {
+            warn!(event = event.event, "Webhook event dropped: {}", e);
+        }
+    }
+}
+
+impl Drop for Webhook {
+    fn drop(&mut self) {
+        // Ends the thread once it's through the queue
+        self.queue = None;
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl EngineObserver for Webhook {
+    fn on_applied(&self, transaction: &Transaction, account: &Account) {
+        if transaction.tx_type == TransactionType::Chargeback {
+            self.notify("chargeback", account, transaction.tx_id);
+        }
+    }
+
+    fn on_account_locked(&self, account: &Account, tx: TransactionId) {
+        self.notify("account_locked", account, tx);
+    }
+}
+
+#[test]
+fn test_webhook() {
+    use crate::PaymentEngine;
+    use std::io::BufReader;
+    use std::net::TcpListener;
+    use std::thread;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/hooks/fraud", listener.local_addr().unwrap());
+    let receiver = thread::spawn(move || {
+        let mut received = Vec::new();
+        for stream in listener.incoming().take(2) {
+            let mut stream = stream.unwrap();
+            let request = http::read_request(&mut BufReader::new(&stream)).unwrap();
+            assert_eq!(
+                (request.method.as_str(), request.path.as_str()),
+                ("POST", "/hooks/fraud")
+            );
+            received.push(String::from_utf8(request.body).unwrap());
+            http::write_response(&mut stream, 200, "text/plain", b"").unwrap();
+        }
+        received
+    });
+
+    let mut engine = PaymentEngine::new().with_observer(WebhookSpec::new(url).build().unwrap());
+    engine
+        .import_reader(
+            &b"type,client,tx,amount\ndeposit,1,7,10\ndispute,1,7,\nchargeback,1,7,\n"[..],
+        )
+        .unwrap();
+    assert_eq!(
+        receiver.join().unwrap(),
+        vec![
+            r#"{"event":"chargeback","client":1,"tx":7,"available":"10","held":"0","total":"10","locked":true}"#,
+            r#"{"event":"account_locked","client":1,"tx":7,"available":"10","held":"0","total":"10","locked":true}"#,
+        ]
+    );
+}
+
+#[test]
+fn test_slow_webhook() {
+    use crate::PaymentEngine;
+    use std::net::TcpListener;
+    use std::time::Instant;
+
+    // Takes the connection and never answers
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut spec = WebhookSpec::new(format!("http://{}/", listener.local_addr().unwrap()));
+    spec.timeout_ms = 2000;
+    spec.retry.max_attempts = 1;
+    spec.circuit_breaker.failure_threshold = 1;
+    let mut engine = PaymentEngine::new().with_observer(spec.build().unwrap());
+    let started = Instant::now();
+    engine
+        .import_reader(
+            &b"type,client,tx,amount\ndeposit,1,7,10\ndispute,1,7,\nchargeback,1,7,\n"[..],
+        )
+        .unwrap();
+    assert!(started.elapsed() < Duration::from_millis(1000));
+    // Waits for the delivery to time out
+    drop(engine);
+    assert!(started.elapsed() >= Duration::from_millis(2000));
+}
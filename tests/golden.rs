@@ -0,0 +1,112 @@
+/*
+Runs the binary on every tests/golden/<name>.csv (with the extra flags in
+<name>.args, if there's one) and compares the exported accounts with
+<name>.expected. Accounts come out in no particular order so lines are sorted
+before comparing, header first.
+
+To add a case, drop in the CSV and run with UPDATE_GOLDEN=1 to write its
+.expected, then check it by hand before committing.
+*/
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+fn sorted(output: &str) -> String {
+    let mut lines: Vec<&str> = output.lines().collect();
+    let header = if lines.is_empty() {
+        None
+    } else {
+        Some(lines.remove(0))
+    };
+    lines.sort_unstable();
+    header
+        .into_iter()
+        .chain(lines)
+        .map(|line| format!("{}\n", line))
+        .collect()
+}
+
+fn run(input: &Path) -> String {
+    let args = fs::read_to_string(input.with_extension("args")).unwrap_or_default();
+    let output = Command::new(env!("CARGO_BIN_EXE_payments-engine"))
+        .args(args.split_whitespace())
+        .arg(input)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?} failed: {:?}", input, output);
+    sorted(&String::from_utf8(output.stdout).unwrap())
+}
+
+#[test]
+fn test_golden_files() {
+    let update = std::env::var_os("UPDATE_GOLDEN").is_some();
+    let mut cases = 0;
+    for entry in fs::read_dir("tests/golden").unwrap() {
+        let input = entry.unwrap().path();
+        if input.extension() != Some("csv".as_ref()) {
+            continue;
+        }
+        cases += 1;
+        let golden = input.with_extension("expected");
+        let actual = run(&input);
+        if update {
+            fs::write(&golden, &actual).unwrap();
+            continue;
+        }
+        let expected = fs::read_to_string(&golden)
+            .unwrap_or_else(|_| panic!("No {:?}, run with UPDATE_GOLDEN=1", golden));
+        assert_eq!(actual, expected, "{:?}", input);
+    }
+    assert!(cases > 0);
+}
+
+#[test]
+fn test_exit_codes() {
+    let dir = std::env::temp_dir().join(format!("exit-codes-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let cases = [
+        ("parse", "deposit,1,1,1x\n", 4, Some("parse")),
+        (
+            "invariant",
+            "deposit,1,1,1\ndeposit,1,1,1\n",
+            5,
+            Some("invariant"),
+        ),
+        ("ok", "deposit,1,1,1\n", 0, None),
+    ];
+    for (name, rows, code, expected) in cases.iter() {
+        let input = dir.join(format!("{}.csv", name));
+        fs::write(&input, format!("type,client,tx,amount\n{}", rows)).unwrap();
+        let json = dir.join(format!("{}.json", name));
+        let status = Command::new(env!("CARGO_BIN_EXE_payments-engine"))
+            .arg("--error-json")
+            .arg(&json)
+            .arg(&input)
+            .output()
+            .unwrap()
+            .status;
+        assert_eq!(status.code(), Some(*code), "{}", name);
+        let category = fs::read_to_string(&json).ok().map(|json| {
+            let json: serde_json::Value = serde_json::from_str(&json).unwrap();
+            json["category"].as_str().unwrap().to_string()
+        });
+        assert_eq!(category.as_deref(), *expected, "{}", name);
+    }
+    let status = Command::new(env!("CARGO_BIN_EXE_payments-engine"))
+        .arg(dir.join("missing.csv"))
+        .output()
+        .unwrap()
+        .status;
+    assert_eq!(status.code(), Some(3));
+    let control = dir.join("expected.csv");
+    fs::write(&control, "client,total\n1,2\n").unwrap();
+    let status = Command::new(env!("CARGO_BIN_EXE_payments-engine"))
+        .arg("--reconcile")
+        .arg(&control)
+        .arg(dir.join("ok.csv"))
+        .output()
+        .unwrap()
+        .status;
+    assert_eq!(status.code(), Some(6));
+    fs::remove_dir_all(&dir).unwrap();
+}